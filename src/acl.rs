@@ -0,0 +1,903 @@
+//! ACL users, the commands, keys and channels they may use, and the log of what they
+//! were denied
+//!
+//! There are no selectors, every user has a single set of permissions.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::command_table::{all_commands, lookup_full_name, CommandSpec};
+use crate::config::split_args;
+use crate::glob::glob_match;
+use crate::resp::RespValue;
+use crate::sha256::sha256_hex;
+
+/// Categories `+@name` and `-@name` rules work on, as ACL CAT lists them
+pub const ACL_CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+/// Denials closer together than this, in milliseconds, are counted in one ACL LOG entry
+const ACL_LOG_GROUPING_MAX_TIME_DELTA: i64 = 60000;
+
+/// Keys matching `pattern` may be read and or written
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyPattern {
+    pub pattern: String,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        let prefix = match (self.read, self.write) {
+            (true, false) => "%R~",
+            (false, true) => "%W~",
+            _ => "~",
+        };
+        format!("{}{}", prefix, self.pattern)
+    }
+}
+
+/// Why a command was refused, with the key or channel it wasn't allowed to use
+#[derive(Debug, PartialEq)]
+pub enum Denied {
+    Command,
+    Key(String),
+    Channel(String),
+}
+
+impl Denied {
+    /// Reason shown in ACL LOG
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Denied::Command => "command",
+            Denied::Key(_) => "key",
+            Denied::Channel(_) => "channel",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    /// Any password is accepted
+    pub nopass: bool,
+    /// Hex encoded SHA256 of each password
+    passwords: Vec<String>,
+    /// Full names of the commands the user may run, like `config|get`
+    allowed: BTreeSet<&'static str>,
+    /// Whether the command rules start from all commands or none
+    all_commands: bool,
+    /// `+` and `-` rules applied on top of that, as ACL LIST shows them
+    command_rules: Vec<String>,
+    all_keys: bool,
+    key_patterns: Vec<KeyPattern>,
+    all_channels: bool,
+    channel_patterns: Vec<String>,
+}
+
+impl User {
+    /// A user that is off and may do nothing, what ACL SETUSER starts new users from
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            allowed: BTreeSet::new(),
+            all_commands: false,
+            command_rules: Vec::new(),
+            all_keys: false,
+            key_patterns: Vec::new(),
+            all_channels: false,
+            channel_patterns: Vec::new(),
+        }
+    }
+
+    /// Apply one ACL SETUSER rule like `on`, `>password`, `~key:*` or `+@read`
+    pub fn set_rule(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => {
+                self.all_keys = true;
+                self.key_patterns.clear();
+            }
+            "resetkeys" => {
+                self.all_keys = false;
+                self.key_patterns.clear();
+            }
+            "allchannels" => {
+                self.all_channels = true;
+                self.channel_patterns.clear();
+            }
+            "resetchannels" => {
+                self.all_channels = false;
+                self.channel_patterns.clear();
+            }
+            "allcommands" => self.command_rule("+@all")?,
+            "nocommands" => self.command_rule("-@all")?,
+            "reset" => *self = User::new(&self.name),
+            _ => match rule.split_at(rule.chars().next().map_or(0, char::len_utf8)) {
+                (">", password) => self.add_password(sha256_hex(password.as_bytes())),
+                ("<", password) => self.remove_password(&sha256_hex(password.as_bytes()))?,
+                ("#", hash) => self.add_password(valid_hash(hash)?),
+                ("!", hash) => self.remove_password(&valid_hash(hash)?)?,
+                ("~", pattern) => self.add_key_pattern(pattern, true, true)?,
+                ("%", _) => {
+                    let (flags, pattern) = rule[1..].split_once('~').ok_or("Syntax error")?;
+                    let flags = flags.to_uppercase();
+                    if flags.is_empty() || flags.chars().any(|c| c != 'R' && c != 'W') {
+                        return Err("Syntax error".into());
+                    }
+                    self.add_key_pattern(pattern, flags.contains('R'), flags.contains('W'))?;
+                }
+                ("&", pattern) => self.add_channel_pattern(pattern)?,
+                ("+" | "-", _) => self.command_rule(rule)?,
+                _ => return Err("Syntax error".into()),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<(), String> {
+        let Some(i) = self.passwords.iter().position(|p| p == hash) else {
+            return Err(
+                "The password you are trying to remove from the user does not exist".into(),
+            );
+        };
+        self.passwords.remove(i);
+        Ok(())
+    }
+
+    fn add_key_pattern(&mut self, pattern: &str, read: bool, write: bool) -> Result<(), String> {
+        if self.all_keys {
+            return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns".into());
+        }
+        if pattern == "*" && read && write {
+            self.all_keys = true;
+            self.key_patterns.clear();
+            return Ok(());
+        }
+
+        match self.key_patterns.iter_mut().find(|p| p.pattern == pattern) {
+            Some(existing) => {
+                existing.read |= read;
+                existing.write |= write;
+            }
+            None => self.key_patterns.push(KeyPattern {
+                pattern: pattern.into(),
+                read,
+                write,
+            }),
+        }
+        Ok(())
+    }
+
+    fn add_channel_pattern(&mut self, pattern: &str) -> Result<(), String> {
+        if self.all_channels {
+            return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels".into());
+        }
+        if pattern == "*" {
+            self.all_channels = true;
+            self.channel_patterns.clear();
+        } else if !self.channel_patterns.iter().any(|p| p == pattern) {
+            self.channel_patterns.push(pattern.into());
+        }
+        Ok(())
+    }
+
+    /// `+name`, `-name`, `+@category` or `-@category`
+    fn command_rule(&mut self, rule: &str) -> Result<(), String> {
+        let allow = rule.starts_with('+');
+        let name = rule[1..].to_lowercase();
+        let unknown = || "Unknown command or category name in ACL".to_string();
+
+        if name == "@all" {
+            self.all_commands = allow;
+            self.command_rules.clear();
+            self.allowed.clear();
+            if allow {
+                self.allowed.extend(all_commands().map(|c| c.name));
+            }
+            return Ok(());
+        }
+
+        let commands: Vec<&'static CommandSpec> = match name.strip_prefix('@') {
+            Some(category) => {
+                if !ACL_CATEGORIES.contains(&category) {
+                    return Err(unknown());
+                }
+                all_commands()
+                    .filter(|c| c.acl_categories.contains(&category))
+                    .collect()
+            }
+            None => {
+                let spec = lookup_full_name(&name).ok_or_else(unknown)?;
+                std::iter::once(spec).chain(spec.subcommands).collect()
+            }
+        };
+        for spec in commands {
+            match allow {
+                true => self.allowed.insert(spec.name),
+                false => self.allowed.remove(spec.name),
+            };
+        }
+
+        // a later rule for the same name replaces the earlier one
+        self.command_rules.retain(|r| r[1..] != name);
+        self.command_rules
+            .push(format!("{}{}", if allow { '+' } else { '-' }, name));
+        Ok(())
+    }
+
+    /// Whether `password` logs in as this user
+    pub fn password_matches(&self, password: &str) -> bool {
+        self.nopass || self.passwords.contains(&sha256_hex(password.as_bytes()))
+    }
+
+    /// Check that the user may run `spec` with the full command line `args`, subscribing
+    /// or publishing to `channels`, which are patterns when `patterns` is set
+    pub fn check(
+        &self,
+        spec: &CommandSpec,
+        args: &[String],
+        channels: &[String],
+        patterns: bool,
+    ) -> Result<(), Denied> {
+        // commands that work before authenticating can't be taken away
+        if !self.allowed.contains(spec.name) && !spec.flags.contains(&"no_auth") {
+            return Err(Denied::Command);
+        }
+
+        if !self.all_keys {
+            for key_spec in spec.key_specs {
+                if key_spec.flags.contains(&"not_key") {
+                    continue;
+                }
+                let read = key_spec.flags.contains(&"access");
+                let write = key_spec
+                    .flags
+                    .iter()
+                    .any(|f| matches!(*f, "insert" | "delete" | "update"));
+
+                for key in key_spec.keys(args).unwrap_or_default() {
+                    if !self.key_allowed(key, read, write) {
+                        return Err(Denied::Key(key.clone()));
+                    }
+                }
+            }
+        }
+
+        if !self.all_channels {
+            for channel in channels {
+                // a pattern subscription has to be allowed as it is
+                let allowed = match patterns {
+                    true => self.channel_patterns.contains(channel),
+                    false => self
+                        .channel_patterns
+                        .iter()
+                        .any(|p| glob_match(p, channel, false)),
+                };
+                if !allowed {
+                    return Err(Denied::Channel(channel.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn key_allowed(&self, key: &str, read: bool, write: bool) -> bool {
+        self.key_patterns
+            .iter()
+            .any(|p| (p.read || !read) && (p.write || !write) && glob_match(&p.pattern, key, false))
+    }
+
+    fn describe_commands(&self) -> String {
+        let base = if self.all_commands { "+@all" } else { "-@all" };
+        std::iter::once(base.to_string())
+            .chain(self.command_rules.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn describe_keys(&self) -> String {
+        if self.all_keys {
+            return "~*".into();
+        }
+        self.key_patterns
+            .iter()
+            .map(KeyPattern::describe)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn describe_channels(&self) -> String {
+        if self.all_channels {
+            return "&*".into();
+        }
+        self.channel_patterns
+            .iter()
+            .map(|p| format!("&{}", p))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The user as a line of ACL LIST, rules that recreate it
+    pub fn describe(&self) -> String {
+        let mut parts = vec![
+            "user".to_string(),
+            self.name.clone(),
+            if self.enabled { "on" } else { "off" }.into(),
+        ];
+        if self.nopass {
+            parts.push("nopass".into());
+        }
+        parts.extend(self.passwords.iter().map(|p| format!("#{}", p)));
+
+        let keys = self.describe_keys();
+        if !keys.is_empty() {
+            parts.push(keys);
+        }
+        if !self.all_channels {
+            parts.push("resetchannels".into());
+        }
+        let channels = self.describe_channels();
+        if !channels.is_empty() {
+            parts.push(channels);
+        }
+        parts.push(self.describe_commands());
+
+        parts.join(" ")
+    }
+
+    /// Reply to ACL GETUSER
+    pub fn reply(&self) -> RespValue {
+        let bulk = |s: &str| RespValue::BulkString(s.into());
+
+        let mut flags = vec![bulk(if self.enabled { "on" } else { "off" })];
+        if self.nopass {
+            flags.push(bulk("nopass"));
+        }
+
+        RespValue::Map(vec![
+            (bulk("flags"), RespValue::Array(flags)),
+            (
+                bulk("passwords"),
+                RespValue::Array(self.passwords.iter().map(|p| bulk(p)).collect()),
+            ),
+            (bulk("commands"), bulk(&self.describe_commands())),
+            (bulk("keys"), bulk(&self.describe_keys())),
+            (bulk("channels"), bulk(&self.describe_channels())),
+            (bulk("selectors"), RespValue::Array(vec![])),
+        ])
+    }
+}
+
+fn valid_username(name: &str) -> Result<(), String> {
+    if name.contains(|c: char| c.is_whitespace() || c == '\0') {
+        return Err("Usernames can't contain spaces or null characters".into());
+    }
+    Ok(())
+}
+
+/// `hash` if it is a hex encoded SHA256 the way ACL LIST shows passwords
+fn valid_hash(hash: &str) -> Result<String, String> {
+    if hash.len() != 64
+        || !hash
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    {
+        return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".into());
+    }
+    Ok(hash.into())
+}
+
+pub struct Acl {
+    users: BTreeMap<String, User>,
+}
+
+impl Acl {
+    /// Only the `default` user, who may do anything without a password
+    pub fn new() -> Self {
+        let mut default = User::new("default");
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            default.set_rule(rule).unwrap();
+        }
+
+        Self {
+            users: BTreeMap::from([("default".to_string(), default)]),
+        }
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    /// All users, by name
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Apply `rules` to the user called `name`, creating it if needed
+    ///
+    /// Either all rules are applied or none of them.
+    pub fn set_user(&mut self, name: &str, rules: &[String]) -> Result<(), String> {
+        valid_username(name)?;
+
+        let mut user = self
+            .users
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.set_rule(rule)
+                .map_err(|e| format!("Error in ACL SETUSER modifier '{}': {}", rule, e))?;
+        }
+
+        self.users.insert(name.into(), user);
+        Ok(())
+    }
+
+    /// Remove the user called `name`, whether there was one
+    pub fn delete_user(&mut self, name: &str) -> bool {
+        self.users.remove(name).is_some()
+    }
+
+    /// Users from an ACL file of `user <name> [rule ...]` lines, with the default user
+    /// the way it starts if the file doesn't have it
+    ///
+    /// All errors in the file are reported together.
+    pub fn load_file(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Error loading ACLs, opening file '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+
+        let mut users = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = format!("{}:{}", path.display(), n + 1);
+
+            let args = match split_args(line) {
+                Some(args) if args.len() >= 2 && args[0] == "user" => args,
+                Some(_) => {
+                    errors.push(format!("{}: line should start with user keyword", at));
+                    continue;
+                }
+                None => {
+                    errors.push(format!("{}: unbalanced quotes in acl line", at));
+                    continue;
+                }
+            };
+
+            let name = &args[1];
+            if let Err(e) = valid_username(name) {
+                errors.push(format!("{}: {}", at, e));
+                continue;
+            }
+            if users.contains_key(name) {
+                errors.push(format!("{}: Duplicate user '{}' found", at, name));
+                continue;
+            }
+
+            let mut user = User::new(name);
+            let failed = args[2..]
+                .iter()
+                .find_map(|rule| user.set_rule(rule).err().map(|e| (rule, e)));
+            match failed {
+                Some((rule, e)) => errors.push(format!(
+                    "{}: Error in user declaration '{}': {}",
+                    at, rule, e
+                )),
+                None => {
+                    users.insert(name.clone(), user);
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(format!("{}.", errors.join(". ")));
+        }
+
+        let mut acl = Acl::new();
+        acl.users.extend(users);
+        Ok(acl)
+    }
+
+    /// Write every user to `path` the way ACL LIST shows them
+    pub fn save_file(&self, path: &Path) -> Result<(), String> {
+        let contents: String = self.users().map(|u| u.describe() + "\n").collect();
+
+        // write a temporary file and move it over the old one, so a failed write
+        // doesn't lose the users saved before
+        let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(&tmp, contents)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("Saving ACL file '{}': {}", path.display(), e))
+    }
+
+    /// Whether `password` logs in as the user called `name`, who has to be on
+    pub fn authenticate(&self, name: &str, password: &str) -> bool {
+        self.users
+            .get(name)
+            .is_some_and(|u| u.enabled && u.password_matches(password))
+    }
+}
+
+pub struct AclLogEntry {
+    pub id: u64,
+    /// How many times the same denial happened
+    pub count: u64,
+    pub reason: &'static str,
+    /// `toplevel`, `multi` or `lua`
+    pub context: &'static str,
+    /// The command, key or channel that was denied
+    pub object: String,
+    pub username: String,
+    /// Unix time in milliseconds
+    pub created: i64,
+    pub updated: i64,
+    /// CLIENT INFO line of the client that was denied last
+    pub client_info: String,
+}
+
+impl AclLogEntry {
+    pub fn new(
+        reason: &'static str,
+        context: &'static str,
+        object: String,
+        username: String,
+        client_info: String,
+        now: i64,
+    ) -> Self {
+        Self {
+            id: 0,
+            count: 1,
+            reason,
+            context,
+            object,
+            username,
+            created: now,
+            updated: now,
+            client_info,
+        }
+    }
+
+    pub fn reply(&self, now: i64) -> RespValue {
+        let bulk = |s: &str| RespValue::BulkString(s.into());
+
+        RespValue::Map(vec![
+            (bulk("count"), RespValue::Integer(self.count as i64)),
+            (bulk("reason"), bulk(self.reason)),
+            (bulk("context"), bulk(self.context)),
+            (bulk("object"), bulk(&self.object)),
+            (bulk("username"), bulk(&self.username)),
+            (
+                bulk("age-seconds"),
+                bulk(&format!("{:.3}", (now - self.created) as f64 / 1000.0)),
+            ),
+            (bulk("client-info"), bulk(&self.client_info)),
+            (bulk("entry-id"), RespValue::Integer(self.id as i64)),
+            (bulk("timestamp-created"), RespValue::Integer(self.created)),
+            (
+                bulk("timestamp-last-updated"),
+                RespValue::Integer(self.updated),
+            ),
+        ])
+    }
+}
+
+#[derive(Default)]
+pub struct AclLog {
+    /// Newest first
+    entries: VecDeque<AclLogEntry>,
+    next_id: u64,
+}
+
+impl AclLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry, or count it in a recent one for the same denial, dropping the oldest
+    /// ones past `max_len`
+    pub fn push(&mut self, mut entry: AclLogEntry, max_len: usize) {
+        let similar = self.entries.iter().position(|e| {
+            e.reason == entry.reason
+                && e.context == entry.context
+                && e.object == entry.object
+                && e.username == entry.username
+                && entry.created - e.updated < ACL_LOG_GROUPING_MAX_TIME_DELTA
+        });
+
+        match similar.and_then(|i| self.entries.remove(i)) {
+            Some(mut existing) => {
+                existing.count += 1;
+                existing.updated = entry.created;
+                existing.client_info = entry.client_info;
+                self.entries.push_front(existing);
+            }
+            None => {
+                entry.id = self.next_id;
+                self.next_id += 1;
+                self.entries.push_front(entry);
+            }
+        }
+
+        self.entries.truncate(max_len);
+    }
+
+    /// The `count` newest entries
+    pub fn newest(&self, count: usize) -> impl Iterator<Item = &AclLogEntry> {
+        self.entries.iter().take(count)
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_table::lookup;
+
+    fn user(rules: &[&str]) -> User {
+        let mut user = User::new("alice");
+        for rule in rules {
+            user.set_rule(rule).unwrap();
+        }
+        user
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            Acl::new().user("default").unwrap().describe(),
+            "user default on nopass ~* &* +@all"
+        );
+        assert_eq!(
+            User::new("alice").describe(),
+            "user alice off resetchannels -@all"
+        );
+
+        let alice = user(&[
+            "on",
+            ">pass",
+            "~a:*",
+            "%R~b:*",
+            "&news",
+            "+@read",
+            "-get",
+            "+get",
+            "+config|get",
+        ]);
+        assert_eq!(
+            alice.describe(),
+            format!(
+                "user alice on #{} ~a:* %R~b:* resetchannels &news -@all +@read +get +config|get",
+                sha256_hex(b"pass")
+            )
+        );
+    }
+
+    #[test]
+    fn test_set_rule() {
+        let mut alice = user(&[">a", ">b"]);
+        assert!(alice.password_matches("a"));
+        alice.set_rule("<a").unwrap();
+        assert!(!alice.password_matches("a"));
+        assert!(alice.set_rule("<a").is_err());
+        assert!(alice.set_rule("#nothex").is_err());
+        alice.set_rule("nopass").unwrap();
+        assert!(alice.password_matches("anything"));
+
+        assert_eq!(
+            alice.set_rule("+nope"),
+            Err("Unknown command or category name in ACL".into())
+        );
+        assert!(alice.set_rule("+@nope").is_err());
+        assert!(alice.set_rule("%X~a").is_err());
+        assert!(alice.set_rule("bogus").is_err());
+
+        alice.set_rule("allkeys").unwrap();
+        assert!(alice.set_rule("~a").is_err());
+
+        let mut acl = Acl::new();
+        // nothing is applied when a rule fails
+        assert!(acl
+            .set_user("bob", &args(&["on", "nopass", "+nope"]))
+            .is_err());
+        assert!(acl.user("bob").is_none());
+        acl.set_user("bob", &args(&["on", ">pw"])).unwrap();
+        assert!(acl.authenticate("bob", "pw"));
+        acl.set_user("bob", &args(&["off"])).unwrap();
+        assert!(!acl.authenticate("bob", "pw"));
+    }
+
+    #[test]
+    fn test_check() {
+        let alice = user(&[
+            "+@read",
+            "+set",
+            "-config",
+            "+config|get",
+            "%R~r:*",
+            "~rw:*",
+            "&news.*",
+        ]);
+        let check = |line: &[&str], channels: &[&str], patterns: bool| {
+            let line = args(line);
+            let spec = crate::command_table::lookup_args(&line).unwrap();
+            alice.check(spec, &line, &args(channels), patterns)
+        };
+
+        assert_eq!(check(&["get", "r:1"], &[], false), Ok(()));
+        assert_eq!(
+            check(&["set", "r:1", "v"], &[], false),
+            Err(Denied::Key("r:1".into()))
+        );
+        assert_eq!(check(&["set", "rw:1", "v"], &[], false), Ok(()));
+        assert_eq!(check(&["del", "rw:1"], &[], false), Err(Denied::Command));
+        assert_eq!(check(&["config", "get", "port"], &[], false), Ok(()));
+        assert_eq!(
+            check(&["config", "set", "port", "1"], &[], false),
+            Err(Denied::Command)
+        );
+        // always allowed
+        assert_eq!(check(&["auth", "x"], &[], false), Ok(()));
+
+        let publish = lookup("publish").unwrap();
+        let line = args(&["publish", "news.a", "hi"]);
+        assert_eq!(
+            alice.check(publish, &line, &args(&["news.a"]), false),
+            Err(Denied::Command)
+        );
+        let alice = user(&["+@pubsub", "&news.*"]);
+        assert_eq!(
+            alice.check(publish, &line, &args(&["news.a"]), false),
+            Ok(())
+        );
+        assert_eq!(
+            alice.check(publish, &line, &args(&["other"]), false),
+            Err(Denied::Channel("other".into()))
+        );
+        let psubscribe = lookup("psubscribe").unwrap();
+        let line = args(&["psubscribe", "news.*"]);
+        assert_eq!(
+            alice.check(psubscribe, &line, &args(&["news.*"]), true),
+            Ok(())
+        );
+        assert!(alice
+            .check(psubscribe, &line, &args(&["news.a*"]), true)
+            .is_err());
+    }
+
+    #[test]
+    fn test_load_save() {
+        let path = std::env::temp_dir().join(format!("acl-test-{}.acl", std::process::id()));
+
+        let mut acl = Acl::new();
+        acl.set_user(
+            "alice",
+            &args(&["on", ">pw", "%R~app:*", "&news", "+@read"]),
+        )
+        .unwrap();
+        acl.save_file(&path).unwrap();
+        let loaded = Acl::load_file(&path).unwrap();
+        let lines = |acl: &Acl| acl.users().map(User::describe).collect::<Vec<_>>();
+        assert_eq!(lines(&loaded), lines(&acl));
+        assert!(loaded.authenticate("alice", "pw"));
+
+        // the default user keeps its settings when the file leaves it out
+        fs::write(&path, "# users\n\nuser bob on nopass +get\n").unwrap();
+        let loaded = Acl::load_file(&path).unwrap();
+        assert_eq!(
+            lines(&loaded),
+            vec![
+                "user bob on nopass resetchannels -@all +get",
+                "user default on nopass ~* &* +@all"
+            ]
+        );
+
+        fs::write(
+            &path,
+            "user bob on\nuser bob off\nbob +get\nuser carol +nope\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Acl::load_file(&path).err().unwrap(),
+            format!(
+                "{0}:2: Duplicate user 'bob' found. {0}:3: line should start with user keyword. {0}:4: Error in user declaration '+nope': Unknown command or category name in ACL.",
+                path.display()
+            )
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(Acl::load_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_log() {
+        let mut log = AclLog::new();
+        let entry = |object: &str, now| {
+            AclLogEntry::new(
+                "key",
+                "toplevel",
+                object.into(),
+                "alice".into(),
+                "".into(),
+                now,
+            )
+        };
+
+        log.push(entry("a", 0), 2);
+        log.push(entry("a", 1000), 2);
+        log.push(entry("b", 2000), 2);
+        let objects: Vec<(&str, u64)> = log
+            .newest(10)
+            .map(|e| (e.object.as_str(), e.count))
+            .collect();
+        assert_eq!(objects, vec![("b", 1), ("a", 2)]);
+
+        // too long after the last one to be counted in it
+        log.push(entry("a", 100_000), 2);
+        let ids: Vec<u64> = log.newest(10).map(|e| e.id).collect();
+        assert_eq!(ids, vec![2, 1]);
+
+        log.reset();
+        assert_eq!(log.newest(10).count(), 0);
+    }
+}
@@ -0,0 +1,294 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::time::Instant;
+
+use crate::commads::{ClientType, CommandParseResult, ReplyMode};
+use crate::multi::MultiState;
+use crate::pubsub::Subscriptions;
+use crate::tracking::TrackingState;
+
+/// Most bytes read from a connection at a time
+pub const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Longest a query buffer may get before the client is closed, the default
+/// client-query-buffer-limit
+pub const MAX_QUERY_BUFFER: usize = 1024 * 1024 * 1024;
+
+/// A client's end of a TCP or Unix socket connection
+pub enum Connection {
+    Tcp(TcpStream),
+    /// Connected to the socket at the path
+    Unix(UnixStream, String),
+}
+
+impl Connection {
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(how),
+            Connection::Unix(stream, _) => stream.shutdown(how),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Connection::Unix(stream, _) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Send TCP keepalive probes once the connection was idle for `interval` seconds
+    pub fn set_keepalive(&self, interval: i32) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => set_keepalive(stream, interval),
+            Connection::Unix(..) => Ok(()),
+        }
+    }
+
+    /// Addresses of the client's and the server's end, Unix sockets show as `path:0`
+    /// for both like redis does
+    fn addrs(&self) -> (String, String) {
+        match self {
+            Connection::Tcp(stream) => {
+                let addr = |a: io::Result<std::net::SocketAddr>| {
+                    a.map(|a| a.to_string()).unwrap_or_default()
+                };
+                (addr(stream.peer_addr()), addr(stream.local_addr()))
+            }
+            Connection::Unix(_, path) => (format!("{}:0", path), format!("{}:0", path)),
+        }
+    }
+}
+
+impl Read for &Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).read(buf),
+            Connection::Unix(stream, _) => (&*stream).read(buf),
+        }
+    }
+}
+
+impl Write for &Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).write(buf),
+            Connection::Unix(stream, _) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => (&*stream).flush(),
+            Connection::Unix(stream, _) => (&*stream).flush(),
+        }
+    }
+}
+
+/// Turns on keepalive with the idle time, interval and probe count redis uses, std has no
+/// setter for these
+#[cfg(target_os = "linux")]
+fn set_keepalive(stream: &TcpStream, interval: i32) -> io::Result<()> {
+    use std::ffi::{c_int, c_void};
+    use std::os::unix::io::AsRawFd;
+
+    extern "C" {
+        fn setsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+    }
+    const SOL_SOCKET: c_int = 1;
+    const SO_KEEPALIVE: c_int = 9;
+    const IPPROTO_TCP: c_int = 6;
+    const TCP_KEEPIDLE: c_int = 4;
+    const TCP_KEEPINTVL: c_int = 5;
+    const TCP_KEEPCNT: c_int = 6;
+
+    for (level, name, value) in [
+        (SOL_SOCKET, SO_KEEPALIVE, 1),
+        (IPPROTO_TCP, TCP_KEEPIDLE, interval),
+        (IPPROTO_TCP, TCP_KEEPINTVL, (interval / 3).max(1)),
+        (IPPROTO_TCP, TCP_KEEPCNT, 3),
+    ] {
+        let value: c_int = value;
+        // SAFETY: the fd stays open while `stream` is borrowed and value is a live c_int
+        let ret = unsafe {
+            setsockopt(
+                stream.as_raw_fd(),
+                level,
+                name,
+                &value as *const c_int as *const c_void,
+                std::mem::size_of::<c_int>() as u32,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Other systems keep their default keepalive settings
+#[cfg(not(target_os = "linux"))]
+fn set_keepalive(_stream: &TcpStream, _interval: i32) -> io::Result<()> {
+    Ok(())
+}
+
+/// A connected client and its per-connection state
+pub struct Client {
+    pub id: u64,
+    pub stream: Connection,
+    /// Set with CLIENT SETNAME
+    pub name: Option<String>,
+    /// Address of the client's end of the connection
+    pub addr: String,
+    /// Address of the server's end of the connection
+    pub laddr: String,
+    pub created: Instant,
+    /// When the client last sent a command
+    pub last_interaction: Instant,
+    /// Full name of the last command, like `client|list`
+    pub last_command: String,
+    /// What the client sent that wasn't run yet, pipelined commands and the start of
+    /// ones still being sent
+    pub query_buf: Vec<u8>,
+    /// Length the query buffer needs before the request at its start can be complete
+    pub query_needed: usize,
+    /// Arguments of the last command, as SLOWLOG shows them
+    pub argv: Vec<String>,
+    /// Index of the selected database
+    pub db: usize,
+    pub multi: MultiState,
+    pub subscriptions: Subscriptions,
+    /// Protocol version picked with HELLO
+    pub resp: u8,
+    /// Close the connection once the pending reply is written
+    pub close_after_reply: bool,
+    /// Set once the connection asked for the replication stream with PSYNC
+    pub replica: bool,
+    /// Port a replica said it listens on with REPLCONF listening-port
+    pub listening_port: Option<u32>,
+    pub tracking: TrackingState,
+    pub reply_mode: ReplyMode,
+    /// Set with CLIENT NO-EVICT
+    pub no_evict: bool,
+    /// Set by MONITOR, gets a line for every command the server processes
+    pub monitor: bool,
+    /// Request read while clients are paused, run once the pause is over
+    pub held: Option<(String, CommandParseResult)>,
+    /// ACL user the client runs commands as
+    pub user: String,
+    /// Set once AUTH or HELLO AUTH succeeded
+    pub authenticated: bool,
+}
+
+impl Client {
+    pub fn new(id: u64, stream: Connection) -> Self {
+        let (addr, laddr) = stream.addrs();
+        let now = Instant::now();
+
+        Self {
+            id,
+            stream,
+            name: None,
+            addr,
+            laddr,
+            created: now,
+            last_interaction: now,
+            last_command: "NULL".into(),
+            query_buf: Vec::new(),
+            query_needed: 0,
+            argv: Vec::new(),
+            db: 0,
+            multi: MultiState::default(),
+            subscriptions: Subscriptions::default(),
+            resp: 2,
+            close_after_reply: false,
+            replica: false,
+            listening_port: None,
+            tracking: TrackingState::default(),
+            reply_mode: ReplyMode::On,
+            no_evict: false,
+            monitor: false,
+            held: None,
+            user: "default".into(),
+            authenticated: false,
+        }
+    }
+
+    /// RESP2 clients with subscriptions may only run pub/sub commands
+    pub fn in_subscribed_mode(&self) -> bool {
+        self.resp == 2 && !self.subscriptions.is_empty()
+    }
+
+    pub fn client_type(&self) -> ClientType {
+        if self.replica {
+            ClientType::Replica
+        } else if !self.subscriptions.is_empty() {
+            ClientType::PubSub
+        } else {
+            ClientType::Normal
+        }
+    }
+
+    /// The line describing this client in CLIENT LIST and CLIENT INFO
+    pub fn info_line(&self) -> String {
+        let mut flags = String::new();
+        for (set, flag) in [
+            (self.replica, 'S'),
+            (self.monitor, 'O'),
+            (!self.subscriptions.is_empty(), 'P'),
+            (self.multi.in_multi(), 'x'),
+            (self.multi.dirty_cas, 'd'),
+            (self.close_after_reply, 'c'),
+            (self.tracking.enabled, 't'),
+            (self.tracking.redirect_broken, 'R'),
+            (self.tracking.bcast, 'B'),
+            (self.no_evict, 'e'),
+        ] {
+            if set {
+                flags.push(flag);
+            }
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+
+        let multi = match &self.multi.queued {
+            Some(queued) => queued.len() as i64,
+            None => -1,
+        };
+        let redir = match (self.tracking.enabled, self.tracking.redirect) {
+            (true, Some(r)) => r as i64,
+            (true, None) => 0,
+            (false, _) => -1,
+        };
+
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub={} psub={} ssub={} multi={} qbuf={} qbuf-free={} rbs={} obl=0 oll=0 omem=0 events=r cmd={} user={} redir={} resp={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            self.name.as_deref().unwrap_or_default(),
+            self.created.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            flags,
+            self.db,
+            self.subscriptions.channels.len(),
+            self.subscriptions.patterns.len(),
+            self.subscriptions.shard_channels.len(),
+            multi,
+            self.query_buf.len(),
+            self.query_buf.capacity() - self.query_buf.len(),
+            READ_BUFFER_SIZE,
+            self.last_command,
+            self.user,
+            redir,
+            self.resp,
+        )
+    }
+}
@@ -0,0 +1,54 @@
+//! Hash slots, the unit keys and shard channels are distributed by in cluster mode
+
+/// Number of hash slots
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+/// CRC16-CCITT (XMODEM), the checksum Redis Cluster hashes keys with
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+
+    for &b in bytes {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+
+    crc
+}
+
+/// Slot of `key`, only the part inside the first non-empty `{...}` is hashed if there is one
+pub fn key_hash_slot(key: &str) -> u16 {
+    let bytes = key.as_bytes();
+
+    let hashed = match bytes.iter().position(|&b| b == b'{') {
+        Some(start) => match bytes[start + 1..].iter().position(|&b| b == b'}') {
+            Some(0) | None => bytes,
+            Some(len) => &bytes[start + 1..start + 1 + len],
+        },
+        None => bytes,
+    };
+
+    crc16(hashed) & (CLUSTER_SLOTS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_hash_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_hash_slot("foo"), 12182);
+        assert_eq!(
+            key_hash_slot("{user1000}.following"),
+            key_hash_slot("user1000")
+        );
+        // empty hash tags hash the whole key
+        assert_eq!(key_hash_slot("foo{}bar"), crc16(b"foo{}bar") & 16383);
+        assert_eq!(key_hash_slot("foo{bar"), crc16(b"foo{bar") & 16383);
+    }
+}
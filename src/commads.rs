@@ -1,11 +1,17 @@
 use std::{
+    collections::BTreeSet,
     error::Error,
     fmt::Display,
     iter::Peekable,
     time::{Duration, Instant},
+    vec,
 };
 
-use crate::{resp::RespValue, server::StoredValue};
+use crate::{
+    command_table,
+    db::{unix_time_ms, StoredValue, Value},
+    resp::RespValue,
+};
 
 #[derive(PartialEq, Debug)]
 pub struct SetCommand {
@@ -14,21 +20,345 @@ pub struct SetCommand {
 }
 
 #[derive(PartialEq, Debug)]
-pub enum InfoType {
-    Replication,
+pub struct CopyCommand {
+    pub source: String,
+    pub destination: String,
+    pub db: Option<usize>,
+    pub replace: bool,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ScanCommand {
+    pub cursor: u64,
+    pub pattern: Option<String>,
+    pub count: usize,
+    pub key_type: Option<String>,
+}
+
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT all boil down to this
+#[derive(PartialEq, Debug)]
+pub struct ExpireCommand {
+    pub key: String,
+    /// Absolute expire time in unix milliseconds
+    pub at_ms: i64,
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum PubSubCommand {
+    Channels(Option<String>),
+    NumSub(Vec<String>),
+    NumPat,
+    ShardChannels(Option<String>),
+    ShardNumSub(Vec<String>),
+}
+
+/// CLIENT TRACKING on|off and its options
+#[derive(PartialEq, Debug, Default)]
+pub struct TrackingCommand {
+    pub on: bool,
+    pub redirect: Option<u64>,
+    pub prefixes: Vec<String>,
+    pub bcast: bool,
+    pub optin: bool,
+    pub optout: bool,
+    pub noloop: bool,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ClientCommand {
+    Tracking(TrackingCommand),
+    Caching(bool),
+    TrackingInfo,
+    GetRedir,
+    Id,
+    /// An empty name clears it
+    SetName(String),
+    GetName,
+    List(ClientListCommand),
+    Info,
+    Kill(ClientKillCommand),
+    Pause(Duration, PauseMode),
+    Unpause,
+    Reply(ReplyMode),
+    NoEvict(bool),
+}
+
+/// Kinds of connections CLIENT LIST and CLIENT KILL can filter on
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ClientType {
+    Normal,
+    Master,
+    Replica,
+    PubSub,
 }
 
-impl InfoType {
-    pub fn from_str(value: &str) -> Result<InfoType, CommandErr> {
-        match value {
-            "replication" => Ok(InfoType::Replication),
-            s => Err(CommandErr {
-                msg: format!("invalid info specifier {}", s),
+impl ClientType {
+    pub fn from_str(value: &str) -> Result<ClientType, CommandErr> {
+        match value.to_lowercase().as_str() {
+            "normal" => Ok(ClientType::Normal),
+            "master" => Ok(ClientType::Master),
+            "replica" | "slave" => Ok(ClientType::Replica),
+            "pubsub" => Ok(ClientType::PubSub),
+            _ => Err(CommandErr {
+                msg: format!("Unknown client type '{}'", value),
             }),
         }
     }
 }
 
+#[derive(PartialEq, Debug, Default)]
+pub struct ClientListCommand {
+    pub client_type: Option<ClientType>,
+    /// Only these clients, all of them if empty
+    pub ids: Vec<u64>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ClientKillCommand {
+    /// The old `CLIENT KILL addr:port` form
+    Addr(String),
+    Filter(ClientKillFilter),
+}
+
+/// Clients CLIENT KILL closes, every given field has to match
+#[derive(PartialEq, Debug)]
+pub struct ClientKillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    pub user: Option<String>,
+    pub client_type: Option<ClientType>,
+    /// Connected for at least this many seconds
+    pub maxage: Option<u64>,
+    /// Leave the calling client alone
+    pub skipme: bool,
+}
+
+impl Default for ClientKillFilter {
+    fn default() -> Self {
+        Self {
+            id: None,
+            addr: None,
+            laddr: None,
+            user: None,
+            client_type: None,
+            maxage: None,
+            skipme: true,
+        }
+    }
+}
+
+/// Which commands CLIENT PAUSE holds back
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum PauseMode {
+    /// Commands that may write to the dataset or the replication stream
+    Write,
+    All,
+}
+
+/// CLIENT REPLY, whether the server answers a client
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum ReplyMode {
+    #[default]
+    On,
+    Off,
+    /// Skip the reply to the next command only
+    Skip,
+}
+
+/// EVAL and EVALSHA, FCALL and FCALL_RO
+#[derive(PartialEq, Debug)]
+pub struct EvalCommand {
+    /// Script body for EVAL, its SHA1 for EVALSHA, the function name for FCALL
+    pub script: String,
+    pub keys: Vec<String>,
+    pub args: Vec<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ScriptCommand {
+    Load(String),
+    Exists(Vec<String>),
+    Flush,
+    Kill,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ConfigCommand {
+    /// Glob style patterns of parameter names
+    Get(Vec<String>),
+    /// Parameter names and values, set together or not at all
+    Set(Vec<(String, String)>),
+    ResetStat,
+    Rewrite,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum AclCommand {
+    /// All categories, or the commands in one
+    Cat(Option<String>),
+    DelUser(Vec<String>),
+    /// User and the command line they would run
+    DryRun(String, Vec<String>),
+    GetUser(String),
+    List,
+    /// Replace the users with the ones in the ACL file
+    Load,
+    /// How many of the newest entries
+    Log(usize),
+    LogReset,
+    /// Write the users to the ACL file
+    Save,
+    /// User and the rules to apply to them
+    SetUser(String, Vec<String>),
+    Users,
+    WhoAmI,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum SlowLogCommand {
+    /// How many of the newest entries, all of them for None
+    Get(Option<usize>),
+    Len,
+    Reset,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum LatencyCommand {
+    Latest,
+    History(String),
+    /// No events means all of them
+    Reset(Vec<String>),
+    /// No commands means all of them
+    Histogram(Vec<String>),
+    Doctor,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum CommandListFilter {
+    Module(String),
+    AclCat(String),
+    Pattern(String),
+}
+
+#[derive(PartialEq, Debug)]
+pub enum IntrospectCommand {
+    /// No names means all commands
+    Info(Vec<String>),
+    Count,
+    /// No names means all commands
+    Docs(Vec<String>),
+    List(Option<CommandListFilter>),
+    /// A full command line to find the keys of
+    GetKeys(Vec<String>),
+}
+
+#[derive(PartialEq, Debug)]
+pub enum FunctionCommand {
+    Load {
+        code: String,
+        replace: bool,
+    },
+    List {
+        /// Glob style pattern library names must match
+        library_name: Option<String>,
+        with_code: bool,
+    },
+    Delete(String),
+    Dump,
+    Restore {
+        payload: String,
+        policy: RestorePolicy,
+    },
+    Flush,
+    Kill,
+}
+
+/// What FUNCTION RESTORE does with libraries already loaded
+#[derive(PartialEq, Debug, Default)]
+pub enum RestorePolicy {
+    /// Keep them, restoring one with the same name fails
+    #[default]
+    Append,
+    /// Drop the ones with the same name
+    Replace,
+    /// Drop all of them
+    Flush,
+}
+
+/// Sections of INFO, in the order they are listed
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum InfoSection {
+    Server,
+    Clients,
+    Memory,
+    Persistence,
+    Stats,
+    Replication,
+    Cpu,
+    Modules,
+    CommandStats,
+    ErrorStats,
+    Cluster,
+    Keyspace,
+}
+
+impl InfoSection {
+    const ALL: [InfoSection; 12] = [
+        InfoSection::Server,
+        InfoSection::Clients,
+        InfoSection::Memory,
+        InfoSection::Persistence,
+        InfoSection::Stats,
+        InfoSection::Replication,
+        InfoSection::Cpu,
+        InfoSection::Modules,
+        InfoSection::CommandStats,
+        InfoSection::ErrorStats,
+        InfoSection::Cluster,
+        InfoSection::Keyspace,
+    ];
+
+    /// Sections a section name or `default`, `all` or `everything` stand for, unknown
+    /// names stand for none
+    pub fn from_name(name: &str) -> Vec<InfoSection> {
+        match name.to_lowercase().as_str() {
+            "all" | "everything" => Self::ALL.to_vec(),
+            // commandstats can get long, it is only listed when asked for
+            "default" => Self::ALL
+                .into_iter()
+                .filter(|s| *s != InfoSection::CommandStats)
+                .collect(),
+            name => Self::ALL
+                .into_iter()
+                .filter(|s| s.title().to_lowercase() == name)
+                .collect(),
+        }
+    }
+
+    /// Title in the `# Title` line starting the section
+    pub fn title(&self) -> &'static str {
+        match self {
+            InfoSection::Server => "Server",
+            InfoSection::Clients => "Clients",
+            InfoSection::Memory => "Memory",
+            InfoSection::Persistence => "Persistence",
+            InfoSection::Stats => "Stats",
+            InfoSection::Replication => "Replication",
+            InfoSection::Cpu => "CPU",
+            InfoSection::Modules => "Modules",
+            InfoSection::CommandStats => "Commandstats",
+            InfoSection::ErrorStats => "Errorstats",
+            InfoSection::Cluster => "Cluster",
+            InfoSection::Keyspace => "Keyspace",
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum ReplconfType {
     ListeningPort(u32),
@@ -39,12 +369,200 @@ pub enum ReplconfType {
 pub enum Command {
     Ping,
     Echo(RespValue),
-    Llen,
     Shutdown,
     Set(SetCommand),
     Get(String),
-    Info(InfoType),
+    Info(BTreeSet<InfoSection>),
     Replconf(ReplconfType),
+    Del(Vec<String>),
+    Unlink(Vec<String>),
+    Exists(Vec<String>),
+    Type(String),
+    Rename(String, String),
+    RenameNx(String, String),
+    Copy(CopyCommand),
+    Touch(Vec<String>),
+    RandomKey,
+    DbSize,
+    Keys(String),
+    Scan(ScanCommand),
+    Expire(ExpireCommand),
+    Ttl(String),
+    Pttl(String),
+    ExpireTime(String),
+    PexpireTime(String),
+    Persist(String),
+    Select(usize),
+    SwapDb(usize, usize),
+    Move(String, usize),
+    /// Whether to free the old contents in the background
+    FlushDb(bool),
+    FlushAll(bool),
+    Multi,
+    Exec,
+    Discard,
+    Watch(Vec<String>),
+    Unwatch,
+    Subscribe(Vec<String>),
+    /// No channels means all of them
+    Unsubscribe(Vec<String>),
+    PSubscribe(Vec<String>),
+    PUnsubscribe(Vec<String>),
+    Publish(String, String),
+    SSubscribe(Vec<String>),
+    /// No channels means all of them
+    SUnsubscribe(Vec<String>),
+    SPublish(String, String),
+    PubSub(PubSubCommand),
+    /// Replication id and offset the replica asks to continue from
+    Psync(String, i64),
+    /// Protocol version, and user and password to authenticate with
+    Hello(Option<u8>, Option<(String, String)>),
+    /// Username, the default user when None, and password
+    Auth(Option<String>, String),
+    Quit,
+    Reset,
+    Client(ClientCommand),
+    Eval(EvalCommand),
+    EvalSha(EvalCommand),
+    Script(ScriptCommand),
+    Function(FunctionCommand),
+    FCall(EvalCommand),
+    FCallRo(EvalCommand),
+    Config(ConfigCommand),
+    /// COMMAND and its subcommands
+    Introspect(IntrospectCommand),
+    SlowLog(SlowLogCommand),
+    Latency(LatencyCommand),
+    Monitor,
+    Acl(AclCommand),
+}
+
+impl Command {
+    /// Commands RESP2 clients may run while subscribed to channels
+    pub fn is_allowed_in_subscribed_mode(&self) -> bool {
+        matches!(
+            self,
+            Command::Subscribe(_)
+                | Command::Unsubscribe(_)
+                | Command::PSubscribe(_)
+                | Command::PUnsubscribe(_)
+                | Command::SSubscribe(_)
+                | Command::SUnsubscribe(_)
+                | Command::Ping
+                | Command::Quit
+                | Command::Reset
+        )
+    }
+
+    /// Keys a read-only command looks at, remembered for clients with tracking on
+    pub fn read_keys(&self) -> Vec<String> {
+        match self {
+            Command::Get(key)
+            | Command::Type(key)
+            | Command::Ttl(key)
+            | Command::Pttl(key)
+            | Command::ExpireTime(key)
+            | Command::PexpireTime(key) => vec![key.clone()],
+            Command::Exists(keys) => keys.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Channels the command subscribes or publishes to and whether they are patterns,
+    /// checked against what the ACL user may access
+    pub fn acl_channels(&self) -> (&[String], bool) {
+        match self {
+            Command::Subscribe(channels) | Command::SSubscribe(channels) => (channels, false),
+            Command::PSubscribe(patterns) => (patterns, true),
+            Command::Publish(channel, _) | Command::SPublish(channel, _) => {
+                (std::slice::from_ref(channel), false)
+            }
+            _ => (&[], false),
+        }
+    }
+
+    /// Commands that change the dataset, replicated when a script runs them
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::Del(_)
+                | Command::Unlink(_)
+                | Command::Rename(..)
+                | Command::RenameNx(..)
+                | Command::Copy(_)
+                | Command::Expire(_)
+                | Command::Persist(_)
+                | Command::Move(..)
+                | Command::SwapDb(..)
+                | Command::FlushDb(_)
+                | Command::FlushAll(_)
+        )
+    }
+
+    /// Commands held back while clients are paused with CLIENT PAUSE WRITE
+    pub fn may_replicate(&self) -> bool {
+        self.is_write()
+            || matches!(
+                self,
+                Command::Eval(_)
+                    | Command::EvalSha(_)
+                    | Command::FCall(_)
+                    | Command::Publish(..)
+                    | Command::SPublish(..)
+                    | Command::Function(
+                        FunctionCommand::Load { .. }
+                            | FunctionCommand::Delete(_)
+                            | FunctionCommand::Restore { .. }
+                            | FunctionCommand::Flush
+                    )
+            )
+    }
+
+    /// Commands scripts may not call
+    pub fn is_noscript(&self) -> bool {
+        matches!(
+            self,
+            Command::Multi
+                | Command::Exec
+                | Command::Discard
+                | Command::Watch(_)
+                | Command::Unwatch
+                | Command::Subscribe(_)
+                | Command::Unsubscribe(_)
+                | Command::PSubscribe(_)
+                | Command::PUnsubscribe(_)
+                | Command::SSubscribe(_)
+                | Command::SUnsubscribe(_)
+                | Command::Psync(..)
+                | Command::Replconf(_)
+                | Command::Hello(..)
+                | Command::Auth(..)
+                | Command::Quit
+                | Command::Reset
+                | Command::Shutdown
+                | Command::Client(_)
+                | Command::Eval(_)
+                | Command::EvalSha(_)
+                | Command::Script(_)
+                | Command::Function(_)
+                | Command::FCall(_)
+                | Command::FCallRo(_)
+                | Command::Config(_)
+                | Command::Latency(_)
+                | Command::Monitor
+                | Command::Acl(_)
+        )
+    }
+
+    /// Commands that run right away instead of being queued inside MULTI
+    pub fn is_transaction_control(&self) -> bool {
+        matches!(
+            self,
+            Command::Multi | Command::Exec | Command::Discard | Command::Watch(_) | Command::Reset
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -62,21 +580,25 @@ impl Display for CommandErr {
     }
 }
 
-pub struct CommandParser<I: Iterator<Item = RespValue>> {
-    resp_it: Peekable<I>,
+pub struct CommandParser {
+    resp_it: Peekable<vec::IntoIter<RespValue>>,
     idx: usize,
 }
 
-impl<I: Iterator<Item = RespValue>> CommandParser<I> {
-    pub fn new(resp_it: I) -> Self {
+impl CommandParser {
+    pub fn new<I: IntoIterator<Item = RespValue>>(resp_it: I) -> Self {
         Self {
-            resp_it: resp_it.peekable(),
+            resp_it: resp_it
+                .into_iter()
+                .collect::<Vec<_>>()
+                .into_iter()
+                .peekable(),
             idx: 0,
         }
     }
 
     pub fn err(&mut self, msg: String) -> CommandParseResult {
-        Err(CommandErr { msg: msg })
+        Err(CommandErr { msg })
     }
 
     fn next(&mut self) -> Option<RespValue> {
@@ -88,6 +610,53 @@ impl<I: Iterator<Item = RespValue>> CommandParser<I> {
         self.resp_it.peek()
     }
 
+    fn wrong_args(cmd: &str) -> CommandErr {
+        CommandErr {
+            msg: format!("wrong number of arguments for '{}' command", cmd),
+        }
+    }
+
+    /// Consume the next item as a string argument of `cmd`
+    fn next_string(&mut self, cmd: &str) -> Result<String, CommandErr> {
+        match self.next() {
+            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => Ok(s),
+            Some(RespValue::Integer(i)) => Ok(i.to_string()),
+            Some(s) => Err(CommandErr {
+                msg: format!("invalid type expected SS or BS got: {:?}", s),
+            }),
+            None => Err(Self::wrong_args(cmd)),
+        }
+    }
+
+    /// Consume all remaining items as string arguments of `cmd`, at least one is required
+    pub fn remaining_strings(&mut self, cmd: &str) -> Result<Vec<String>, CommandErr> {
+        let mut strings = vec![self.next_string(cmd)?];
+
+        while self.peek().is_some() {
+            strings.push(self.next_string(cmd)?);
+        }
+
+        Ok(strings)
+    }
+
+    /// Consume the next item as an integer argument of `cmd`
+    fn next_integer<T: std::str::FromStr>(&mut self, cmd: &str) -> Result<T, CommandErr> {
+        match self.next_string(cmd)?.parse::<T>() {
+            Ok(i) => Ok(i),
+            Err(_) => Err(CommandErr {
+                msg: "value is not an integer or out of range".into(),
+            }),
+        }
+    }
+
+    /// Make sure all arguments of `cmd` have been consumed
+    fn end(&mut self, cmd: &str) -> Result<(), CommandErr> {
+        match self.peek() {
+            Some(_) => Err(Self::wrong_args(cmd)),
+            None => Ok(()),
+        }
+    }
+
     pub fn echo(&mut self) -> CommandParseResult {
         match self.next() {
             Some(s) => Ok(Command::Echo(s)),
@@ -99,19 +668,19 @@ impl<I: Iterator<Item = RespValue>> CommandParser<I> {
         Ok(Command::Ping)
     }
 
+    /// INFO [section [section ...]], the default sections without one
     pub fn info(&mut self) -> CommandParseResult {
-        let next_value = match self.next() {
-            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => s,
-            Some(s) => return self.err(format!("invalid type expected SS or BS got: {:?}", s)),
-            None => return self.err("expected infotype sepcifier after INFO command".into()),
-        };
-
-        let info_type = match InfoType::from_str(&next_value) {
-            Ok(v) => v,
-            Err(e) => return self.err(e.to_string()),
+        let names = match self.peek() {
+            Some(_) => self.remaining_strings("info")?,
+            None => vec!["default".into()],
         };
 
-        Ok(Command::Info(info_type))
+        Ok(Command::Info(
+            names
+                .iter()
+                .flat_map(|name| InfoSection::from_name(name))
+                .collect(),
+        ))
     }
 
     pub fn set(&mut self) -> CommandParseResult {
@@ -128,13 +697,19 @@ impl<I: Iterator<Item = RespValue>> CommandParser<I> {
         };
 
         let px = match self.peek() {
-            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => match s.as_str() {
+            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => match s
+                .to_uppercase()
+                .as_str()
+            {
                 "PX" => {
                     self.next().unwrap();
                     match self.next() {
                         Some(RespValue::Integer(i)) if i > 0 => {
                             Some(Instant::now() + Duration::from_millis(i as u64))
                         }
+                        Some(RespValue::BulkString(s)) if s.parse::<u64>().is_ok_and(|i| i > 0) => {
+                            Some(Instant::now() + Duration::from_millis(s.parse().unwrap()))
+                        }
                         Some(r) => {
                             return self.err(format!(
                                 "expected positive integer after PX in SET, got {:?}",
@@ -151,8 +726,8 @@ impl<I: Iterator<Item = RespValue>> CommandParser<I> {
         };
 
         let set_command = SetCommand {
-            key: key,
-            value: StoredValue::new(value, px),
+            key,
+            value: StoredValue::new(Value::String(value), px),
         };
 
         Ok(Command::Set(set_command))
@@ -166,55 +741,1455 @@ impl<I: Iterator<Item = RespValue>> CommandParser<I> {
         }
     }
 
+    pub fn replconf(&mut self) -> CommandParseResult {
+        let opt = self.next_string("replconf")?;
+
+        let replconf_type = match opt.to_lowercase().as_str() {
+            "listening-port" => match self.next_string("replconf")?.parse::<u32>() {
+                Ok(p) => ReplconfType::ListeningPort(p),
+                Err(_) => return self.err("invalid listening-port for REPLCONF".into()),
+            },
+            "capa" => ReplconfType::Capa(self.next_string("replconf")?),
+            o => return self.err(format!("unrecognized REPLCONF option: {}", o)),
+        };
+
+        Ok(Command::Replconf(replconf_type))
+    }
+
     pub fn shutdown(&mut self) -> CommandParseResult {
         Ok(Command::Shutdown)
     }
 
-    pub fn parse_next(&mut self) -> CommandParseResult {
-        let raw_cmd = match self.next() {
-            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => s,
-            _ => {
-                return Err(CommandErr {
-                    msg: "can only parse command from BulkString or SimpleString".into(),
-                })
+    pub fn del(&mut self) -> CommandParseResult {
+        Ok(Command::Del(self.remaining_strings("del")?))
+    }
+
+    pub fn unlink(&mut self) -> CommandParseResult {
+        Ok(Command::Unlink(self.remaining_strings("unlink")?))
+    }
+
+    pub fn exists(&mut self) -> CommandParseResult {
+        Ok(Command::Exists(self.remaining_strings("exists")?))
+    }
+
+    pub fn touch(&mut self) -> CommandParseResult {
+        Ok(Command::Touch(self.remaining_strings("touch")?))
+    }
+
+    pub fn key_type(&mut self) -> CommandParseResult {
+        let key = self.next_string("type")?;
+        self.end("type")?;
+
+        Ok(Command::Type(key))
+    }
+
+    pub fn rename(&mut self) -> CommandParseResult {
+        let key = self.next_string("rename")?;
+        let newkey = self.next_string("rename")?;
+        self.end("rename")?;
+
+        Ok(Command::Rename(key, newkey))
+    }
+
+    pub fn renamenx(&mut self) -> CommandParseResult {
+        let key = self.next_string("renamenx")?;
+        let newkey = self.next_string("renamenx")?;
+        self.end("renamenx")?;
+
+        Ok(Command::RenameNx(key, newkey))
+    }
+
+    /// COPY source destination [DB destination-db] [REPLACE]
+    pub fn copy(&mut self) -> CommandParseResult {
+        let source = self.next_string("copy")?;
+        let destination = self.next_string("copy")?;
+
+        let mut db = None;
+        let mut replace = false;
+
+        while self.peek().is_some() {
+            let opt = self.next_string("copy")?;
+
+            match opt.to_uppercase().as_str() {
+                "REPLACE" => replace = true,
+                "DB" => db = Some(self.next_integer("copy")?),
+                _ => return self.err("syntax error".into()),
             }
+        }
+
+        Ok(Command::Copy(CopyCommand {
+            source,
+            destination,
+            db,
+            replace,
+        }))
+    }
+
+    pub fn keys(&mut self) -> CommandParseResult {
+        let pattern = self.next_string("keys")?;
+        self.end("keys")?;
+
+        Ok(Command::Keys(pattern))
+    }
+
+    /// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+    pub fn scan(&mut self) -> CommandParseResult {
+        let cursor = match self.next_string("scan")?.parse::<u64>() {
+            Ok(c) => c,
+            Err(_) => return self.err("invalid cursor".into()),
         };
 
-        let cmd = match raw_cmd.to_uppercase().as_str() {
-            "PING" => self.ping()?,
-            "ECHO" => self.echo()?,
-            "SHUTDOWN" => self.shutdown()?,
-            "SET" => self.set()?,
-            "GET" => self.get()?,
-            "INFO" => self.info()?,
-            a => return self.err(format!("invalid command: '{}' provided", a)),
+        let mut scan_command = ScanCommand {
+            cursor,
+            pattern: None,
+            count: 10,
+            key_type: None,
         };
 
-        Ok(cmd)
+        while self.peek().is_some() {
+            let opt = self.next_string("scan")?;
+
+            match opt.to_uppercase().as_str() {
+                "MATCH" => scan_command.pattern = Some(self.next_string("scan")?),
+                "COUNT" => match self.next_integer::<usize>("scan")? {
+                    0 => return self.err("syntax error".into()),
+                    c => scan_command.count = c,
+                },
+                "TYPE" => scan_command.key_type = Some(self.next_string("scan")?.to_lowercase()),
+                _ => return self.err("syntax error".into()),
+            }
+        }
+
+        Ok(Command::Scan(scan_command))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test_ping() {
-        let resp_values = vec![RespValue::BulkString("ping".into())];
-        let mut parser = CommandParser::new(resp_values.into_iter());
+    /// EXPIRE key seconds [NX | XX | GT | LT] and friends
+    ///
+    /// `unit_ms` is the size of the time unit in milliseconds and `absolute` tells whether the
+    /// time is a unix timestamp or relative to now
+    pub fn expire(&mut self, cmd: &str, unit_ms: i64, absolute: bool) -> CommandParseResult {
+        let key = self.next_string(cmd)?;
+        let time = self.next_integer::<i64>(cmd)?;
 
-        assert_eq!(Command::Ping, parser.parse_next().unwrap());
+        let invalid = || CommandErr {
+            msg: format!("invalid expire time in '{}' command", cmd),
+        };
+
+        let mut at_ms = time.checked_mul(unit_ms).ok_or_else(invalid)?;
+        if !absolute {
+            at_ms = at_ms.checked_add(unix_time_ms()).ok_or_else(invalid)?;
+        }
+
+        let mut expire_command = ExpireCommand {
+            key,
+            at_ms,
+            nx: false,
+            xx: false,
+            gt: false,
+            lt: false,
+        };
+
+        while self.peek().is_some() {
+            let opt = self.next_string(cmd)?;
+
+            match opt.to_uppercase().as_str() {
+                "NX" => expire_command.nx = true,
+                "XX" => expire_command.xx = true,
+                "GT" => expire_command.gt = true,
+                "LT" => expire_command.lt = true,
+                o => return self.err(format!("Unsupported option {}", o)),
+            }
+        }
+
+        let ExpireCommand { nx, xx, gt, lt, .. } = expire_command;
+
+        if nx && (xx || gt || lt) {
+            return self
+                .err("NX and XX, GT or LT options at the same time are not compatible".into());
+        }
+
+        if gt && lt {
+            return self.err("GT and LT options at the same time are not compatible".into());
+        }
+
+        Ok(Command::Expire(expire_command))
     }
 
-    #[test]
-    fn test_echo() {
-        let resp_values = vec![
-            RespValue::BulkString("echo".into()),
-            RespValue::BulkString("hello world".into()),
-        ];
-        let mut parser = CommandParser::new(resp_values.into_iter());
+    /// Commands taking a single key and nothing else
+    pub fn single_key(&mut self, cmd: &str, f: fn(String) -> Command) -> CommandParseResult {
+        let key = self.next_string(cmd)?;
+        self.end(cmd)?;
 
-        assert_eq!(
-            Command::Echo(RespValue::BulkString("hello world".into())),
+        Ok(f(key))
+    }
+
+    pub fn select(&mut self) -> CommandParseResult {
+        let index = self.next_integer("select")?;
+        self.end("select")?;
+
+        Ok(Command::Select(index))
+    }
+
+    pub fn swapdb(&mut self) -> CommandParseResult {
+        let a = self.next_integer("swapdb")?;
+        let b = self.next_integer("swapdb")?;
+        self.end("swapdb")?;
+
+        Ok(Command::SwapDb(a, b))
+    }
+
+    pub fn move_key(&mut self) -> CommandParseResult {
+        let key = self.next_string("move")?;
+        let db = self.next_integer("move")?;
+        self.end("move")?;
+
+        Ok(Command::Move(key, db))
+    }
+
+    /// FLUSHDB and FLUSHALL take an optional ASYNC or SYNC
+    pub fn flush(&mut self, cmd: &str, f: fn(bool) -> Command) -> CommandParseResult {
+        let lazy = match self.peek() {
+            Some(_) => match self.next_string(cmd)?.to_uppercase().as_str() {
+                "ASYNC" => true,
+                "SYNC" => false,
+                _ => return self.err("syntax error".into()),
+            },
+            None => false,
+        };
+        self.end(cmd)?;
+
+        Ok(f(lazy))
+    }
+
+    /// Commands without arguments
+    pub fn no_args(&mut self, cmd: &str, command: Command) -> CommandParseResult {
+        self.end(cmd)?;
+        Ok(command)
+    }
+
+    pub fn watch(&mut self) -> CommandParseResult {
+        Ok(Command::Watch(self.remaining_strings("watch")?))
+    }
+
+    /// All remaining arguments, possibly none
+    pub fn optional_strings(&mut self, cmd: &str) -> Result<Vec<String>, CommandErr> {
+        match self.peek() {
+            Some(_) => self.remaining_strings(cmd),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// PUBLISH or SPUBLISH, `f` builds the command from channel and message
+    pub fn publish(&mut self, cmd: &str, f: fn(String, String) -> Command) -> CommandParseResult {
+        let channel = self.next_string(cmd)?;
+        let message = self.next_string(cmd)?;
+        self.end(cmd)?;
+
+        Ok(f(channel, message))
+    }
+
+    pub fn psync(&mut self) -> CommandParseResult {
+        let replid = self.next_string("psync")?;
+        let offset = self.next_integer::<i64>("psync")?;
+        self.end("psync")?;
+
+        Ok(Command::Psync(replid, offset))
+    }
+
+    /// PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT
+    pub fn pubsub(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("pubsub")?;
+
+        let pubsub_command = match subcommand.to_uppercase().as_str() {
+            "CHANNELS" => PubSubCommand::Channels(self.channel_pattern("pubsub|channels")?),
+            "NUMSUB" => PubSubCommand::NumSub(self.optional_strings("pubsub|numsub")?),
+            "SHARDCHANNELS" => {
+                PubSubCommand::ShardChannels(self.channel_pattern("pubsub|shardchannels")?)
+            }
+            "SHARDNUMSUB" => {
+                PubSubCommand::ShardNumSub(self.optional_strings("pubsub|shardnumsub")?)
+            }
+            "NUMPAT" => {
+                self.end("pubsub|numpat")?;
+                PubSubCommand::NumPat
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try PUBSUB HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::PubSub(pubsub_command))
+    }
+
+    /// CLIENT LIST [TYPE type] [ID id [id ...]]
+    fn client_list(&mut self) -> Result<ClientListCommand, CommandErr> {
+        let mut list = ClientListCommand::default();
+
+        while self.peek().is_some() {
+            match self.next_string("client|list")?.to_uppercase().as_str() {
+                "TYPE" => {
+                    list.client_type =
+                        Some(ClientType::from_str(&self.next_string("client|list")?)?)
+                }
+                "ID" => {
+                    list.ids.push(self.client_id("client|list")?);
+                    while self.peek().is_some() {
+                        list.ids.push(self.client_id("client|list")?);
+                    }
+                }
+                _ => {
+                    return Err(CommandErr {
+                        msg: "syntax error".into(),
+                    })
+                }
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn client_id(&mut self, cmd: &str) -> Result<u64, CommandErr> {
+        match self.next_string(cmd)?.parse::<u64>() {
+            Ok(id) if id > 0 => Ok(id),
+            _ => Err(CommandErr {
+                msg: "Invalid client ID".into(),
+            }),
+        }
+    }
+
+    /// CLIENT KILL addr:port, or CLIENT KILL with filters
+    fn client_kill(&mut self) -> Result<ClientKillCommand, CommandErr> {
+        let first = self.next_string("client|kill")?;
+        if self.peek().is_none() {
+            return Ok(ClientKillCommand::Addr(first));
+        }
+
+        let mut filter = ClientKillFilter::default();
+        let mut name = first;
+        loop {
+            let value = self.next_string("client|kill")?;
+            match name.to_uppercase().as_str() {
+                "ID" => match value.parse::<u64>() {
+                    Ok(id) if id > 0 => filter.id = Some(id),
+                    _ => {
+                        return Err(CommandErr {
+                            msg: "client-id should be greater than 0".into(),
+                        })
+                    }
+                },
+                "ADDR" => filter.addr = Some(value),
+                "LADDR" => filter.laddr = Some(value),
+                "USER" => filter.user = Some(value),
+                "TYPE" => filter.client_type = Some(ClientType::from_str(&value)?),
+                "MAXAGE" => match value.parse::<u64>() {
+                    Ok(age) => filter.maxage = Some(age),
+                    Err(_) => {
+                        return Err(CommandErr {
+                            msg: "value is not an integer or out of range".into(),
+                        })
+                    }
+                },
+                "SKIPME" => match value.to_lowercase().as_str() {
+                    "yes" => filter.skipme = true,
+                    "no" => filter.skipme = false,
+                    _ => {
+                        return Err(CommandErr {
+                            msg: "syntax error".into(),
+                        })
+                    }
+                },
+                _ => {
+                    return Err(CommandErr {
+                        msg: "syntax error".into(),
+                    })
+                }
+            }
+
+            if self.peek().is_none() {
+                break;
+            }
+            name = self.next_string("client|kill")?;
+        }
+
+        Ok(ClientKillCommand::Filter(filter))
+    }
+
+    /// Optional pattern closing a PUBSUB CHANNELS or SHARDCHANNELS
+    fn channel_pattern(&mut self, cmd: &str) -> Result<Option<String>, CommandErr> {
+        let pattern = match self.peek() {
+            Some(_) => Some(self.next_string(cmd)?),
+            None => None,
+        };
+        self.end(cmd)?;
+
+        Ok(pattern)
+    }
+
+    /// CLIENT TRACKING | CACHING | TRACKINGINFO | GETREDIR | ID | SETNAME | GETNAME |
+    /// LIST | INFO | KILL | PAUSE | UNPAUSE | REPLY | NO-EVICT
+    pub fn client(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("client")?;
+
+        let client_command = match subcommand.to_uppercase().as_str() {
+            "ID" => {
+                self.end("client|id")?;
+                ClientCommand::Id
+            }
+            "SETNAME" => {
+                let name = self.next_string("client|setname")?;
+                self.end("client|setname")?;
+                ClientCommand::SetName(name)
+            }
+            "GETNAME" => {
+                self.end("client|getname")?;
+                ClientCommand::GetName
+            }
+            "LIST" => ClientCommand::List(self.client_list()?),
+            "INFO" => {
+                self.end("client|info")?;
+                ClientCommand::Info
+            }
+            "KILL" => ClientCommand::Kill(self.client_kill()?),
+            "PAUSE" => {
+                let timeout = match self.next_string("client|pause")?.parse::<u64>() {
+                    Ok(ms) => Duration::from_millis(ms),
+                    Err(_) => return self.err("timeout is not an integer or out of range".into()),
+                };
+                let mode = match self.peek() {
+                    Some(_) => match self.next_string("client|pause")?.to_uppercase().as_str() {
+                        "WRITE" => PauseMode::Write,
+                        "ALL" => PauseMode::All,
+                        _ => return self.err("syntax error".into()),
+                    },
+                    None => PauseMode::All,
+                };
+                self.end("client|pause")?;
+                ClientCommand::Pause(timeout, mode)
+            }
+            "UNPAUSE" => {
+                self.end("client|unpause")?;
+                ClientCommand::Unpause
+            }
+            "REPLY" => {
+                let mode = match self.next_string("client|reply")?.to_uppercase().as_str() {
+                    "ON" => ReplyMode::On,
+                    "OFF" => ReplyMode::Off,
+                    "SKIP" => ReplyMode::Skip,
+                    _ => return self.err("syntax error".into()),
+                };
+                self.end("client|reply")?;
+                ClientCommand::Reply(mode)
+            }
+            "NO-EVICT" => {
+                let no_evict = match self.next_string("client|no-evict")?.to_lowercase().as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return self.err("syntax error".into()),
+                };
+                self.end("client|no-evict")?;
+                ClientCommand::NoEvict(no_evict)
+            }
+            "TRACKING" => ClientCommand::Tracking(self.client_tracking()?),
+            "CACHING" => {
+                let caching = match self.next_string("client|caching")?.to_lowercase().as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return self.err("syntax error".into()),
+                };
+                self.end("client|caching")?;
+                ClientCommand::Caching(caching)
+            }
+            "TRACKINGINFO" => {
+                self.end("client|trackinginfo")?;
+                ClientCommand::TrackingInfo
+            }
+            "GETREDIR" => {
+                self.end("client|getredir")?;
+                ClientCommand::GetRedir
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try CLIENT HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::Client(client_command))
+    }
+
+    fn client_tracking(&mut self) -> Result<TrackingCommand, CommandErr> {
+        let on = match self.next_string("client|tracking")?.to_lowercase().as_str() {
+            "on" => true,
+            "off" => false,
+            _ => {
+                return Err(CommandErr {
+                    msg: "syntax error".into(),
+                })
+            }
+        };
+        let mut tracking = TrackingCommand {
+            on,
+            ..Default::default()
+        };
+
+        while self.peek().is_some() {
+            match self.next_string("client|tracking")?.to_uppercase().as_str() {
+                "REDIRECT" => tracking.redirect = Some(self.next_integer("client|tracking")?),
+                "PREFIX" => tracking.prefixes.push(self.next_string("client|tracking")?),
+                "BCAST" => tracking.bcast = true,
+                "OPTIN" => tracking.optin = true,
+                "OPTOUT" => tracking.optout = true,
+                "NOLOOP" => tracking.noloop = true,
+                _ => {
+                    return Err(CommandErr {
+                        msg: "syntax error".into(),
+                    })
+                }
+            }
+        }
+
+        let msg = if !tracking.bcast && !tracking.prefixes.is_empty() {
+            "PREFIX option requires BCAST mode to be enabled"
+        } else if tracking.optin && tracking.optout {
+            "You can't use OPTIN and OPTOUT at the same time"
+        } else if tracking.bcast && (tracking.optin || tracking.optout) {
+            "OPTIN and OPTOUT are not compatible with BCAST"
+        } else {
+            return Ok(tracking);
+        };
+
+        Err(CommandErr { msg: msg.into() })
+    }
+
+    /// EVAL script numkeys [key ...] [arg ...] and EVALSHA
+    pub fn eval(&mut self, cmd: &str, f: fn(EvalCommand) -> Command) -> CommandParseResult {
+        let script = self.next_string(cmd)?;
+        let numkeys = self.next_integer::<i64>(cmd)?;
+        let mut args = self.optional_strings(cmd)?;
+
+        if numkeys < 0 {
+            return self.err("Number of keys can't be negative".into());
+        }
+        if numkeys as usize > args.len() {
+            return self.err("Number of keys can't be greater than number of args".into());
+        }
+        let args_after_keys = args.split_off(numkeys as usize);
+
+        Ok(f(EvalCommand {
+            script,
+            keys: args,
+            args: args_after_keys,
+        }))
+    }
+
+    /// SCRIPT LOAD | EXISTS | FLUSH | KILL
+    pub fn script(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("script")?;
+
+        let script_command = match subcommand.to_uppercase().as_str() {
+            "LOAD" => {
+                let body = self.next_string("script|load")?;
+                self.end("script|load")?;
+                ScriptCommand::Load(body)
+            }
+            "EXISTS" => ScriptCommand::Exists(self.remaining_strings("script|exists")?),
+            "FLUSH" => {
+                // the cache is dropped right away either way
+                if self.peek().is_some() {
+                    match self.next_string("script|flush")?.to_uppercase().as_str() {
+                        "ASYNC" | "SYNC" => {}
+                        _ => return self.err("syntax error".into()),
+                    }
+                }
+                self.end("script|flush")?;
+                ScriptCommand::Flush
+            }
+            "KILL" => {
+                self.end("script|kill")?;
+                ScriptCommand::Kill
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try SCRIPT HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::Script(script_command))
+    }
+
+    /// FUNCTION LOAD | LIST | DELETE | DUMP | RESTORE | FLUSH | KILL
+    pub fn function(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("function")?;
+
+        let function_command = match subcommand.to_uppercase().as_str() {
+            "LOAD" => {
+                let mut code = self.next_string("function|load")?;
+                let mut replace = false;
+                if code.eq_ignore_ascii_case("REPLACE") {
+                    replace = true;
+                    code = self.next_string("function|load")?;
+                }
+                self.end("function|load")?;
+                FunctionCommand::Load { code, replace }
+            }
+            "LIST" => {
+                let mut library_name = None;
+                let mut with_code = false;
+                while self.peek().is_some() {
+                    match self.next_string("function|list")?.to_uppercase().as_str() {
+                        "WITHCODE" => with_code = true,
+                        "LIBRARYNAME" => library_name = Some(self.next_string("function|list")?),
+                        _ => return self.err("syntax error".into()),
+                    }
+                }
+                FunctionCommand::List {
+                    library_name,
+                    with_code,
+                }
+            }
+            "DELETE" => {
+                let name = self.next_string("function|delete")?;
+                self.end("function|delete")?;
+                FunctionCommand::Delete(name)
+            }
+            "DUMP" => {
+                self.end("function|dump")?;
+                FunctionCommand::Dump
+            }
+            "RESTORE" => {
+                let payload = self.next_string("function|restore")?;
+                let policy = match self.peek() {
+                    Some(_) => match self
+                        .next_string("function|restore")?
+                        .to_uppercase()
+                        .as_str()
+                    {
+                        "APPEND" => RestorePolicy::Append,
+                        "REPLACE" => RestorePolicy::Replace,
+                        "FLUSH" => RestorePolicy::Flush,
+                        _ => return self.err("syntax error".into()),
+                    },
+                    None => RestorePolicy::default(),
+                };
+                self.end("function|restore")?;
+                FunctionCommand::Restore { payload, policy }
+            }
+            "FLUSH" => {
+                // the libraries are dropped right away either way
+                if self.peek().is_some() {
+                    match self.next_string("function|flush")?.to_uppercase().as_str() {
+                        "ASYNC" | "SYNC" => {}
+                        _ => return self.err("syntax error".into()),
+                    }
+                }
+                self.end("function|flush")?;
+                FunctionCommand::Flush
+            }
+            "KILL" => {
+                self.end("function|kill")?;
+                FunctionCommand::Kill
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try FUNCTION HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::Function(function_command))
+    }
+
+    /// HELLO [protover], only the protocol negotiation part
+    /// CONFIG GET | SET | RESETSTAT | REWRITE
+    pub fn config(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("config")?;
+
+        let config_command = match subcommand.to_uppercase().as_str() {
+            "GET" => ConfigCommand::Get(self.remaining_strings("config|get")?),
+            "SET" => {
+                let mut pairs = vec![(
+                    self.next_string("config|set")?,
+                    self.next_string("config|set")?,
+                )];
+                while self.peek().is_some() {
+                    pairs.push((
+                        self.next_string("config|set")?,
+                        self.next_string("config|set")?,
+                    ));
+                }
+                ConfigCommand::Set(pairs)
+            }
+            "RESETSTAT" => {
+                self.end("config|resetstat")?;
+                ConfigCommand::ResetStat
+            }
+            "REWRITE" => {
+                self.end("config|rewrite")?;
+                ConfigCommand::Rewrite
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try CONFIG HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::Config(config_command))
+    }
+
+    /// COMMAND [COUNT | INFO [name ...] | DOCS [name ...] | LIST [FILTERBY ...] | GETKEYS ...]
+    pub fn command(&mut self) -> CommandParseResult {
+        if self.peek().is_none() {
+            return Ok(Command::Introspect(IntrospectCommand::Info(Vec::new())));
+        }
+        let subcommand = self.next_string("command")?;
+
+        let introspect_command = match subcommand.to_uppercase().as_str() {
+            "COUNT" => {
+                self.end("command|count")?;
+                IntrospectCommand::Count
+            }
+            "INFO" => IntrospectCommand::Info(self.optional_strings("command|info")?),
+            "DOCS" => IntrospectCommand::Docs(self.optional_strings("command|docs")?),
+            "LIST" => {
+                let mut filter = None;
+                if self.peek().is_some() {
+                    if self.next_string("command|list")?.to_uppercase() != "FILTERBY" {
+                        return self.err("syntax error".into());
+                    }
+                    let kind = self.next_string("command|list")?;
+                    let value = self.next_string("command|list")?;
+                    filter = Some(match kind.to_uppercase().as_str() {
+                        "MODULE" => CommandListFilter::Module(value),
+                        "ACLCAT" => CommandListFilter::AclCat(value),
+                        "PATTERN" => CommandListFilter::Pattern(value),
+                        _ => return self.err("syntax error".into()),
+                    });
+                }
+                self.end("command|list")?;
+                IntrospectCommand::List(filter)
+            }
+            "GETKEYS" => IntrospectCommand::GetKeys(self.remaining_strings("command|getkeys")?),
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try COMMAND HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::Introspect(introspect_command))
+    }
+
+    /// SLOWLOG GET [count] | LEN | RESET
+    pub fn slowlog(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("slowlog")?;
+
+        let slowlog_command = match subcommand.to_uppercase().as_str() {
+            "GET" => {
+                let mut count = Some(10);
+                if self.peek().is_some() {
+                    count = match self.next_integer::<i64>("slowlog|get")? {
+                        -1 => None,
+                        c if c >= 0 => Some(c as usize),
+                        _ => return self.err("count should be greater than or equal to -1".into()),
+                    };
+                }
+                self.end("slowlog|get")?;
+                SlowLogCommand::Get(count)
+            }
+            "LEN" => {
+                self.end("slowlog|len")?;
+                SlowLogCommand::Len
+            }
+            "RESET" => {
+                self.end("slowlog|reset")?;
+                SlowLogCommand::Reset
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try SLOWLOG HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::SlowLog(slowlog_command))
+    }
+
+    /// LATENCY LATEST | HISTORY event | RESET [event ...] | HISTOGRAM [command ...] | DOCTOR
+    pub fn latency(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("latency")?;
+
+        let latency_command = match subcommand.to_uppercase().as_str() {
+            "LATEST" => {
+                self.end("latency|latest")?;
+                LatencyCommand::Latest
+            }
+            "HISTORY" => {
+                let event = self.next_string("latency|history")?;
+                self.end("latency|history")?;
+                LatencyCommand::History(event)
+            }
+            "RESET" => LatencyCommand::Reset(self.optional_strings("latency|reset")?),
+            "HISTOGRAM" => LatencyCommand::Histogram(self.optional_strings("latency|histogram")?),
+            "DOCTOR" => {
+                self.end("latency|doctor")?;
+                LatencyCommand::Doctor
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try LATENCY HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::Latency(latency_command))
+    }
+
+    /// HELLO [protover [AUTH username password]]
+    pub fn hello(&mut self) -> CommandParseResult {
+        let protover = match self.peek() {
+            Some(_) => match self.next_string("hello")?.parse::<u8>() {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    return self.err("Protocol version is not an integer or out of range".into())
+                }
+            },
+            None => None,
+        };
+
+        let mut auth = None;
+        while self.peek().is_some() {
+            let opt = self.next_string("hello")?;
+            match opt.to_uppercase().as_str() {
+                "AUTH" => {
+                    let username = self.next_string("hello")?;
+                    let password = self.next_string("hello")?;
+                    auth = Some((username, password));
+                }
+                _ => return self.err(format!("Syntax error in HELLO option '{}'", opt)),
+            }
+        }
+
+        Ok(Command::Hello(protover, auth))
+    }
+
+    /// AUTH [username] password
+    pub fn auth(&mut self) -> CommandParseResult {
+        let first = self.next_string("auth")?;
+        let auth = match self.peek() {
+            Some(_) => Command::Auth(Some(first), self.next_string("auth")?),
+            None => Command::Auth(None, first),
+        };
+        if self.peek().is_some() {
+            return self.err("syntax error".into());
+        }
+
+        Ok(auth)
+    }
+
+    /// ACL CAT [category] | DELUSER username [username ...] | DRYRUN username command [arg ...]
+    /// | GETUSER username | LIST | LOAD | LOG [count | RESET] | SAVE | SETUSER username
+    /// [rule ...] | USERS | WHOAMI
+    pub fn acl(&mut self) -> CommandParseResult {
+        let subcommand = self.next_string("acl")?;
+
+        let acl_command = match subcommand.to_uppercase().as_str() {
+            "CAT" => {
+                let category = match self.peek() {
+                    Some(_) => Some(self.next_string("acl|cat")?),
+                    None => None,
+                };
+                self.end("acl|cat")?;
+                AclCommand::Cat(category)
+            }
+            "DELUSER" => AclCommand::DelUser(self.remaining_strings("acl|deluser")?),
+            "DRYRUN" => {
+                let username = self.next_string("acl|dryrun")?;
+                AclCommand::DryRun(username, self.remaining_strings("acl|dryrun")?)
+            }
+            "GETUSER" => {
+                let username = self.next_string("acl|getuser")?;
+                self.end("acl|getuser")?;
+                AclCommand::GetUser(username)
+            }
+            "LIST" => {
+                self.end("acl|list")?;
+                AclCommand::List
+            }
+            "LOAD" => {
+                self.end("acl|load")?;
+                AclCommand::Load
+            }
+            "LOG" => {
+                let acl_command = match self.peek() {
+                    Some(_) => {
+                        let arg = self.next_string("acl|log")?;
+                        match arg.parse::<i64>() {
+                            _ if arg.eq_ignore_ascii_case("RESET") => AclCommand::LogReset,
+                            Ok(count) if count >= 0 => AclCommand::Log(count as usize),
+                            Ok(_) => {
+                                return self.err("value is out of range, must be positive".into())
+                            }
+                            Err(_) => {
+                                return self.err("value is not an integer or out of range".into())
+                            }
+                        }
+                    }
+                    None => AclCommand::Log(10),
+                };
+                self.end("acl|log")?;
+                acl_command
+            }
+            "SAVE" => {
+                self.end("acl|save")?;
+                AclCommand::Save
+            }
+            "SETUSER" => {
+                let username = self.next_string("acl|setuser")?;
+                AclCommand::SetUser(username, self.optional_strings("acl|setuser")?)
+            }
+            "USERS" => {
+                self.end("acl|users")?;
+                AclCommand::Users
+            }
+            "WHOAMI" => {
+                self.end("acl|whoami")?;
+                AclCommand::WhoAmI
+            }
+            s => {
+                return self.err(format!(
+                    "unknown subcommand '{}'. Try ACL HELP.",
+                    s.to_lowercase()
+                ))
+            }
+        };
+
+        Ok(Command::Acl(acl_command))
+    }
+
+    pub fn randomkey(&mut self) -> CommandParseResult {
+        self.end("randomkey")?;
+        Ok(Command::RandomKey)
+    }
+
+    pub fn dbsize(&mut self) -> CommandParseResult {
+        self.end("dbsize")?;
+        Ok(Command::DbSize)
+    }
+
+    pub fn parse_next(&mut self) -> CommandParseResult {
+        let raw_cmd = match self.next() {
+            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => s,
+            _ => {
+                return Err(CommandErr {
+                    msg: "can only parse command from BulkString or SimpleString".into(),
+                })
+            }
+        };
+
+        let Some(spec) = command_table::lookup(&raw_cmd) else {
+            let args: String = self
+                .resp_it
+                .by_ref()
+                .map(|arg| match arg {
+                    RespValue::BulkString(s) | RespValue::SimpleString(s) => format!("'{}' ", s),
+                    other => format!("'{:?}' ", other),
+                })
+                .collect();
+            return self.err(format!(
+                "unknown command '{}', with args beginning with: {}",
+                raw_cmd, args
+            ));
+        };
+
+        let argc = self.resp_it.len() + 1;
+        if !spec.arity_matches(argc) {
+            return Err(Self::wrong_args(spec.name));
+        }
+        let subcommand = match self.peek() {
+            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => spec.subcommand(s),
+            _ => None,
+        };
+        if let Some(sub) = subcommand {
+            if !sub.arity_matches(argc) {
+                return Err(Self::wrong_args(sub.name));
+            }
+        }
+
+        let cmd = spec.parse(self)?;
+
+        Ok(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_ping() {
+        let resp_values = vec![RespValue::BulkString("ping".into())];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(Command::Ping, parser.parse_next().unwrap());
+    }
+
+    #[test]
+    fn test_echo() {
+        let resp_values = vec![
+            RespValue::BulkString("echo".into()),
+            RespValue::BulkString("hello world".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Echo(RespValue::BulkString("hello world".into())),
+            parser.parse_next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_exists_repeated_keys() {
+        let resp_values = vec![
+            RespValue::BulkString("EXISTS".into()),
+            RespValue::BulkString("a".into()),
+            RespValue::BulkString("a".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Exists(vec!["a".into(), "a".into()]),
+            parser.parse_next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_del_requires_key() {
+        let resp_values = vec![RespValue::BulkString("del".into())];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert!(parser.parse_next().is_err());
+    }
+
+    #[test]
+    fn test_command_table_dispatch() {
+        let parse = |args: &[&str]| {
+            CommandParser::new(args.iter().map(|s| RespValue::BulkString(s.to_string())))
+                .parse_next()
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(
+            parse(&["nope", "a", "b"]),
+            Err("unknown command 'nope', with args beginning with: 'a' 'b' ".into())
+        );
+        assert_eq!(
+            parse(&["get", "a", "b"]),
+            Err("wrong number of arguments for 'get' command".into())
+        );
+        assert_eq!(
+            parse(&["config", "get"]),
+            Err("wrong number of arguments for 'config|get' command".into())
+        );
+        assert_eq!(parse(&["GeT", "a"]), Ok(Command::Get("a".into())));
+
+        assert_eq!(
+            parse(&["command", "list", "filterby", "aclcat", "scripting"]),
+            Ok(Command::Introspect(IntrospectCommand::List(Some(
+                CommandListFilter::AclCat("scripting".into())
+            ))))
+        );
+        assert_eq!(
+            parse(&["command"]),
+            Ok(Command::Introspect(IntrospectCommand::Info(vec![])))
+        );
+        assert!(parse(&["command", "list", "filterby", "color", "red"]).is_err());
+    }
+
+    #[test]
+    fn test_scan() {
+        let resp_values = vec![
+            RespValue::BulkString("scan".into()),
+            RespValue::BulkString("42".into()),
+            RespValue::BulkString("match".into()),
+            RespValue::BulkString("user:*".into()),
+            RespValue::BulkString("count".into()),
+            RespValue::BulkString("100".into()),
+            RespValue::BulkString("type".into()),
+            RespValue::BulkString("STRING".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Scan(ScanCommand {
+                cursor: 42,
+                pattern: Some("user:*".into()),
+                count: 100,
+                key_type: Some("string".into()),
+            }),
+            parser.parse_next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_expire_flags() {
+        let resp_values = vec![
+            RespValue::BulkString("expireat".into()),
+            RespValue::BulkString("k".into()),
+            RespValue::BulkString("100".into()),
+            RespValue::BulkString("xx".into()),
+            RespValue::BulkString("gt".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Expire(ExpireCommand {
+                key: "k".into(),
+                at_ms: 100_000,
+                nx: false,
+                xx: true,
+                gt: true,
+                lt: false,
+            }),
+            parser.parse_next().unwrap()
+        );
+
+        let resp_values = vec![
+            RespValue::BulkString("pexpire".into()),
+            RespValue::BulkString("k".into()),
+            RespValue::BulkString("100".into()),
+            RespValue::BulkString("nx".into()),
+            RespValue::BulkString("lt".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert!(parser.parse_next().is_err());
+    }
+
+    #[test]
+    fn test_pubsub() {
+        let resp_values = vec![
+            RespValue::BulkString("pubsub".into()),
+            RespValue::BulkString("numsub".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::PubSub(PubSubCommand::NumSub(vec![])),
+            parser.parse_next().unwrap()
+        );
+
+        let resp_values = vec![RespValue::BulkString("unsubscribe".into())];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(Command::Unsubscribe(vec![]), parser.parse_next().unwrap());
+
+        let resp_values = vec![
+            RespValue::BulkString("spublish".into()),
+            RespValue::BulkString("news".into()),
+            RespValue::BulkString("hi".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::SPublish("news".into(), "hi".into()),
+            parser.parse_next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_info() {
+        let parse = |args: &[&str]| {
+            let resp_values = args
+                .iter()
+                .map(|s| RespValue::BulkString(s.to_string()))
+                .collect::<Vec<_>>();
+            match CommandParser::new(resp_values).parse_next() {
+                Ok(Command::Info(sections)) => sections.into_iter().collect::<Vec<_>>(),
+                other => panic!("{:?}", other),
+            }
+        };
+
+        assert_eq!(
+            parse(&["info", "KEYSPACE", "cpu", "nosuch", "cpu"]),
+            vec![InfoSection::Cpu, InfoSection::Keyspace]
+        );
+        assert_eq!(parse(&["info"]).len(), 11);
+        assert!(!parse(&["info"]).contains(&InfoSection::CommandStats));
+        assert_eq!(parse(&["info", "all"]).len(), 12);
+        assert_eq!(parse(&["info", "default", "commandstats"]).len(), 12);
+    }
+
+    #[test]
+    fn test_client() {
+        let resp_values = [
+            "client", "kill", "type", "pubsub", "skipme", "no", "maxage", "5",
+        ]
+        .into_iter()
+        .map(|s| RespValue::BulkString(s.into()))
+        .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Client(ClientCommand::Kill(ClientKillCommand::Filter(
+                ClientKillFilter {
+                    client_type: Some(ClientType::PubSub),
+                    maxage: Some(5),
+                    skipme: false,
+                    ..Default::default()
+                }
+            ))),
+            parser.parse_next().unwrap()
+        );
+
+        let resp_values = ["client", "list", "id", "3", "4"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Client(ClientCommand::List(ClientListCommand {
+                client_type: None,
+                ids: vec![3, 4],
+            })),
+            parser.parse_next().unwrap()
+        );
+
+        for args in [
+            vec!["client", "pause", "-1"],
+            vec!["client", "list", "type", "nosuch"],
+            vec!["client", "kill", "id", "0"],
+            vec!["client", "reply", "maybe"],
+        ] {
+            let resp_values = args
+                .into_iter()
+                .map(|s| RespValue::BulkString(s.into()))
+                .collect::<Vec<_>>();
+            let mut parser = CommandParser::new(resp_values);
+
+            assert!(parser.parse_next().is_err());
+        }
+    }
+
+    #[test]
+    fn test_client_tracking() {
+        let resp_values = [
+            "client", "tracking", "on", "bcast", "prefix", "a:", "noloop",
+        ]
+        .into_iter()
+        .map(|s| RespValue::BulkString(s.into()))
+        .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Client(ClientCommand::Tracking(TrackingCommand {
+                on: true,
+                prefixes: vec!["a:".into()],
+                bcast: true,
+                noloop: true,
+                ..Default::default()
+            })),
+            parser.parse_next().unwrap()
+        );
+
+        let resp_values = ["client", "tracking", "on", "prefix", "a:"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert!(parser.parse_next().is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let resp_values = ["eval", "return 1", "1", "k", "a", "b"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Eval(EvalCommand {
+                script: "return 1".into(),
+                keys: vec!["k".into()],
+                args: vec!["a".into(), "b".into()],
+            }),
+            parser.parse_next().unwrap()
+        );
+
+        for numkeys in ["2", "-1"] {
+            let resp_values = ["evalsha", "abc", numkeys, "k"]
+                .into_iter()
+                .map(|s| RespValue::BulkString(s.into()))
+                .collect::<Vec<_>>();
+            let mut parser = CommandParser::new(resp_values);
+
+            assert!(parser.parse_next().is_err());
+        }
+    }
+
+    #[test]
+    fn test_function() {
+        let resp_values = ["function", "load", "replace", "#!lua name=lib"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Function(FunctionCommand::Load {
+                code: "#!lua name=lib".into(),
+                replace: true,
+            }),
+            parser.parse_next().unwrap()
+        );
+
+        let resp_values = ["function", "list", "withcode", "libraryname", "l*"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Function(FunctionCommand::List {
+                library_name: Some("l*".into()),
+                with_code: true,
+            }),
+            parser.parse_next().unwrap()
+        );
+
+        let resp_values = ["function", "restore", "payload", "merge"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert!(parser.parse_next().is_err());
+    }
+
+    #[test]
+    fn test_auth_and_acl() {
+        let parse = |args: &[&str]| {
+            CommandParser::new(args.iter().map(|s| RespValue::BulkString(s.to_string())))
+                .parse_next()
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(parse(&["auth", "pw"]), Ok(Command::Auth(None, "pw".into())));
+        assert_eq!(
+            parse(&["auth", "alice", "pw"]),
+            Ok(Command::Auth(Some("alice".into()), "pw".into()))
+        );
+        assert_eq!(parse(&["auth", "a", "b", "c"]), Err("syntax error".into()));
+        assert_eq!(
+            parse(&["hello", "3", "auth", "alice", "pw"]),
+            Ok(Command::Hello(Some(3), Some(("alice".into(), "pw".into()))))
+        );
+
+        assert_eq!(
+            parse(&["acl", "setuser", "alice", "on", "+@all"]),
+            Ok(Command::Acl(AclCommand::SetUser(
+                "alice".into(),
+                vec!["on".into(), "+@all".into()]
+            )))
+        );
+        assert_eq!(
+            parse(&["acl", "setuser", "alice"]),
+            Ok(Command::Acl(AclCommand::SetUser("alice".into(), vec![])))
+        );
+        assert_eq!(
+            parse(&["acl", "log"]),
+            Ok(Command::Acl(AclCommand::Log(10)))
+        );
+        assert_eq!(parse(&["acl", "save"]), Ok(Command::Acl(AclCommand::Save)));
+        assert_eq!(
+            parse(&["acl", "load", "x"]),
+            Err("wrong number of arguments for 'acl|load' command".into())
+        );
+        assert_eq!(
+            parse(&["acl", "log", "reset"]),
+            Ok(Command::Acl(AclCommand::LogReset))
+        );
+        assert_eq!(
+            parse(&["acl", "log", "-1"]),
+            Err("value is out of range, must be positive".into())
+        );
+        assert_eq!(
+            parse(&["acl", "dryrun", "alice", "get", "k"]),
+            Ok(Command::Acl(AclCommand::DryRun(
+                "alice".into(),
+                vec!["get".into(), "k".into()]
+            )))
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let resp_values = ["config", "set", "maxmemory", "1mb", "timeout", "5"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Config(ConfigCommand::Set(vec![
+                ("maxmemory".into(), "1mb".into()),
+                ("timeout".into(), "5".into()),
+            ])),
+            parser.parse_next().unwrap()
+        );
+
+        let resp_values = ["config", "set", "maxmemory", "1mb", "timeout"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert!(parser.parse_next().is_err());
+
+        let resp_values = ["config", "get", "max*", "port"]
+            .into_iter()
+            .map(|s| RespValue::BulkString(s.into()))
+            .collect::<Vec<_>>();
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Config(ConfigCommand::Get(vec!["max*".into(), "port".into()])),
+            parser.parse_next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_copy() {
+        let resp_values = vec![
+            RespValue::BulkString("copy".into()),
+            RespValue::BulkString("src".into()),
+            RespValue::BulkString("dst".into()),
+            RespValue::BulkString("db".into()),
+            RespValue::BulkString("0".into()),
+            RespValue::BulkString("replace".into()),
+        ];
+        let mut parser = CommandParser::new(resp_values);
+
+        assert_eq!(
+            Command::Copy(CopyCommand {
+                source: "src".into(),
+                destination: "dst".into(),
+                db: Some(0),
+                replace: true,
+            }),
             parser.parse_next().unwrap()
         );
     }
@@ -0,0 +1,1931 @@
+//! Every command the server knows, with what `COMMAND` reports about it and how its
+//! arguments are parsed
+
+use crate::commads::{Command, CommandParseResult, CommandParser};
+use crate::resp::RespValue;
+
+/// Reads the arguments after the command name
+pub type ParseFn = fn(&mut CommandParser) -> CommandParseResult;
+
+/// How to find the keys among the arguments after the one at `begin`
+#[derive(Clone, Copy)]
+pub enum FindKeys {
+    /// Keys up to `lastkey` after the first one, negative counts from the end
+    Range { lastkey: i64, step: usize },
+    /// The argument at `keynumidx` says how many keys follow from `firstkey`
+    KeyNum {
+        keynumidx: usize,
+        firstkey: usize,
+        keystep: usize,
+    },
+}
+
+pub struct KeySpec {
+    /// How the keys are used, like `RO` and `access`
+    pub flags: &'static [&'static str],
+    /// Index of the argument the search starts at, the name being 0
+    pub begin: usize,
+    pub find: FindKeys,
+}
+
+const fn range(flags: &'static [&'static str], begin: usize, lastkey: i64) -> KeySpec {
+    KeySpec {
+        flags,
+        begin,
+        find: FindKeys::Range { lastkey, step: 1 },
+    }
+}
+
+/// Keys given after a key count, like EVAL's
+const fn keynum(flags: &'static [&'static str]) -> KeySpec {
+    KeySpec {
+        flags,
+        begin: 2,
+        find: FindKeys::KeyNum {
+            keynumidx: 0,
+            firstkey: 1,
+            keystep: 1,
+        },
+    }
+}
+
+impl KeySpec {
+    /// The keys this spec finds in the full command line `args`
+    pub fn keys<'a>(&self, args: &'a [String]) -> Result<Vec<&'a String>, String> {
+        let invalid = || "Invalid arguments specified for command".to_string();
+
+        let (first, count, step) = match self.find {
+            FindKeys::Range { lastkey, step } => {
+                let last = match lastkey {
+                    l if l < 0 => args.len() as i64 + l,
+                    l => (self.begin as i64) + l,
+                };
+                let count = (last - self.begin as i64) / step as i64 + 1;
+                (self.begin, count.max(0) as usize, step)
+            }
+            FindKeys::KeyNum {
+                keynumidx,
+                firstkey,
+                keystep,
+            } => {
+                let count = args
+                    .get(self.begin + keynumidx)
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .ok_or_else(invalid)?;
+                (self.begin + firstkey, count, keystep)
+            }
+        };
+
+        (0..count)
+            .map(|i| args.get(first + i * step))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)
+    }
+
+    fn reply(&self) -> RespValue {
+        let bulk = |s: &str| RespValue::BulkString(s.into());
+
+        let (find_type, find_spec) = match self.find {
+            FindKeys::Range { lastkey, step } => (
+                "range",
+                vec![
+                    (bulk("lastkey"), RespValue::Integer(lastkey)),
+                    (bulk("keystep"), RespValue::Integer(step as i64)),
+                    (bulk("limit"), RespValue::Integer(0)),
+                ],
+            ),
+            FindKeys::KeyNum {
+                keynumidx,
+                firstkey,
+                keystep,
+            } => (
+                "keynum",
+                vec![
+                    (bulk("keynumidx"), RespValue::Integer(keynumidx as i64)),
+                    (bulk("firstkey"), RespValue::Integer(firstkey as i64)),
+                    (bulk("keystep"), RespValue::Integer(keystep as i64)),
+                ],
+            ),
+        };
+
+        RespValue::Map(vec![
+            (bulk("flags"), simple_strings(self.flags, "")),
+            (
+                bulk("begin_search"),
+                RespValue::Map(vec![
+                    (bulk("type"), bulk("index")),
+                    (
+                        bulk("spec"),
+                        RespValue::Map(vec![(
+                            bulk("index"),
+                            RespValue::Integer(self.begin as i64),
+                        )]),
+                    ),
+                ]),
+            ),
+            (
+                bulk("find_keys"),
+                RespValue::Map(vec![
+                    (bulk("type"), bulk(find_type)),
+                    (bulk("spec"), RespValue::Map(find_spec)),
+                ]),
+            ),
+        ])
+    }
+}
+
+fn simple_strings(strings: &[&str], prefix: &str) -> RespValue {
+    RespValue::Array(
+        strings
+            .iter()
+            .map(|s| RespValue::SimpleString(format!("{}{}", prefix, s)))
+            .collect(),
+    )
+}
+
+pub struct CommandSpec {
+    /// Lowercase, like `client|list` for subcommands
+    pub name: &'static str,
+    /// Number of arguments counting the name, negative for at least that many
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    /// ACL categories without the `@`
+    pub acl_categories: &'static [&'static str],
+    pub key_specs: &'static [KeySpec],
+    pub group: &'static str,
+    pub since: &'static str,
+    pub summary: &'static str,
+    pub subcommands: &'static [CommandSpec],
+    /// None for subcommands, their container's parser reads them
+    parse: Option<ParseFn>,
+}
+
+impl CommandSpec {
+    pub fn arity_matches(&self, argc: usize) -> bool {
+        match self.arity {
+            a if a >= 0 => argc as i64 == a,
+            a => argc as i64 >= -a,
+        }
+    }
+
+    pub fn parse(&self, parser: &mut CommandParser) -> CommandParseResult {
+        match self.parse {
+            Some(parse) => parse(parser),
+            None => parser.err(format!("{} can't be parsed on its own", self.name)),
+        }
+    }
+
+    pub fn subcommand(&self, name: &str) -> Option<&'static CommandSpec> {
+        self.subcommands.iter().find(|s| {
+            s.name
+                .split_once('|')
+                .is_some_and(|(_, sub)| sub.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// First key, last key and step the way COMMAND reported them before key specs,
+    /// all zero when keys can't be found by position
+    fn legacy_key_range(&self) -> (i64, i64, i64) {
+        let mut key_range = (0, 0, 0);
+
+        for spec in self.key_specs {
+            let FindKeys::Range { lastkey, step } = spec.find else {
+                return (0, 0, 0);
+            };
+            let first = spec.begin as i64;
+            let last = if lastkey < 0 {
+                lastkey
+            } else {
+                first + lastkey
+            };
+
+            if key_range.0 == 0 {
+                key_range = (first, last, step as i64);
+            } else if key_range.1 >= 0 {
+                key_range.1 = if last < 0 {
+                    last
+                } else {
+                    key_range.1.max(last)
+                };
+            }
+        }
+
+        key_range
+    }
+
+    /// Reply to COMMAND INFO
+    pub fn info(&self) -> RespValue {
+        let (first, last, step) = self.legacy_key_range();
+
+        RespValue::Array(vec![
+            RespValue::BulkString(self.name.into()),
+            RespValue::Integer(self.arity),
+            simple_strings(self.flags, ""),
+            RespValue::Integer(first),
+            RespValue::Integer(last),
+            RespValue::Integer(step),
+            simple_strings(self.acl_categories, "@"),
+            // no command tips
+            RespValue::Array(vec![]),
+            RespValue::Array(self.key_specs.iter().map(KeySpec::reply).collect()),
+            RespValue::Array(self.subcommands.iter().map(CommandSpec::info).collect()),
+        ])
+    }
+
+    /// Reply to COMMAND DOCS
+    pub fn docs(&self) -> RespValue {
+        let bulk = |s: &str| RespValue::BulkString(s.into());
+
+        let mut docs = vec![
+            (bulk("summary"), bulk(self.summary)),
+            (bulk("since"), bulk(self.since)),
+            (bulk("group"), bulk(self.group)),
+        ];
+        if !self.subcommands.is_empty() {
+            docs.push((
+                bulk("subcommands"),
+                RespValue::Map(
+                    self.subcommands
+                        .iter()
+                        .map(|s| (bulk(s.name), s.docs()))
+                        .collect(),
+                ),
+            ));
+        }
+
+        RespValue::Map(docs)
+    }
+}
+
+/// The command called `name`, case insensitive
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|c| c.name.eq_ignore_ascii_case(name))
+}
+
+/// The command or subcommand with its full name, like `config|get`
+pub fn lookup_full_name(name: &str) -> Option<&'static CommandSpec> {
+    match name.split_once('|') {
+        Some((container, sub)) => lookup(container)?.subcommand(sub),
+        None => lookup(name),
+    }
+}
+
+/// All commands and subcommands
+pub fn all_commands() -> impl Iterator<Item = &'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .flat_map(|c| std::iter::once(c).chain(c.subcommands))
+}
+
+/// The command or subcommand a full command line runs
+pub fn lookup_args(args: &[String]) -> Option<&'static CommandSpec> {
+    let spec = lookup(args.first()?)?;
+    Some(
+        args.get(1)
+            .and_then(|sub| spec.subcommand(sub))
+            .unwrap_or(spec),
+    )
+}
+
+/// COMMAND GETKEYS, the keys in a full command line
+pub fn get_keys(args: &[String]) -> Result<Vec<String>, String> {
+    let spec = lookup_args(args).ok_or("Invalid command specified")?;
+    if !spec.arity_matches(args.len()) {
+        return Err("Invalid number of arguments specified for command".into());
+    }
+
+    let mut keys = Vec::new();
+    for key_spec in spec.key_specs {
+        keys.extend(key_spec.keys(args)?.into_iter().cloned());
+    }
+    if keys.is_empty() {
+        return Err("The command has no key arguments".into());
+    }
+
+    Ok(keys)
+}
+
+const KEY_RO: &[KeySpec] = &[range(&["RO", "access"], 1, 0)];
+const KEY_RW: &[KeySpec] = &[range(&["RW", "update"], 1, 0)];
+const KEYS_RO: &[KeySpec] = &[range(&["RO"], 1, -1)];
+const KEYS_RM: &[KeySpec] = &[range(&["RM", "delete"], 1, -1)];
+const SHARD_CHANNELS: &[KeySpec] = &[range(&["not_key"], 1, -1)];
+
+/// Flags of commands only used by clients to talk to the server
+const CONNECTION_FLAGS: &[&str] = &["noscript", "loading", "stale"];
+const TRANSACTION_FLAGS: &[&str] = &["noscript", "loading", "stale", "fast", "allow_busy"];
+const SUBSCRIBE_FLAGS: &[&str] = &["pubsub", "noscript", "loading", "stale"];
+const SCRIPT_FLAGS: &[&str] = &[
+    "noscript",
+    "stale",
+    "skip_monitor",
+    "may_replicate",
+    "no_mandatory_keys",
+    "movablekeys",
+];
+
+const CLIENT_ADMIN_ACL: &[&str] = &["admin", "slow", "dangerous", "connection"];
+const CLIENT_ADMIN_FLAGS: &[&str] = &["admin", "noscript", "loading", "stale"];
+
+static CLIENT_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "client|id",
+        arity: 2,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "5.0.0",
+        summary: "Returns the unique client ID of the connection.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|setname",
+        arity: 3,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "2.6.9",
+        summary: "Sets the connection name.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|getname",
+        arity: 2,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "2.6.9",
+        summary: "Returns the name of the connection.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|list",
+        arity: -2,
+        flags: CLIENT_ADMIN_FLAGS,
+        acl_categories: CLIENT_ADMIN_ACL,
+        key_specs: &[],
+        group: "connection",
+        since: "2.4.0",
+        summary: "Lists open connections.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|info",
+        arity: 2,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "6.2.0",
+        summary: "Returns information about the connection.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|kill",
+        arity: -3,
+        flags: CLIENT_ADMIN_FLAGS,
+        acl_categories: CLIENT_ADMIN_ACL,
+        key_specs: &[],
+        group: "connection",
+        since: "2.4.0",
+        summary: "Terminates open connections.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|pause",
+        arity: -3,
+        flags: CLIENT_ADMIN_FLAGS,
+        acl_categories: CLIENT_ADMIN_ACL,
+        key_specs: &[],
+        group: "connection",
+        since: "3.0.0",
+        summary: "Suspends commands processing.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|unpause",
+        arity: 2,
+        flags: CLIENT_ADMIN_FLAGS,
+        acl_categories: CLIENT_ADMIN_ACL,
+        key_specs: &[],
+        group: "connection",
+        since: "6.2.0",
+        summary: "Resumes processing commands from paused clients.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|reply",
+        arity: 3,
+        // it changes how replies are written, which can't happen inside EXEC's reply
+        flags: &["noscript", "loading", "stale", "no_multi"],
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "3.2.0",
+        summary: "Instructs the server whether to reply to commands.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|no-evict",
+        arity: 3,
+        flags: CLIENT_ADMIN_FLAGS,
+        acl_categories: CLIENT_ADMIN_ACL,
+        key_specs: &[],
+        group: "connection",
+        since: "7.0.0",
+        summary: "Sets the client eviction mode of the connection.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|tracking",
+        arity: -3,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "6.0.0",
+        summary: "Controls server-assisted client-side caching for the connection.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|caching",
+        arity: 3,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "6.0.0",
+        summary: "Instructs the server whether to track the keys in the next request.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|trackinginfo",
+        arity: 2,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "6.2.0",
+        summary:
+            "Returns information about server-assisted client-side caching for the connection.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "client|getredir",
+        arity: 2,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "6.0.0",
+        summary:
+            "Returns the client ID to which the connection's tracking notifications are redirected.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+const PUBSUB_FLAGS: &[&str] = &["pubsub", "loading", "stale"];
+
+static PUBSUB_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "pubsub|channels",
+        arity: -2,
+        flags: PUBSUB_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.8.0",
+        summary: "Returns the active channels.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "pubsub|numsub",
+        arity: -2,
+        flags: PUBSUB_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.8.0",
+        summary: "Returns a count of subscribers to channels.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "pubsub|numpat",
+        arity: 2,
+        flags: PUBSUB_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.8.0",
+        summary: "Returns a count of unique pattern subscriptions.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "pubsub|shardchannels",
+        arity: -2,
+        flags: PUBSUB_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Returns the active shard channels.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "pubsub|shardnumsub",
+        arity: -2,
+        flags: PUBSUB_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Returns the count of subscribers of shard channels.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+static SCRIPT_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "script|load",
+        arity: 3,
+        flags: &["noscript", "stale"],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "2.6.0",
+        summary: "Loads a server-side Lua script to the script cache.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "script|exists",
+        arity: -3,
+        flags: &["noscript"],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "2.6.0",
+        summary: "Determines whether server-side Lua scripts exist in the script cache.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "script|flush",
+        arity: -2,
+        flags: &["noscript"],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "2.6.0",
+        summary: "Removes all server-side Lua scripts from the script cache.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "script|kill",
+        arity: 2,
+        flags: &["noscript", "allow_busy"],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "2.6.0",
+        summary: "Terminates a server-side Lua script during execution.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+static FUNCTION_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "function|load",
+        arity: -3,
+        flags: &["write", "denyoom", "noscript"],
+        acl_categories: &["write", "slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Creates a library.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "function|list",
+        arity: -2,
+        flags: &["noscript"],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Returns information about all libraries.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "function|delete",
+        arity: 3,
+        flags: &["write", "noscript"],
+        acl_categories: &["write", "slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Deletes a library and its functions.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "function|dump",
+        arity: 2,
+        flags: &["noscript"],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Dumps all libraries into a serialized binary payload.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "function|restore",
+        arity: -3,
+        flags: &["write", "denyoom", "noscript"],
+        acl_categories: &["write", "slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Restores all libraries from a payload.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "function|flush",
+        arity: -2,
+        flags: &["write", "noscript"],
+        acl_categories: &["write", "slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Deletes all libraries and functions.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "function|kill",
+        arity: 2,
+        flags: &["noscript", "allow_busy"],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Terminates a function during execution.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+const CONFIG_FLAGS: &[&str] = &["admin", "noscript", "loading", "stale"];
+const CONFIG_ACL: &[&str] = &["admin", "slow", "dangerous"];
+
+static CONFIG_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "config|get",
+        arity: -3,
+        flags: CONFIG_FLAGS,
+        acl_categories: CONFIG_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.0.0",
+        summary: "Returns the effective values of configuration parameters.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "config|set",
+        arity: -4,
+        flags: &["admin", "noscript", "loading", "stale", "may_replicate"],
+        acl_categories: CONFIG_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.0.0",
+        summary: "Sets configuration parameters in-flight.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "config|resetstat",
+        arity: 2,
+        flags: CONFIG_FLAGS,
+        acl_categories: CONFIG_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.0.0",
+        summary: "Resets the server's statistics.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "config|rewrite",
+        arity: 2,
+        flags: CONFIG_FLAGS,
+        acl_categories: CONFIG_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.8.0",
+        summary: "Persists the effective configuration to file.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+static COMMAND_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "command|count",
+        arity: 2,
+        flags: &["loading", "stale"],
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns a count of commands.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "command|info",
+        arity: -2,
+        flags: &["loading", "stale"],
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns information about one, multiple or all commands.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "command|docs",
+        arity: -2,
+        flags: &["loading", "stale"],
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "server",
+        since: "7.0.0",
+        summary: "Returns documentary information about one, multiple or all commands.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "command|list",
+        arity: -2,
+        flags: &["loading", "stale"],
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "server",
+        since: "7.0.0",
+        summary: "Returns a list of command names.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "command|getkeys",
+        arity: -3,
+        flags: &["loading", "stale"],
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Extracts the key names from an arbitrary command.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+const SLOWLOG_FLAGS: &[&str] = &["admin", "loading", "stale"];
+const LATENCY_FLAGS: &[&str] = &["admin", "noscript", "loading", "stale"];
+const MONITORING_ACL: &[&str] = &["admin", "slow", "dangerous"];
+
+static SLOWLOG_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "slowlog|get",
+        arity: -2,
+        flags: SLOWLOG_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.2.12",
+        summary: "Returns the slow log's entries.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "slowlog|len",
+        arity: 2,
+        flags: SLOWLOG_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.2.12",
+        summary: "Returns the number of entries in the slow log.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "slowlog|reset",
+        arity: 2,
+        flags: SLOWLOG_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.2.12",
+        summary: "Clears all entries from the slow log.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+static LATENCY_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "latency|latest",
+        arity: 2,
+        flags: LATENCY_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns the latest latency samples for all events.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "latency|history",
+        arity: 3,
+        flags: LATENCY_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns timestamp-latency samples for an event.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "latency|reset",
+        arity: -2,
+        flags: LATENCY_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Resets the latency data for one or more events.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "latency|histogram",
+        arity: -2,
+        flags: LATENCY_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "7.0.0",
+        summary: "Returns the cumulative distribution of latencies of a subset or all commands.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "latency|doctor",
+        arity: 2,
+        flags: LATENCY_FLAGS,
+        acl_categories: MONITORING_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns a human-readable latency analysis report.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+const ACL_FLAGS: &[&str] = &["admin", "noscript", "loading", "stale"];
+const ACL_ADMIN_ACL: &[&str] = &["admin", "slow", "dangerous"];
+
+static ACL_SUBCOMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "acl|cat",
+        arity: -2,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Lists the ACL categories, or the commands inside a category.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|deluser",
+        arity: -3,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Deletes ACL users, and terminates their connections.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|dryrun",
+        arity: -4,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "7.0.0",
+        summary: "Simulates the execution of a command by a user, without executing the command.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|getuser",
+        arity: 3,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Lists the ACL rules of a user.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|list",
+        arity: 2,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Dumps the effective rules in ACL file format.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|load",
+        arity: 2,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Reloads the rules from the configured ACL file.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|log",
+        arity: -2,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Lists recent security events generated due to ACL rules.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|save",
+        arity: 2,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Saves the effective ACL rules in the configured ACL file.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|setuser",
+        arity: -3,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Creates and modifies an ACL user and its rules.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|users",
+        arity: 2,
+        flags: ACL_FLAGS,
+        acl_categories: ACL_ADMIN_ACL,
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Lists all ACL users.",
+        subcommands: &[],
+        parse: None,
+    },
+    CommandSpec {
+        name: "acl|whoami",
+        arity: 2,
+        flags: CONNECTION_FLAGS,
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "Returns the authenticated username of the current connection.",
+        subcommands: &[],
+        parse: None,
+    },
+];
+
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    CommandSpec {
+        name: "ping",
+        arity: -1,
+        flags: &["fast"],
+        acl_categories: &["fast", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the server's liveliness response.",
+        subcommands: &[],
+        parse: Some(CommandParser::ping),
+    },
+    CommandSpec {
+        name: "echo",
+        arity: 2,
+        flags: &["fast"],
+        acl_categories: &["fast", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the given string.",
+        subcommands: &[],
+        parse: Some(CommandParser::echo),
+    },
+    CommandSpec {
+        name: "shutdown",
+        arity: -1,
+        flags: &["admin", "noscript", "loading", "stale", "no_multi", "allow_busy"],
+        acl_categories: &["admin", "slow", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "1.0.0",
+        summary: "Synchronously saves the database(s) to disk and shuts down the Redis server.",
+        subcommands: &[],
+        parse: Some(CommandParser::shutdown),
+    },
+    CommandSpec {
+        name: "set",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        acl_categories: &["write", "string", "slow"],
+        key_specs: &[range(&["RW", "access", "update"], 1, 0)],
+        group: "string",
+        since: "1.0.0",
+        summary: "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+        subcommands: &[],
+        parse: Some(CommandParser::set),
+    },
+    CommandSpec {
+        name: "get",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["read", "string", "fast"],
+        key_specs: KEY_RO,
+        group: "string",
+        since: "1.0.0",
+        summary: "Returns the string value of a key.",
+        subcommands: &[],
+        parse: Some(CommandParser::get),
+    },
+    CommandSpec {
+        name: "info",
+        arity: -1,
+        flags: &["loading", "stale"],
+        acl_categories: &["slow", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns information and statistics about the server.",
+        subcommands: &[],
+        parse: Some(CommandParser::info),
+    },
+    CommandSpec {
+        name: "replconf",
+        arity: -1,
+        flags: &["admin", "noscript", "loading", "stale", "allow_busy"],
+        acl_categories: &["admin", "slow", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "3.0.0",
+        summary: "An internal command for configuring the replication stream.",
+        subcommands: &[],
+        parse: Some(CommandParser::replconf),
+    },
+    CommandSpec {
+        name: "del",
+        arity: -2,
+        flags: &["write"],
+        acl_categories: &["keyspace", "write", "slow"],
+        key_specs: KEYS_RM,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Deletes one or more keys.",
+        subcommands: &[],
+        parse: Some(CommandParser::del),
+    },
+    CommandSpec {
+        name: "unlink",
+        arity: -2,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: KEYS_RM,
+        group: "generic",
+        since: "4.0.0",
+        summary: "Asynchronously deletes one or more keys.",
+        subcommands: &[],
+        parse: Some(CommandParser::unlink),
+    },
+    CommandSpec {
+        name: "exists",
+        arity: -2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: KEYS_RO,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Determines whether one or more keys exist.",
+        subcommands: &[],
+        parse: Some(CommandParser::exists),
+    },
+    CommandSpec {
+        name: "type",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: &[range(&["RO"], 1, 0)],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Determines the type of value stored at a key.",
+        subcommands: &[],
+        parse: Some(CommandParser::key_type),
+    },
+    CommandSpec {
+        name: "rename",
+        arity: 3,
+        flags: &["write"],
+        acl_categories: &["keyspace", "write", "slow"],
+        key_specs: &[
+            range(&["RW", "access", "delete"], 1, 0),
+            range(&["OW", "update"], 2, 0),
+        ],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Renames a key and overwrites the destination.",
+        subcommands: &[],
+        parse: Some(CommandParser::rename),
+    },
+    CommandSpec {
+        name: "renamenx",
+        arity: 3,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: &[
+            range(&["RW", "access", "delete"], 1, 0),
+            range(&["OW", "insert"], 2, 0),
+        ],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Renames a key only when the target key name doesn't exist.",
+        subcommands: &[],
+        parse: Some(CommandParser::renamenx),
+    },
+    CommandSpec {
+        name: "copy",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        acl_categories: &["keyspace", "write", "slow"],
+        key_specs: &[
+            range(&["RO", "access"], 1, 0),
+            range(&["OW", "update"], 2, 0),
+        ],
+        group: "generic",
+        since: "6.2.0",
+        summary: "Copies the value of a key to a new key.",
+        subcommands: &[],
+        parse: Some(CommandParser::copy),
+    },
+    CommandSpec {
+        name: "touch",
+        arity: -2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: KEYS_RO,
+        group: "generic",
+        since: "3.2.1",
+        summary: "Returns the number of existing keys out of those specified after updating the time they were last accessed.",
+        subcommands: &[],
+        parse: Some(CommandParser::touch),
+    },
+    CommandSpec {
+        name: "randomkey",
+        arity: 1,
+        flags: &["readonly"],
+        acl_categories: &["keyspace", "read", "slow"],
+        key_specs: &[],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Returns a random key name from the database.",
+        subcommands: &[],
+        parse: Some(CommandParser::randomkey),
+    },
+    CommandSpec {
+        name: "dbsize",
+        arity: 1,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: &[],
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns the number of keys in the database.",
+        subcommands: &[],
+        parse: Some(CommandParser::dbsize),
+    },
+    CommandSpec {
+        name: "keys",
+        arity: 2,
+        flags: &["readonly"],
+        acl_categories: &["keyspace", "read", "slow", "dangerous"],
+        key_specs: &[],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Returns all key names that match a pattern.",
+        subcommands: &[],
+        parse: Some(CommandParser::keys),
+    },
+    CommandSpec {
+        name: "scan",
+        arity: -2,
+        flags: &["readonly"],
+        acl_categories: &["keyspace", "read", "slow"],
+        key_specs: &[],
+        group: "generic",
+        since: "2.8.0",
+        summary: "Iterates over the key names in the database.",
+        subcommands: &[],
+        parse: Some(CommandParser::scan),
+    },
+    CommandSpec {
+        name: "expire",
+        arity: -3,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: KEY_RW,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Sets the expiration time of a key in seconds.",
+        subcommands: &[],
+        parse: Some(|p| p.expire("expire", 1000, false)),
+    },
+    CommandSpec {
+        name: "pexpire",
+        arity: -3,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: KEY_RW,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Sets the expiration time of a key in milliseconds.",
+        subcommands: &[],
+        parse: Some(|p| p.expire("pexpire", 1, false)),
+    },
+    CommandSpec {
+        name: "expireat",
+        arity: -3,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: KEY_RW,
+        group: "generic",
+        since: "1.2.0",
+        summary: "Sets the expiration time of a key to a Unix timestamp.",
+        subcommands: &[],
+        parse: Some(|p| p.expire("expireat", 1000, true)),
+    },
+    CommandSpec {
+        name: "pexpireat",
+        arity: -3,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: KEY_RW,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        subcommands: &[],
+        parse: Some(|p| p.expire("pexpireat", 1, true)),
+    },
+    CommandSpec {
+        name: "ttl",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: KEY_RO,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Returns the expiration time in seconds of a key.",
+        subcommands: &[],
+        parse: Some(|p| p.single_key("ttl", Command::Ttl)),
+    },
+    CommandSpec {
+        name: "pttl",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: KEY_RO,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Returns the expiration time in milliseconds of a key.",
+        subcommands: &[],
+        parse: Some(|p| p.single_key("pttl", Command::Pttl)),
+    },
+    CommandSpec {
+        name: "expiretime",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: KEY_RO,
+        group: "generic",
+        since: "7.0.0",
+        summary: "Returns the expiration time of a key as a Unix timestamp.",
+        subcommands: &[],
+        parse: Some(|p| p.single_key("expiretime", Command::ExpireTime)),
+    },
+    CommandSpec {
+        name: "pexpiretime",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        acl_categories: &["keyspace", "read", "fast"],
+        key_specs: KEY_RO,
+        group: "generic",
+        since: "7.0.0",
+        summary: "Returns the expiration time of a key as a Unix milliseconds timestamp.",
+        subcommands: &[],
+        parse: Some(|p| p.single_key("pexpiretime", Command::PexpireTime)),
+    },
+    CommandSpec {
+        name: "persist",
+        arity: 2,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: KEY_RW,
+        group: "generic",
+        since: "2.2.0",
+        summary: "Removes the expiration time of a key.",
+        subcommands: &[],
+        parse: Some(|p| p.single_key("persist", Command::Persist)),
+    },
+    CommandSpec {
+        name: "select",
+        arity: 2,
+        flags: &["loading", "stale", "fast"],
+        acl_categories: &["fast", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "1.0.0",
+        summary: "Changes the selected database.",
+        subcommands: &[],
+        parse: Some(CommandParser::select),
+    },
+    CommandSpec {
+        name: "swapdb",
+        arity: 3,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "4.0.0",
+        summary: "Swaps two Redis databases.",
+        subcommands: &[],
+        parse: Some(CommandParser::swapdb),
+    },
+    CommandSpec {
+        name: "move",
+        arity: 3,
+        flags: &["write", "fast"],
+        acl_categories: &["keyspace", "write", "fast"],
+        key_specs: &[range(&["RW", "access", "update"], 1, 0)],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Moves a key to another database.",
+        subcommands: &[],
+        parse: Some(CommandParser::move_key),
+    },
+    CommandSpec {
+        name: "flushdb",
+        arity: -1,
+        flags: &["write"],
+        acl_categories: &["keyspace", "write", "slow", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "1.0.0",
+        summary: "Remove all keys from the current database.",
+        subcommands: &[],
+        parse: Some(|p| p.flush("flushdb", Command::FlushDb)),
+    },
+    CommandSpec {
+        name: "flushall",
+        arity: -1,
+        flags: &["write"],
+        acl_categories: &["keyspace", "write", "slow", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "1.0.0",
+        summary: "Removes all keys from all databases.",
+        subcommands: &[],
+        parse: Some(|p| p.flush("flushall", Command::FlushAll)),
+    },
+    CommandSpec {
+        name: "multi",
+        arity: 1,
+        flags: TRANSACTION_FLAGS,
+        acl_categories: &["fast", "transaction"],
+        key_specs: &[],
+        group: "transactions",
+        since: "1.2.0",
+        summary: "Starts a transaction.",
+        subcommands: &[],
+        parse: Some(|p| p.no_args("multi", Command::Multi)),
+    },
+    CommandSpec {
+        name: "exec",
+        arity: 1,
+        flags: &["noscript", "loading", "stale", "skip_slowlog"],
+        acl_categories: &["slow", "transaction"],
+        key_specs: &[],
+        group: "transactions",
+        since: "1.2.0",
+        summary: "Executes all commands in a transaction.",
+        subcommands: &[],
+        parse: Some(|p| p.no_args("exec", Command::Exec)),
+    },
+    CommandSpec {
+        name: "discard",
+        arity: 1,
+        flags: TRANSACTION_FLAGS,
+        acl_categories: &["fast", "transaction"],
+        key_specs: &[],
+        group: "transactions",
+        since: "2.0.0",
+        summary: "Discards a transaction.",
+        subcommands: &[],
+        parse: Some(|p| p.no_args("discard", Command::Discard)),
+    },
+    CommandSpec {
+        name: "watch",
+        arity: -2,
+        flags: TRANSACTION_FLAGS,
+        acl_categories: &["fast", "transaction"],
+        key_specs: KEYS_RO,
+        group: "transactions",
+        since: "2.2.0",
+        summary: "Monitors changes to keys to determine the execution of a transaction.",
+        subcommands: &[],
+        parse: Some(CommandParser::watch),
+    },
+    CommandSpec {
+        name: "unwatch",
+        arity: 1,
+        flags: TRANSACTION_FLAGS,
+        acl_categories: &["fast", "transaction"],
+        key_specs: &[],
+        group: "transactions",
+        since: "2.2.0",
+        summary: "Forgets about watched keys of a transaction.",
+        subcommands: &[],
+        parse: Some(|p| p.no_args("unwatch", Command::Unwatch)),
+    },
+    CommandSpec {
+        name: "subscribe",
+        arity: -2,
+        flags: SUBSCRIBE_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Listens for messages published to channels.",
+        subcommands: &[],
+        parse: Some(|p| Ok(Command::Subscribe(p.remaining_strings("subscribe")?))),
+    },
+    CommandSpec {
+        name: "unsubscribe",
+        arity: -1,
+        flags: SUBSCRIBE_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Stops listening to messages posted to channels.",
+        subcommands: &[],
+        parse: Some(|p| Ok(Command::Unsubscribe(p.optional_strings("unsubscribe")?))),
+    },
+    CommandSpec {
+        name: "psubscribe",
+        arity: -2,
+        flags: SUBSCRIBE_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Listens for messages published to channels that match one or more patterns.",
+        subcommands: &[],
+        parse: Some(|p| Ok(Command::PSubscribe(p.remaining_strings("psubscribe")?))),
+    },
+    CommandSpec {
+        name: "punsubscribe",
+        arity: -1,
+        flags: SUBSCRIBE_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Stops listening to messages published to channels that match one or more patterns.",
+        subcommands: &[],
+        parse: Some(|p| Ok(Command::PUnsubscribe(p.optional_strings("punsubscribe")?))),
+    },
+    CommandSpec {
+        name: "publish",
+        arity: 3,
+        flags: &["pubsub", "loading", "stale", "fast", "may_replicate"],
+        acl_categories: &["pubsub", "fast"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Posts a message to a channel.",
+        subcommands: &[],
+        parse: Some(|p| p.publish("publish", Command::Publish)),
+    },
+    CommandSpec {
+        name: "ssubscribe",
+        arity: -2,
+        flags: SUBSCRIBE_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: SHARD_CHANNELS,
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Listens for messages published to shard channels.",
+        subcommands: &[],
+        parse: Some(|p| Ok(Command::SSubscribe(p.remaining_strings("ssubscribe")?))),
+    },
+    CommandSpec {
+        name: "sunsubscribe",
+        arity: -1,
+        flags: SUBSCRIBE_FLAGS,
+        acl_categories: &["pubsub", "slow"],
+        key_specs: SHARD_CHANNELS,
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Stops listening to messages posted to shard channels.",
+        subcommands: &[],
+        parse: Some(|p| Ok(Command::SUnsubscribe(p.optional_strings("sunsubscribe")?))),
+    },
+    CommandSpec {
+        name: "spublish",
+        arity: 3,
+        flags: &["pubsub", "loading", "stale", "fast", "may_replicate"],
+        acl_categories: &["pubsub", "fast"],
+        key_specs: &[range(&["not_key"], 1, 0)],
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Post a message to a shard channel",
+        subcommands: &[],
+        parse: Some(|p| p.publish("spublish", Command::SPublish)),
+    },
+    CommandSpec {
+        name: "psync",
+        arity: -3,
+        flags: &["admin", "noscript", "no_multi"],
+        acl_categories: &["admin", "slow", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "2.8.0",
+        summary: "An internal command used in replication.",
+        subcommands: &[],
+        parse: Some(CommandParser::psync),
+    },
+    CommandSpec {
+        name: "pubsub",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "pubsub",
+        since: "2.8.0",
+        summary: "A container for Pub/Sub commands.",
+        subcommands: PUBSUB_SUBCOMMANDS,
+        parse: Some(CommandParser::pubsub),
+    },
+    CommandSpec {
+        name: "hello",
+        arity: -1,
+        flags: &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        acl_categories: &["fast", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "6.0.0",
+        summary: "Handshakes with the Redis server.",
+        subcommands: &[],
+        parse: Some(CommandParser::hello),
+    },
+    CommandSpec {
+        name: "auth",
+        arity: -2,
+        flags: &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        acl_categories: &["fast", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "1.0.0",
+        summary: "Authenticates the connection.",
+        subcommands: &[],
+        parse: Some(CommandParser::auth),
+    },
+    CommandSpec {
+        name: "quit",
+        arity: -1,
+        flags: &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        acl_categories: &["fast", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "1.0.0",
+        summary: "Closes the connection.",
+        subcommands: &[],
+        parse: Some(|p| p.no_args("quit", Command::Quit)),
+    },
+    CommandSpec {
+        name: "reset",
+        arity: 1,
+        flags: &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        acl_categories: &["fast", "connection"],
+        key_specs: &[],
+        group: "connection",
+        since: "6.2.0",
+        summary: "Resets the connection.",
+        subcommands: &[],
+        parse: Some(|p| p.no_args("reset", Command::Reset)),
+    },
+    CommandSpec {
+        name: "client",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "connection",
+        since: "2.4.0",
+        summary: "A container for client connection commands.",
+        subcommands: CLIENT_SUBCOMMANDS,
+        parse: Some(CommandParser::client),
+    },
+    CommandSpec {
+        name: "eval",
+        arity: -3,
+        flags: SCRIPT_FLAGS,
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[keynum(&["RW", "access", "update"])],
+        group: "scripting",
+        since: "2.6.0",
+        summary: "Executes a server-side Lua script.",
+        subcommands: &[],
+        parse: Some(|p| p.eval("eval", Command::Eval)),
+    },
+    CommandSpec {
+        name: "evalsha",
+        arity: -3,
+        flags: SCRIPT_FLAGS,
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[keynum(&["RW", "access", "update"])],
+        group: "scripting",
+        since: "2.6.0",
+        summary: "Executes a server-side Lua script by SHA1 digest.",
+        subcommands: &[],
+        parse: Some(|p| p.eval("evalsha", Command::EvalSha)),
+    },
+    CommandSpec {
+        name: "script",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "scripting",
+        since: "2.6.0",
+        summary: "A container for Lua scripts management commands.",
+        subcommands: SCRIPT_SUBCOMMANDS,
+        parse: Some(CommandParser::script),
+    },
+    CommandSpec {
+        name: "function",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "A container for function commands.",
+        subcommands: FUNCTION_SUBCOMMANDS,
+        parse: Some(CommandParser::function),
+    },
+    CommandSpec {
+        name: "fcall",
+        arity: -3,
+        flags: SCRIPT_FLAGS,
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[keynum(&["RW", "access", "update"])],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Invokes a function.",
+        subcommands: &[],
+        parse: Some(|p| p.eval("fcall", Command::FCall)),
+    },
+    CommandSpec {
+        name: "fcall_ro",
+        arity: -3,
+        flags: &[
+            "readonly",
+            "noscript",
+            "stale",
+            "skip_monitor",
+            "no_mandatory_keys",
+            "movablekeys",
+        ],
+        acl_categories: &["slow", "scripting"],
+        key_specs: &[keynum(&["RO", "access"])],
+        group: "scripting",
+        since: "7.0.0",
+        summary: "Invokes a read-only function.",
+        subcommands: &[],
+        parse: Some(|p| p.eval("fcall_ro", Command::FCallRo)),
+    },
+    CommandSpec {
+        name: "config",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "server",
+        since: "2.0.0",
+        summary: "A container for server configuration commands.",
+        subcommands: CONFIG_SUBCOMMANDS,
+        parse: Some(CommandParser::config),
+    },
+    CommandSpec {
+        name: "command",
+        arity: -1,
+        flags: &["loading", "stale"],
+        acl_categories: &["slow", "connection"],
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns detailed information about all commands.",
+        subcommands: COMMAND_SUBCOMMANDS,
+        parse: Some(CommandParser::command),
+    },
+    CommandSpec {
+        name: "slowlog",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "server",
+        since: "2.2.12",
+        summary: "A container for slow log commands.",
+        subcommands: SLOWLOG_SUBCOMMANDS,
+        parse: Some(CommandParser::slowlog),
+    },
+    CommandSpec {
+        name: "latency",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "server",
+        since: "2.8.13",
+        summary: "A container for latency diagnostics commands.",
+        subcommands: LATENCY_SUBCOMMANDS,
+        parse: Some(CommandParser::latency),
+    },
+    CommandSpec {
+        name: "monitor",
+        arity: 1,
+        flags: &["admin", "noscript", "loading", "stale"],
+        acl_categories: &["admin", "slow", "dangerous"],
+        key_specs: &[],
+        group: "server",
+        since: "1.0.0",
+        summary: "Listens for all requests received by the server in real-time.",
+        subcommands: &[],
+        parse: Some(|p| p.no_args("monitor", Command::Monitor)),
+    },
+    CommandSpec {
+        name: "acl",
+        arity: -2,
+        flags: &[],
+        acl_categories: &["slow"],
+        key_specs: &[],
+        group: "server",
+        since: "6.0.0",
+        summary: "A container for Access List Control commands.",
+        subcommands: ACL_SUBCOMMANDS,
+        parse: Some(CommandParser::acl),
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("GET").unwrap().name, "get");
+        assert_eq!(lookup_full_name("config|get").unwrap().name, "config|get");
+        assert!(lookup_full_name("config|nope").is_none());
+        assert!(lookup("nope").is_none());
+
+        // every name is unique and lowercase
+        let names: Vec<&str> = all_commands().map(|c| c.name).collect();
+        for name in &names {
+            assert_eq!(name.to_lowercase(), *name);
+            assert_eq!(names.iter().filter(|n| *n == name).count(), 1, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_legacy_key_range() {
+        assert_eq!(lookup("get").unwrap().legacy_key_range(), (1, 1, 1));
+        assert_eq!(lookup("del").unwrap().legacy_key_range(), (1, -1, 1));
+        assert_eq!(lookup("rename").unwrap().legacy_key_range(), (1, 2, 1));
+        assert_eq!(lookup("eval").unwrap().legacy_key_range(), (0, 0, 0));
+        assert_eq!(lookup("ping").unwrap().legacy_key_range(), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_get_keys() {
+        assert_eq!(
+            get_keys(&args(&["set", "k", "v", "EX", "10"])),
+            Ok(args(&["k"]))
+        );
+        assert_eq!(
+            get_keys(&args(&["del", "a", "b", "c"])),
+            Ok(args(&["a", "b", "c"]))
+        );
+        assert_eq!(get_keys(&args(&["copy", "a", "b"])), Ok(args(&["a", "b"])));
+        assert_eq!(
+            get_keys(&args(&["eval", "return 1", "2", "a", "b", "arg"])),
+            Ok(args(&["a", "b"]))
+        );
+
+        assert!(get_keys(&args(&["eval", "return 1", "3", "a"])).is_err());
+        assert_eq!(
+            get_keys(&args(&["eval", "return 1", "0"])),
+            Err("The command has no key arguments".into())
+        );
+        assert_eq!(
+            get_keys(&args(&["get"])),
+            Err("Invalid number of arguments specified for command".into())
+        );
+        assert_eq!(
+            get_keys(&args(&["nope", "a"])),
+            Err("Invalid command specified".into())
+        );
+    }
+}
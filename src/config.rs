@@ -0,0 +1,813 @@
+//! Configuration parameters, read from a redis.conf style file and `--name value`
+//! arguments, changed at runtime with CONFIG SET and written back with CONFIG REWRITE
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::eviction::parse_memory;
+use crate::glob::glob_match;
+use crate::log::LOG_LEVELS;
+use crate::notify::{notify_flags_to_string, parse_notify_flags};
+use crate::server::REDIS_VERSION;
+
+/// How the values of a parameter are checked and shown
+#[derive(Clone, Copy)]
+enum Kind {
+    /// Integer within an inclusive range
+    Int(i64, i64),
+    /// Byte count, units like `100mb` are accepted
+    Memory,
+    /// `yes` or `no`
+    Bool,
+    Enum(&'static [&'static str]),
+    /// File name without a directory
+    FileName,
+    /// Existing directory, kept as an absolute path
+    Dir,
+    /// Space separated words
+    List,
+    /// Keyspace notification classes like `KEA`
+    NotifyFlags,
+    /// `host port`, empty for none
+    HostPort,
+    /// Any string
+    Str,
+    /// File permission bits in octal like `700`
+    Octal,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+}
+
+struct Param {
+    name: &'static str,
+    /// Older name that still works
+    alias: Option<&'static str>,
+    kind: Kind,
+    default: &'static str,
+    /// Whether CONFIG SET may change it
+    mutable: bool,
+}
+
+const MAXMEMORY_POLICIES: &[&str] = &[
+    "noeviction",
+    "allkeys-lru",
+    "volatile-lru",
+    "allkeys-lfu",
+    "volatile-lfu",
+    "allkeys-random",
+    "volatile-random",
+    "volatile-ttl",
+];
+
+const PARAMS: &[Param] = &[
+    Param {
+        name: "port",
+        alias: None,
+        kind: Kind::Int(0, 65535),
+        default: "6379",
+        mutable: false,
+    },
+    Param {
+        name: "bind",
+        alias: None,
+        kind: Kind::List,
+        default: "127.0.0.1 -::1",
+        mutable: false,
+    },
+    Param {
+        name: "protected-mode",
+        alias: None,
+        kind: Kind::Bool,
+        default: "yes",
+        mutable: true,
+    },
+    Param {
+        name: "dir",
+        alias: None,
+        kind: Kind::Dir,
+        default: ".",
+        mutable: true,
+    },
+    Param {
+        name: "dbfilename",
+        alias: None,
+        kind: Kind::FileName,
+        default: "dump.rdb",
+        mutable: true,
+    },
+    // only the function libraries are saved, keys aren't persisted
+    Param {
+        name: "save",
+        alias: None,
+        kind: Kind::List,
+        default: "3600 1 300 100 60 10000",
+        mutable: true,
+    },
+    Param {
+        name: "loglevel",
+        alias: None,
+        kind: Kind::Enum(LOG_LEVELS),
+        default: "notice",
+        mutable: true,
+    },
+    Param {
+        name: "logfile",
+        alias: None,
+        kind: Kind::Str,
+        default: "",
+        mutable: false,
+    },
+    Param {
+        name: "databases",
+        alias: None,
+        kind: Kind::Int(1, i32::MAX as i64),
+        default: "16",
+        mutable: false,
+    },
+    Param {
+        name: "maxmemory",
+        alias: None,
+        kind: Kind::Memory,
+        default: "0",
+        mutable: true,
+    },
+    Param {
+        name: "maxmemory-policy",
+        alias: None,
+        kind: Kind::Enum(MAXMEMORY_POLICIES),
+        default: "noeviction",
+        mutable: true,
+    },
+    Param {
+        name: "notify-keyspace-events",
+        alias: None,
+        kind: Kind::NotifyFlags,
+        default: "",
+        mutable: true,
+    },
+    Param {
+        name: "busy-reply-threshold",
+        alias: Some("lua-time-limit"),
+        kind: Kind::Int(0, i64::MAX),
+        default: "5000",
+        mutable: true,
+    },
+    Param {
+        name: "timeout",
+        alias: None,
+        kind: Kind::Int(0, i32::MAX as i64),
+        default: "0",
+        mutable: true,
+    },
+    Param {
+        name: "tcp-keepalive",
+        alias: None,
+        kind: Kind::Int(0, i32::MAX as i64),
+        default: "300",
+        mutable: true,
+    },
+    Param {
+        name: "maxclients",
+        alias: None,
+        kind: Kind::Int(1, i64::MAX),
+        default: "10000",
+        mutable: true,
+    },
+    Param {
+        name: "slowlog-log-slower-than",
+        alias: None,
+        kind: Kind::Int(-1, i64::MAX),
+        default: "10000",
+        mutable: true,
+    },
+    Param {
+        name: "slowlog-max-len",
+        alias: None,
+        kind: Kind::Int(0, i64::MAX),
+        default: "128",
+        mutable: true,
+    },
+    Param {
+        name: "latency-monitor-threshold",
+        alias: None,
+        kind: Kind::Int(0, i64::MAX),
+        default: "0",
+        mutable: true,
+    },
+    Param {
+        name: "latency-tracking",
+        alias: None,
+        kind: Kind::Bool,
+        default: "yes",
+        mutable: true,
+    },
+    Param {
+        name: "requirepass",
+        alias: None,
+        kind: Kind::Str,
+        default: "",
+        mutable: true,
+    },
+    Param {
+        name: "acllog-max-len",
+        alias: None,
+        kind: Kind::Int(0, i64::MAX),
+        default: "128",
+        mutable: true,
+    },
+    Param {
+        name: "aclfile",
+        alias: None,
+        kind: Kind::Str,
+        default: "",
+        mutable: false,
+    },
+    Param {
+        name: "unixsocket",
+        alias: None,
+        kind: Kind::Str,
+        default: "",
+        mutable: false,
+    },
+    Param {
+        name: "unixsocketperm",
+        alias: None,
+        kind: Kind::Octal,
+        default: "0",
+        mutable: false,
+    },
+    Param {
+        name: "replicaof",
+        alias: Some("slaveof"),
+        kind: Kind::HostPort,
+        default: "",
+        mutable: false,
+    },
+];
+
+/// Comment CONFIG REWRITE puts above the parameters it appends
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+fn parse_value(kind: Kind, value: &str) -> Result<ConfigValue, String> {
+    let value = match kind {
+        Kind::Int(min, max) => {
+            let i = value
+                .parse::<i64>()
+                .map_err(|_| "argument couldn't be parsed into an integer".to_string())?;
+            if i < min || i > max {
+                return Err(format!(
+                    "argument must be between {} and {} inclusive",
+                    min, max
+                ));
+            }
+            ConfigValue::Int(i)
+        }
+        Kind::Memory => match parse_memory(value) {
+            Some(bytes) if bytes <= i64::MAX as u64 => ConfigValue::Int(bytes as i64),
+            _ => return Err("argument must be a memory value".into()),
+        },
+        Kind::Bool => match value.to_lowercase().as_str() {
+            "yes" => ConfigValue::Bool(true),
+            "no" => ConfigValue::Bool(false),
+            _ => return Err("argument must be 'yes' or 'no'".into()),
+        },
+        Kind::Enum(names) => {
+            let value = value.to_lowercase();
+            if !names.contains(&value.as_str()) {
+                return Err(format!(
+                    "argument(s) must be one of the following: {}",
+                    names.join(", ")
+                ));
+            }
+            ConfigValue::Str(value)
+        }
+        Kind::FileName => {
+            if value.contains('/') {
+                return Err("dbfilename can't be a path, just a filename".into());
+            }
+            ConfigValue::Str(value.into())
+        }
+        Kind::Dir => match fs::canonicalize(value) {
+            Ok(path) if path.is_dir() => ConfigValue::Str(path.to_string_lossy().into()),
+            Ok(_) => return Err("Not a directory".into()),
+            Err(_) => return Err("No such file or directory".into()),
+        },
+        Kind::List => ConfigValue::Str(value.split_whitespace().collect::<Vec<_>>().join(" ")),
+        Kind::NotifyFlags => match parse_notify_flags(value) {
+            Some(flags) => ConfigValue::Int(flags as i64),
+            None => return Err("Invalid event class character. Use 'Ag$lshzxeKEtmn'.".into()),
+        },
+        Kind::HostPort => {
+            let words: Vec<&str> = value.split_whitespace().collect();
+            match words.as_slice() {
+                [] => ConfigValue::Str(String::new()),
+                [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => {
+                    ConfigValue::Str(String::new())
+                }
+                [host, port] if port.parse::<u16>().is_ok() => {
+                    ConfigValue::Str(format!("{} {}", host, port))
+                }
+                _ => return Err("argument must be a host and a port".into()),
+            }
+        }
+        Kind::Str => ConfigValue::Str(value.into()),
+        Kind::Octal => match u32::from_str_radix(value, 8) {
+            Ok(mode) if mode <= 0o777 => ConfigValue::Int(mode as i64),
+            _ => return Err("argument must be octal permission bits like 700".into()),
+        },
+    };
+
+    Ok(value)
+}
+
+fn format_value(kind: Kind, value: &ConfigValue) -> String {
+    match (kind, value) {
+        (Kind::NotifyFlags, ConfigValue::Int(flags)) => notify_flags_to_string(*flags as u32),
+        (Kind::Octal, ConfigValue::Int(mode)) => format!("{:o}", mode),
+        (_, ConfigValue::Int(i)) => i.to_string(),
+        (_, ConfigValue::Bool(true)) => "yes".into(),
+        (_, ConfigValue::Bool(false)) => "no".into(),
+        (_, ConfigValue::Str(s)) => s.clone(),
+    }
+}
+
+/// Index of the parameter called `name` or with `name` as its alias
+fn param_index(name: &str) -> Option<usize> {
+    PARAMS.iter().position(|p| {
+        p.name.eq_ignore_ascii_case(name)
+            || p.alias
+                .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Split a config file line into arguments, handling double and single quotes the way
+/// redis.conf does, None on unbalanced quotes
+pub fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Some(args);
+        };
+
+        let mut arg = String::new();
+        if first == '"' || first == '\'' {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '\\' if first == '"' => match chars.next()? {
+                        'n' => arg.push('\n'),
+                        'r' => arg.push('\r'),
+                        't' => arg.push('\t'),
+                        'b' => arg.push('\u{8}'),
+                        'a' => arg.push('\u{7}'),
+                        'x' => {
+                            let hex: String = [chars.next()?, chars.next()?].iter().collect();
+                            arg.push(u8::from_str_radix(&hex, 16).ok()? as char);
+                        }
+                        c => arg.push(c),
+                    },
+                    '\\' if chars.peek() == Some(&'\'') => arg.push(chars.next()?),
+                    c if c == first => break,
+                    c => arg.push(c),
+                }
+            }
+            // a closing quote must end the argument
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return None;
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Quote `arg` for a config file line if it needs it
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\".contains(c)) {
+        return arg.into();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug)]
+pub struct Config {
+    values: Vec<ConfigValue>,
+    defaults: Vec<ConfigValue>,
+    /// File the configuration was read from, CONFIG REWRITE writes to it
+    file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let defaults: Vec<ConfigValue> = PARAMS
+            .iter()
+            .map(|p| parse_value(p.kind, p.default).unwrap())
+            .collect();
+
+        Self {
+            values: defaults.clone(),
+            defaults,
+            file: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configuration from command line arguments, an optional config file path followed by
+    /// `--name value` overrides
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Result<Config, String> {
+        let mut args = args.peekable();
+        let mut config = Config::new();
+
+        if let Some(path) = args.next_if(|a| !a.starts_with("--")) {
+            config.load_file(Path::new(&path))?;
+        }
+
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or(format!("unexpected arg: {}", arg))?;
+            let mut values = Vec::new();
+            while let Some(value) = args.next_if(|a| !a.starts_with("--")) {
+                values.push(value);
+            }
+
+            config
+                .set_directive(name, &values)
+                .map_err(|e| format!("invalid argument --{}: {}", name, e))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Read the parameters in a redis.conf style file, remembering it for CONFIG REWRITE
+    pub fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "Fatal error, can't open config file '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+
+        for (n, line) in contents.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let result = match split_args(trimmed) {
+                Some(args) => self.set_directive(&args[0], &args[1..]),
+                None => Err("Unbalanced quotes in configuration line".into()),
+            };
+            if let Err(e) = result {
+                return Err(format!(
+                    "\n*** FATAL CONFIG FILE ERROR (Redis {}) ***\nReading the configuration file, at line {}\n>>> '{}'\n{}",
+                    REDIS_VERSION,
+                    n + 1,
+                    trimmed,
+                    e
+                ));
+            }
+        }
+
+        self.file = Some(fs::canonicalize(path).unwrap_or(path.into()));
+        Ok(())
+    }
+
+    /// Set a parameter from a config file line or command line argument
+    fn set_directive(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        let idx = param_index(name).ok_or("Bad directive or wrong number of arguments")?;
+        let kind = PARAMS[idx].kind;
+
+        let value = match (kind, args) {
+            (Kind::List | Kind::HostPort, args) => args.join(" "),
+            (_, [value]) => value.clone(),
+            _ => return Err("wrong number of arguments".into()),
+        };
+        self.values[idx] = parse_value(kind, &value)?;
+
+        Ok(())
+    }
+
+    /// Set a parameter by its name, for values the server decides on itself
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.set_directive(name, &[value.to_string()])
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        PARAMS.iter().map(|p| p.name)
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    fn value(&self, name: &str) -> &ConfigValue {
+        let idx = param_index(name).unwrap_or_else(|| panic!("unknown config {}", name));
+        &self.values[idx]
+    }
+
+    pub fn int(&self, name: &str) -> i64 {
+        match self.value(name) {
+            ConfigValue::Int(i) => *i,
+            v => panic!("config {} is not an integer: {:?}", name, v),
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        match self.value(name) {
+            ConfigValue::Bool(b) => *b,
+            v => panic!("config {} is not a boolean: {:?}", name, v),
+        }
+    }
+
+    pub fn string(&self, name: &str) -> &str {
+        match self.value(name) {
+            ConfigValue::Str(s) => s,
+            v => panic!("config {} is not a string: {:?}", name, v),
+        }
+    }
+
+    /// Parameters matching any of the glob style `patterns` with their values, an alias
+    /// only matches when given in full
+    pub fn matching(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        let mut matching = Vec::new();
+
+        for (param, value) in PARAMS.iter().zip(&self.values) {
+            let value = format_value(param.kind, value);
+            if patterns.iter().any(|p| glob_match(p, param.name, true)) {
+                matching.push((param.name, value.clone()));
+            }
+            if let Some(alias) = param.alias {
+                if patterns.iter().any(|p| p.eq_ignore_ascii_case(alias)) {
+                    matching.push((alias, value));
+                }
+            }
+        }
+
+        matching
+    }
+
+    /// CONFIG SET, either all of `pairs` are applied or none, returns the names of the
+    /// parameters set
+    pub fn set_runtime(&mut self, pairs: &[(String, String)]) -> Result<Vec<&'static str>, String> {
+        let mut parsed: Vec<(usize, ConfigValue)> = Vec::new();
+
+        for (name, value) in pairs {
+            let failed = |reason: &str| {
+                format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name, reason
+                )
+            };
+
+            let idx = param_index(name).ok_or(format!(
+                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            ))?;
+            if parsed.iter().any(|(i, _)| *i == idx) {
+                return Err(failed("duplicate parameter"));
+            }
+            if !PARAMS[idx].mutable {
+                return Err(failed("can't set immutable config"));
+            }
+
+            parsed.push((
+                idx,
+                parse_value(PARAMS[idx].kind, value).map_err(|e| failed(&e))?,
+            ));
+        }
+
+        let mut names = Vec::new();
+        for (idx, value) in parsed {
+            self.values[idx] = value;
+            names.push(PARAMS[idx].name);
+        }
+        Ok(names)
+    }
+
+    /// The config file line setting the parameter at `idx`
+    fn directive(&self, idx: usize) -> String {
+        let param = &PARAMS[idx];
+        let value = format_value(param.kind, &self.values[idx]);
+
+        let value = match param.kind {
+            Kind::List | Kind::HostPort if !value.is_empty() => value,
+            _ => quote_arg(&value),
+        };
+        format!("{} {}", param.name, value)
+    }
+
+    /// CONFIG REWRITE, update the config file to the current values, keeping its comments
+    /// and the order of its lines
+    pub fn rewrite(&self) -> Result<(), String> {
+        let path = self
+            .file
+            .as_ref()
+            .ok_or("The server is running without a config file")?;
+        // a config file deleted since the start is written from scratch
+        let old = fs::read_to_string(path).unwrap_or_default();
+
+        let mut written = vec![false; PARAMS.len()];
+        let mut lines = Vec::new();
+        for line in old.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                lines.push(line.to_string());
+                continue;
+            }
+
+            let idx = split_args(trimmed)
+                .and_then(|args| args.first().and_then(|name| param_index(name)));
+            match idx {
+                // later lines for the same parameter would override it again
+                Some(idx) if written[idx] => {}
+                Some(idx) => {
+                    written[idx] = true;
+                    lines.push(self.directive(idx));
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+
+        let missing: Vec<usize> = (0..PARAMS.len())
+            .filter(|&i| !written[i] && self.values[i] != self.defaults[i])
+            .collect();
+        if !missing.is_empty() {
+            // a file rewritten before already has it above its appended parameters
+            if !lines.iter().any(|l| l.trim() == REWRITE_SIGNATURE) {
+                lines.push(REWRITE_SIGNATURE.into());
+            }
+            lines.extend(missing.into_iter().map(|i| self.directive(i)));
+        }
+
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+
+        // write a temporary file and move it over the old one, so a failed write
+        // doesn't leave half a config file
+        let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(&tmp, contents)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("Rewriting config file: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> std::vec::IntoIter<String> {
+        args.iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args(r#"save "" 'a b' "x\ty" plain"#),
+            Some(vec![
+                "save".into(),
+                "".into(),
+                "a b".into(),
+                "x\ty".into(),
+                "plain".into()
+            ])
+        );
+        assert_eq!(split_args(r#"dir "/tmp"#), None);
+        assert_eq!(split_args(r#"dir "/tmp"x"#), None);
+        assert_eq!(split_args("   "), Some(vec![]));
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = Config::from_args(args(&[
+            "--port",
+            "7000",
+            "--maxmemory",
+            "1kb",
+            "--replicaof",
+            "localhost",
+            "6379",
+            "--notify-keyspace-events",
+            "EKA",
+        ]))
+        .unwrap();
+
+        assert_eq!(config.int("port"), 7000);
+        assert_eq!(config.int("maxmemory"), 1024);
+        assert_eq!(config.string("replicaof"), "localhost 6379");
+        assert_eq!(
+            config.matching(&["notify-*".into()]),
+            vec![("notify-keyspace-events", "AKE".to_string())]
+        );
+
+        assert!(Config::from_args(args(&["--port", "70000"])).is_err());
+        assert!(Config::from_args(args(&["--nope", "1"])).is_err());
+        assert!(Config::from_args(args(&["--databases"])).is_err());
+    }
+
+    #[test]
+    fn test_set_runtime() {
+        let mut config = Config::new();
+
+        assert_eq!(
+            config.set_runtime(&[
+                ("maxmemory".into(), "100mb".into()),
+                ("lua-time-limit".into(), "10".into()),
+            ]),
+            Ok(vec!["maxmemory", "busy-reply-threshold"])
+        );
+        assert_eq!(config.int("maxmemory"), 100 * 1024 * 1024);
+        assert_eq!(config.int("busy-reply-threshold"), 10);
+
+        // nothing is applied when one of them fails
+        assert!(config
+            .set_runtime(&[
+                ("timeout".into(), "5".into()),
+                ("maxmemory-policy".into(), "sometimes".into()),
+            ])
+            .is_err());
+        assert_eq!(config.int("timeout"), 0);
+
+        assert_eq!(
+            config.set_runtime(&[("port".into(), "1".into())]),
+            Err("CONFIG SET failed (possibly related to argument 'port') - can't set immutable config".into())
+        );
+        assert!(config
+            .set_runtime(&[
+                ("timeout".into(), "5".into()),
+                ("timeout".into(), "6".into()),
+            ])
+            .is_err());
+        assert!(config
+            .set_runtime(&[("dbfilename".into(), "a/b.rdb".into())])
+            .is_err());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let dir = std::env::temp_dir().join(format!("config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(&path, "# my config\nport 7000\n\ntimeout 10 \ntimeout 20\n").unwrap();
+
+        let mut config = Config::from_args(args(&[path.to_str().unwrap()])).unwrap();
+        assert_eq!(config.int("timeout"), 20);
+
+        config
+            .set_runtime(&[
+                ("timeout".into(), "30".into()),
+                ("maxmemory-policy".into(), "allkeys-lru".into()),
+            ])
+            .unwrap();
+        config.rewrite().unwrap();
+        config.rewrite().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# my config\nport 7000\n\ntimeout 30\n# Generated by CONFIG REWRITE\nmaxmemory-policy allkeys-lru\n"
+        );
+
+        fs::write(&path, "port 7000\nbogus yes\n").unwrap();
+        assert!(Config::from_args(args(&[path.to_str().unwrap()]))
+            .unwrap_err()
+            .contains("at line 2"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(Config::new().rewrite().is_err());
+    }
+}
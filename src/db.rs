@@ -0,0 +1,538 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::eviction::{lfu_decayed, lfu_log_incr, LFU_INIT_VAL};
+use crate::server::random_u64;
+
+/// The kinds of values that can be stored under a key
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    String(String),
+}
+
+impl Value {
+    /// Name of the value kind as reported by `TYPE`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+        }
+    }
+
+    /// Approximate number of bytes held by the value
+    pub fn approx_size(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct StoredValue {
+    pub value: Value,
+    pub px: Option<Instant>,
+    /// Unix time in milliseconds of the last access
+    pub lru: i64,
+    /// Logarithmic access frequency counter
+    pub lfu: u8,
+}
+
+impl StoredValue {
+    pub fn new(value: Value, px: Option<Instant>) -> StoredValue {
+        Self {
+            value,
+            px,
+            lru: unix_time_ms(),
+            lfu: LFU_INIT_VAL,
+        }
+    }
+
+    /// Record an access for the LRU and LFU eviction policies
+    pub fn touch(&mut self) {
+        self.lfu = lfu_log_incr(lfu_decayed(self));
+        self.lru = unix_time_ms();
+    }
+}
+
+/// Rough fixed cost of a key in the map and indexes on top of the key and value
+const ENTRY_OVERHEAD: usize = 96;
+
+fn entry_size(key: &str, value: &StoredValue) -> usize {
+    ENTRY_OVERHEAD + key.len() + value.value.approx_size()
+}
+
+/// Milliseconds since the unix epoch
+pub fn unix_time_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// Convert a unix timestamp in milliseconds to an [`Instant`], `None` if it is not in the future
+pub fn instant_from_unix_ms(ms: i64) -> Option<Instant> {
+    let remaining = ms.checked_sub(unix_time_ms())?;
+
+    match remaining > 0 {
+        true => Some(Instant::now() + Duration::from_millis(remaining as u64)),
+        false => None,
+    }
+}
+
+/// Convert an [`Instant`] to a unix timestamp in milliseconds
+pub fn unix_ms_from_instant(instant: Instant) -> i64 {
+    let now = Instant::now();
+
+    match instant.checked_duration_since(now) {
+        Some(d) => unix_time_ms() + d.as_millis() as i64,
+        None => unix_time_ms() - now.duration_since(instant).as_millis() as i64,
+    }
+}
+
+/// Position of `key` in the `SCAN` order
+///
+/// Uses a fixed hasher so the order is stable no matter how the keyspace grows or shrinks
+fn scan_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Keys sampled per round of the active expire cycle
+const ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP: usize = 20;
+
+/// Keep sampling while more than this percentage of the sampled keys were expired
+const ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE: usize = 25;
+
+/// A set of keys that can be sampled at random
+#[derive(Default)]
+struct SampleSet {
+    keys: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl SampleSet {
+    fn insert(&mut self, key: &str) {
+        if self.positions.contains_key(key) {
+            return;
+        }
+
+        self.positions.insert(key.to_string(), self.keys.len());
+        self.keys.push(key.to_string());
+    }
+
+    fn remove(&mut self, key: &str) {
+        let pos = match self.positions.remove(key) {
+            Some(pos) => pos,
+            None => return,
+        };
+
+        self.keys.swap_remove(pos);
+        if let Some(moved) = self.keys.get(pos) {
+            self.positions.insert(moved.clone(), pos);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn random(&self) -> Option<&String> {
+        match self.keys.len() {
+            0 => None,
+            n => self.keys.get(random_u64() as usize % n),
+        }
+    }
+}
+
+fn is_expired(value: &StoredValue, now: Instant) -> bool {
+    value.px.is_some_and(|px| px <= now)
+}
+
+/// A keyspace
+///
+/// Wraps the key-value map and keeps the indexes needed to iterate it in sync.
+/// Expired keys are removed when accessed and by [`Db::active_expire_cycle`], until then
+/// they are invisible to every read.
+#[derive(Default)]
+pub struct Db {
+    entries: HashMap<String, StoredValue>,
+    /// Keys ordered by [`scan_hash`], cursors are positions in this order
+    scan_index: BTreeSet<(u64, String)>,
+    all: SampleSet,
+    /// Keys with an expire time set
+    volatile: SampleSet,
+    /// Approximate bytes held by the keys and values
+    used_memory: usize,
+    /// Keys written, deleted or expired since the last [`Db::take_modified`]
+    modified: Vec<String>,
+    /// Keys removed because their ttl passed since the last [`Db::take_expired`]
+    expired: Vec<String>,
+}
+
+impl Db {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove `key` if its ttl has passed, returns true if it was removed
+    fn expire_if_needed(&mut self, key: &str) -> bool {
+        match self.entries.get(key) {
+            Some(v) if is_expired(v, Instant::now()) => {
+                self.remove_entry(key);
+                self.expired.push(key.to_string());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Look up `key`, counting as an access for eviction
+    pub fn get(&mut self, key: &str) -> Option<&StoredValue> {
+        self.expire_if_needed(key);
+
+        let value = self.entries.get_mut(key)?;
+        value.touch();
+
+        Some(value)
+    }
+
+    /// Set or clear the expire time of `key`, returns false if it doesn't exist
+    pub fn set_expire(&mut self, key: &str, px: Option<Instant>) -> bool {
+        self.expire_if_needed(key);
+
+        match self.entries.get_mut(key) {
+            Some(v) => {
+                v.px = px;
+                self.modified.push(key.to_string());
+                match px {
+                    Some(_) => self.volatile.insert(key),
+                    None => self.volatile.remove(key),
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains_key(&mut self, key: &str) -> bool {
+        self.expire_if_needed(key);
+        self.entries.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: String, value: StoredValue) -> Option<StoredValue> {
+        if !self.entries.contains_key(&key) {
+            self.scan_index.insert((scan_hash(&key), key.clone()));
+            self.all.insert(&key);
+        }
+
+        match value.px {
+            Some(_) => self.volatile.insert(&key),
+            None => self.volatile.remove(&key),
+        }
+
+        if let Some(old) = self.entries.get(&key) {
+            self.used_memory -= entry_size(&key, old);
+        }
+        self.used_memory += entry_size(&key, &value);
+        self.modified.push(key.clone());
+        self.entries.insert(key, value)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<StoredValue> {
+        let removed = self.entries.remove(key)?;
+        self.scan_index.remove(&(scan_hash(key), key.to_string()));
+        self.all.remove(key);
+        self.volatile.remove(key);
+        self.used_memory -= entry_size(key, &removed);
+        self.modified.push(key.to_string());
+
+        Some(removed)
+    }
+
+    /// Keys modified since the last call, in order and possibly repeated
+    pub fn take_modified(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.modified)
+    }
+
+    /// Keys expired lazily or by [`Db::active_expire_cycle`] since the last call
+    pub fn take_expired(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.expired)
+    }
+
+    /// Remove `key`, an already expired key counts as missing
+    pub fn remove(&mut self, key: &str) -> Option<StoredValue> {
+        match self.expire_if_needed(key) {
+            true => None,
+            false => self.remove_entry(key),
+        }
+    }
+
+    /// Number of keys, including expired ones not yet removed
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Approximate bytes held by the keys and values
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// Up to `count` random keys, only from those with an expire time if `volatile_only`
+    ///
+    /// The same key may come up more than once
+    pub fn sample(&self, volatile_only: bool, count: usize) -> Vec<(&String, &StoredValue)> {
+        let set = match volatile_only {
+            true => &self.volatile,
+            false => &self.all,
+        };
+
+        (0..count.min(set.len()))
+            .filter_map(|_| set.random())
+            .filter_map(|k| self.entries.get_key_value(k))
+            .collect()
+    }
+
+    /// Number of keys with an expire time
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    /// Average remaining ttl of the keys with an expire time, in milliseconds
+    pub fn avg_ttl_ms(&self) -> u64 {
+        if self.volatile.len() == 0 {
+            return 0;
+        }
+
+        let now = Instant::now();
+        let total: u128 = self
+            .volatile
+            .keys
+            .iter()
+            .filter_map(|k| self.entries.get(k)?.px)
+            .map(|px| px.saturating_duration_since(now).as_millis())
+            .sum();
+
+        (total / self.volatile.len() as u128) as u64
+    }
+
+    /// Keys that haven't expired yet
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        let now = Instant::now();
+
+        self.entries
+            .iter()
+            .filter(move |(_, v)| !is_expired(v, now))
+            .map(|(k, _)| k)
+    }
+
+    /// A random key that hasn't expired, expired keys hit along the way are removed
+    pub fn random_key(&mut self) -> Option<String> {
+        // give up on a keyspace made up of expired keys rather than looping forever
+        for _ in 0..100 {
+            let key = self.all.random()?.clone();
+
+            if !self.expire_if_needed(&key) {
+                return Some(key);
+            }
+        }
+
+        None
+    }
+
+    /// Visit roughly `count` keys starting from `cursor`
+    ///
+    /// Returns the cursor to continue from, `0` once the iteration is complete.
+    /// Every key present for the whole iteration is returned exactly once, keys added or
+    /// removed in between may or may not be.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let now = Instant::now();
+        let mut keys = Vec::with_capacity(count);
+        let mut it = self.scan_index.range((cursor, String::new())..).peekable();
+
+        while let Some((hash, key)) = it.next() {
+            if self.entries.get(key).is_some_and(|v| !is_expired(v, now)) {
+                keys.push(key.clone());
+            }
+
+            // never split keys sharing a hash across calls, the cursor can't tell them apart
+            let next_hash = match it.peek() {
+                Some((next_hash, _)) => *next_hash,
+                None => break,
+            };
+
+            if keys.len() >= count && next_hash != *hash {
+                return (next_hash, keys);
+            }
+        }
+
+        (0, keys)
+    }
+
+    /// Remove expired keys by sampling the keys with a ttl
+    ///
+    /// Samples batches of keys and keeps going as long as a good share of each batch turned
+    /// out to be expired, so the work scales with the number of expiring keys rather than the
+    /// size of the keyspace. Stops at `deadline` and returns the number of removed keys.
+    pub fn active_expire_cycle(&mut self, deadline: Instant) -> usize {
+        let mut removed = 0;
+        let mut iteration = 0;
+
+        loop {
+            let now = Instant::now();
+            let sample_size = ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP.min(self.volatile.len());
+            if sample_size == 0 {
+                break;
+            }
+
+            let mut expired = 0;
+            for _ in 0..sample_size {
+                let key = match self.volatile.random() {
+                    Some(k) => k.clone(),
+                    None => break,
+                };
+
+                if self.entries.get(&key).is_some_and(|v| is_expired(v, now)) {
+                    self.remove_entry(&key);
+                    self.expired.push(key);
+                    removed += 1;
+                    expired += 1;
+                }
+            }
+
+            // checking the clock is not free, only do it every few rounds
+            iteration += 1;
+            if iteration % 16 == 0 && Instant::now() >= deadline {
+                break;
+            }
+
+            if expired * 100 <= sample_size * ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE {
+                break;
+            }
+        }
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value() -> StoredValue {
+        StoredValue::new(Value::String("v".into()), None)
+    }
+
+    #[test]
+    fn test_scan_visits_every_key() {
+        let mut db = Db::new();
+        for i in 0..100 {
+            db.insert(format!("key:{}", i), value());
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = db.scan(cursor, 7);
+            seen.extend(keys);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 100);
+    }
+
+    #[test]
+    fn test_scan_while_keyspace_changes() {
+        let mut db = Db::new();
+        for i in 0..50 {
+            db.insert(format!("stable:{}", i), value());
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        let mut round = 0;
+        loop {
+            let (next, keys) = db.scan(cursor, 5);
+            seen.extend(keys);
+
+            // grow and shrink the keyspace between calls
+            for i in 0..20 {
+                db.insert(format!("volatile:{}:{}", round, i), value());
+            }
+            if round > 0 {
+                for i in 0..20 {
+                    db.remove(&format!("volatile:{}:{}", round - 1, i));
+                }
+            }
+            round += 1;
+
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        for i in 0..50 {
+            assert!(seen.contains(&format!("stable:{}", i)));
+        }
+    }
+
+    #[test]
+    fn test_used_memory_after_overwrite() {
+        let mut db = Db::new();
+        let before = db.used_memory();
+        for _ in 0..20 {
+            db.insert("k".repeat(200), value());
+        }
+        db.remove(&"k".repeat(200));
+
+        assert_eq!(db.used_memory(), before);
+    }
+
+    #[test]
+    fn test_lazy_expire() {
+        let mut db = Db::new();
+        let past = Instant::now() - Duration::from_millis(1);
+        db.insert(
+            "gone".into(),
+            StoredValue::new(Value::String("v".into()), Some(past)),
+        );
+
+        assert_eq!(db.keys().count(), 0);
+        assert!(db.get("gone").is_none());
+        assert_eq!(db.len(), 0);
+        assert_eq!(db.volatile_len(), 0);
+    }
+
+    #[test]
+    fn test_active_expire_cycle() {
+        let mut db = Db::new();
+        let past = Instant::now() - Duration::from_millis(1);
+        let future = Instant::now() + Duration::from_secs(100);
+
+        for i in 0..200 {
+            let px = if i % 2 == 0 { past } else { future };
+            db.insert(
+                format!("volatile:{}", i),
+                StoredValue::new(Value::String("v".into()), Some(px)),
+            );
+        }
+        db.insert("persistent".into(), value());
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut removed = 0;
+        while db.volatile_len() > 100 {
+            removed += db.active_expire_cycle(deadline);
+        }
+
+        assert_eq!(removed, 100);
+        assert_eq!(db.len(), 101);
+        assert_eq!(db.take_expired().len(), 100);
+    }
+}
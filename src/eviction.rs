@@ -0,0 +1,237 @@
+//! maxmemory policies and the sampled eviction pool
+//!
+//! Like Redis, eviction is approximated: a few keys are sampled from every db and the best
+//! candidates are kept in a small pool ordered by how good a choice they are to evict.
+
+use std::fmt::Display;
+
+use crate::db::{unix_time_ms, Db, StoredValue};
+use crate::server::random_u64;
+
+/// Initial LFU counter of new keys, so they are not evicted before they get a chance
+pub const LFU_INIT_VAL: u8 = 5;
+
+/// Higher values make the logarithmic LFU counter saturate more slowly
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// Minutes for the LFU counter to decay by one
+const LFU_DECAY_TIME_MIN: i64 = 1;
+
+/// Number of candidates kept in the eviction pool
+const EVICTION_POOL_SIZE: usize = 16;
+
+/// Keys sampled per db when filling the eviction pool
+pub const MAXMEMORY_SAMPLES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysLfu,
+    VolatileLfu,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    pub fn from_str(value: &str) -> Option<MaxmemoryPolicy> {
+        let policy = match value.to_lowercase().as_str() {
+            "noeviction" => Self::NoEviction,
+            "allkeys-lru" => Self::AllKeysLru,
+            "volatile-lru" => Self::VolatileLru,
+            "allkeys-lfu" => Self::AllKeysLfu,
+            "volatile-lfu" => Self::VolatileLfu,
+            "allkeys-random" => Self::AllKeysRandom,
+            "volatile-random" => Self::VolatileRandom,
+            "volatile-ttl" => Self::VolatileTtl,
+            _ => return None,
+        };
+
+        Some(policy)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::VolatileLru => "volatile-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// Whether only keys with an expire time may be evicted
+    pub fn volatile_only(&self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileLfu | Self::VolatileRandom | Self::VolatileTtl
+        )
+    }
+
+    pub fn is_random(&self) -> bool {
+        matches!(self, Self::AllKeysRandom | Self::VolatileRandom)
+    }
+}
+
+impl Display for MaxmemoryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Parse a memory size like `100mb` or `1gb` into bytes
+pub fn parse_memory(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+
+    let unit = match &value[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn random_f64() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Logarithmically increment an LFU counter, the higher it is the less likely it grows
+pub fn lfu_log_incr(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+
+    let baseval = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let p = 1.0 / (baseval * LFU_LOG_FACTOR + 1.0);
+
+    match random_f64() < p {
+        true => counter + 1,
+        false => counter,
+    }
+}
+
+/// The LFU counter of `value` after decaying it for the time since its last access
+pub fn lfu_decayed(value: &StoredValue) -> u8 {
+    let elapsed_min = (unix_time_ms() - value.lru) / 60_000;
+    let periods = elapsed_min / LFU_DECAY_TIME_MIN;
+
+    value
+        .lfu
+        .saturating_sub(periods.clamp(0, u8::MAX as i64) as u8)
+}
+
+/// How good a candidate for eviction `value` is under `policy`, higher is better
+fn eviction_score(policy: MaxmemoryPolicy, value: &StoredValue) -> u64 {
+    match policy {
+        MaxmemoryPolicy::AllKeysLru | MaxmemoryPolicy::VolatileLru => {
+            (unix_time_ms() - value.lru).max(0) as u64
+        }
+        MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu => {
+            (u8::MAX - lfu_decayed(value)) as u64
+        }
+        // the sooner it expires the better
+        MaxmemoryPolicy::VolatileTtl => match value.px {
+            Some(px) => u64::MAX - crate::db::unix_ms_from_instant(px).max(0) as u64,
+            None => 0,
+        },
+        MaxmemoryPolicy::NoEviction
+        | MaxmemoryPolicy::AllKeysRandom
+        | MaxmemoryPolicy::VolatileRandom => 0,
+    }
+}
+
+struct PoolEntry {
+    score: u64,
+    db: usize,
+    key: String,
+}
+
+/// The best eviction candidates seen so far, ordered by ascending score
+#[derive(Default)]
+pub struct EvictionPool {
+    entries: Vec<PoolEntry>,
+}
+
+impl EvictionPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sample keys from `db` and keep the ones scoring better than the current candidates
+    pub fn populate(&mut self, db_index: usize, db: &Db, policy: MaxmemoryPolicy, samples: usize) {
+        for (key, value) in db.sample(policy.volatile_only(), samples) {
+            let score = eviction_score(policy, value);
+
+            // pool is full and this one is worse than all of them
+            if self.entries.len() >= EVICTION_POOL_SIZE && score <= self.entries[0].score {
+                continue;
+            }
+
+            if self
+                .entries
+                .iter()
+                .any(|e| e.db == db_index && e.key == *key)
+            {
+                continue;
+            }
+
+            let pos = self.entries.partition_point(|e| e.score < score);
+            self.entries.insert(
+                pos,
+                PoolEntry {
+                    score,
+                    db: db_index,
+                    key: key.clone(),
+                },
+            );
+
+            if self.entries.len() > EVICTION_POOL_SIZE {
+                self.entries.remove(0);
+            }
+        }
+    }
+
+    /// Take the best candidate out of the pool
+    pub fn pop(&mut self) -> Option<(usize, String)> {
+        self.entries.pop().map(|e| (e.db, e.key))
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("100"), Some(100));
+        assert_eq!(parse_memory("1kb"), Some(1024));
+        assert_eq!(parse_memory("2MB"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_memory("1g"), Some(1000 * 1000 * 1000));
+        assert_eq!(parse_memory("1tb"), None);
+        assert_eq!(parse_memory("mb"), None);
+    }
+
+    #[test]
+    fn test_lfu_counter_saturates() {
+        assert_eq!(lfu_log_incr(u8::MAX), u8::MAX);
+
+        // below the initial value incrementing is certain
+        assert_eq!(lfu_log_incr(0), 1);
+    }
+}
@@ -0,0 +1,590 @@
+//! Redis Functions, libraries of named Lua functions loaded with FUNCTION LOAD
+//!
+//! A library starts with a `#!lua name=<library>` line and registers its functions with
+//! `redis.register_function` when it runs. Lua values can't outlive the interpreter that
+//! made them, so only the code is kept: it is run once on load to find the functions
+//! and again on every FCALL to get at the one being called.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::glob::glob_match;
+use crate::log::{Level, Log};
+use crate::lua::{self, Block, Host, Interp, LuaError, LuaResult, Table, Value};
+use crate::resp::RespValue;
+use crate::scripting::{redis_base_lib, redis_lib, script_reply, string_array};
+use crate::sha1::sha1_hex;
+
+/// Flags `redis.register_function` accepts
+pub const FUNCTION_FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// How long a library may take to register its functions
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Global the functions registered so far are kept in, not a valid Lua name so the
+/// library can't touch it
+const REGISTRY: &str = "registered functions";
+
+/// First line of a FUNCTION DUMP payload
+const DUMP_HEADER: &str = "FUNCTIONS 1\n";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+impl FunctionInfo {
+    /// Functions flagged `no-writes` may run with FCALL_RO and on replicas
+    pub fn no_writes(&self) -> bool {
+        self.flags.iter().any(|f| f == "no-writes")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Library {
+    pub name: String,
+    pub code: String,
+    /// In the order they were registered
+    pub functions: Vec<FunctionInfo>,
+}
+
+/// Loaded libraries by name
+#[derive(Default, Clone)]
+pub struct Libraries {
+    libraries: BTreeMap<String, Library>,
+}
+
+impl Libraries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a library, one with the same name is only replaced if `replace` is set
+    ///
+    /// Function names are shared by all libraries.
+    pub fn add(&mut self, library: Library, replace: bool) -> Result<(), String> {
+        if !replace && self.libraries.contains_key(&library.name) {
+            return Err(format!("Library '{}' already exists", library.name));
+        }
+
+        for function in &library.functions {
+            if let Some((other, _)) = self.function(&function.name) {
+                if other.name != library.name {
+                    return Err(format!("Function {} already exists", function.name));
+                }
+            }
+        }
+
+        self.libraries.insert(library.name.clone(), library);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.libraries.remove(name).is_some()
+    }
+
+    pub fn flush(&mut self) {
+        self.libraries.clear();
+    }
+
+    /// Number of functions in all libraries
+    pub fn function_count(&self) -> usize {
+        self.libraries.values().map(|l| l.functions.len()).sum()
+    }
+
+    /// The library a function is in and the function
+    pub fn function(&self, name: &str) -> Option<(&Library, &FunctionInfo)> {
+        self.libraries.values().find_map(|library| {
+            library
+                .functions
+                .iter()
+                .find(|f| f.name == name)
+                .map(|f| (library, f))
+        })
+    }
+
+    /// Libraries whose name matches the glob style `pattern`, all of them without one
+    pub fn list<'a>(&'a self, pattern: Option<&'a str>) -> impl Iterator<Item = &'a Library> {
+        self.libraries
+            .values()
+            .filter(move |library| match pattern {
+                Some(pattern) => glob_match(pattern, &library.name, false),
+                None => true,
+            })
+    }
+
+    /// Payload FUNCTION RESTORE takes back, the code of every library with a checksum
+    pub fn dump(&self) -> String {
+        let mut payload = DUMP_HEADER.to_string();
+        for library in self.libraries.values() {
+            payload.push_str(&format!("{}\n{}", library.code.len(), library.code));
+        }
+        let checksum = sha1_hex(payload.as_bytes());
+        payload + &checksum
+    }
+}
+
+/// Library code in a FUNCTION DUMP payload, None if it is damaged
+pub fn parse_dump(payload: &str) -> Option<Vec<String>> {
+    let split = payload.len().checked_sub(40)?;
+    let (data, checksum) = (payload.get(..split)?, payload.get(split..)?);
+    if sha1_hex(data.as_bytes()) != checksum {
+        return None;
+    }
+
+    let mut rest = data.strip_prefix(DUMP_HEADER)?;
+    let mut codes = Vec::new();
+    while !rest.is_empty() {
+        let (len, after) = rest.split_once('\n')?;
+        let len: usize = len.parse().ok()?;
+        codes.push(after.get(..len)?.to_string());
+        rest = after.get(len..)?;
+    }
+    Some(codes)
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Name of the library from the `#!lua name=<library>` line and the code after it
+///
+/// The first line is blanked rather than removed to keep line numbers right.
+fn parse_metadata(code: &str) -> Result<(String, String), String> {
+    let shebang = match code.strip_prefix("#!") {
+        Some(rest) => rest.lines().next().unwrap_or_default(),
+        None => return Err("Missing library metadata".into()),
+    };
+
+    let mut parts = shebang.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("Engine '{}' not found", engine));
+    }
+
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(format!("Invalid metadata value given: {}", part)),
+        }
+    }
+
+    let name = name.ok_or_else(|| "Library name was not given".to_string())?;
+    if !valid_name(&name) {
+        return Err("Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".into());
+    }
+
+    let body = &code[2 + shebang.len()..];
+    Ok((name, body.to_string()))
+}
+
+/// Check a library and find out which functions it registers
+///
+/// Errors are messages for an ERR reply.
+pub fn load_library(code: &str, log: &Log) -> Result<Library, String> {
+    let (name, body) = parse_metadata(code)?;
+    let block = compile(&body)?;
+
+    let mut host = LoadHost {
+        started: Instant::now(),
+        log,
+    };
+    let mut interp = Interp::new(&mut host, library_globals());
+    interp.chunk = "user_function";
+    let functions = register_functions(&mut interp, block)?
+        .into_iter()
+        .map(|(info, _)| info)
+        .collect();
+
+    Ok(Library {
+        name,
+        code: code.to_string(),
+        functions,
+    })
+}
+
+/// Run `function` from `library` with the given keys and arguments
+pub fn call_function(
+    host: &mut dyn Host,
+    library: &Library,
+    function: &str,
+    keys: Vec<String>,
+    args: Vec<String>,
+) -> RespValue {
+    let block = match parse_metadata(&library.code).and_then(|(_, body)| compile(&body)) {
+        Ok(block) => block,
+        Err(e) => return RespValue::SimpleError(format!("ERR {}", e)),
+    };
+
+    let globals = library_globals();
+    let mut interp = Interp::new(host, globals.clone());
+    interp.chunk = "user_function";
+    let functions = match register_functions(&mut interp, block) {
+        Ok(functions) => functions,
+        Err(e) => return RespValue::SimpleError(format!("ERR {}", e)),
+    };
+    let callback = match functions
+        .into_iter()
+        .find(|(info, _)| info.name == function)
+    {
+        Some((_, callback)) => callback,
+        None => return RespValue::SimpleError("ERR Function not found".into()),
+    };
+
+    // the rest of the redis library is only there once the library has loaded
+    globals.borrow_mut().set_str("redis", redis_lib());
+    lua::reset_random();
+
+    let result = interp.call(&callback, vec![string_array(keys), string_array(args)]);
+    script_reply(result, function, "user_function")
+}
+
+fn compile(body: &str) -> Result<Block, String> {
+    lua::parse(body).map_err(|e| {
+        format!(
+            "Error compiling function: user_function:{}: {}",
+            e.line, e.msg
+        )
+    })
+}
+
+/// Globals a library runs with, the `redis` library can only register functions and log
+fn library_globals() -> Rc<RefCell<Table>> {
+    let globals = Rc::new(RefCell::new(Table::new()));
+    {
+        let mut globals = globals.borrow_mut();
+        lua::open_libs(&mut globals);
+
+        let mut redis = redis_base_lib();
+        redis.set_str("register_function", Value::builtin(register_function));
+        globals.set_str("redis", Value::table(redis));
+        globals.set_str(REGISTRY, Value::table(Table::new()));
+    }
+    globals
+}
+
+/// Run a library, returns the functions it registered with their callbacks
+fn register_functions(
+    interp: &mut Interp<'_>,
+    block: Block,
+) -> Result<Vec<(FunctionInfo, Value)>, String> {
+    match interp.run_chunk(block, Vec::new()) {
+        Ok(_) => {}
+        Err(LuaError::Error { value, .. }) => {
+            return Err(format!(
+                "Error registering functions: {}",
+                value.to_display()
+            ))
+        }
+        Err(LuaError::Killed) => return Err("FUNCTION LOAD timeout".into()),
+    }
+
+    let registry = match interp.globals.borrow().get_str(REGISTRY) {
+        Value::Table(t) => t,
+        _ => return Err("No functions registered".into()),
+    };
+    let registry = registry.borrow();
+
+    let functions: Vec<_> = (1..=registry.len())
+        .filter_map(|i| match registry.get(&Value::Number(i as f64)) {
+            Value::Table(entry) => Some(entry),
+            _ => None,
+        })
+        .map(|entry| {
+            let entry = entry.borrow();
+            let flags = match entry.get_str("flags") {
+                Value::Table(flags) => {
+                    let flags = flags.borrow();
+                    (1..=flags.len())
+                        .map(|i| flags.get(&Value::Number(i as f64)).to_display())
+                        .collect()
+                }
+                _ => Vec::new(),
+            };
+            let info = FunctionInfo {
+                name: entry.get_str("name").to_display(),
+                description: entry.get_str("description").to_str().map(|s| s.to_string()),
+                flags,
+            };
+            (info, entry.get_str("callback"))
+        })
+        .collect();
+
+    if functions.is_empty() {
+        return Err("No functions registered".into());
+    }
+    Ok(functions)
+}
+
+/// `redis.register_function(name, callback)` or
+/// `redis.register_function{function_name=..., callback=..., flags={...}, description=...}`
+fn register_function(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let (name, callback, flags, description) = match args.as_slice() {
+        [Value::Table(t)] => {
+            let t = t.borrow();
+            let mut key = Value::Nil;
+            while let Some((k, _)) = t.next(&key).map_err(|e| interp.error(e))? {
+                match k.to_str().as_deref() {
+                    Some("function_name" | "callback" | "flags" | "description") => {}
+                    _ => {
+                        return Err(
+                            interp.error("unknown argument given to redis.register_function")
+                        )
+                    }
+                }
+                key = k;
+            }
+            (
+                t.get_str("function_name"),
+                t.get_str("callback"),
+                t.get_str("flags"),
+                t.get_str("description"),
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => return Err(interp.error("wrong number of arguments to redis.register_function")),
+    };
+
+    let name = match name {
+        Value::Str(name) if valid_name(&name) => name,
+        Value::Str(_) => return Err(interp.error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long")),
+        _ => return Err(interp.error("function_name argument given to redis.register_function must be a string")),
+    };
+    if !matches!(callback, Value::Function(_)) {
+        return Err(
+            interp.error("callback argument given to redis.register_function must be a function")
+        );
+    }
+
+    let flags = match flags {
+        Value::Nil => Table::new(),
+        Value::Table(flags) => {
+            let flags = flags.borrow();
+            let mut checked = Vec::new();
+            for i in 1..=flags.len() {
+                match flags.get(&Value::Number(i as f64)) {
+                    Value::Str(flag) if FUNCTION_FLAGS.contains(&&*flag) => {
+                        checked.push(Value::Str(flag))
+                    }
+                    _ => return Err(interp.error("unknown flag given")),
+                }
+            }
+            Table::from_array(checked)
+        }
+        _ => return Err(interp.error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    };
+    let description = match description {
+        Value::Nil | Value::Str(_) => description,
+        _ => {
+            return Err(interp
+                .error("description argument given to redis.register_function must be a string"))
+        }
+    };
+
+    let registry = match interp.globals.borrow().get_str(REGISTRY) {
+        Value::Table(t) => t,
+        _ => {
+            return Err(
+                interp.error("redis.register_function can only be called on FUNCTION LOAD command")
+            )
+        }
+    };
+    let mut registry = registry.borrow_mut();
+    let exists = (1..=registry.len()).any(|i| match registry.get(&Value::Number(i as f64)) {
+        Value::Table(entry) => entry
+            .borrow()
+            .get_str("name")
+            .raw_eq(&Value::Str(name.clone())),
+        _ => false,
+    });
+    if exists {
+        return Err(interp.error("Function already exists in the library"));
+    }
+
+    let mut entry = Table::new();
+    entry.set_str("name", Value::Str(name));
+    entry.set_str("callback", callback);
+    entry.set_str("flags", Value::table(flags));
+    entry.set_str("description", description);
+    let next = Value::Number((registry.len() + 1) as f64);
+    registry
+        .set(next, Value::table(entry))
+        .map_err(|e| interp.error(e))?;
+
+    Ok(Vec::new())
+}
+
+/// Host for loading a library, which can't run commands and is stopped past the timeout
+struct LoadHost<'a> {
+    started: Instant,
+    log: &'a Log,
+}
+
+impl Host for LoadHost<'_> {
+    fn call(&mut self, _args: Vec<String>) -> RespValue {
+        RespValue::SimpleError("ERR commands are not allowed while loading a library".into())
+    }
+
+    fn log(&mut self, level: Level, msg: &str) {
+        self.log.log(level, msg);
+    }
+
+    fn keep_running(&mut self) -> bool {
+        self.started.elapsed() < LOAD_TIMEOUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoHost;
+
+    impl Host for NoHost {
+        fn call(&mut self, args: Vec<String>) -> RespValue {
+            RespValue::Array(args.into_iter().map(RespValue::BulkString).collect())
+        }
+
+        fn log(&mut self, _level: Level, _msg: &str) {}
+
+        fn keep_running(&mut self) -> bool {
+            true
+        }
+    }
+
+    fn load(code: &str) -> Result<Library, String> {
+        let log = Log {
+            level: Level::Nothing,
+            file: String::new(),
+        };
+        load_library(code, &log)
+    }
+
+    #[test]
+    fn test_load() {
+        let library = load(
+            "#!lua name=mylib\n\
+             redis.register_function('first', function(keys, args) return keys[1] end)\n\
+             redis.register_function{function_name='second', callback=function() end, flags={'no-writes'}}",
+        )
+        .unwrap();
+
+        assert_eq!(library.name, "mylib");
+        assert_eq!(
+            library.functions,
+            vec![
+                FunctionInfo {
+                    name: "first".into(),
+                    description: None,
+                    flags: Vec::new(),
+                },
+                FunctionInfo {
+                    name: "second".into(),
+                    description: None,
+                    flags: vec!["no-writes".into()],
+                },
+            ]
+        );
+        assert!(library.functions[1].no_writes());
+
+        for (code, err) in [
+            ("return 1", "Missing library metadata"),
+            ("#!js name=lib\n", "Engine 'js' not found"),
+            ("#!lua\n", "Library name was not given"),
+            ("#!lua name=lib\nreturn 1", "No functions registered"),
+        ] {
+            assert_eq!(load(code).unwrap_err(), err);
+        }
+
+        let err = load(
+            "#!lua name=lib\n\nredis.register_function{function_name='f', callback=function() end, flags={'bad'}}",
+        )
+        .unwrap_err();
+        assert!(
+            err.ends_with("user_function:3: unknown flag given"),
+            "{}",
+            err
+        );
+
+        let err = load("#!lua name=lib\nredis.call('ping')").unwrap_err();
+        assert!(err.starts_with("Error registering functions"), "{}", err);
+    }
+
+    #[test]
+    fn test_call_function() {
+        let library = load(
+            "#!lua name=mylib\n\
+             local function echo(keys, args) return redis.call('echo', keys[1], args[1]) end\n\
+             redis.register_function('echo', echo)\n\
+             redis.register_function('fail', function() error('oops') end)",
+        )
+        .unwrap();
+
+        assert_eq!(
+            call_function(
+                &mut NoHost,
+                &library,
+                "echo",
+                vec!["k".into()],
+                vec!["a".into()]
+            ),
+            RespValue::Array(vec![
+                RespValue::BulkString("echo".into()),
+                RespValue::BulkString("k".into()),
+                RespValue::BulkString("a".into()),
+            ])
+        );
+        assert_eq!(
+            call_function(&mut NoHost, &library, "fail", Vec::new(), Vec::new()),
+            RespValue::SimpleError(
+                "ERR user_function:4: oops script: fail, on @user_function:4.".into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_libraries() {
+        let mut libraries = Libraries::new();
+        let first = load("#!lua name=first\nredis.register_function('f', function() end)");
+        libraries.add(first.clone().unwrap(), false).unwrap();
+
+        assert_eq!(
+            libraries.add(first.clone().unwrap(), false).unwrap_err(),
+            "Library 'first' already exists"
+        );
+        assert!(libraries.add(first.unwrap(), true).is_ok());
+
+        let second = load("#!lua name=second\nredis.register_function('f', function() end)");
+        assert_eq!(
+            libraries.add(second.unwrap(), false).unwrap_err(),
+            "Function f already exists"
+        );
+        assert_eq!(libraries.function("f").unwrap().0.name, "first");
+
+        let payload = libraries.dump();
+        let codes = parse_dump(&payload).unwrap();
+        assert_eq!(
+            codes,
+            vec!["#!lua name=first\nredis.register_function('f', function() end)"]
+        );
+        assert!(parse_dump(&payload.replace("first", "frist")).is_none());
+
+        assert_eq!(libraries.list(Some("f*")).count(), 1);
+        assert_eq!(libraries.list(Some("s*")).count(), 0);
+        assert!(libraries.remove("first"));
+        assert!(libraries.function("f").is_none());
+    }
+}
@@ -0,0 +1,168 @@
+//! Redis compatible glob-style pattern matching
+//!
+//! Supports `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape the next character.
+//! Used by `KEYS`, `SCAN ... MATCH` and pattern subscriptions.
+
+/// Match `string` against the glob `pattern`
+pub fn glob_match(pattern: &str, string: &str, nocase: bool) -> bool {
+    let p = pattern.as_bytes();
+    let s = string.as_bytes();
+
+    let mut pi = 0;
+    let mut si = 0;
+    // position in pattern right after the last `*` and the string position it is matched from
+    let mut star: Option<(usize, usize)> = None;
+
+    while si < s.len() {
+        if pi < p.len() {
+            if p[pi] == b'*' {
+                while pi < p.len() && p[pi] == b'*' {
+                    pi += 1;
+                }
+
+                // trailing star matches the rest of the string
+                if pi == p.len() {
+                    return true;
+                }
+
+                star = Some((pi, si));
+                continue;
+            }
+
+            if let Some(next) = match_single(p, pi, s[si], nocase) {
+                pi = next;
+                si += 1;
+                continue;
+            }
+        }
+
+        // mismatch, let the last star swallow one more character
+        match star {
+            Some((star_pi, star_si)) => {
+                pi = star_pi;
+                si = star_si + 1;
+                star = Some((star_pi, star_si + 1));
+            }
+            None => return false,
+        }
+    }
+
+    while pi < p.len() && p[pi] == b'*' {
+        pi += 1;
+    }
+
+    pi == p.len()
+}
+
+/// Whether `pattern` contains no special characters and can only match itself
+pub fn is_literal(pattern: &str) -> bool {
+    !pattern.contains(['*', '?', '[', '\\'])
+}
+
+fn eq(a: u8, b: u8, nocase: bool) -> bool {
+    if nocase {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        a == b
+    }
+}
+
+/// Match the single pattern token at `pi` against `c`
+///
+/// Returns the position of the next token on a match
+fn match_single(p: &[u8], pi: usize, c: u8, nocase: bool) -> Option<usize> {
+    match p[pi] {
+        b'?' => Some(pi + 1),
+        b'\\' if pi + 1 < p.len() => eq(p[pi + 1], c, nocase).then_some(pi + 2),
+        b'[' => match_class(p, pi + 1, c, nocase),
+        pc => eq(pc, c, nocase).then_some(pi + 1),
+    }
+}
+
+/// Match a `[...]` character class starting right after the `[`
+fn match_class(p: &[u8], mut pi: usize, c: u8, nocase: bool) -> Option<usize> {
+    let negate = pi < p.len() && p[pi] == b'^';
+    if negate {
+        pi += 1;
+    }
+
+    let c = if nocase { c.to_ascii_lowercase() } else { c };
+    let mut matched = false;
+
+    // an unterminated class ends with the pattern
+    while pi < p.len() {
+        match p[pi] {
+            b']' => {
+                pi += 1;
+                break;
+            }
+            b'\\' if pi + 1 < p.len() => {
+                matched |= eq(p[pi + 1], c, nocase);
+                pi += 2;
+            }
+            start if pi + 2 < p.len() && p[pi + 1] == b'-' => {
+                let mut start = start;
+                let mut end = p[pi + 2];
+
+                if start > end {
+                    std::mem::swap(&mut start, &mut end);
+                }
+
+                if nocase {
+                    start = start.to_ascii_lowercase();
+                    end = end.to_ascii_lowercase();
+                }
+
+                matched |= c >= start && c <= end;
+                pi += 3;
+            }
+            pc => {
+                matched |= eq(pc, c, nocase);
+                pi += 1;
+            }
+        }
+    }
+
+    (matched != negate).then_some(pi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(glob_match("*", "", false));
+        assert!(glob_match("*", "anything", false));
+        assert!(glob_match("h?llo", "hello", false));
+        assert!(!glob_match("h?llo", "hllo", false));
+        assert!(glob_match("h*llo", "heeeello", false));
+        assert!(glob_match("h*llo", "hllo", false));
+        assert!(glob_match("*a*b*", "xxaxxbxx", false));
+        assert!(!glob_match("*a*b", "xxaxxbxx", false));
+        assert!(glob_match("user:*:name", "user:1:2:name", false));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(glob_match("h[ae]llo", "hello", false));
+        assert!(glob_match("h[ae]llo", "hallo", false));
+        assert!(!glob_match("h[ae]llo", "hillo", false));
+        assert!(glob_match("h[^e]llo", "hallo", false));
+        assert!(!glob_match("h[^e]llo", "hello", false));
+        assert!(glob_match("h[a-b]llo", "hbllo", false));
+        assert!(glob_match("h[b-a]llo", "hallo", false));
+        assert!(!glob_match("h[a-b]llo", "hcllo", false));
+        assert!(glob_match("[\\]]", "]", false));
+    }
+
+    #[test]
+    fn test_escapes_and_case() {
+        assert!(glob_match("a\\*b", "a*b", false));
+        assert!(!glob_match("a\\*b", "axb", false));
+        assert!(glob_match("a\\", "a\\", false));
+        assert!(glob_match("HELLO", "hello", true));
+        assert!(glob_match("[A-Z]", "q", true));
+        assert!(!glob_match("HELLO", "hello", false));
+    }
+}
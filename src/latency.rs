@@ -0,0 +1,231 @@
+//! LATENCY, spikes over `latency-monitor-threshold` by event class and per command
+//! latency histograms
+//!
+//! Events are `command` and `fast-command` for commands by their `fast` flag and
+//! `expire-cycle` for the active expire cycle. There is no forking or AOF here, so
+//! `fork` and `aof-fsync` never happen.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::resp::RespValue;
+
+/// Samples kept per event
+const LATENCY_TS_LEN: usize = 160;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencySample {
+    /// Unix time in seconds
+    pub time: i64,
+    /// Milliseconds
+    pub latency: u64,
+}
+
+#[derive(Default)]
+pub struct LatencyEvent {
+    /// Oldest first, at most one per second
+    pub history: VecDeque<LatencySample>,
+    /// Highest latency ever seen
+    pub max: u64,
+}
+
+#[derive(Default)]
+pub struct LatencyMonitor {
+    events: BTreeMap<String, LatencyEvent>,
+}
+
+impl LatencyMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: &str, time: i64, latency: u64) {
+        let event = self.events.entry(event.to_string()).or_default();
+        event.max = event.max.max(latency);
+
+        // spikes within the same second are merged into the highest one
+        match event.history.back_mut() {
+            Some(last) if last.time == time => last.latency = last.latency.max(latency),
+            _ => {
+                event.history.push_back(LatencySample { time, latency });
+                if event.history.len() > LATENCY_TS_LEN {
+                    event.history.pop_front();
+                }
+            }
+        }
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = (&String, &LatencyEvent)> {
+        self.events.iter()
+    }
+
+    pub fn event(&self, name: &str) -> Option<&LatencyEvent> {
+        self.events.get(name)
+    }
+
+    /// Forget the given events, all of them when empty, returns how many were dropped
+    pub fn reset(&mut self, events: &[String]) -> usize {
+        if events.is_empty() {
+            let count = self.events.len();
+            self.events.clear();
+            return count;
+        }
+
+        events
+            .iter()
+            .filter(|e| self.events.remove(e.as_str()).is_some())
+            .count()
+    }
+
+    /// Human readable analysis of the events for LATENCY DOCTOR
+    pub fn doctor(&self, enabled: bool) -> String {
+        if self.events.is_empty() {
+            return if enabled {
+                "Dave, no latency spike was observed during the lifetime of this Redis instance, not in the slightest bit. I honestly think you ought to sleep tonight.\n".into()
+            } else {
+                "I'm sorry, Dave, I can't do that. Latency monitoring is disabled in this Redis instance. You may use \"CONFIG SET latency-monitor-threshold <milliseconds>.\" in order to enable it.\n".into()
+            };
+        }
+
+        let mut report = String::from(
+            "Dave, I have observed latency spikes in this Redis instance. You don't mind talking about it, do you Dave?\n\n",
+        );
+        let mut advices = Vec::new();
+
+        for (n, (name, event)) in self.events.iter().enumerate() {
+            let samples = event.history.len() as u64;
+            let avg = event.history.iter().map(|s| s.latency).sum::<u64>() / samples;
+            let deviation = event
+                .history
+                .iter()
+                .map(|s| s.latency.abs_diff(avg))
+                .sum::<u64>()
+                / samples;
+            let first = event.history.front().map(|s| s.time).unwrap_or_default();
+            let last = event.history.back().map(|s| s.time).unwrap_or_default();
+            let period = (last - first) as f64 / samples as f64;
+
+            report.push_str(&format!(
+                "{}. {}: {} latency spikes (average {}ms, mean deviation {}ms, period {:.2} sec). Worst all time event {}ms.\n",
+                n + 1,
+                name,
+                samples,
+                avg,
+                deviation,
+                period,
+                event.max
+            ));
+
+            let advice = match name.as_str() {
+                "command" | "fast-command" => "- Check your Slow Log to understand what are the commands you are running which are too slow to execute. Please check https://redis.io/commands/slowlog for more information.",
+                "expire-cycle" => "- Deleting, expiring or evicting (because of maxmemory policy) large objects is a blocking operation. If you have very large objects that are often deleted, expired, or evicted, try to fragment those objects into multiple smaller objects.",
+                _ => continue,
+            };
+            if !advices.contains(&advice) {
+                advices.push(advice);
+            }
+        }
+
+        report.push_str("\nI have a few advices for you:\n\n");
+        for advice in advices {
+            report.push_str(advice);
+            report.push('\n');
+        }
+        report
+    }
+}
+
+/// Call counts of one command by power of two microsecond buckets
+#[derive(Default, Debug, PartialEq)]
+pub struct Histogram {
+    pub calls: u64,
+    /// Upper bound of the bucket in microseconds, 1, 2, 4 and so on
+    buckets: BTreeMap<u64, u64>,
+}
+
+impl Histogram {
+    pub fn record(&mut self, usec: u64) {
+        self.calls += 1;
+        *self
+            .buckets
+            .entry(usec.max(1).next_power_of_two())
+            .or_default() += 1;
+    }
+
+    /// Reply to LATENCY HISTOGRAM, bucket counts are cumulative
+    pub fn reply(&self) -> RespValue {
+        let mut total = 0;
+        let buckets = self
+            .buckets
+            .iter()
+            .map(|(bucket, count)| {
+                total += count;
+                (
+                    RespValue::Integer(*bucket as i64),
+                    RespValue::Integer(total as i64),
+                )
+            })
+            .collect();
+
+        RespValue::Map(vec![
+            (
+                RespValue::BulkString("calls".into()),
+                RespValue::Integer(self.calls as i64),
+            ),
+            (
+                RespValue::BulkString("histogram_usec".into()),
+                RespValue::Map(buckets),
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut monitor = LatencyMonitor::new();
+        monitor.record("command", 10, 5);
+        monitor.record("command", 10, 3);
+        monitor.record("command", 11, 2);
+        monitor.record("expire-cycle", 11, 7);
+
+        let command = monitor.event("command").unwrap();
+        assert_eq!(command.max, 5);
+        assert_eq!(
+            command.history,
+            vec![
+                LatencySample {
+                    time: 10,
+                    latency: 5
+                },
+                LatencySample {
+                    time: 11,
+                    latency: 2
+                },
+            ]
+        );
+        assert!(monitor.doctor(true).contains(
+            "1. command: 2 latency spikes (average 3ms, mean deviation 1ms, period 0.50 sec). Worst all time event 5ms."
+        ));
+
+        assert_eq!(monitor.reset(&["command".into(), "nope".into()]), 1);
+        assert_eq!(monitor.reset(&[]), 1);
+        assert!(monitor.doctor(true).starts_with("Dave, no latency spike"));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        for usec in [0, 1, 3, 4, 100] {
+            histogram.record(usec);
+        }
+
+        assert_eq!(histogram.calls, 5);
+        assert_eq!(
+            histogram.buckets,
+            BTreeMap::from([(1, 2), (4, 2), (128, 1)])
+        );
+    }
+}
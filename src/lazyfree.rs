@@ -0,0 +1,50 @@
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use crate::db::{Db, StoredValue};
+
+/// Values bigger than this (in bytes) are freed on the background thread
+pub const LAZYFREE_THRESHOLD: usize = 64 * 1024;
+
+/// Frees large values off the request path
+///
+/// Values are sent over a channel to a background thread which simply drops them,
+/// the thread exits once the [`LazyFree`] is dropped
+pub struct LazyFree {
+    tx: Sender<Box<dyn Send>>,
+}
+
+impl LazyFree {
+    pub fn new() -> Self {
+        let (tx, rx) = channel::<Box<dyn Send>>();
+
+        thread::spawn(move || {
+            while let Ok(value) = rx.recv() {
+                drop(value);
+            }
+        });
+
+        Self { tx }
+    }
+
+    /// Free `value`, in the background if it is large enough to be worth it
+    pub fn free(&self, value: StoredValue) {
+        if value.value.approx_size() <= LAZYFREE_THRESHOLD {
+            return;
+        }
+
+        // if the background thread is gone the value is dropped here instead
+        let _ = self.tx.send(Box::new(value));
+    }
+
+    /// Free a whole db in the background
+    pub fn free_db(&self, db: Db) {
+        let _ = self.tx.send(Box::new(db));
+    }
+}
+
+impl Default for LazyFree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,167 @@
+//! The server log, written to `logfile` or stdout when it's empty, in the same line
+//! format as Redis
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+
+/// Names `loglevel` accepts, from the most to the least verbose
+pub const LOG_LEVELS: &[&str] = &["debug", "verbose", "notice", "warning", "nothing"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+    /// Only used as `loglevel`, to log nothing at all
+    Nothing,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        let level = match name {
+            "debug" => Level::Debug,
+            "verbose" => Level::Verbose,
+            "notice" => Level::Notice,
+            "warning" => Level::Warning,
+            "nothing" => Level::Nothing,
+            _ => return None,
+        };
+        Some(level)
+    }
+
+    /// Level of the `redis.LOG_*` constants scripts log with
+    pub fn from_script(level: i64) -> Option<Level> {
+        [Level::Debug, Level::Verbose, Level::Notice, Level::Warning]
+            .get(usize::try_from(level).ok()?)
+            .copied()
+    }
+
+    /// Character that tells the levels apart in log lines
+    fn marker(self) -> char {
+        match self {
+            Level::Debug => '.',
+            Level::Verbose => '-',
+            Level::Notice => '*',
+            Level::Warning | Level::Nothing => '#',
+        }
+    }
+}
+
+pub struct Log {
+    /// Lines below this level are left out
+    pub level: Level,
+    /// File lines are appended to, stdout when empty
+    pub file: String,
+}
+
+impl Log {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            level: Level::from_name(config.string("loglevel")).unwrap(),
+            file: config.string("logfile").to_string(),
+        }
+    }
+
+    pub fn log(&self, level: Level, msg: &str) {
+        if level < self.level {
+            return;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let line = format!(
+            "{}:M {} {} {}",
+            std::process::id(),
+            timestamp(now),
+            level.marker(),
+            msg
+        );
+
+        // like Redis the file is opened for every line, so it can be rotated underneath
+        if self.file.is_empty() {
+            println!("{}", line);
+        } else if let Ok(mut file) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)
+        {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+/// Time since the epoch as `18 Oct 2026 09:05:03.250`, in UTC since there is no time zone
+/// data to go by
+fn timestamp(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let time = secs % 86400;
+
+    // days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{} {} {} {:02}:{:02}:{:02}.{:03}",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        let at = |ms| timestamp(Duration::from_millis(ms));
+
+        assert_eq!(at(0), "1 Jan 1970 00:00:00.000");
+        assert_eq!(at(951868799999), "29 Feb 2000 23:59:59.999");
+        assert_eq!(at(1792314303250), "18 Oct 2026 09:05:03.250");
+    }
+
+    #[test]
+    fn test_levels() {
+        let path = std::env::temp_dir().join(format!("log-{}.log", std::process::id()));
+        let log = Log {
+            level: Level::Notice,
+            file: path.to_str().unwrap().to_string(),
+        };
+        log.log(Level::Verbose, "hidden");
+        log.log(Level::Notice, "shown");
+        log.log(Level::Warning, "warned");
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2, "{:?}", lines);
+        assert!(lines[0].starts_with(&format!("{}:M ", std::process::id())));
+        assert!(lines[0].ends_with(" * shown"));
+        assert!(lines[1].ends_with(" # warned"));
+        assert_eq!(Level::from_script(3), Some(Level::Warning));
+        assert_eq!(Level::from_script(4), None);
+        assert_eq!(Level::from_script(-1), None);
+    }
+}
@@ -0,0 +1,102 @@
+//! Syntax tree the parser produces and the interpreter walks
+
+use std::rc::Rc;
+
+pub type Name = Rc<str>;
+
+pub type Block = Vec<Stat>;
+
+#[derive(Debug)]
+pub struct Stat {
+    pub line: usize,
+    pub kind: StatKind,
+}
+
+#[derive(Debug)]
+pub enum StatKind {
+    Local(Vec<Name>, Vec<Expr>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Do(Block),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    /// Condition and block of the `if` and each `elseif`, then the `else` block
+    If(Vec<(Expr, Block)>, Option<Block>),
+    NumericFor {
+        var: Name,
+        start: Expr,
+        limit: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    GenericFor(Vec<Name>, Vec<Expr>, Block),
+    LocalFunction(Name, Rc<FuncBody>),
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug)]
+pub struct FuncBody {
+    pub params: Vec<Name>,
+    pub vararg: bool,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub enum TableField {
+    Positional(Expr),
+    Named(Expr, Expr),
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    Str(Rc<str>),
+    Vararg,
+    Function(Rc<FuncBody>),
+    Table(Vec<TableField>),
+    Name(Name),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, Name, Vec<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    /// Parentheses cut multiple results down to one
+    Paren(Box<Expr>),
+}
+
+impl Expr {
+    /// Calls and `...` can produce any number of values
+    pub fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..) | Expr::Vararg)
+    }
+}
@@ -0,0 +1,789 @@
+//! Tree walking interpreter
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::ast::{BinOp, Block, Expr, FuncBody, Name, Stat, StatKind, TableField, UnOp};
+use super::value::{Function, Table, TableRef, Value};
+use crate::log::Level;
+use crate::resp::RespValue;
+
+/// How scripts reach the server
+pub trait Host {
+    /// Run a command for `redis.call` and `redis.pcall`
+    fn call(&mut self, args: Vec<String>) -> RespValue;
+
+    /// Write a `redis.log` line to the server log
+    fn log(&mut self, level: Level, msg: &str);
+
+    /// Called every [`HOOK_STEPS`] statements and blocks, returning false aborts the script
+    fn keep_running(&mut self) -> bool;
+}
+
+/// Statements and blocks between calls to [`Host::keep_running`]
+pub const HOOK_STEPS: u64 = 1000;
+
+/// Longest string a script may build, the default proto-max-bulk-len
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// Nested calls allowed before giving up with a stack overflow
+const MAX_DEPTH: usize = 200;
+
+/// Native stack the interpreter may use, evaluating a Lua call takes several rust frames
+/// so this runs out before [`MAX_DEPTH`] in debug builds
+const MAX_STACK_BYTES: usize = 512 * 1024;
+
+/// Address of a local, to tell how much stack is in use
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+pub enum LuaError {
+    /// A raised error value and the line it was raised on
+    Error { value: Value, line: usize },
+    /// The host stopped the script, can't be caught by `pcall`
+    Killed,
+}
+
+pub type LuaResult<T> = Result<T, LuaError>;
+
+/// Local variables of a block, chained to the enclosing blocks
+pub struct Scope {
+    vars: RefCell<Vec<(Name, Value)>>,
+    parent: Option<Rc<Scope>>,
+    varargs: Option<Rc<Vec<Value>>>,
+}
+
+impl Scope {
+    pub fn root() -> Rc<Scope> {
+        Scope::child(None, None)
+    }
+
+    fn child(parent: Option<Rc<Scope>>, varargs: Option<Rc<Vec<Value>>>) -> Rc<Scope> {
+        Rc::new(Scope {
+            vars: RefCell::new(Vec::new()),
+            parent,
+            varargs,
+        })
+    }
+
+    fn declare(&self, name: Name, value: Value) {
+        self.vars.borrow_mut().push((name, value));
+    }
+
+    fn lookup(&self, name: &str) -> Option<Value> {
+        let mut scope = Some(self);
+        while let Some(s) = scope {
+            // later declarations shadow earlier ones in the same block
+            if let Some((_, v)) = s.vars.borrow().iter().rev().find(|(n, _)| &**n == name) {
+                return Some(v.clone());
+            }
+            scope = s.parent.as_deref();
+        }
+        None
+    }
+
+    fn assign(&self, name: &str, value: Value) -> bool {
+        let mut scope = Some(self);
+        while let Some(s) = scope {
+            if let Some((_, v)) = s
+                .vars
+                .borrow_mut()
+                .iter_mut()
+                .rev()
+                .find(|(n, _)| &**n == name)
+            {
+                *v = value;
+                return true;
+            }
+            scope = s.parent.as_deref();
+        }
+        false
+    }
+
+    fn varargs(&self) -> Rc<Vec<Value>> {
+        let mut scope = Some(self);
+        while let Some(s) = scope {
+            if let Some(varargs) = &s.varargs {
+                return varargs.clone();
+            }
+            scope = s.parent.as_deref();
+        }
+        Rc::new(Vec::new())
+    }
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+pub struct Interp<'h> {
+    pub host: &'h mut dyn Host,
+    /// Read only for scripts, assigning to an undeclared name is an error
+    pub globals: TableRef,
+    /// Line of the statement being run, for error messages
+    pub line: usize,
+    /// Name errors give the code, like `user_script` in `user_script:1: msg`
+    pub chunk: &'static str,
+    /// Steps since the last call to [`Host::keep_running`]
+    steps: u64,
+    depth: usize,
+    stack_base: usize,
+}
+
+impl<'h> Interp<'h> {
+    pub fn new(host: &'h mut dyn Host, globals: TableRef) -> Self {
+        Self {
+            host,
+            globals,
+            line: 0,
+            chunk: "user_script",
+            steps: 0,
+            depth: 0,
+            stack_base: stack_position(),
+        }
+    }
+
+    /// Runtime error at the current line
+    pub fn error(&self, msg: impl AsRef<str>) -> LuaError {
+        LuaError::Error {
+            value: Value::str(&format!("{}:{}: {}", self.chunk, self.line, msg.as_ref())),
+            line: self.line,
+        }
+    }
+
+    /// Run a parsed chunk as a vararg function
+    pub fn run_chunk(&mut self, block: Block, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+        let chunk = Value::Function(Rc::new(Function::Lua {
+            body: Rc::new(FuncBody {
+                params: Vec::new(),
+                vararg: true,
+                body: block,
+            }),
+            env: Scope::root(),
+        }));
+
+        self.call(&chunk, args)
+    }
+
+    pub fn call(&mut self, f: &Value, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+        let f = match f {
+            Value::Function(f) => f.clone(),
+            v => return Err(self.error(format!("attempt to call a {} value", v.type_name()))),
+        };
+
+        if self.depth >= MAX_DEPTH || self.stack_base.abs_diff(stack_position()) > MAX_STACK_BYTES {
+            return Err(self.error("stack overflow"));
+        }
+
+        self.depth += 1;
+        let line = self.line;
+
+        let result = match &*f {
+            Function::Builtin(builtin) => builtin(self, args),
+            Function::Lua { body, env } => {
+                let varargs = match body.vararg && args.len() > body.params.len() {
+                    true => args.split_off(body.params.len()),
+                    false => Vec::new(),
+                };
+                let scope = Scope::child(Some(env.clone()), body.vararg.then(|| Rc::new(varargs)));
+
+                let mut args = args.into_iter();
+                for param in body.params.iter() {
+                    scope.declare(param.clone(), args.next().unwrap_or_default());
+                }
+
+                match self.exec_block(&body.body, &scope) {
+                    Ok(Flow::Return(values)) => Ok(values),
+                    Ok(_) => Ok(Vec::new()),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+
+        self.depth -= 1;
+        self.line = line;
+        result
+    }
+
+    /// Count a step, every [`HOOK_STEPS`] the host gets to stop the script
+    fn tick(&mut self) -> LuaResult<()> {
+        self.steps += 1;
+        if self.steps == HOOK_STEPS {
+            self.steps = 0;
+            if !self.host.keep_running() {
+                return Err(LuaError::Killed);
+            }
+        }
+        Ok(())
+    }
+
+    fn exec_block(&mut self, block: &[Stat], scope: &Rc<Scope>) -> LuaResult<Flow> {
+        // counted too so empty loop bodies can be stopped
+        self.tick()?;
+
+        for stat in block {
+            match self.exec(stat, scope)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, stat: &Stat, scope: &Rc<Scope>) -> LuaResult<Flow> {
+        self.line = stat.line;
+
+        self.tick()?;
+
+        match &stat.kind {
+            StatKind::Local(names, exprs) => {
+                let mut values = self.eval_list(exprs, scope)?.into_iter();
+                for name in names {
+                    scope.declare(name.clone(), values.next().unwrap_or_default());
+                }
+            }
+            StatKind::Assign(targets, exprs) => {
+                let mut values = self.eval_list(exprs, scope)?.into_iter();
+                for target in targets {
+                    let value = values.next().unwrap_or_default();
+                    self.assign(target, value, scope)?;
+                }
+            }
+            StatKind::Call(expr) => {
+                self.eval_multi(expr, scope)?;
+            }
+            StatKind::Do(body) => {
+                return self.exec_block(body, &Scope::child(Some(scope.clone()), None));
+            }
+            StatKind::While(cond, body) => {
+                while self.eval(cond, scope)?.truthy() {
+                    match self.exec_block(body, &Scope::child(Some(scope.clone()), None))? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StatKind::Repeat(body, cond) => loop {
+                // the condition sees the locals of the body
+                let body_scope = Scope::child(Some(scope.clone()), None);
+                match self.exec_block(body, &body_scope)? {
+                    Flow::Break => break,
+                    Flow::Return(values) => return Ok(Flow::Return(values)),
+                    Flow::Normal => {}
+                }
+                if self.eval(cond, &body_scope)?.truthy() {
+                    break;
+                }
+            },
+            StatKind::If(branches, otherwise) => {
+                for (cond, body) in branches {
+                    if self.eval(cond, scope)?.truthy() {
+                        return self.exec_block(body, &Scope::child(Some(scope.clone()), None));
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.exec_block(body, &Scope::child(Some(scope.clone()), None));
+                }
+            }
+            StatKind::NumericFor {
+                var,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                let start = self.for_number(start, scope, "initial")?;
+                let limit = self.for_number(limit, scope, "limit")?;
+                let step = match step {
+                    Some(step) => self.for_number(step, scope, "step")?,
+                    None => 1.0,
+                };
+
+                let mut i = start;
+                while (step > 0.0 && i <= limit) || (step <= 0.0 && i >= limit) {
+                    let body_scope = Scope::child(Some(scope.clone()), None);
+                    body_scope.declare(var.clone(), Value::Number(i));
+                    match self.exec_block(body, &body_scope)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                    i += step;
+                }
+            }
+            StatKind::GenericFor(names, exprs, body) => {
+                let mut values = self.eval_list(exprs, scope)?.into_iter();
+                let f = values.next().unwrap_or_default();
+                let state = values.next().unwrap_or_default();
+                let mut control = values.next().unwrap_or_default();
+
+                loop {
+                    let results = self.call(&f, vec![state.clone(), control.clone()])?;
+                    let first = results.first().cloned().unwrap_or_default();
+                    if first.is_nil() {
+                        break;
+                    }
+                    control = first;
+
+                    let body_scope = Scope::child(Some(scope.clone()), None);
+                    let mut results = results.into_iter();
+                    for name in names {
+                        body_scope.declare(name.clone(), results.next().unwrap_or_default());
+                    }
+
+                    match self.exec_block(body, &body_scope)? {
+                        Flow::Break => break,
+                        Flow::Return(values) => return Ok(Flow::Return(values)),
+                        Flow::Normal => {}
+                    }
+                }
+            }
+            StatKind::LocalFunction(name, body) => {
+                // declared first so the function can call itself
+                scope.declare(name.clone(), Value::Nil);
+                let f = Value::Function(Rc::new(Function::Lua {
+                    body: body.clone(),
+                    env: scope.clone(),
+                }));
+                scope.assign(name, f);
+            }
+            StatKind::Return(exprs) => return Ok(Flow::Return(self.eval_list(exprs, scope)?)),
+            StatKind::Break => return Ok(Flow::Break),
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn for_number(&mut self, expr: &Expr, scope: &Rc<Scope>, what: &str) -> LuaResult<f64> {
+        match self.eval(expr, scope)?.to_number() {
+            Some(n) => Ok(n),
+            None => Err(self.error(format!("'for' {} value must be a number", what))),
+        }
+    }
+
+    fn assign(&mut self, target: &Expr, value: Value, scope: &Rc<Scope>) -> LuaResult<()> {
+        match target {
+            Expr::Name(name) => match scope.assign(name, value) {
+                true => Ok(()),
+                false => Err(self.error("Attempt to modify a readonly table")),
+            },
+            Expr::Index(obj, key) => {
+                let obj = self.eval(obj, scope)?;
+                let key = self.eval(key, scope)?;
+                self.set_index(&obj, key, value)
+            }
+            _ => Err(self.error("cannot assign to this expression")),
+        }
+    }
+
+    pub fn set_index(&mut self, obj: &Value, key: Value, value: Value) -> LuaResult<()> {
+        match obj {
+            Value::Table(t) => match t.borrow_mut().set(key, value) {
+                Ok(()) => Ok(()),
+                Err(e) => Err(self.error(e)),
+            },
+            v => Err(self.error(format!("attempt to index a {} value", v.type_name()))),
+        }
+    }
+
+    pub fn index(&mut self, obj: &Value, key: &Value) -> LuaResult<Value> {
+        match obj {
+            Value::Table(t) => Ok(t.borrow().get(key)),
+            // string methods like s:upper()
+            Value::Str(_) => match self.globals.borrow().get_str("string") {
+                Value::Table(string) => Ok(string.borrow().get(key)),
+                _ => Ok(Value::Nil),
+            },
+            v => Err(self.error(format!("attempt to index a {} value", v.type_name()))),
+        }
+    }
+
+    /// Evaluate expressions, only the last one may expand to several values
+    fn eval_list(&mut self, exprs: &[Expr], scope: &Rc<Scope>) -> LuaResult<Vec<Value>> {
+        let mut values = Vec::with_capacity(exprs.len());
+
+        for (i, expr) in exprs.iter().enumerate() {
+            match i == exprs.len() - 1 {
+                true => values.extend(self.eval_multi(expr, scope)?),
+                false => values.push(self.eval(expr, scope)?),
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Evaluate an expression keeping all the values a call or `...` produces
+    fn eval_multi(&mut self, expr: &Expr, scope: &Rc<Scope>) -> LuaResult<Vec<Value>> {
+        match expr {
+            Expr::Call(f, args) => {
+                let f = self.eval(f, scope)?;
+                let args = self.eval_list(args, scope)?;
+                self.call(&f, args)
+            }
+            Expr::Method(obj, name, args) => {
+                let obj = self.eval(obj, scope)?;
+                let f = self.index(&obj, &Value::Str(name.clone()))?;
+                let mut call_args = vec![obj];
+                call_args.extend(self.eval_list(args, scope)?);
+                self.call(&f, call_args)
+            }
+            Expr::Vararg => Ok(scope.varargs().to_vec()),
+            expr => Ok(vec![self.eval(expr, scope)?]),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, scope: &Rc<Scope>) -> LuaResult<Value> {
+        let value = match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Bool(true),
+            Expr::False => Value::Bool(false),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Function(body) => Value::Function(Rc::new(Function::Lua {
+                body: body.clone(),
+                env: scope.clone(),
+            })),
+            Expr::Table(fields) => self.table(fields, scope)?,
+            Expr::Name(name) => match scope.lookup(name) {
+                Some(v) => v,
+                None => match self.globals.borrow().get_str(name) {
+                    Value::Nil => {
+                        return Err(self.error(format!(
+                            "Script attempted to access nonexistent global variable '{}'",
+                            name
+                        )))
+                    }
+                    v => v,
+                },
+            },
+            Expr::Index(obj, key) => {
+                let obj = self.eval(obj, scope)?;
+                let key = self.eval(key, scope)?;
+                self.index(&obj, &key)?
+            }
+            Expr::Call(..) | Expr::Method(..) | Expr::Vararg => self
+                .eval_multi(expr, scope)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Paren(expr) => self.eval(expr, scope)?,
+            Expr::Binary(BinOp::And, left, right) => {
+                let left = self.eval(left, scope)?;
+                match left.truthy() {
+                    true => self.eval(right, scope)?,
+                    false => left,
+                }
+            }
+            Expr::Binary(BinOp::Or, left, right) => {
+                let left = self.eval(left, scope)?;
+                match left.truthy() {
+                    true => left,
+                    false => self.eval(right, scope)?,
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left, scope)?;
+                let right = self.eval(right, scope)?;
+                self.binary(*op, left, right)?
+            }
+            Expr::Unary(op, operand) => {
+                let operand = self.eval(operand, scope)?;
+                match op {
+                    UnOp::Not => Value::Bool(!operand.truthy()),
+                    UnOp::Neg => match operand.to_number() {
+                        Some(n) => Value::Number(-n),
+                        None => {
+                            return Err(self.error(format!(
+                                "attempt to perform arithmetic on a {} value",
+                                operand.type_name()
+                            )))
+                        }
+                    },
+                    UnOp::Len => match &operand {
+                        Value::Str(s) => Value::Number(s.len() as f64),
+                        Value::Table(t) => Value::Number(t.borrow().len() as f64),
+                        v => {
+                            return Err(self.error(format!(
+                                "attempt to get length of a {} value",
+                                v.type_name()
+                            )))
+                        }
+                    },
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn table(&mut self, fields: &[TableField], scope: &Rc<Scope>) -> LuaResult<Value> {
+        let mut table = Table::new();
+        let mut position = 1;
+
+        for (i, field) in fields.iter().enumerate() {
+            match field {
+                TableField::Named(key, value) => {
+                    let key = self.eval(key, scope)?;
+                    let value = self.eval(value, scope)?;
+                    if let Err(e) = table.set(key, value) {
+                        return Err(self.error(e));
+                    }
+                }
+                // a call or ... at the end expands into all its values
+                TableField::Positional(expr) if i == fields.len() - 1 && expr.is_multi() => {
+                    for value in self.eval_multi(expr, scope)? {
+                        let _ = table.set(Value::Number(position as f64), value);
+                        position += 1;
+                    }
+                }
+                TableField::Positional(expr) => {
+                    let value = self.eval(expr, scope)?;
+                    let _ = table.set(Value::Number(position as f64), value);
+                    position += 1;
+                }
+            }
+        }
+
+        Ok(Value::table(table))
+    }
+
+    fn binary(&mut self, op: BinOp, left: Value, right: Value) -> LuaResult<Value> {
+        let value = match op {
+            BinOp::Eq => Value::Bool(left.raw_eq(&right)),
+            BinOp::Ne => Value::Bool(!left.raw_eq(&right)),
+            BinOp::Lt => Value::Bool(self.less_than(&left, &right)?),
+            BinOp::Le => Value::Bool(!self.less_than(&right, &left)?),
+            BinOp::Gt => Value::Bool(self.less_than(&right, &left)?),
+            BinOp::Ge => Value::Bool(!self.less_than(&left, &right)?),
+            BinOp::Concat => match (left.to_str(), right.to_str()) {
+                (Some(a), Some(b)) if a.len() + b.len() > MAX_STRING_LEN => {
+                    return Err(self.error("string length overflow"));
+                }
+                (Some(a), Some(b)) => Value::str(&format!("{}{}", a, b)),
+                _ => {
+                    let bad = if left.to_str().is_none() {
+                        &left
+                    } else {
+                        &right
+                    };
+                    return Err(self.error(format!(
+                        "attempt to concatenate a {} value",
+                        bad.type_name()
+                    )));
+                }
+            },
+            op => {
+                let (a, b) = match (left.to_number(), right.to_number()) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => {
+                        let bad = if left.to_number().is_none() {
+                            &left
+                        } else {
+                            &right
+                        };
+                        return Err(self.error(format!(
+                            "attempt to perform arithmetic on a {} value",
+                            bad.type_name()
+                        )));
+                    }
+                };
+
+                Value::Number(match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Div => a / b,
+                    BinOp::Mod => a - (a / b).floor() * b,
+                    BinOp::Pow => a.powf(b),
+                    _ => unreachable!("logical and comparison operators are handled above"),
+                })
+            }
+        };
+
+        Ok(value)
+    }
+
+    pub fn less_than(&self, left: &Value, right: &Value) -> LuaResult<bool> {
+        match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Ok(a < b),
+            (Value::Str(a), Value::Str(b)) => Ok(a < b),
+            (a, b) if a.type_name() == b.type_name() => {
+                Err(self.error(format!("attempt to compare two {} values", a.type_name())))
+            }
+            (a, b) => Err(self.error(format!(
+                "attempt to compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::parse;
+    use super::super::stdlib::open_libs;
+    use super::*;
+
+    struct NoHost;
+
+    impl Host for NoHost {
+        fn call(&mut self, _args: Vec<String>) -> RespValue {
+            RespValue::Nil
+        }
+
+        fn log(&mut self, _level: Level, _msg: &str) {}
+
+        fn keep_running(&mut self) -> bool {
+            true
+        }
+    }
+
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let mut host = NoHost;
+        let globals = Rc::new(RefCell::new(Table::new()));
+        open_libs(&mut globals.borrow_mut());
+
+        let mut interp = Interp::new(&mut host, globals);
+        match interp.run_chunk(parse(source).map_err(|e| e.msg)?, Vec::new()) {
+            Ok(values) => Ok(values.iter().map(|v| v.to_display()).collect()),
+            Err(LuaError::Error { value, .. }) => Err(value.to_display()),
+            Err(LuaError::Killed) => Err("killed".into()),
+        }
+    }
+
+    #[test]
+    fn test_closures_and_loops() {
+        let source = r#"
+            local function counter()
+                local n = 0
+                return function() n = n + 1 return n end
+            end
+            local c = counter()
+            c() c()
+
+            local sum = 0
+            for i = 10, 1, -2 do sum = sum + i end
+            for _, v in ipairs({1, 2, 3}) do sum = sum + v end
+
+            local t = {a = 1, b = 2}
+            local keys = 0
+            for k in pairs(t) do keys = keys + 1 end
+
+            local i = 0
+            repeat local j = i i = i + 1 until j >= 3
+
+            return c(), sum, keys, i, #"abc" .. "!", 7 % 3, 2 ^ 10
+        "#;
+
+        assert_eq!(
+            run(source).unwrap(),
+            vec!["3", "36", "2", "4", "3!", "1", "1024"]
+        );
+    }
+
+    #[test]
+    fn test_varargs_and_errors() {
+        let source = r#"
+            local function f(...) return select('#', ...), ... end
+            local ok, err = pcall(function() error("boom") end)
+            local ok2, err2 = pcall(error, {code = 1})
+            return ok, err, err2.code, f(1, nil, 3)
+        "#;
+
+        assert_eq!(
+            run(source).unwrap(),
+            vec!["false", "user_script:3: boom", "1", "3", "1", "nil", "3"]
+        );
+
+        assert_eq!(
+            run("return x").unwrap_err(),
+            "user_script:1: Script attempted to access nonexistent global variable 'x'"
+        );
+        assert_eq!(
+            run("y = 1").unwrap_err(),
+            "user_script:1: Attempt to modify a readonly table"
+        );
+        assert_eq!(
+            run("local t = nil\nreturn t.x").unwrap_err(),
+            "user_script:2: attempt to index a nil value"
+        );
+        assert_eq!(
+            run("local function f() return 1 + f() end return f()").unwrap_err(),
+            "user_script:1: stack overflow"
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        let source = r#"
+            local t = {}
+            return 1 + 2 * 3 ^ 2, -2 ^ 2, 7 / 2, "10" + 1, 1 .. 2, "a" < "b",
+                nil or "default", false and error("not evaluated"), not nil, 1 == "1",
+                #{1, 2, 3}, t == t, {} == {}
+        "#;
+
+        assert_eq!(
+            run(source).unwrap(),
+            vec![
+                "19", "-4", "3.5", "11", "12", "true", "default", "false", "true", "false", "3",
+                "true", "false"
+            ]
+        );
+
+        assert_eq!(
+            run("return 1 + nil").unwrap_err(),
+            "user_script:1: attempt to perform arithmetic on a nil value"
+        );
+        assert_eq!(
+            run("return 1 < 'x'").unwrap_err(),
+            "user_script:1: attempt to compare number with string"
+        );
+        assert_eq!(
+            run("local f\nf()").unwrap_err(),
+            "user_script:2: attempt to call a nil value"
+        );
+        assert_eq!(
+            run("return {} .. 'x'").unwrap_err(),
+            "user_script:1: attempt to concatenate a table value"
+        );
+    }
+
+    #[test]
+    fn test_assignment_and_scopes() {
+        let source = r#"
+            local a, b, c = 1, 2
+            a, b = b, a
+            local x = 1
+            do local x = 2 end
+
+            local n = 0
+            while true do
+                n = n + 1
+                for i = 1, 10 do if i == 2 then break end end
+                if n == 5 then break end
+            end
+
+            local obj = {v = 40}
+            function obj:add(d) return self.v + d end
+
+            local function three() return 1, 2, 3 end
+            local packed = {three(), three()}
+            local function count(...) return #{...} end
+
+            return a, b, c, x, n, obj:add(2), #packed, count(three())
+        "#;
+
+        assert_eq!(
+            run(source).unwrap(),
+            vec!["2", "1", "nil", "1", "5", "42", "4", "3"]
+        );
+    }
+}
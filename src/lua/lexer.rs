@@ -0,0 +1,470 @@
+//! Splits Lua source into tokens
+
+use super::LuaSyntaxError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    Number(f64),
+    Str(String),
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Hash,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+    Assign,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Semi,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Dots,
+    Eof,
+}
+
+impl Token {
+    /// How the token is shown in syntax errors
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Name(n) => n.clone(),
+            Token::Number(n) => n.to_string(),
+            Token::Str(s) => s.clone(),
+            Token::Eof => "<eof>".into(),
+            Token::Plus => "+".into(),
+            Token::Minus => "-".into(),
+            Token::Star => "*".into(),
+            Token::Slash => "/".into(),
+            Token::Percent => "%".into(),
+            Token::Caret => "^".into(),
+            Token::Hash => "#".into(),
+            Token::Eq => "==".into(),
+            Token::Ne => "~=".into(),
+            Token::Le => "<=".into(),
+            Token::Ge => ">=".into(),
+            Token::Lt => "<".into(),
+            Token::Gt => ">".into(),
+            Token::Assign => "=".into(),
+            Token::LParen => "(".into(),
+            Token::RParen => ")".into(),
+            Token::LBrace => "{".into(),
+            Token::RBrace => "}".into(),
+            Token::LBracket => "[".into(),
+            Token::RBracket => "]".into(),
+            Token::Semi => ";".into(),
+            Token::Colon => ":".into(),
+            Token::Comma => ",".into(),
+            Token::Dot => ".".into(),
+            Token::Concat => "..".into(),
+            Token::Dots => "...".into(),
+            // keywords
+            t => format!("{:?}", t).to_lowercase(),
+        }
+    }
+}
+
+fn keyword(name: &str) -> Option<Token> {
+    let token = match name {
+        "and" => Token::And,
+        "break" => Token::Break,
+        "do" => Token::Do,
+        "else" => Token::Else,
+        "elseif" => Token::Elseif,
+        "end" => Token::End,
+        "false" => Token::False,
+        "for" => Token::For,
+        "function" => Token::Function,
+        "if" => Token::If,
+        "in" => Token::In,
+        "local" => Token::Local,
+        "nil" => Token::Nil,
+        "not" => Token::Not,
+        "or" => Token::Or,
+        "repeat" => Token::Repeat,
+        "return" => Token::Return,
+        "then" => Token::Then,
+        "true" => Token::True,
+        "until" => Token::Until,
+        "while" => Token::While,
+        _ => return None,
+    };
+
+    Some(token)
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+/// Tokens of `source` with the line each of them starts on
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, LuaSyntaxError> {
+    let mut lexer = Lexer {
+        chars: source.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+
+    let mut tokens = Vec::new();
+    loop {
+        lexer.skip_whitespace_and_comments()?;
+        let line = lexer.line;
+        let token = lexer.next_token()?;
+        let eof = token == Token::Eof;
+        tokens.push((token, line));
+
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, msg: impl Into<String>) -> LuaSyntaxError {
+        LuaSyntaxError {
+            msg: msg.into(),
+            line: self.line,
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), LuaSyntaxError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('-') if self.peek_at(1) == Some('-') => {
+                    self.pos += 2;
+                    match self.long_bracket_level() {
+                        Some(level) => {
+                            self.long_string(level)?;
+                        }
+                        None => {
+                            while !matches!(self.peek(), Some('\n') | None) {
+                                self.bump();
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Level of a `[==[` opening at the current position, without consuming it
+    fn long_bracket_level(&self) -> Option<usize> {
+        if self.peek() != Some('[') {
+            return None;
+        }
+
+        let mut level = 0;
+        while self.peek_at(1 + level) == Some('=') {
+            level += 1;
+        }
+
+        match self.peek_at(1 + level) {
+            Some('[') => Some(level),
+            _ => None,
+        }
+    }
+
+    /// Reads a long string or comment whose opening bracket of `level` starts here
+    fn long_string(&mut self, level: usize) -> Result<String, LuaSyntaxError> {
+        self.pos += level + 2;
+
+        // a newline right after the opening bracket is skipped
+        if self.peek() == Some('\r') {
+            self.bump();
+        }
+        if self.peek() == Some('\n') {
+            self.bump();
+        }
+
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some(']') => {
+                    let mut closing = 0;
+                    while self.peek_at(closing) == Some('=') {
+                        closing += 1;
+                    }
+
+                    if closing == level && self.peek_at(closing) == Some(']') {
+                        self.pos += closing + 1;
+                        return Ok(s);
+                    }
+                    s.push(']');
+                }
+                Some(c) => s.push(c),
+                None => return Err(self.error("unfinished long string")),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, LuaSyntaxError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(Token::Eof),
+        };
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(c) = self
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            {
+                name.push(c);
+                self.pos += 1;
+            }
+            return Ok(keyword(&name).unwrap_or(Token::Name(name)));
+        }
+
+        if c.is_ascii_digit() || (c == '.' && self.peek_at(1).is_some_and(|c| c.is_ascii_digit())) {
+            return self.number();
+        }
+
+        if c == '"' || c == '\'' {
+            return self.string(c);
+        }
+
+        if let Some(level) = self.long_bracket_level() {
+            return Ok(Token::Str(self.long_string(level)?));
+        }
+
+        self.pos += 1;
+        let token = match c {
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '^' => Token::Caret,
+            '#' => Token::Hash,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ';' => Token::Semi,
+            ':' => Token::Colon,
+            ',' => Token::Comma,
+            '=' => self.followed_by('=', Token::Eq, Token::Assign),
+            '<' => self.followed_by('=', Token::Le, Token::Lt),
+            '>' => self.followed_by('=', Token::Ge, Token::Gt),
+            '~' if self.peek() == Some('=') => {
+                self.pos += 1;
+                Token::Ne
+            }
+            '.' if self.peek() == Some('.') => {
+                self.pos += 1;
+                self.followed_by('.', Token::Dots, Token::Concat)
+            }
+            '.' => Token::Dot,
+            c => return Err(self.error(format!("unexpected symbol near '{}'", c))),
+        };
+
+        Ok(token)
+    }
+
+    fn followed_by(&mut self, next: char, yes: Token, no: Token) -> Token {
+        match self.peek() == Some(next) {
+            true => {
+                self.pos += 1;
+                yes
+            }
+            false => no,
+        }
+    }
+
+    fn number(&mut self) -> Result<Token, LuaSyntaxError> {
+        let start = self.pos;
+        let hex = self.peek() == Some('0') && matches!(self.peek_at(1), Some('x' | 'X'));
+        if hex {
+            self.pos += 2;
+        }
+
+        while let Some(c) = self.peek() {
+            let exponent = !hex && matches!(c, 'e' | 'E');
+            if exponent && matches!(self.peek_at(1), Some('+' | '-')) {
+                self.pos += 2;
+            } else if c.is_ascii_alphanumeric() || c == '.' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match str_to_number(&text) {
+            Some(n) => Ok(Token::Number(n)),
+            None => Err(self.error(format!("malformed number near '{}'", text))),
+        }
+    }
+
+    fn string(&mut self, quote: char) -> Result<Token, LuaSyntaxError> {
+        self.pos += 1;
+
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => return Err(self.error("unfinished string")),
+                Some(c) => c,
+            };
+            self.pos += 1;
+
+            if c == quote {
+                return Ok(Token::Str(s));
+            }
+
+            if c != '\\' {
+                s.push(c);
+                continue;
+            }
+
+            let escaped = match self.bump() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('a') => '\x07',
+                Some('b') => '\x08',
+                Some('f') => '\x0c',
+                Some('v') => '\x0b',
+                Some(c @ ('\\' | '"' | '\'' | '\n')) => c,
+                Some(c) if c.is_ascii_digit() => {
+                    let mut code = c.to_digit(10).unwrap();
+                    for _ in 0..2 {
+                        match self.peek().and_then(|c| c.to_digit(10)) {
+                            Some(d) => {
+                                code = code * 10 + d;
+                                self.pos += 1;
+                            }
+                            None => break,
+                        }
+                    }
+                    match char::from_u32(code).filter(|_| code <= 255) {
+                        Some(c) => c,
+                        None => return Err(self.error("escape sequence too large")),
+                    }
+                }
+                _ => return Err(self.error("invalid escape sequence")),
+            };
+            s.push(escaped);
+        }
+    }
+}
+
+/// Parses numbers the way `tonumber` does, decimal or hexadecimal
+pub fn str_to_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+
+    if let Some(hex) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    {
+        let n = u64::from_str_radix(hex, 16).ok()? as f64;
+        return Some(if negative { -n } else { n });
+    }
+
+    // rust also accepts things like "inf" and "nan" which Lua does not
+    let valid = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+
+    match valid {
+        true => s.parse().ok(),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens: Vec<Token> =
+            tokenize("local x = 0x10 .. 'a\\n' -- comment\n--[[ long\n]] [==[b]==] ...")
+                .unwrap()
+                .into_iter()
+                .map(|(t, _)| t)
+                .collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Local,
+                Token::Name("x".into()),
+                Token::Assign,
+                Token::Number(16.0),
+                Token::Concat,
+                Token::Str("a\n".into()),
+                Token::Str("b".into()),
+                Token::Dots,
+                Token::Eof,
+            ]
+        );
+
+        assert_eq!(tokenize("a\n\nb").unwrap()[1].1, 3);
+        assert!(tokenize("'open").is_err());
+        assert_eq!(str_to_number(" 1.5e2 "), Some(150.0));
+        assert_eq!(str_to_number("inf"), None);
+    }
+}
@@ -0,0 +1,32 @@
+//! A small Lua 5.1 interpreter for running scripts
+//!
+//! Only what scripts need is there: the language itself, the base functions and the
+//! string, table and math libraries. There are no metatables or coroutines.
+
+mod ast;
+mod interp;
+mod lexer;
+mod parser;
+mod pattern;
+mod stdlib;
+mod value;
+
+use std::fmt;
+
+pub use ast::Block;
+pub use interp::{Host, Interp, LuaError, LuaResult};
+pub use parser::parse;
+pub use stdlib::{open_libs, reset_random};
+pub use value::{Table, Value};
+
+#[derive(Debug)]
+pub struct LuaSyntaxError {
+    pub msg: String,
+    pub line: usize,
+}
+
+impl fmt::Display for LuaSyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user_script:{}: {}", self.line, self.msg)
+    }
+}
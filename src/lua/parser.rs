@@ -0,0 +1,570 @@
+//! Recursive descent parser for the Lua 5.1 grammar
+
+use std::rc::Rc;
+
+use super::ast::{BinOp, Block, Expr, FuncBody, Name, Stat, StatKind, TableField, UnOp};
+use super::lexer::{tokenize, Token};
+use super::LuaSyntaxError;
+
+/// Priority of unary operators, binds tighter than everything but `^`
+const UNARY_PRIORITY: u8 = 8;
+
+/// Left and right priority of a binary operator, right associative ones bind tighter on the left
+fn binary_priority(token: &Token) -> Option<(BinOp, u8, u8)> {
+    let op = match token {
+        Token::Or => (BinOp::Or, 1, 1),
+        Token::And => (BinOp::And, 2, 2),
+        Token::Lt => (BinOp::Lt, 3, 3),
+        Token::Gt => (BinOp::Gt, 3, 3),
+        Token::Le => (BinOp::Le, 3, 3),
+        Token::Ge => (BinOp::Ge, 3, 3),
+        Token::Ne => (BinOp::Ne, 3, 3),
+        Token::Eq => (BinOp::Eq, 3, 3),
+        Token::Concat => (BinOp::Concat, 5, 4),
+        Token::Plus => (BinOp::Add, 6, 6),
+        Token::Minus => (BinOp::Sub, 6, 6),
+        Token::Star => (BinOp::Mul, 7, 7),
+        Token::Slash => (BinOp::Div, 7, 7),
+        Token::Percent => (BinOp::Mod, 7, 7),
+        Token::Caret => (BinOp::Pow, 10, 9),
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Number of enclosing loops, `break` is only valid inside one
+    loops: usize,
+    /// Whether `...` is valid in the function being parsed
+    vararg: bool,
+}
+
+/// Parse a chunk, which is the body of a vararg function
+pub fn parse(source: &str) -> Result<Block, LuaSyntaxError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        loops: 0,
+        vararg: true,
+    };
+
+    let block = parser.block()?;
+    match parser.peek() {
+        Token::Eof => Ok(block),
+        t => Err(parser.error(format!("'<eof>' expected near '{}'", t.describe()))),
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, msg: String) -> LuaSyntaxError {
+        LuaSyntaxError {
+            msg,
+            line: self.line(),
+        }
+    }
+
+    fn check(&mut self, token: Token) -> bool {
+        match *self.peek() == token {
+            true => {
+                self.next();
+                true
+            }
+            false => false,
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), LuaSyntaxError> {
+        match self.check(token.clone()) {
+            true => Ok(()),
+            false => Err(self.error(format!(
+                "'{}' expected near '{}'",
+                token.describe(),
+                self.peek().describe()
+            ))),
+        }
+    }
+
+    fn name(&mut self) -> Result<Name, LuaSyntaxError> {
+        match self.next() {
+            Token::Name(n) => Ok(n.into()),
+            t => Err(self.error(format!("<name> expected near '{}'", t.describe()))),
+        }
+    }
+
+    fn block_ends(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Eof | Token::End | Token::Else | Token::Elseif | Token::Until
+        )
+    }
+
+    fn block(&mut self) -> Result<Block, LuaSyntaxError> {
+        let mut block = Vec::new();
+
+        while !self.block_ends() {
+            if self.check(Token::Semi) {
+                continue;
+            }
+
+            let line = self.line();
+            let kind = self.statement()?;
+            let last = matches!(kind, StatKind::Return(_) | StatKind::Break);
+            block.push(Stat { line, kind });
+
+            // return and break have to be the last statement of a block
+            if last {
+                self.check(Token::Semi);
+                if !self.block_ends() {
+                    return Err(
+                        self.error(format!("'end' expected near '{}'", self.peek().describe()))
+                    );
+                }
+            }
+        }
+
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<StatKind, LuaSyntaxError> {
+        match self.peek() {
+            Token::Do => {
+                self.next();
+                let body = self.block()?;
+                self.expect(Token::End)?;
+                Ok(StatKind::Do(body))
+            }
+            Token::While => {
+                self.next();
+                let cond = self.expr()?;
+                self.expect(Token::Do)?;
+                let body = self.loop_body()?;
+                self.expect(Token::End)?;
+                Ok(StatKind::While(cond, body))
+            }
+            Token::Repeat => {
+                self.next();
+                let body = self.loop_body()?;
+                self.expect(Token::Until)?;
+                let cond = self.expr()?;
+                Ok(StatKind::Repeat(body, cond))
+            }
+            Token::If => self.if_statement(),
+            Token::For => self.for_statement(),
+            Token::Function => {
+                self.next();
+                let (target, method) = self.function_name()?;
+                let body = self.function_body(method)?;
+                Ok(StatKind::Assign(vec![target], vec![Expr::Function(body)]))
+            }
+            Token::Local => {
+                self.next();
+                if self.check(Token::Function) {
+                    let name = self.name()?;
+                    let body = self.function_body(false)?;
+                    return Ok(StatKind::LocalFunction(name, body));
+                }
+
+                let mut names = vec![self.name()?];
+                while self.check(Token::Comma) {
+                    names.push(self.name()?);
+                }
+
+                let exprs = match self.check(Token::Assign) {
+                    true => self.expr_list()?,
+                    false => Vec::new(),
+                };
+                Ok(StatKind::Local(names, exprs))
+            }
+            Token::Return => {
+                self.next();
+                let exprs = match self.block_ends() || *self.peek() == Token::Semi {
+                    true => Vec::new(),
+                    false => self.expr_list()?,
+                };
+                Ok(StatKind::Return(exprs))
+            }
+            Token::Break => {
+                self.next();
+                if self.loops == 0 {
+                    return Err(self.error("no loop to break near 'break'".into()));
+                }
+                Ok(StatKind::Break)
+            }
+            _ => self.expr_statement(),
+        }
+    }
+
+    fn loop_body(&mut self) -> Result<Block, LuaSyntaxError> {
+        self.loops += 1;
+        let body = self.block();
+        self.loops -= 1;
+        body
+    }
+
+    fn if_statement(&mut self) -> Result<StatKind, LuaSyntaxError> {
+        let mut branches = Vec::new();
+
+        loop {
+            // `if` or `elseif`
+            self.next();
+            let cond = self.expr()?;
+            self.expect(Token::Then)?;
+            branches.push((cond, self.block()?));
+
+            if *self.peek() != Token::Elseif {
+                break;
+            }
+        }
+
+        let otherwise = match self.check(Token::Else) {
+            true => Some(self.block()?),
+            false => None,
+        };
+        self.expect(Token::End)?;
+
+        Ok(StatKind::If(branches, otherwise))
+    }
+
+    fn for_statement(&mut self) -> Result<StatKind, LuaSyntaxError> {
+        self.next();
+        let first = self.name()?;
+
+        if self.check(Token::Assign) {
+            let start = self.expr()?;
+            self.expect(Token::Comma)?;
+            let limit = self.expr()?;
+            let step = match self.check(Token::Comma) {
+                true => Some(self.expr()?),
+                false => None,
+            };
+            self.expect(Token::Do)?;
+            let body = self.loop_body()?;
+            self.expect(Token::End)?;
+
+            return Ok(StatKind::NumericFor {
+                var: first,
+                start,
+                limit,
+                step,
+                body,
+            });
+        }
+
+        let mut names = vec![first];
+        while self.check(Token::Comma) {
+            names.push(self.name()?);
+        }
+        self.expect(Token::In)?;
+        let exprs = self.expr_list()?;
+        self.expect(Token::Do)?;
+        let body = self.loop_body()?;
+        self.expect(Token::End)?;
+
+        Ok(StatKind::GenericFor(names, exprs, body))
+    }
+
+    /// `a.b.c` or `a.b:c`, returns the target and whether it is a method
+    fn function_name(&mut self) -> Result<(Expr, bool), LuaSyntaxError> {
+        let mut target = Expr::Name(self.name()?);
+
+        while self.check(Token::Dot) {
+            let key = Expr::Str(self.name()?);
+            target = Expr::Index(Box::new(target), Box::new(key));
+        }
+
+        if self.check(Token::Colon) {
+            let key = Expr::Str(self.name()?);
+            return Ok((Expr::Index(Box::new(target), Box::new(key)), true));
+        }
+
+        Ok((target, false))
+    }
+
+    fn function_body(&mut self, method: bool) -> Result<Rc<FuncBody>, LuaSyntaxError> {
+        self.expect(Token::LParen)?;
+
+        let mut params: Vec<Name> = Vec::new();
+        if method {
+            params.push("self".into());
+        }
+
+        let mut vararg = false;
+        if *self.peek() != Token::RParen {
+            loop {
+                if self.check(Token::Dots) {
+                    vararg = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.check(Token::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen)?;
+
+        // loops and varargs don't reach into nested functions
+        let outer = (self.loops, self.vararg);
+        self.loops = 0;
+        self.vararg = vararg;
+        let body = self.block();
+        (self.loops, self.vararg) = outer;
+        let body = body?;
+
+        self.expect(Token::End)?;
+
+        Ok(Rc::new(FuncBody {
+            params,
+            vararg,
+            body,
+        }))
+    }
+
+    fn expr_statement(&mut self) -> Result<StatKind, LuaSyntaxError> {
+        let expr = self.suffixed_expr()?;
+
+        if matches!(self.peek(), Token::Assign | Token::Comma) {
+            let mut targets = vec![expr];
+            while self.check(Token::Comma) {
+                targets.push(self.suffixed_expr()?);
+            }
+
+            if targets
+                .iter()
+                .any(|t| !matches!(t, Expr::Name(_) | Expr::Index(..)))
+            {
+                return Err(self.error("syntax error near '='".into()));
+            }
+
+            self.expect(Token::Assign)?;
+            let exprs = self.expr_list()?;
+            return Ok(StatKind::Assign(targets, exprs));
+        }
+
+        match expr {
+            Expr::Call(..) | Expr::Method(..) => Ok(StatKind::Call(expr)),
+            _ => Err(self.error(format!("syntax error near '{}'", self.peek().describe()))),
+        }
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, LuaSyntaxError> {
+        let mut exprs = vec![self.expr()?];
+        while self.check(Token::Comma) {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr, LuaSyntaxError> {
+        self.sub_expr(0)
+    }
+
+    /// Expression whose binary operators bind tighter than `limit`
+    fn sub_expr(&mut self, limit: u8) -> Result<Expr, LuaSyntaxError> {
+        let unary = match self.peek() {
+            Token::Not => Some(UnOp::Not),
+            Token::Minus => Some(UnOp::Neg),
+            Token::Hash => Some(UnOp::Len),
+            _ => None,
+        };
+
+        let mut left = match unary {
+            Some(op) => {
+                self.next();
+                let operand = self.sub_expr(UNARY_PRIORITY)?;
+                match (op, operand) {
+                    // fold negative literals
+                    (UnOp::Neg, Expr::Number(n)) => Expr::Number(-n),
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                }
+            }
+            None => self.simple_expr()?,
+        };
+
+        while let Some((op, left_priority, right_priority)) = binary_priority(self.peek()) {
+            if left_priority <= limit {
+                break;
+            }
+
+            self.next();
+            let right = self.sub_expr(right_priority)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn simple_expr(&mut self) -> Result<Expr, LuaSyntaxError> {
+        let expr = match self.peek() {
+            Token::Number(n) => Expr::Number(*n),
+            Token::Str(s) => Expr::Str(s.as_str().into()),
+            Token::Nil => Expr::Nil,
+            Token::True => Expr::True,
+            Token::False => Expr::False,
+            Token::Dots => {
+                if !self.vararg {
+                    return Err(
+                        self.error("cannot use '...' outside a vararg function near '...'".into())
+                    );
+                }
+                Expr::Vararg
+            }
+            Token::Function => {
+                self.next();
+                return Ok(Expr::Function(self.function_body(false)?));
+            }
+            Token::LBrace => return self.table(),
+            _ => return self.suffixed_expr(),
+        };
+
+        self.next();
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, LuaSyntaxError> {
+        match self.next() {
+            Token::Name(n) => Ok(Expr::Name(n.into())),
+            Token::LParen => {
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            t => Err(self.error(format!("unexpected symbol near '{}'", t.describe()))),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> Result<Expr, LuaSyntaxError> {
+        let mut expr = self.primary_expr()?;
+
+        loop {
+            expr = match self.peek() {
+                Token::Dot => {
+                    self.next();
+                    let key = Expr::Str(self.name()?);
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::LBracket => {
+                    self.next();
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::Colon => {
+                    self.next();
+                    let name = self.name()?;
+                    let args = self.call_args()?;
+                    Expr::Method(Box::new(expr), name, args)
+                }
+                Token::LParen | Token::LBrace | Token::Str(_) => {
+                    let args = self.call_args()?;
+                    Expr::Call(Box::new(expr), args)
+                }
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    fn call_args(&mut self) -> Result<Vec<Expr>, LuaSyntaxError> {
+        match self.peek() {
+            Token::Str(s) => {
+                let arg = Expr::Str(s.as_str().into());
+                self.next();
+                Ok(vec![arg])
+            }
+            Token::LBrace => Ok(vec![self.table()?]),
+            _ => {
+                self.expect(Token::LParen)?;
+                if self.check(Token::RParen) {
+                    return Ok(Vec::new());
+                }
+                let args = self.expr_list()?;
+                self.expect(Token::RParen)?;
+                Ok(args)
+            }
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, LuaSyntaxError> {
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+
+        while *self.peek() != Token::RBrace {
+            let field = match self.peek() {
+                Token::LBracket => {
+                    self.next();
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    self.expect(Token::Assign)?;
+                    TableField::Named(key, self.expr()?)
+                }
+                Token::Name(_) if self.tokens[self.pos + 1].0 == Token::Assign => {
+                    let key = Expr::Str(self.name()?);
+                    self.next();
+                    TableField::Named(key, self.expr()?)
+                }
+                _ => TableField::Positional(self.expr()?),
+            };
+            fields.push(field);
+
+            if !self.check(Token::Comma) && !self.check(Token::Semi) {
+                break;
+            }
+        }
+        self.expect(Token::RBrace)?;
+
+        Ok(Expr::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let block = parse("local a, b = 1, -2 ^ 2 .. 'x' return a").unwrap();
+        assert_eq!(block.len(), 2);
+
+        // ^ binds tighter than unary minus, .. is right associative
+        match &block[0].kind {
+            StatKind::Local(names, exprs) => {
+                assert_eq!(names.len(), 2);
+                match &exprs[1] {
+                    Expr::Binary(BinOp::Concat, left, _) => {
+                        assert!(matches!(**left, Expr::Unary(UnOp::Neg, _)))
+                    }
+                    e => panic!("unexpected {:?}", e),
+                }
+            }
+            s => panic!("unexpected {:?}", s),
+        }
+
+        assert!(parse("function t.a.b:c(x, ...) return self, ... end").is_ok());
+        assert!(parse("for i = 1, 3 do if i then break end end").is_ok());
+
+        let err = parse("x = = 1").unwrap_err();
+        assert_eq!(err.msg, "unexpected symbol near '='");
+        assert!(parse("break").is_err());
+        assert!(parse("return 1 x = 2").is_err());
+        assert!(parse("f() = 1").is_err());
+    }
+}
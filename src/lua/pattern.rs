@@ -0,0 +1,464 @@
+//! Lua pattern matching, a port of the matcher in Lua 5.1's lstrlib.c
+
+const MAX_CAPTURES: usize = 32;
+const MAX_DEPTH: usize = 200;
+
+const CAP_UNFINISHED: isize = -1;
+const CAP_POSITION: isize = -2;
+
+/// A capture, either a substring or a position (`()` in the pattern)
+#[derive(Debug, PartialEq)]
+pub enum Capture {
+    Str(String),
+    Position(usize),
+}
+
+struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    level: usize,
+    captures: [(usize, isize); MAX_CAPTURES],
+    depth: usize,
+}
+
+type MatchResult = Result<Option<usize>, String>;
+
+/// Characters that make a pattern more than a plain substring search
+pub fn has_specials(pattern: &str) -> bool {
+    pattern.bytes().any(|b| b"^$*+?.([%-".contains(&b))
+}
+
+/// Find the first match of `pattern` in `src` starting at byte `init`
+///
+/// Returns the start and end of the match and its captures, empty if the pattern has
+/// none.
+pub fn find(
+    src: &str,
+    pattern: &str,
+    init: usize,
+) -> Result<Option<(usize, usize, Vec<Capture>)>, String> {
+    let (anchored, pattern) = match pattern.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+
+    let mut start = init;
+    loop {
+        if let Some((end, captures)) = match_at(src, pattern, start)? {
+            return Ok(Some((start, end, captures)));
+        }
+
+        start += 1;
+        if anchored || start > src.len() {
+            return Ok(None);
+        }
+    }
+}
+
+/// Captures of a match, or the whole match if the pattern has none
+pub fn captures_or_whole(
+    src: &str,
+    start: usize,
+    end: usize,
+    captures: Vec<Capture>,
+) -> Vec<Capture> {
+    match captures.is_empty() {
+        true => vec![Capture::Str(
+            String::from_utf8_lossy(&src.as_bytes()[start..end]).to_string(),
+        )],
+        false => captures,
+    }
+}
+
+/// Match `pattern` at exactly byte `start` of `src`, returning the end of the match
+/// and its captures
+pub fn match_at(
+    src: &str,
+    pattern: &str,
+    start: usize,
+) -> Result<Option<(usize, Vec<Capture>)>, String> {
+    let mut ms = MatchState {
+        src: src.as_bytes(),
+        pat: pattern.as_bytes(),
+        level: 0,
+        captures: [(0, 0); MAX_CAPTURES],
+        depth: 0,
+    };
+
+    match ms.do_match(start, 0)? {
+        Some(end) => Ok(Some((end, ms.captures()?))),
+        None => Ok(None),
+    }
+}
+
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+
+    match class.is_ascii_uppercase() {
+        true => !matches,
+        false => matches,
+    }
+}
+
+impl MatchState<'_> {
+    fn captures(&self) -> Result<Vec<Capture>, String> {
+        (0..self.level).map(|i| self.capture(i)).collect()
+    }
+
+    fn capture(&self, i: usize) -> Result<Capture, String> {
+        let (init, len) = self.captures[i];
+        match len {
+            CAP_UNFINISHED => Err("unfinished capture".into()),
+            CAP_POSITION => Ok(Capture::Position(init + 1)),
+            len => Ok(Capture::Str(
+                String::from_utf8_lossy(&self.src[init..init + len as usize]).to_string(),
+            )),
+        }
+    }
+
+    /// Index just past the single character class starting at `p`
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pat[p];
+        p += 1;
+
+        if c == b'%' {
+            if p >= self.pat.len() {
+                return Err("malformed pattern (ends with '%')".into());
+            }
+            return Ok(p + 1);
+        }
+
+        if c == b'[' {
+            if self.pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // the first character is part of the set even if it is a ']'
+            loop {
+                if p >= self.pat.len() {
+                    return Err("malformed pattern (missing ']')".into());
+                }
+                let c = self.pat[p];
+                p += 1;
+                if c == b'%' && p < self.pat.len() {
+                    p += 1;
+                }
+                if self.pat.get(p) == Some(&b']') {
+                    return Ok(p + 1);
+                }
+            }
+        }
+
+        Ok(p)
+    }
+
+    /// Whether `c` is in the set from `p` (the `[`) to `end` (the `]`)
+    fn match_bracket_class(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let mut sig = true;
+        if self.pat[p + 1] == b'^' {
+            sig = false;
+            p += 1;
+        }
+
+        p += 1;
+        while p < end {
+            if self.pat[p] == b'%' {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return sig;
+                }
+            } else if self.pat[p + 1] == b'-' && p + 2 < end {
+                p += 2;
+                if self.pat[p - 2] <= c && c <= self.pat[p] {
+                    return sig;
+                }
+            } else if self.pat[p] == c {
+                return sig;
+            }
+            p += 1;
+        }
+
+        !sig
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let c = match self.src.get(s) {
+            Some(c) => *c,
+            None => return false,
+        };
+
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn do_match(&mut self, s: usize, p: usize) -> MatchResult {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("pattern too complex".into());
+        }
+
+        let result = self.match_here(s, p);
+        self.depth -= 1;
+        result
+    }
+
+    fn match_here(&mut self, mut s: usize, mut p: usize) -> MatchResult {
+        loop {
+            if p == self.pat.len() {
+                return Ok(Some(s));
+            }
+
+            match self.pat[p] {
+                b'(' => {
+                    return match self.pat.get(p + 1) {
+                        Some(b')') => self.start_capture(s, p + 2, CAP_POSITION),
+                        _ => self.start_capture(s, p + 1, CAP_UNFINISHED),
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => {
+                    return Ok((s == self.src.len()).then_some(s));
+                }
+                b'%' if self.pat.get(p + 1) == Some(&b'b') => {
+                    match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                b'%' if self.pat.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pat.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".into());
+                    }
+                    let ep = self.class_end(p)?;
+                    let prev = if s == 0 { 0 } else { self.src[s - 1] };
+                    let cur = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(prev, p, ep - 1)
+                        && self.match_bracket_class(cur, p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                b'%' if self.pat.get(p + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                    match self.match_capture(s, self.pat[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => {}
+            }
+
+            let ep = self.class_end(p)?;
+            let matched = self.single_match(s, p, ep);
+
+            match self.pat.get(ep) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                            return Ok(Some(end));
+                        }
+                    }
+                    p = ep + 1;
+                }
+                Some(b'*') => return self.max_expand(s, p, ep),
+                Some(b'+') => {
+                    return match matched {
+                        true => self.max_expand(s + 1, p, ep),
+                        false => Ok(None),
+                    };
+                }
+                Some(b'-') => return self.min_expand(s, p, ep),
+                _ => {
+                    if !matched {
+                        return Ok(None);
+                    }
+                    s += 1;
+                    p = ep;
+                }
+            }
+        }
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> MatchResult {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+
+        // try the longest repetition first
+        loop {
+            if let Some(end) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> MatchResult {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if !self.single_match(s, p, ep) {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: isize) -> MatchResult {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".into());
+        }
+
+        self.captures[self.level] = (s, what);
+        self.level += 1;
+
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> MatchResult {
+        let open = match (0..self.level)
+            .rev()
+            .find(|i| self.captures[*i].1 == CAP_UNFINISHED)
+        {
+            Some(i) => i,
+            None => return Err("invalid pattern capture".into()),
+        };
+
+        self.captures[open].1 = (s - self.captures[open].0) as isize;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[open].1 = CAP_UNFINISHED;
+        }
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> MatchResult {
+        if p + 1 >= self.pat.len() {
+            return Err("unbalanced pattern".into());
+        }
+
+        let (open, close) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+
+        let mut depth = 1;
+        for i in s + 1..self.src.len() {
+            if self.src[i] == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if self.src[i] == open {
+                depth += 1;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn match_capture(&self, s: usize, digit: u8) -> MatchResult {
+        let i = (digit - b'1') as usize;
+        let (init, len) = match self.captures.get(i) {
+            Some(&(init, len)) if i < self.level && len >= 0 => (init, len as usize),
+            _ => return Err("invalid capture index".into()),
+        };
+
+        let captured = &self.src[init..init + len];
+        match self.src[s..].starts_with(captured) {
+            true => Ok(Some(s + len)),
+            false => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first(src: &str, pattern: &str) -> Option<Vec<Capture>> {
+        find(src, pattern, 0)
+            .unwrap()
+            .map(|(start, end, c)| captures_or_whole(src, start, end, c))
+    }
+
+    fn s(s: &str) -> Capture {
+        Capture::Str(s.into())
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(first("hello world", "o w"), Some(vec![s("o w")]));
+        assert_eq!(
+            first("key:123:x", "(%a+):(%d+)"),
+            Some(vec![s("key"), s("123")])
+        );
+        assert_eq!(first("  trim  ", "^%s*(.-)%s*$"), Some(vec![s("trim")]));
+        assert_eq!(first("f(a(b)c)", "%b()"), Some(vec![s("(a(b)c)")]));
+        assert_eq!(
+            first("abc", "()b()"),
+            Some(vec![Capture::Position(2), Capture::Position(3)])
+        );
+        assert_eq!(
+            first("x = 'q'", "(['\"])(.-)%1"),
+            Some(vec![s("'"), s("q")])
+        );
+        assert_eq!(first("THE (quick) fox", "%f[%a]%a+"), Some(vec![s("THE")]));
+        assert_eq!(first("a]b", "[]]"), Some(vec![s("]")]));
+        assert_eq!(first("abc", "^b"), None);
+
+        assert!(find("a", "[a", 0).is_err());
+        assert!(find("a", "%", 0).is_err());
+    }
+
+    #[test]
+    fn test_quantifiers_and_classes() {
+        assert_eq!(first("<a><b>", "<(.-)>"), Some(vec![s("a")]));
+        assert_eq!(first("<a><b>", "<(.*)>"), Some(vec![s("a><b")]));
+        assert_eq!(first("color colour", "colou?r"), Some(vec![s("color")]));
+        assert_eq!(first("abc123", "[^%a]+"), Some(vec![s("123")]));
+        assert_eq!(first("0x1F!", "%x+",), Some(vec![s("0")]));
+        assert_eq!(first("hi, there", "%p"), Some(vec![s(",")]));
+        assert_eq!(first("a$b", "a$b"), Some(vec![s("a$b")]));
+        assert_eq!(first("end", "d$"), Some(vec![s("d")]));
+        assert_eq!(first("aaa", "a-"), Some(vec![s("")]));
+        assert_eq!(first("x", "()"), Some(vec![Capture::Position(1)]));
+
+        assert!(find("a", "(a", 0).is_err());
+        assert!(find("a", "%b", 0).is_err());
+    }
+}
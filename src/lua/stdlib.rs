@@ -0,0 +1,1250 @@
+//! The parts of the Lua standard library scripts can use
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use super::interp::{Interp, LuaError, LuaResult, MAX_STRING_LEN};
+use super::pattern::{self, Capture};
+use super::value::{format_g, Builtin, Table, TableRef, Value};
+
+/// Most values `unpack` returns, the C stack limit of Lua 5.1
+const MAX_UNPACK: i64 = 8000;
+
+/// Register the base functions and the string, table and math libraries
+pub fn open_libs(globals: &mut Table) {
+    let base: &[(&str, Builtin)] = &[
+        ("assert", assert),
+        ("error", error),
+        ("ipairs", ipairs),
+        ("next", next),
+        ("pairs", pairs),
+        ("pcall", pcall),
+        ("rawequal", rawequal),
+        ("rawget", rawget),
+        ("rawset", rawset),
+        ("select", select),
+        ("tonumber", tonumber),
+        ("tostring", tostring),
+        ("type", type_),
+        ("unpack", unpack),
+    ];
+    for (name, f) in base {
+        globals.set_str(name, Value::builtin(*f));
+    }
+
+    globals.set_str(
+        "string",
+        lib(&[
+            ("byte", str_byte),
+            ("char", str_char),
+            ("find", str_find),
+            ("format", str_format),
+            ("gmatch", str_gmatch),
+            ("gsub", str_gsub),
+            ("len", str_len),
+            ("lower", str_lower),
+            ("match", str_match),
+            ("rep", str_rep),
+            ("reverse", str_reverse),
+            ("sub", str_sub),
+            ("upper", str_upper),
+        ]),
+    );
+
+    globals.set_str(
+        "table",
+        lib(&[
+            ("concat", table_concat),
+            ("getn", table_getn),
+            ("insert", table_insert),
+            ("remove", table_remove),
+            ("sort", table_sort),
+        ]),
+    );
+
+    let math = lib(&[
+        ("abs", math_abs),
+        ("ceil", math_ceil),
+        ("exp", math_exp),
+        ("floor", math_floor),
+        ("fmod", math_fmod),
+        ("log", math_log),
+        ("log10", math_log10),
+        ("max", math_max),
+        ("min", math_min),
+        ("modf", math_modf),
+        ("pow", math_pow),
+        ("random", math_random),
+        ("randomseed", math_randomseed),
+        ("sqrt", math_sqrt),
+    ]);
+    if let Value::Table(t) = &math {
+        t.borrow_mut().set_str("huge", Value::Number(f64::INFINITY));
+        t.borrow_mut()
+            .set_str("pi", Value::Number(std::f64::consts::PI));
+    }
+    globals.set_str("math", math);
+}
+
+fn lib(functions: &[(&str, Builtin)]) -> Value {
+    let mut table = Table::new();
+    for (name, f) in functions {
+        table.set_str(name, Value::builtin(*f));
+    }
+    Value::table(table)
+}
+
+fn arg(args: &[Value], i: usize) -> Value {
+    args.get(i).cloned().unwrap_or_default()
+}
+
+fn bad_arg(interp: &Interp<'_>, i: usize, name: &str, msg: &str) -> LuaError {
+    interp.error(format!("bad argument #{} to '{}' ({})", i + 1, name, msg))
+}
+
+fn expected(interp: &Interp<'_>, args: &[Value], i: usize, name: &str, what: &str) -> LuaError {
+    let got = match args.get(i) {
+        Some(v) => v.type_name(),
+        None => "no value",
+    };
+    bad_arg(interp, i, name, &format!("{} expected, got {}", what, got))
+}
+
+fn check_str(interp: &Interp<'_>, args: &[Value], i: usize, name: &str) -> LuaResult<Rc<str>> {
+    match arg(args, i).to_str() {
+        Some(s) => Ok(s),
+        None => Err(expected(interp, args, i, name, "string")),
+    }
+}
+
+fn check_number(interp: &Interp<'_>, args: &[Value], i: usize, name: &str) -> LuaResult<f64> {
+    match arg(args, i).to_number() {
+        Some(n) => Ok(n),
+        None => Err(expected(interp, args, i, name, "number")),
+    }
+}
+
+/// Numbers are truncated to integers like `lua_tointeger` does
+fn check_int(interp: &Interp<'_>, args: &[Value], i: usize, name: &str) -> LuaResult<i64> {
+    Ok(check_number(interp, args, i, name)? as i64)
+}
+
+fn opt_int(
+    interp: &Interp<'_>,
+    args: &[Value],
+    i: usize,
+    name: &str,
+    default: i64,
+) -> LuaResult<i64> {
+    match arg(args, i) {
+        Value::Nil => Ok(default),
+        _ => check_int(interp, args, i, name),
+    }
+}
+
+fn check_table(interp: &Interp<'_>, args: &[Value], i: usize, name: &str) -> LuaResult<TableRef> {
+    match arg(args, i) {
+        Value::Table(t) => Ok(t),
+        _ => Err(expected(interp, args, i, name, "table")),
+    }
+}
+
+fn check_any(interp: &Interp<'_>, args: &[Value], i: usize, name: &str) -> LuaResult<Value> {
+    match args.get(i) {
+        Some(v) => Ok(v.clone()),
+        None => Err(bad_arg(interp, i, name, "value expected")),
+    }
+}
+
+fn number(n: impl Into<f64>) -> Value {
+    Value::Number(n.into())
+}
+
+fn assert(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if check_any(interp, &args, 0, "assert")?.truthy() {
+        return Ok(args);
+    }
+
+    let msg = match args.get(1) {
+        Some(v) => v.to_str().unwrap_or_else(|| "assertion failed!".into()),
+        None => "assertion failed!".into(),
+    };
+    Err(LuaError::Error {
+        value: Value::Str(msg),
+        line: interp.line,
+    })
+}
+
+/// Raise any value, strings get the position of the caller unless the level is 0
+fn error(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let level = opt_int(interp, &args, 1, "error", 1)?;
+    match arg(&args, 0) {
+        Value::Str(msg) if level > 0 => Err(interp.error(&*msg)),
+        value => Err(LuaError::Error {
+            value,
+            line: interp.line,
+        }),
+    }
+}
+
+fn ipairs(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "ipairs")?;
+    Ok(vec![
+        Value::builtin(ipairs_step),
+        Value::Table(t),
+        number(0),
+    ])
+}
+
+fn ipairs_step(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "ipairs")?;
+    let i = check_number(interp, &args, 1, "ipairs")? + 1.0;
+    let value = t.borrow().get(&number(i));
+    match value {
+        Value::Nil => Ok(vec![Value::Nil]),
+        v => Ok(vec![number(i), v]),
+    }
+}
+
+fn next(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "next")?;
+    let entry = t.borrow().next(&arg(&args, 1));
+    match entry {
+        Ok(Some((k, v))) => Ok(vec![k, v]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(e) => Err(interp.error(e)),
+    }
+}
+
+fn pairs(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "pairs")?;
+    Ok(vec![Value::builtin(next), Value::Table(t), Value::Nil])
+}
+
+/// Call a function catching errors, a killed script stays killed
+fn pcall(interp: &mut Interp<'_>, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let f = check_any(interp, &args, 0, "pcall")?;
+    let rest = args.split_off(1);
+
+    match interp.call(&f, rest) {
+        Ok(mut values) => {
+            values.insert(0, Value::Bool(true));
+            Ok(values)
+        }
+        Err(LuaError::Error { value, .. }) => Ok(vec![Value::Bool(false), value]),
+        Err(LuaError::Killed) => Err(LuaError::Killed),
+    }
+}
+
+fn rawequal(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let a = check_any(interp, &args, 0, "rawequal")?;
+    let b = check_any(interp, &args, 1, "rawequal")?;
+    Ok(vec![Value::Bool(a.raw_eq(&b))])
+}
+
+fn rawget(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "rawget")?;
+    let value = t.borrow().get(&arg(&args, 1));
+    Ok(vec![value])
+}
+
+fn rawset(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "rawset")?;
+    interp.set_index(&Value::Table(t.clone()), arg(&args, 1), arg(&args, 2))?;
+    Ok(vec![Value::Table(t)])
+}
+
+fn select(interp: &mut Interp<'_>, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if let Value::Str(s) = arg(&args, 0) {
+        if &*s == "#" {
+            return Ok(vec![number((args.len() - 1) as f64)]);
+        }
+    }
+
+    let n = check_int(interp, &args, 0, "select")?;
+    let count = args.len() as i64 - 1;
+    let start = match n {
+        n if n < 0 && -n <= count => count + n + 1,
+        n if n < 1 => return Err(bad_arg(interp, 0, "select", "index out of range")),
+        n => n.min(count + 1),
+    };
+
+    Ok(args.split_off(start as usize))
+}
+
+fn tonumber(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let base = opt_int(interp, &args, 1, "tonumber", 10)?;
+    if base == 10 {
+        let value = check_any(interp, &args, 0, "tonumber")?;
+        return Ok(vec![value
+            .to_number()
+            .map(Value::Number)
+            .unwrap_or_default()]);
+    }
+
+    if !(2..=36).contains(&base) {
+        return Err(bad_arg(interp, 1, "tonumber", "base out of range"));
+    }
+    let s = check_str(interp, &args, 0, "tonumber")?;
+    let n = i64::from_str_radix(s.trim(), base as u32).ok();
+    Ok(vec![n.map(|n| number(n as f64)).unwrap_or_default()])
+}
+
+fn tostring(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let value = check_any(interp, &args, 0, "tostring")?;
+    Ok(vec![Value::str(&value.to_display())])
+}
+
+fn type_(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let value = check_any(interp, &args, 0, "type")?;
+    Ok(vec![Value::str(value.type_name())])
+}
+
+fn unpack(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "unpack")?;
+    let len = t.borrow().len() as i64;
+    let i = opt_int(interp, &args, 1, "unpack", 1)?;
+    let j = opt_int(interp, &args, 2, "unpack", len)?;
+    if i <= j && !matches!(j.checked_sub(i), Some(n) if n < MAX_UNPACK) {
+        return Err(interp.error("too many results to unpack"));
+    }
+
+    let t = t.borrow();
+    Ok((i..=j).map(|k| t.get(&number(k as f64))).collect())
+}
+
+/// Turn a negative string position into one counted from the start
+fn str_position(pos: i64, len: usize) -> i64 {
+    match pos {
+        pos if pos < 0 => len as i64 + pos + 1,
+        pos => pos,
+    }
+}
+
+fn bytes_to_value(bytes: &[u8]) -> Value {
+    Value::str(&String::from_utf8_lossy(bytes))
+}
+
+fn str_len(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "len")?;
+    Ok(vec![number(s.len() as f64)])
+}
+
+fn str_sub(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "sub")?;
+    let start = str_position(check_int(interp, &args, 1, "sub")?, s.len()).max(1);
+    let end = str_position(opt_int(interp, &args, 2, "sub", -1)?, s.len()).min(s.len() as i64);
+
+    match start <= end {
+        true => Ok(vec![bytes_to_value(
+            &s.as_bytes()[start as usize - 1..end as usize],
+        )]),
+        false => Ok(vec![Value::str("")]),
+    }
+}
+
+fn str_upper(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "upper")?;
+    Ok(vec![Value::str(&s.to_ascii_uppercase())])
+}
+
+fn str_lower(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "lower")?;
+    Ok(vec![Value::str(&s.to_ascii_lowercase())])
+}
+
+fn str_rep(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "rep")?;
+    let n = check_int(interp, &args, 1, "rep")?.max(0) as usize;
+    if !matches!(s.len().checked_mul(n), Some(len) if len <= MAX_STRING_LEN) {
+        return Err(interp.error("resulting string too large"));
+    }
+    Ok(vec![Value::str(&s.repeat(n))])
+}
+
+fn str_reverse(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "reverse")?;
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.reverse();
+    Ok(vec![bytes_to_value(&bytes)])
+}
+
+fn str_byte(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "byte")?;
+    let start = str_position(opt_int(interp, &args, 1, "byte", 1)?, s.len()).max(1);
+    let end = str_position(opt_int(interp, &args, 2, "byte", start)?, s.len()).min(s.len() as i64);
+
+    match start <= end {
+        true => Ok(s.as_bytes()[start as usize - 1..end as usize]
+            .iter()
+            .map(|b| number(*b))
+            .collect()),
+        false => Ok(Vec::new()),
+    }
+}
+
+fn str_char(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let mut bytes = Vec::with_capacity(args.len());
+    for i in 0..args.len() {
+        match check_int(interp, &args, i, "char")? {
+            c @ 0..=255 => bytes.push(c as u8),
+            _ => return Err(bad_arg(interp, i, "char", "invalid value")),
+        }
+    }
+    Ok(vec![bytes_to_value(&bytes)])
+}
+
+fn capture_value(capture: Capture) -> Value {
+    match capture {
+        Capture::Str(s) => Value::str(&s),
+        Capture::Position(p) => number(p as f64),
+    }
+}
+
+/// `string.find` and `string.match`, which only differ in what they return
+fn str_find_aux(interp: &mut Interp<'_>, args: Vec<Value>, find: bool) -> LuaResult<Vec<Value>> {
+    let name = if find { "find" } else { "match" };
+    let s = check_str(interp, &args, 0, name)?;
+    let p = check_str(interp, &args, 1, name)?;
+    let init = str_position(opt_int(interp, &args, 2, name, 1)?, s.len()).max(1) - 1;
+    if init > s.len() as i64 {
+        return Ok(vec![Value::Nil]);
+    }
+    let init = init as usize;
+
+    if find && (arg(&args, 3).truthy() || !pattern::has_specials(&p)) {
+        return match find_plain(&s.as_bytes()[init..], p.as_bytes()) {
+            Some(i) => Ok(vec![
+                number((init + i + 1) as f64),
+                number((init + i + p.len()) as f64),
+            ]),
+            None => Ok(vec![Value::Nil]),
+        };
+    }
+
+    match pattern::find(&s, &p, init) {
+        Ok(Some((start, end, captures))) => {
+            let mut values = Vec::new();
+            if find {
+                values.push(number((start + 1) as f64));
+                values.push(number(end as f64));
+                values.extend(captures.into_iter().map(capture_value));
+            } else {
+                let captures = pattern::captures_or_whole(&s, start, end, captures);
+                values.extend(captures.into_iter().map(capture_value));
+            }
+            Ok(values)
+        }
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(e) => Err(interp.error(e)),
+    }
+}
+
+fn find_plain(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn str_find(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    str_find_aux(interp, args, true)
+}
+
+fn str_match(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    str_find_aux(interp, args, false)
+}
+
+/// Returns the iterator and its state, for use in a generic `for`
+fn str_gmatch(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "gmatch")?;
+    let p = check_str(interp, &args, 1, "gmatch")?;
+
+    let mut state = Table::new();
+    state.set_str("s", Value::Str(s));
+    state.set_str("p", Value::Str(p));
+    state.set_str("pos", number(0));
+    Ok(vec![Value::builtin(gmatch_step), Value::table(state)])
+}
+
+fn gmatch_step(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let state = check_table(interp, &args, 0, "gmatch")?;
+    let (s, p, pos) = {
+        let state = state.borrow();
+        let s = state.get_str("s").to_str().unwrap_or_default();
+        let p = state.get_str("p").to_str().unwrap_or_default();
+        let pos = state.get_str("pos").to_number().unwrap_or_default() as usize;
+        (s, p, pos)
+    };
+
+    for start in pos..=s.len() {
+        match pattern::match_at(&s, &p, start) {
+            Ok(Some((end, captures))) => {
+                // an empty match moves on by one so the loop ends
+                let next = if end == start { end + 1 } else { end };
+                state.borrow_mut().set_str("pos", number(next as f64));
+                let captures = pattern::captures_or_whole(&s, start, end, captures);
+                return Ok(captures.into_iter().map(capture_value).collect());
+            }
+            Ok(None) => {}
+            Err(e) => return Err(interp.error(e)),
+        }
+    }
+
+    state
+        .borrow_mut()
+        .set_str("pos", number((s.len() + 1) as f64));
+    Ok(vec![Value::Nil])
+}
+
+fn str_gsub(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_str(interp, &args, 0, "gsub")?;
+    let p = check_str(interp, &args, 1, "gsub")?;
+    let repl = arg(&args, 2);
+    if !matches!(
+        repl,
+        Value::Number(_) | Value::Str(_) | Value::Table(_) | Value::Function(_)
+    ) {
+        return Err(expected(interp, &args, 2, "gsub", "string/function/table"));
+    }
+    let max = match arg(&args, 3) {
+        Value::Nil => usize::MAX,
+        _ => check_int(interp, &args, 3, "gsub")?.max(0) as usize,
+    };
+
+    let (anchored, p) = match p.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, &*p),
+    };
+
+    let src = s.as_bytes();
+    let mut out = Vec::with_capacity(src.len());
+    let mut pos = 0;
+    let mut count = 0;
+
+    while count < max {
+        let found = match pattern::match_at(&s, p, pos) {
+            Ok(found) => found,
+            Err(e) => return Err(interp.error(e)),
+        };
+
+        let matched_end = match found {
+            Some((end, captures)) => {
+                count += 1;
+                let whole = &src[pos..end];
+                let captures = pattern::captures_or_whole(&s, pos, end, captures);
+                let replacement = gsub_replacement(interp, &repl, whole, captures)?;
+                match replacement {
+                    Some(r) => out.extend_from_slice(r.as_bytes()),
+                    None => out.extend_from_slice(whole),
+                }
+                if out.len() > MAX_STRING_LEN {
+                    return Err(interp.error("resulting string too large"));
+                }
+                Some(end)
+            }
+            None => None,
+        };
+
+        match matched_end {
+            Some(end) if end > pos => pos = end,
+            _ if pos < src.len() => {
+                out.push(src[pos]);
+                pos += 1;
+            }
+            _ => break,
+        }
+
+        if anchored {
+            break;
+        }
+    }
+
+    out.extend_from_slice(&src[pos.min(src.len())..]);
+    Ok(vec![bytes_to_value(&out), number(count as f64)])
+}
+
+/// What replaces a match, None keeps the original text
+fn gsub_replacement(
+    interp: &mut Interp<'_>,
+    repl: &Value,
+    whole: &[u8],
+    captures: Vec<Capture>,
+) -> LuaResult<Option<Rc<str>>> {
+    let value = match repl {
+        Value::Str(_) | Value::Number(_) => {
+            let template = repl.to_str().unwrap_or_default();
+            let mut out = Vec::new();
+            let mut bytes = template.bytes();
+            while let Some(b) = bytes.next() {
+                if b != b'%' {
+                    out.push(b);
+                    continue;
+                }
+                match bytes.next() {
+                    Some(b'0') => out.extend_from_slice(whole),
+                    Some(d @ b'1'..=b'9') => match captures.get((d - b'1') as usize) {
+                        Some(Capture::Str(c)) => out.extend_from_slice(c.as_bytes()),
+                        Some(Capture::Position(p)) => {
+                            out.extend_from_slice(p.to_string().as_bytes())
+                        }
+                        None => return Err(interp.error("invalid capture index")),
+                    },
+                    Some(other) => out.push(other),
+                    None => out.push(b'%'),
+                }
+            }
+            return Ok(Some(String::from_utf8_lossy(&out).into()));
+        }
+        Value::Table(t) => {
+            let key = captures
+                .into_iter()
+                .next()
+                .map(capture_value)
+                .unwrap_or_default();
+            let value = t.borrow().get(&key);
+            value
+        }
+        f => {
+            let args = captures.into_iter().map(capture_value).collect();
+            interp.call(f, args)?.into_iter().next().unwrap_or_default()
+        }
+    };
+
+    match value {
+        Value::Nil | Value::Bool(false) => Ok(None),
+        Value::Str(_) | Value::Number(_) => Ok(value.to_str()),
+        v => Err(interp.error(format!("invalid replacement value (a {})", v.type_name()))),
+    }
+}
+
+/// Flags, width and precision of a `string.format` conversion
+#[derive(Default)]
+struct FormatSpec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    /// Pad a formatted number, zeros go between the sign and the digits
+    fn pad_number(&self, negative: bool, prefix: &str, digits: String) -> String {
+        let sign = match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        };
+
+        let len = sign.len() + prefix.len() + digits.len();
+        if self.zero && !self.left && len < self.width {
+            return format!(
+                "{}{}{}{}",
+                sign,
+                prefix,
+                "0".repeat(self.width - len),
+                digits
+            );
+        }
+        self.pad(format!("{}{}{}", sign, prefix, digits))
+    }
+
+    fn pad(&self, s: String) -> String {
+        match self.left {
+            true => format!("{:<1$}", s, self.width),
+            false => format!("{:>1$}", s, self.width),
+        }
+    }
+}
+
+/// C's `%e`, rust leaves out the sign and leading zero of the exponent
+fn format_exp(n: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = s.split_once('e').unwrap_or((&s, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+fn str_format(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let fmt = check_str(interp, &args, 0, "format")?;
+    let bytes = fmt.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut next_arg = 1;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        if bytes.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        let mut spec = FormatSpec::default();
+        while let Some(flag) = bytes.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        while let Some(d) = bytes.get(i).filter(|d| d.is_ascii_digit()) {
+            spec.width = spec
+                .width
+                .saturating_mul(10)
+                .saturating_add((d - b'0') as usize);
+            i += 1;
+        }
+        if bytes.get(i) == Some(&b'.') {
+            i += 1;
+            let mut precision: usize = 0;
+            while let Some(d) = bytes.get(i).filter(|d| d.is_ascii_digit()) {
+                precision = precision
+                    .saturating_mul(10)
+                    .saturating_add((d - b'0') as usize);
+                i += 1;
+            }
+            spec.precision = Some(precision);
+        }
+        if spec.width > 99 || spec.precision.is_some_and(|p| p > 99) {
+            return Err(interp.error("invalid format (width or precision too long)"));
+        }
+
+        let conversion = match bytes.get(i) {
+            Some(c) => *c,
+            None => return Err(interp.error("invalid option '%' to 'format'")),
+        };
+        i += 1;
+
+        let n = next_arg;
+        next_arg += 1;
+        let formatted = match conversion {
+            b'd' | b'i' => {
+                let v = check_number(interp, &args, n, "format")? as i64;
+                let mut digits = v.unsigned_abs().to_string();
+                if let Some(p) = spec.precision {
+                    digits = format!("{:0>1$}", digits, p);
+                }
+                spec.pad_number(v < 0, "", digits)
+            }
+            b'u' => {
+                let v = check_number(interp, &args, n, "format")? as i64 as u64;
+                spec.pad_number(false, "", v.to_string())
+            }
+            b'x' | b'X' | b'o' => {
+                let v = check_number(interp, &args, n, "format")? as i64 as u64;
+                let (digits, prefix) = match conversion {
+                    b'x' => (format!("{:x}", v), "0x"),
+                    b'X' => (format!("{:X}", v), "0X"),
+                    _ => (format!("{:o}", v), "0"),
+                };
+                let prefix = if spec.alt && v != 0 { prefix } else { "" };
+                spec.pad_number(false, prefix, digits)
+            }
+            b'c' => {
+                let v = check_number(interp, &args, n, "format")? as i64 as u8;
+                spec.pad(String::from_utf8_lossy(&[v]).into())
+            }
+            b'e' | b'E' | b'f' | b'g' | b'G' => {
+                let v = check_number(interp, &args, n, "format")?;
+                let precision = spec.precision.unwrap_or(6);
+                let digits = match conversion {
+                    _ if !v.is_finite() => format_g(v.abs(), precision),
+                    b'e' => format_exp(v.abs(), precision),
+                    b'E' => format_exp(v.abs(), precision).to_uppercase(),
+                    b'f' => format!("{:.*}", precision, v.abs()),
+                    b'g' => format_g(v.abs(), precision),
+                    _ => format_g(v.abs(), precision).to_uppercase(),
+                };
+                spec.pad_number(v.is_sign_negative() && v != 0.0, "", digits)
+            }
+            b'q' => {
+                let s = check_str(interp, &args, n, "format")?;
+                let mut quoted = String::from("\"");
+                for c in s.chars() {
+                    match c {
+                        '"' | '\\' | '\n' => {
+                            quoted.push('\\');
+                            quoted.push(c);
+                        }
+                        '\r' => quoted.push_str("\\r"),
+                        '\0' => quoted.push_str("\\000"),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('"');
+                quoted
+            }
+            b's' => {
+                let s = check_str(interp, &args, n, "format")?;
+                let s = match spec.precision {
+                    Some(p) if p < s.len() => String::from_utf8_lossy(&s.as_bytes()[..p]).into(),
+                    _ => s.to_string(),
+                };
+                spec.pad(s)
+            }
+            c => return Err(interp.error(format!("invalid option '%{}' to 'format'", c as char))),
+        };
+        out.extend_from_slice(formatted.as_bytes());
+    }
+
+    Ok(vec![bytes_to_value(&out)])
+}
+
+fn table_concat(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "concat")?;
+    let sep = match arg(&args, 1) {
+        Value::Nil => "".into(),
+        _ => check_str(interp, &args, 1, "concat")?,
+    };
+    let len = t.borrow().len() as i64;
+    let i = opt_int(interp, &args, 2, "concat", 1)?;
+    let j = opt_int(interp, &args, 3, "concat", len)?;
+
+    let mut out = String::new();
+    for k in i..=j {
+        let value = t.borrow().get(&number(k as f64));
+        match value.to_str() {
+            Some(s) => out.push_str(&s),
+            None => {
+                return Err(interp.error(format!(
+                    "invalid value (at index {}) in table for 'concat'",
+                    k
+                )))
+            }
+        }
+        if k < j {
+            out.push_str(&sep);
+        }
+        if out.len() > MAX_STRING_LEN {
+            return Err(interp.error("resulting string too large"));
+        }
+    }
+
+    Ok(vec![Value::str(&out)])
+}
+
+fn table_getn(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "getn")?;
+    let len = t.borrow().len();
+    Ok(vec![number(len as f64)])
+}
+
+fn table_insert(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "insert")?;
+    let table = Value::Table(t.clone());
+    let len = t.borrow().len() as i64;
+
+    let (pos, value) = match args.len() {
+        2 => (len + 1, arg(&args, 1)),
+        3 => (check_int(interp, &args, 1, "insert")?, arg(&args, 2)),
+        _ => return Err(interp.error("wrong number of arguments to 'insert'")),
+    };
+
+    // move the elements after pos up one
+    let mut i = len;
+    while i >= pos {
+        let moved = t.borrow().get(&number(i as f64));
+        interp.set_index(&table, number((i + 1) as f64), moved)?;
+        i -= 1;
+    }
+    interp.set_index(&table, number(pos as f64), value)?;
+
+    Ok(Vec::new())
+}
+
+fn table_remove(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "remove")?;
+    let table = Value::Table(t.clone());
+    let len = t.borrow().len() as i64;
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let pos = opt_int(interp, &args, 1, "remove", len)?;
+
+    let removed = t.borrow().get(&number(pos as f64));
+    for i in pos..len {
+        let moved = t.borrow().get(&number((i + 1) as f64));
+        interp.set_index(&table, number(i as f64), moved)?;
+    }
+    interp.set_index(&table, number(len as f64), Value::Nil)?;
+
+    Ok(vec![removed])
+}
+
+/// Sorted with a merge sort since the comparison can fail
+fn table_sort(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let t = check_table(interp, &args, 0, "sort")?;
+    let comp = arg(&args, 1);
+    if !matches!(comp, Value::Nil | Value::Function(_)) {
+        return Err(expected(interp, &args, 1, "sort", "function"));
+    }
+
+    let len = t.borrow().len();
+    let items = (1..=len)
+        .map(|i| t.borrow().get(&number(i as f64)))
+        .collect();
+    let sorted = merge_sort(interp, items, &comp)?;
+
+    let table = Value::Table(t);
+    for (i, value) in sorted.into_iter().enumerate() {
+        interp.set_index(&table, number((i + 1) as f64), value)?;
+    }
+
+    Ok(Vec::new())
+}
+
+fn merge_sort(
+    interp: &mut Interp<'_>,
+    mut items: Vec<Value>,
+    comp: &Value,
+) -> LuaResult<Vec<Value>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(interp, items, comp)?;
+    let right = merge_sort(interp, right, comp)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        let right_first = match comp {
+            Value::Nil => interp.less_than(r, l)?,
+            f => {
+                let args = vec![r.clone(), l.clone()];
+                interp.call(f, args)?.first().is_some_and(|v| v.truthy())
+            }
+        };
+        let next = match right_first {
+            true => right.next(),
+            false => left.next(),
+        };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+fn math_unary(
+    interp: &mut Interp<'_>,
+    args: &[Value],
+    name: &str,
+    f: fn(f64) -> f64,
+) -> LuaResult<Vec<Value>> {
+    let n = check_number(interp, args, 0, name)?;
+    Ok(vec![number(f(n))])
+}
+
+fn math_abs(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_unary(interp, &args, "abs", f64::abs)
+}
+
+fn math_ceil(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_unary(interp, &args, "ceil", f64::ceil)
+}
+
+fn math_exp(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_unary(interp, &args, "exp", f64::exp)
+}
+
+fn math_floor(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_unary(interp, &args, "floor", f64::floor)
+}
+
+fn math_log(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_unary(interp, &args, "log", f64::ln)
+}
+
+fn math_log10(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_unary(interp, &args, "log10", f64::log10)
+}
+
+fn math_sqrt(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    math_unary(interp, &args, "sqrt", f64::sqrt)
+}
+
+fn math_fmod(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let a = check_number(interp, &args, 0, "fmod")?;
+    let b = check_number(interp, &args, 1, "fmod")?;
+    Ok(vec![number(a % b)])
+}
+
+fn math_pow(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let a = check_number(interp, &args, 0, "pow")?;
+    let b = check_number(interp, &args, 1, "pow")?;
+    Ok(vec![number(a.powf(b))])
+}
+
+fn math_modf(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let n = check_number(interp, &args, 0, "modf")?;
+    Ok(vec![number(n.trunc()), number(n.fract())])
+}
+
+fn math_max(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let mut max = check_number(interp, &args, 0, "max")?;
+    for i in 1..args.len() {
+        max = max.max(check_number(interp, &args, i, "max")?);
+    }
+    Ok(vec![number(max)])
+}
+
+fn math_min(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let mut min = check_number(interp, &args, 0, "min")?;
+    for i in 1..args.len() {
+        min = min.min(check_number(interp, &args, i, "min")?);
+    }
+    Ok(vec![number(min)])
+}
+
+thread_local! {
+    /// Scripts get the same sequence every run, like Redis seeding before each script
+    static RANDOM_STATE: Cell<u64> = const { Cell::new(0x2545_f491_4f6c_dd1d) };
+}
+
+fn next_random() -> f64 {
+    RANDOM_STATE.with(|state| {
+        // xorshift64*
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+/// Reset the sequence `math.random` returns
+pub fn reset_random() {
+    RANDOM_STATE.with(|state| state.set(0x2545_f491_4f6c_dd1d));
+}
+
+fn math_random(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let r = next_random();
+    let (low, high) = match args.len() {
+        0 => return Ok(vec![number(r)]),
+        1 => (1, check_int(interp, &args, 0, "random")?),
+        2 => (
+            check_int(interp, &args, 0, "random")?,
+            check_int(interp, &args, 1, "random")?,
+        ),
+        _ => return Err(interp.error("wrong number of arguments")),
+    };
+
+    if low > high {
+        return Err(bad_arg(
+            interp,
+            args.len() - 1,
+            "random",
+            "interval is empty",
+        ));
+    }
+    Ok(vec![number(
+        (r * (high - low + 1) as f64).floor() + low as f64,
+    )])
+}
+
+fn math_randomseed(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let seed = check_int(interp, &args, 0, "randomseed")? as u64;
+    // xorshift gets stuck on 0
+    RANDOM_STATE.with(|state| state.set(seed | 1));
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::super::interp::Host;
+    use super::super::parser::parse;
+    use super::*;
+    use crate::log::Level;
+    use crate::resp::RespValue;
+
+    struct NoHost;
+
+    impl Host for NoHost {
+        fn call(&mut self, _args: Vec<String>) -> RespValue {
+            RespValue::Nil
+        }
+
+        fn log(&mut self, _level: Level, _msg: &str) {}
+
+        fn keep_running(&mut self) -> bool {
+            true
+        }
+    }
+
+    fn run(source: &str) -> Vec<String> {
+        let mut host = NoHost;
+        let globals = Rc::new(RefCell::new(Table::new()));
+        open_libs(&mut globals.borrow_mut());
+
+        let mut interp = Interp::new(&mut host, globals);
+        match interp.run_chunk(parse(source).unwrap(), Vec::new()) {
+            Ok(values) => values.iter().map(|v| v.to_display()).collect(),
+            Err(LuaError::Error { value, .. }) => panic!("{}", value.to_display()),
+            Err(LuaError::Killed) => panic!("killed"),
+        }
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(
+            run(
+                r#"return ("hello"):sub(2, -2), ("%d-%5.2f|%-3s|%x"):format(7, 3.14159, "a", 255)"#
+            ),
+            vec!["ell", "7- 3.14|a  |ff"]
+        );
+        assert_eq!(
+            run(r#"return string.find("a.b", ".", 1, true), string.find("key:1", "(%d)")"#),
+            vec!["2", "5", "5", "1"]
+        );
+        assert_eq!(
+            run(r#"return string.gsub("hello world", "o", "0"), ("abc"):gsub("%w", "%0%0", 2)"#),
+            vec!["hell0 w0rld", "aabbc", "2"]
+        );
+        assert_eq!(
+            run(r#"
+                local words = {}
+                for w in string.gmatch("one two three", "%a+") do
+                    table.insert(words, w:upper())
+                end
+                return table.concat(words, ","), string.rep("ab", 3), string.byte("A"), string.char(72, 105)
+            "#),
+            vec!["ONE,TWO,THREE", "ababab", "65", "Hi"]
+        );
+    }
+
+    fn run_err(source: &str) -> String {
+        let mut host = NoHost;
+        let globals = Rc::new(RefCell::new(Table::new()));
+        open_libs(&mut globals.borrow_mut());
+
+        let mut interp = Interp::new(&mut host, globals);
+        match interp.run_chunk(parse(source).unwrap(), Vec::new()) {
+            Ok(_) => panic!("no error"),
+            Err(LuaError::Error { value, .. }) => value.to_display(),
+            Err(LuaError::Killed) => panic!("killed"),
+        }
+    }
+
+    #[test]
+    fn test_size_limits() {
+        assert!(run_err("return string.rep('x', 1e12)").ends_with("resulting string too large"));
+        assert!(run_err("return string.rep('xx', 2^62)").ends_with("resulting string too large"));
+        assert!(run_err("return unpack({}, 1, 1e8)").ends_with("too many results to unpack"));
+        assert!(run_err("return unpack({}, -2^53, 2^53)").ends_with("too many results to unpack"));
+        assert!(run_err("local s = string.rep('x', 2^28 + 1) return s .. s")
+            .ends_with("string length overflow"));
+        assert!(
+            run_err("return string.format('%99999999999999999999999d', 1)")
+                .ends_with("invalid format (width or precision too long)")
+        );
+
+        assert_eq!(
+            run("return string.rep('ab', 0), string.rep('ab', -1)"),
+            vec!["", ""]
+        );
+        assert_eq!(run("return unpack({1, 2, 3}, 2)"), vec!["2", "3"]);
+        assert_eq!(run("return select('#', unpack({}, 3, 1))"), vec!["0"]);
+        assert_eq!(run("return #string.rep('x', 8000)"), vec!["8000"]);
+    }
+
+    #[test]
+    fn test_base_functions() {
+        assert_eq!(
+            run(
+                r#"return tostring(nil), tonumber(" 10 "), tonumber("1e2"), tonumber("abc"), type(type)"#
+            ),
+            vec!["nil", "10", "100", "nil", "function"]
+        );
+        assert_eq!(
+            run("return select(-1, 'a', 'b'), select(2, 'a', 'b', 'c')"),
+            vec!["b", "b", "c"]
+        );
+        assert_eq!(
+            run(r#"
+                local t = {}
+                rawset(t, "k", 1)
+                return rawget(t, "k"), rawequal(t, t), next({}), assert(1, "unused")
+            "#),
+            vec!["1", "true", "nil", "1", "unused"]
+        );
+        assert_eq!(
+            run(r#"
+                local ok, err = pcall(error, "plain", 0)
+                local ok2, err2 = pcall(assert, false)
+                local ok3, err3 = pcall(string.rep)
+                return err, err2, err3
+            "#),
+            vec![
+                "plain",
+                "assertion failed!",
+                "user_script:4: bad argument #1 to 'rep' (string expected, got no value)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_edge_cases() {
+        assert_eq!(
+            run(
+                r#"return ("hello"):sub(-3), ("hello"):sub(10), ("hello"):sub(0, 2), ("abc"):byte(1, -1)"#
+            ),
+            vec!["llo", "", "he", "97", "98", "99"]
+        );
+        assert_eq!(
+            run(r#"return string.format("%q|%s|%5s|%-5d|%%", 'a"b\n', 1.5, "x", 3)"#),
+            vec!["\"a\\\"b\\\n\"|1.5|    x|3    |%"]
+        );
+        assert_eq!(
+            run(
+                r#"return string.match("k=v", "(%w+)=(%w+)"), string.match("none", "%d"), string.len("")"#
+            ),
+            vec!["k", "nil", "0"]
+        );
+        assert_eq!(
+            run(r#"return string.gsub("abc", "", "-"), string.gsub("a b", "%w", {a = "1"})"#),
+            vec!["-a-b-c-", "1 b", "2"]
+        );
+    }
+
+    #[test]
+    fn test_table_and_math() {
+        assert_eq!(
+            run(r#"
+                local t = {5, 2, 8, 1}
+                table.sort(t)
+                local r = {3, 1, 2}
+                table.sort(r, function(a, b) return a > b end)
+                table.insert(t, 1, 0)
+                local last = table.remove(t)
+                return table.concat(t, " "), last, table.concat(r, " "), unpack({1, 2})
+            "#),
+            vec!["0 1 2 5", "8", "3 2 1", "1", "2"]
+        );
+        assert_eq!(
+            run("return math.max(1, 5, 3), math.floor(-1.5), tonumber('0x1f'), tonumber('z', 36)"),
+            vec!["5", "-2", "31", "35"]
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! Lua values and tables
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::ast::FuncBody;
+use super::interp::{Interp, LuaResult, Scope};
+
+pub type TableRef = Rc<RefCell<Table>>;
+
+/// Functions implemented in rust
+pub type Builtin = fn(&mut Interp<'_>, Vec<Value>) -> LuaResult<Vec<Value>>;
+
+pub enum Function {
+    Lua { body: Rc<FuncBody>, env: Rc<Scope> },
+    Builtin(Builtin),
+}
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Table(TableRef),
+    Function(Rc<Function>),
+}
+
+impl Value {
+    pub fn str(s: &str) -> Value {
+        Value::Str(s.into())
+    }
+
+    pub fn table(table: Table) -> Value {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub fn builtin(f: Builtin) -> Value {
+        Value::Function(Rc::new(Function::Builtin(f)))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    /// Everything but nil and false counts as true
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    /// Numbers and strings that look like numbers, as arithmetic accepts them
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Str(s) => super::lexer::str_to_number(s),
+            _ => None,
+        }
+    }
+
+    /// Strings and numbers, as concatenation accepts them
+    pub fn to_str(&self) -> Option<Rc<str>> {
+        match self {
+            Value::Str(s) => Some(s.clone()),
+            Value::Number(n) => Some(format_number(*n).into()),
+            _ => None,
+        }
+    }
+
+    /// What `tostring` shows
+    pub fn to_display(&self) -> String {
+        match self {
+            Value::Nil => "nil".into(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => format_number(*n),
+            Value::Str(s) => s.to_string(),
+            Value::Table(t) => format!("table: {:p}", Rc::as_ptr(t)),
+            Value::Function(f) => match **f {
+                Function::Builtin(_) => format!("builtin: {:p}", Rc::as_ptr(f)),
+                Function::Lua { .. } => format!("function: {:p}", Rc::as_ptr(f)),
+            },
+        }
+    }
+
+    /// Raw equality, tables and functions compare by identity
+    pub fn raw_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Numbers print like `%.14g` does in C
+pub fn format_number(n: f64) -> String {
+    format_g(n, 14)
+}
+
+/// C's `%.<precision>g`
+pub fn format_g(n: f64, precision: usize) -> String {
+    let precision = precision.max(1) as i32;
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.into();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.into();
+    }
+    if n == n.trunc() && n.abs() < 10f64.powi(precision) {
+        return format!("{}", n as i64);
+    }
+
+    let scientific = format!("{:.*e}", (precision - 1) as usize, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    if exponent < -4 || exponent >= precision {
+        let mantissa = trim_fraction(mantissa);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        return format!("{}e{}{:02}", mantissa, sign, exponent.abs());
+    }
+
+    let decimals = (precision - 1 - exponent).max(0) as usize;
+    trim_fraction(&format!("{:.*}", decimals, n)).to_string()
+}
+
+fn trim_fraction(s: &str) -> &str {
+    match s.contains('.') {
+        true => s.trim_end_matches('0').trim_end_matches('.'),
+        false => s,
+    }
+}
+
+/// Hashable identity of a table key
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Bool(bool),
+    Number(u64),
+    Str(Rc<str>),
+    Ref(usize),
+}
+
+impl Key {
+    fn from_value(value: &Value) -> Result<Key, &'static str> {
+        let key = match value {
+            Value::Nil => return Err("table index is nil"),
+            Value::Number(n) if n.is_nan() => return Err("table index is NaN"),
+            // 0.0 and -0.0 are the same key
+            Value::Number(n) => Key::Number((n + 0.0).to_bits()),
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Str(s) => Key::Str(s.clone()),
+            Value::Table(t) => Key::Ref(Rc::as_ptr(t) as *const u8 as usize),
+            Value::Function(f) => Key::Ref(Rc::as_ptr(f) as *const u8 as usize),
+        };
+
+        Ok(key)
+    }
+}
+
+/// A table with an array part for the keys 1..n and a hash part for the rest
+///
+/// Hash entries set to nil stay in place as tombstones so `next` keeps working while a
+/// traversal clears fields.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+    tombstones: usize,
+}
+
+/// Position of `key` in the array part, counting from 0
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(n) if *n >= 1.0 && n.fract() == 0.0 && *n <= usize::MAX as f64 => {
+            Some(*n as usize - 1)
+        }
+        _ => None,
+    }
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_array(array: Vec<Value>) -> Self {
+        let mut table = Table::new();
+        for (i, v) in array.into_iter().enumerate() {
+            // can't fail, the keys are numbers
+            let _ = table.set(Value::Number((i + 1) as f64), v);
+        }
+        table
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(i) = array_index(key) {
+            if i < self.array.len() {
+                return self.array[i].clone();
+            }
+        }
+
+        match Key::from_value(key) {
+            Ok(k) => match self.index.get(&k) {
+                Some(&i) => self.entries[i].1.clone(),
+                None => Value::Nil,
+            },
+            Err(_) => Value::Nil,
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::str(key))
+    }
+
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        if let Some(i) = array_index(&key) {
+            if i < self.array.len() {
+                self.array[i] = value;
+                while self.array.last().is_some_and(|v| v.is_nil()) {
+                    self.array.pop();
+                }
+                return Ok(());
+            }
+
+            if i == self.array.len() && !value.is_nil() {
+                self.remove_entry(&key);
+                self.array.push(value);
+                self.migrate_to_array();
+                return Ok(());
+            }
+        }
+
+        let k = Key::from_value(&key)?;
+        match self.index.get(&k) {
+            Some(&i) => {
+                if value.is_nil() && !self.entries[i].1.is_nil() {
+                    self.tombstones += 1;
+                } else if !value.is_nil() && self.entries[i].1.is_nil() {
+                    self.tombstones -= 1;
+                }
+                self.entries[i].1 = value;
+            }
+            None if value.is_nil() => {}
+            None => {
+                if self.tombstones > self.entries.len() / 2 {
+                    self.compact();
+                }
+                self.index.insert(k, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        // can't fail, the key is a string
+        let _ = self.set(Value::str(key), value);
+    }
+
+    fn remove_entry(&mut self, key: &Value) {
+        if let Ok(k) = Key::from_value(key) {
+            if let Some(&i) = self.index.get(&k) {
+                if !self.entries[i].1.is_nil() {
+                    self.entries[i].1 = Value::Nil;
+                    self.tombstones += 1;
+                }
+            }
+        }
+    }
+
+    /// Move keys that now continue the array part from the hash part
+    fn migrate_to_array(&mut self) {
+        loop {
+            let key = Value::Number((self.array.len() + 1) as f64);
+            let value = self.get_hash(&key);
+            if value.is_nil() {
+                return;
+            }
+            self.remove_entry(&key);
+            self.array.push(value);
+        }
+    }
+
+    fn get_hash(&self, key: &Value) -> Value {
+        match Key::from_value(key).ok().and_then(|k| self.index.get(&k)) {
+            Some(&i) => self.entries[i].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(|(_, v)| !v.is_nil());
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, (k, _))| (Key::from_value(k).unwrap(), i))
+            .collect();
+        self.tombstones = 0;
+    }
+
+    /// Length as the `#` operator sees it
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    /// The entry after `key` in traversal order, nil starts from the beginning
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, &'static str> {
+        let start_entry = match key {
+            Value::Nil => {
+                if let Some(i) = self.array.iter().position(|v| !v.is_nil()) {
+                    return Ok(Some((Value::Number((i + 1) as f64), self.array[i].clone())));
+                }
+                0
+            }
+            key => match array_index(key).filter(|i| *i < self.array.len()) {
+                Some(i) => {
+                    if let Some(j) = self.array[i + 1..].iter().position(|v| !v.is_nil()) {
+                        let j = i + 1 + j;
+                        return Ok(Some((Value::Number((j + 1) as f64), self.array[j].clone())));
+                    }
+                    0
+                }
+                None => match self.index.get(&Key::from_value(key)?) {
+                    Some(&i) => i + 1,
+                    None => return Err("invalid key to 'next'"),
+                },
+            },
+        };
+
+        Ok(self.entries[start_entry.min(self.entries.len())..]
+            .iter()
+            .find(|(_, v)| !v.is_nil())
+            .cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut t = Table::new();
+        t.set(Value::Number(2.0), Value::str("b")).unwrap();
+        t.set_str("x", Value::Number(1.0));
+        assert_eq!(t.len(), 0);
+
+        // filling the gap pulls 2 into the array part
+        t.set(Value::Number(1.0), Value::str("a")).unwrap();
+        assert_eq!(t.len(), 2);
+
+        let mut keys = Vec::new();
+        let mut key = Value::Nil;
+        while let Some((k, _)) = t.next(&key).unwrap() {
+            keys.push(k.to_display());
+            key = k;
+        }
+        assert_eq!(keys, vec!["1", "2", "x"]);
+
+        t.set(Value::Number(2.0), Value::Nil).unwrap();
+        assert_eq!(t.len(), 1);
+        assert!(t.set(Value::Nil, Value::Nil).is_err());
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(3.0), "3");
+        assert_eq!(format_number(-0.5), "-0.5");
+        assert_eq!(format_number(0.1), "0.1");
+        assert_eq!(format_number(1e20), "1e+20");
+        assert_eq!(format_number(1.0 / 3.0), "0.33333333333333");
+    }
+}
@@ -1,21 +1,54 @@
+mod acl;
+mod client;
+mod cluster;
 mod commads;
+mod command_table;
+mod config;
+mod db;
+mod eviction;
+mod functions;
+mod glob;
+mod latency;
+mod lazyfree;
+mod log;
+mod lua;
+mod multi;
+mod notify;
+mod pubsub;
+mod rdb;
 mod resp;
+mod scripting;
 mod server;
+mod sha1;
+mod sha256;
+mod slowlog;
+mod stats;
+mod tracking;
 
 use commads::{Command, CommandParser};
+use config::Config;
 use resp::{RespParser, RespValue};
-use server::{CliArgs, Replication, Server, ServerRole};
+use server::Server;
 
-use std::env;
+fn main() {
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-// const ADDR: &'static str = "127.0.0.1:6379";
-fn main() -> std::io::Result<()> {
-    let args = CliArgs::from_args().unwrap();
+    let mut server = match Server::from_config(config) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let port = args.port.unwrap_or(6380);
-
-    let mut server = Server::new(format! {"127.0.0.1:{}", port}, args.replicaof);
+    for addr in server.local_addrs() {
+        println!("listening on {}", addr);
+    }
     server.run();
-
-    Ok(())
 }
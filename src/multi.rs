@@ -0,0 +1,106 @@
+//! Bookkeeping for MULTI/EXEC transactions and WATCH
+
+use std::collections::{HashMap, HashSet};
+
+use crate::commads::Command;
+
+/// Per-client transaction state
+#[derive(Default)]
+pub struct MultiState {
+    /// Commands queued since MULTI, `None` outside of a transaction
+    pub queued: Option<Vec<Command>>,
+    /// A command failed to queue, EXEC will abort
+    pub dirty_exec: bool,
+    /// A watched key was modified, EXEC will return a null reply
+    pub dirty_cas: bool,
+    /// Keys this client is watching, as (db, key)
+    pub watched: Vec<(usize, String)>,
+}
+
+impl MultiState {
+    pub fn in_multi(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    /// Leave the transaction, keeping the watched keys
+    pub fn discard(&mut self) -> Vec<Command> {
+        self.dirty_exec = false;
+        self.queued.take().unwrap_or_default()
+    }
+}
+
+/// Which clients watch which keys, so modifying a key can flag their transactions
+#[derive(Default)]
+pub struct WatchedKeys {
+    keys: HashMap<(usize, String), HashSet<u64>>,
+}
+
+impl WatchedKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watch(&mut self, db: usize, key: &str, client_id: u64) {
+        self.keys
+            .entry((db, key.to_string()))
+            .or_default()
+            .insert(client_id);
+    }
+
+    pub fn unwatch(&mut self, db: usize, key: &str, client_id: u64) {
+        let entry = (db, key.to_string());
+
+        if let Some(clients) = self.keys.get_mut(&entry) {
+            clients.remove(&client_id);
+            if clients.is_empty() {
+                self.keys.remove(&entry);
+            }
+        }
+    }
+
+    /// Clients watching `key` in `db`
+    pub fn watchers(&self, db: usize, key: &str) -> Option<&HashSet<u64>> {
+        // avoid allocating the lookup key when nobody watches anything
+        if self.keys.is_empty() {
+            return None;
+        }
+
+        self.keys.get(&(db, key.to_string()))
+    }
+
+    /// Watched keys in `db`
+    pub fn keys_in_db(&self, db: usize) -> Vec<String> {
+        self.keys
+            .keys()
+            .filter(|(d, _)| *d == db)
+            .map(|(_, k)| k.clone())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_and_unwatch() {
+        let mut watched = WatchedKeys::new();
+        watched.watch(0, "a", 1);
+        watched.watch(0, "a", 2);
+        watched.watch(1, "a", 3);
+
+        assert_eq!(watched.watchers(0, "a").unwrap().len(), 2);
+        assert_eq!(watched.keys_in_db(1), vec![String::from("a")]);
+
+        watched.unwatch(0, "a", 1);
+        watched.unwatch(0, "a", 2);
+        watched.unwatch(1, "a", 3);
+
+        assert!(watched.watchers(0, "a").is_none());
+        assert!(watched.is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+//! Keyspace event classes and the `notify-keyspace-events` flag string
+
+/// `K`, publish to `__keyspace@<db>__:<key>`
+pub const NOTIFY_KEYSPACE: u32 = 1 << 0;
+/// `E`, publish to `__keyevent@<db>__:<event>`
+pub const NOTIFY_KEYEVENT: u32 = 1 << 1;
+/// `g`, generic commands like DEL, EXPIRE and RENAME
+pub const NOTIFY_GENERIC: u32 = 1 << 2;
+/// `$`
+pub const NOTIFY_STRING: u32 = 1 << 3;
+/// `l`
+pub const NOTIFY_LIST: u32 = 1 << 4;
+/// `s`
+pub const NOTIFY_SET: u32 = 1 << 5;
+/// `h`
+pub const NOTIFY_HASH: u32 = 1 << 6;
+/// `z`
+pub const NOTIFY_ZSET: u32 = 1 << 7;
+/// `x`, keys removed because their ttl passed
+pub const NOTIFY_EXPIRED: u32 = 1 << 8;
+/// `e`, keys removed to get under maxmemory
+pub const NOTIFY_EVICTED: u32 = 1 << 9;
+/// `t`
+pub const NOTIFY_STREAM: u32 = 1 << 10;
+/// `m`, lookups of missing keys, not part of `A`
+pub const NOTIFY_KEY_MISS: u32 = 1 << 11;
+/// `n`, keys created, not part of `A`
+pub const NOTIFY_NEW: u32 = 1 << 12;
+/// `A`, alias for `g$lshzxet`
+pub const NOTIFY_ALL: u32 = NOTIFY_GENERIC
+    | NOTIFY_STRING
+    | NOTIFY_LIST
+    | NOTIFY_SET
+    | NOTIFY_HASH
+    | NOTIFY_ZSET
+    | NOTIFY_EXPIRED
+    | NOTIFY_EVICTED
+    | NOTIFY_STREAM;
+
+const FLAG_CHARS: [(char, u32); 12] = [
+    ('g', NOTIFY_GENERIC),
+    ('$', NOTIFY_STRING),
+    ('l', NOTIFY_LIST),
+    ('s', NOTIFY_SET),
+    ('h', NOTIFY_HASH),
+    ('z', NOTIFY_ZSET),
+    ('x', NOTIFY_EXPIRED),
+    ('e', NOTIFY_EVICTED),
+    ('t', NOTIFY_STREAM),
+    ('m', NOTIFY_KEY_MISS),
+    ('n', NOTIFY_NEW),
+    ('K', NOTIFY_KEYSPACE),
+];
+
+/// Parse a flag string like `KEA` or `Ex`, returns None on unknown characters
+pub fn parse_notify_flags(flags: &str) -> Option<u32> {
+    let mut parsed = 0;
+
+    for c in flags.chars() {
+        parsed |= match c {
+            'A' => NOTIFY_ALL,
+            'E' => NOTIFY_KEYEVENT,
+            c => FLAG_CHARS.iter().find(|(f, _)| *f == c)?.1,
+        };
+    }
+
+    Some(parsed)
+}
+
+/// Flag string for `flags` as CONFIG GET shows it, like `AKE`
+pub fn notify_flags_to_string(flags: u32) -> String {
+    let mut string = String::new();
+
+    if flags & NOTIFY_ALL == NOTIFY_ALL {
+        string.push('A');
+    } else {
+        for (c, flag) in FLAG_CHARS {
+            if flag & NOTIFY_ALL != 0 && flags & flag != 0 {
+                string.push(c);
+            }
+        }
+    }
+
+    for (c, flag) in [
+        ('K', NOTIFY_KEYSPACE),
+        ('E', NOTIFY_KEYEVENT),
+        ('m', NOTIFY_KEY_MISS),
+        ('n', NOTIFY_NEW),
+    ] {
+        if flags & flag != 0 {
+            string.push(c);
+        }
+    }
+
+    string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_flags() {
+        let flags = parse_notify_flags("KEA").unwrap();
+        assert_eq!(flags, NOTIFY_KEYSPACE | NOTIFY_KEYEVENT | NOTIFY_ALL);
+
+        let flags = parse_notify_flags("Exn").unwrap();
+        assert_eq!(flags, NOTIFY_KEYEVENT | NOTIFY_EXPIRED | NOTIFY_NEW);
+
+        assert_eq!(
+            notify_flags_to_string(parse_notify_flags("EKA").unwrap()),
+            "AKE"
+        );
+        assert_eq!(
+            notify_flags_to_string(parse_notify_flags("nxgE").unwrap()),
+            "gxEn"
+        );
+        assert_eq!(notify_flags_to_string(0), "");
+
+        assert_eq!(parse_notify_flags(""), Some(0));
+        assert_eq!(parse_notify_flags("Kq"), None);
+    }
+}
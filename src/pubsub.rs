@@ -0,0 +1,309 @@
+//! Channel and pattern subscriptions for PUBLISH/SUBSCRIBE, and shard channels for SPUBLISH
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::cluster::key_hash_slot;
+use crate::glob::glob_match;
+use crate::resp::RespValue;
+
+/// Which clients are subscribed to which channels and patterns
+#[derive(Default)]
+pub struct PubSub {
+    channels: HashMap<String, HashSet<u64>>,
+    patterns: HashMap<String, HashSet<u64>>,
+    /// Shard channels grouped by the hash slot they belong to
+    shard_channels: HashMap<u16, HashMap<String, HashSet<u64>>>,
+}
+
+/// What a client subscribes to with each flavour of SUBSCRIBE
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SubscriptionKind {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+impl SubscriptionKind {
+    /// Name of the confirmation frames sent for (un)subscribing
+    pub fn frame_names(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Channel => ("subscribe", "unsubscribe"),
+            Self::Pattern => ("psubscribe", "punsubscribe"),
+            Self::Shard => ("ssubscribe", "sunsubscribe"),
+        }
+    }
+}
+
+/// Per-client subscriptions
+#[derive(Default)]
+pub struct Subscriptions {
+    pub channels: BTreeSet<String>,
+    pub patterns: BTreeSet<String>,
+    pub shard_channels: BTreeSet<String>,
+}
+
+impl Subscriptions {
+    /// Number of channels and patterns, as reported in (un)subscribe replies
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Number of shard channels, as reported in (un)ssubscribe replies
+    pub fn shard_count(&self) -> usize {
+        self.shard_channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() + self.shard_count() == 0
+    }
+
+    pub fn of_kind(&mut self, kind: SubscriptionKind) -> &mut BTreeSet<String> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Count reported in (un)subscribe replies, shard channels are counted on their own
+    pub fn count_for(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::Shard => self.shard_count(),
+            _ => self.count(),
+        }
+    }
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, kind: SubscriptionKind, name: &str, client_id: u64) -> bool {
+        match kind {
+            SubscriptionKind::Channel => self.subscribe(name, client_id),
+            SubscriptionKind::Pattern => self.psubscribe(name, client_id),
+            SubscriptionKind::Shard => self.ssubscribe(name, client_id),
+        }
+    }
+
+    pub fn remove(&mut self, kind: SubscriptionKind, name: &str, client_id: u64) -> bool {
+        match kind {
+            SubscriptionKind::Channel => self.unsubscribe(name, client_id),
+            SubscriptionKind::Pattern => self.punsubscribe(name, client_id),
+            SubscriptionKind::Shard => self.sunsubscribe(name, client_id),
+        }
+    }
+
+    /// Returns false if the client was already subscribed
+    pub fn subscribe(&mut self, channel: &str, client_id: u64) -> bool {
+        self.channels
+            .entry(channel.to_string())
+            .or_default()
+            .insert(client_id)
+    }
+
+    pub fn unsubscribe(&mut self, channel: &str, client_id: u64) -> bool {
+        remove_subscriber(&mut self.channels, channel, client_id)
+    }
+
+    pub fn psubscribe(&mut self, pattern: &str, client_id: u64) -> bool {
+        self.patterns
+            .entry(pattern.to_string())
+            .or_default()
+            .insert(client_id)
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &str, client_id: u64) -> bool {
+        remove_subscriber(&mut self.patterns, pattern, client_id)
+    }
+
+    pub fn ssubscribe(&mut self, channel: &str, client_id: u64) -> bool {
+        self.shard_channels
+            .entry(key_hash_slot(channel))
+            .or_default()
+            .entry(channel.to_string())
+            .or_default()
+            .insert(client_id)
+    }
+
+    pub fn sunsubscribe(&mut self, channel: &str, client_id: u64) -> bool {
+        let slot = key_hash_slot(channel);
+        let channels = match self.shard_channels.get_mut(&slot) {
+            Some(c) => c,
+            None => return false,
+        };
+
+        let removed = remove_subscriber(channels, channel, client_id);
+        if channels.is_empty() {
+            self.shard_channels.remove(&slot);
+        }
+
+        removed
+    }
+
+    /// The clients a message on shard channel `channel` goes to and the message they receive
+    pub fn shard_messages(&self, channel: &str, message: &str) -> Vec<(u64, Vec<RespValue>)> {
+        let clients = match self.shard_clients(channel) {
+            Some(c) => c,
+            None => return Vec::new(),
+        };
+
+        clients
+            .iter()
+            .map(|client| {
+                (
+                    *client,
+                    vec![
+                        RespValue::BulkString("smessage".into()),
+                        RespValue::BulkString(channel.into()),
+                        RespValue::BulkString(message.into()),
+                    ],
+                )
+            })
+            .collect()
+    }
+
+    fn shard_clients(&self, channel: &str) -> Option<&HashSet<u64>> {
+        self.shard_channels
+            .get(&key_hash_slot(channel))
+            .and_then(|channels| channels.get(channel))
+    }
+
+    /// The clients a message on `channel` goes to and the message each of them receives
+    pub fn messages(&self, channel: &str, message: &str) -> Vec<(u64, Vec<RespValue>)> {
+        let mut messages = Vec::new();
+
+        if let Some(clients) = self.channels.get(channel) {
+            for client in clients {
+                messages.push((
+                    *client,
+                    vec![
+                        RespValue::BulkString("message".into()),
+                        RespValue::BulkString(channel.into()),
+                        RespValue::BulkString(message.into()),
+                    ],
+                ));
+            }
+        }
+
+        for (pattern, clients) in self.patterns.iter() {
+            if !glob_match(pattern, channel, false) {
+                continue;
+            }
+
+            for client in clients {
+                messages.push((
+                    *client,
+                    vec![
+                        RespValue::BulkString("pmessage".into()),
+                        RespValue::BulkString(pattern.into()),
+                        RespValue::BulkString(channel.into()),
+                        RespValue::BulkString(message.into()),
+                    ],
+                ));
+            }
+        }
+
+        messages
+    }
+
+    /// Channels with at least one subscriber, optionally matching `pattern`
+    pub fn active_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.channels
+            .keys()
+            .filter(|c| match pattern {
+                Some(p) => glob_match(p, c, false),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn numsub(&self, channel: &str) -> usize {
+        self.channels.get(channel).map_or(0, |c| c.len())
+    }
+
+    /// Shard channels with at least one subscriber, optionally matching `pattern`
+    pub fn active_shard_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.shard_channels
+            .values()
+            .flat_map(|channels| channels.keys())
+            .filter(|c| match pattern {
+                Some(p) => glob_match(p, c, false),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn shard_numsub(&self, channel: &str) -> usize {
+        self.shard_clients(channel).map_or(0, |c| c.len())
+    }
+
+    /// Number of patterns subscribed to by any client
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+fn remove_subscriber(map: &mut HashMap<String, HashSet<u64>>, name: &str, client_id: u64) -> bool {
+    let clients = match map.get_mut(name) {
+        Some(c) => c,
+        None => return false,
+    };
+
+    let removed = clients.remove(&client_id);
+    if clients.is_empty() {
+        map.remove(name);
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let mut pubsub = PubSub::new();
+        pubsub.subscribe("news", 1);
+        pubsub.psubscribe("n*", 2);
+        pubsub.psubscribe("x*", 3);
+
+        let messages = pubsub.messages("news", "hello");
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().any(|(c, m)| *c == 2 && m.len() == 4));
+
+        assert_eq!(pubsub.numsub("news"), 1);
+        assert_eq!(pubsub.numpat(), 2);
+        assert_eq!(pubsub.active_channels(Some("x*")).len(), 0);
+
+        assert!(pubsub.unsubscribe("news", 1));
+        assert!(!pubsub.unsubscribe("news", 1));
+        assert_eq!(pubsub.active_channels(None).len(), 0);
+    }
+
+    #[test]
+    fn test_shard_messages() {
+        let mut pubsub = PubSub::new();
+        pubsub.ssubscribe("{user}.news", 1);
+        pubsub.ssubscribe("{user}.chat", 2);
+        // classic subscribers don't see shard messages and vice versa
+        pubsub.subscribe("{user}.news", 3);
+
+        let messages = pubsub.shard_messages("{user}.news", "hello");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, 1);
+        assert_eq!(messages[0].1[0], RespValue::BulkString("smessage".into()));
+        assert_eq!(pubsub.messages("{user}.news", "hello").len(), 1);
+
+        assert_eq!(pubsub.shard_numsub("{user}.chat"), 1);
+        assert_eq!(pubsub.active_shard_channels(Some("*news")).len(), 1);
+
+        assert!(pubsub.sunsubscribe("{user}.news", 1));
+        assert!(pubsub.sunsubscribe("{user}.chat", 2));
+        assert!(pubsub.shard_channels.is_empty());
+    }
+}
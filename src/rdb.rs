@@ -0,0 +1,293 @@
+//! RDB snapshots, which only hold the function libraries for now
+//!
+//! Keys aren't saved. Loading reads the aux fields and libraries of any RDB, but refuses
+//! one that holds keys: the next save would write over them with just the libraries.
+
+use std::fs;
+use std::path::Path;
+
+use crate::server::REDIS_VERSION;
+
+const MAGIC: &[u8] = b"REDIS";
+const VERSION: &[u8] = b"0011";
+
+const OPCODE_FUNCTION2: u8 = 0xf5;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+/// Length prefixes starting with these two bits are special encodings
+const ENCODED: u8 = 0b11;
+const ENCODED_INT8: u8 = 0;
+const ENCODED_INT16: u8 = 1;
+const ENCODED_INT32: u8 = 2;
+const ENCODED_LZF: u8 = 3;
+
+/// Snapshot holding the code of the given function libraries
+pub fn save<'a>(libraries: impl Iterator<Item = &'a str>) -> Vec<u8> {
+    let mut rdb = [MAGIC, VERSION].concat();
+    for (field, value) in [("redis-ver", REDIS_VERSION), ("redis-bits", "64")] {
+        rdb.push(OPCODE_AUX);
+        write_string(&mut rdb, field.as_bytes());
+        write_string(&mut rdb, value.as_bytes());
+    }
+    for code in libraries {
+        rdb.push(OPCODE_FUNCTION2);
+        write_string(&mut rdb, code.as_bytes());
+    }
+    rdb.push(OPCODE_EOF);
+
+    let checksum = crc64(&rdb);
+    rdb.extend_from_slice(&checksum.to_le_bytes());
+    rdb
+}
+
+/// Write a snapshot to `path` through a temporary file, so a failed save keeps the old one
+pub fn save_file(path: &Path, rdb: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    fs::write(&tmp, rdb)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Failed saving the DB to '{}': {}", path.display(), e)
+        })
+}
+
+/// Code of the function libraries in a snapshot
+pub fn load(data: &[u8]) -> Result<Vec<String>, String> {
+    let body = data
+        .strip_prefix(MAGIC)
+        .filter(|rest| rest.len() >= VERSION.len())
+        .ok_or("Wrong signature trying to load DB from file")?;
+    let version: u32 = std::str::from_utf8(&body[..VERSION.len()])
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or("Wrong signature trying to load DB from file")?;
+    if !(1..=11).contains(&version) {
+        return Err(format!("Can't handle RDB format version {}", version));
+    }
+
+    // a zero checksum means it was saved without one
+    if let Some(split) = data.len().checked_sub(8) {
+        let (content, checksum) = data.split_at(split);
+        let checksum = u64::from_le_bytes(checksum.try_into().unwrap());
+        let checked = version >= 5 && content.last() == Some(&OPCODE_EOF) && checksum != 0;
+        if checked && crc64(content) != checksum {
+            return Err("Wrong RDB checksum".into());
+        }
+    }
+
+    let mut reader = Reader {
+        data: &body[VERSION.len()..],
+    };
+    let mut libraries = Vec::new();
+    loop {
+        match reader.byte()? {
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_FUNCTION2 => {
+                let code = reader.string()?;
+                let code = String::from_utf8(code).map_err(|_| "Invalid library code")?;
+                libraries.push(code);
+            }
+            // Redis only writes databases that have keys
+            OPCODE_SELECTDB => {
+                return Err(
+                    "The RDB holds keys, which can't be loaded and would be lost on the next save. Move it away to start without it".into(),
+                )
+            }
+            OPCODE_EOF => return Ok(libraries),
+            opcode => return Err(format!("Unexpected opcode {} before the keys", opcode)),
+        }
+    }
+}
+
+fn write_length(rdb: &mut Vec<u8>, len: usize) {
+    match len {
+        len if len < 1 << 6 => rdb.push(len as u8),
+        len if len < 1 << 14 => rdb.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]),
+        len if len <= u32::MAX as usize => {
+            rdb.push(0x80);
+            rdb.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        len => {
+            rdb.push(0x81);
+            rdb.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+}
+
+fn write_string(rdb: &mut Vec<u8>, s: &[u8]) {
+    write_length(rdb, s.len());
+    rdb.extend_from_slice(s);
+}
+
+enum Length {
+    Len(usize),
+    /// The kind of special encoding
+    Encoded(u8),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8], String> {
+        if n > self.data.len() {
+            return Err("Short read loading DB".into());
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn length(&mut self) -> Result<Length, String> {
+        let first = self.byte()?;
+        let len = match (first >> 6, first) {
+            (0, _) => (first & 0x3f) as usize,
+            (1, _) => ((first & 0x3f) as usize) << 8 | self.byte()? as usize,
+            (2, 0x80) => u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()) as usize,
+            (2, 0x81) => u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()) as usize,
+            (ENCODED, _) => return Ok(Length::Encoded(first & 0x3f)),
+            _ => return Err(format!("Unknown length encoding {}", first)),
+        };
+        Ok(Length::Len(len))
+    }
+
+    fn plain_length(&mut self) -> Result<usize, String> {
+        match self.length()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err("Unexpected encoded length".into()),
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        let string = match self.length()? {
+            Length::Len(len) => self.bytes(len)?.to_vec(),
+            Length::Encoded(ENCODED_INT8) => (self.byte()? as i8).to_string().into_bytes(),
+            Length::Encoded(ENCODED_INT16) => {
+                let n = i16::from_le_bytes(self.bytes(2)?.try_into().unwrap());
+                n.to_string().into_bytes()
+            }
+            Length::Encoded(ENCODED_INT32) => {
+                let n = i32::from_le_bytes(self.bytes(4)?.try_into().unwrap());
+                n.to_string().into_bytes()
+            }
+            Length::Encoded(ENCODED_LZF) => {
+                let compressed_len = self.plain_length()?;
+                let len = self.plain_length()?;
+                let compressed = self.bytes(compressed_len)?;
+                lzf_decompress(compressed, len).ok_or("Invalid LZF compressed string")?
+            }
+            Length::Encoded(encoding) => {
+                return Err(format!("Unknown RDB string encoding type {}", encoding))
+            }
+        };
+        Ok(string)
+    }
+}
+
+/// LZF as Redis compresses long strings with, None if the data is damaged or doesn't
+/// come out `len` bytes long
+fn lzf_decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+
+        if ctrl < 1 << 5 {
+            // a run of ctrl + 1 literal bytes
+            out.extend_from_slice(input.get(i..i + ctrl + 1)?);
+            i += ctrl + 1;
+        } else {
+            // a back reference copying from the output
+            let mut n = ctrl >> 5;
+            if n == 7 {
+                n += *input.get(i)? as usize;
+                i += 1;
+            }
+            let back = ((ctrl & 0x1f) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+
+            let start = out.len().checked_sub(back)?;
+            for k in start..start + n + 2 {
+                out.push(out[k]);
+            }
+        }
+
+        if out.len() > len {
+            return None;
+        }
+    }
+
+    (out.len() == len).then_some(out)
+}
+
+/// CRC-64 with the Jones polynomial, what Redis checksums RDB files with
+fn crc64(data: &[u8]) -> u64 {
+    let mut crc = 0u64;
+    for &b in data {
+        crc ^= b as u64;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x95ac9329ac4bc9b5,
+                _ => crc >> 1,
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let long = format!("#!lua name=long\n{}", "-- padding\n".repeat(2000));
+        let libraries = vec!["#!lua name=a\nreturn 1".to_string(), long];
+        let rdb = save(libraries.iter().map(|l| l.as_str()));
+
+        assert!(rdb.starts_with(b"REDIS0011"));
+        assert_eq!(load(&rdb), Ok(libraries));
+
+        let mut damaged = rdb.clone();
+        damaged[12] ^= 1;
+        assert_eq!(load(&damaged), Err("Wrong RDB checksum".into()));
+        assert!(load(b"NOTREDIS").is_err());
+        assert!(load(&rdb[..20]).is_err());
+    }
+
+    #[test]
+    fn test_load_redis_encodings() {
+        // aux fields as Redis writes them, an int encoded value and an LZF compressed library
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(b"\xfa\x0aredis-bits\xc0\x40");
+        rdb.extend_from_slice(b"\xf5\xc3\x09\x0d\x05#!lua \xa0\x03");
+        let mut with_keys = rdb.clone();
+        rdb.extend_from_slice(b"\xff");
+        rdb.extend_from_slice(&[0; 8]);
+
+        assert_eq!(load(&rdb), Ok(vec!["#!lua lua lua".into()]));
+
+        with_keys.extend_from_slice(b"\xfe\x00\x00\x01k\x01v\xff");
+        with_keys.extend_from_slice(&[0; 8]);
+        assert!(load(&with_keys)
+            .unwrap_err()
+            .starts_with("The RDB holds keys"));
+    }
+}
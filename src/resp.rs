@@ -9,6 +9,11 @@ pub enum RespValue {
     Boolean(bool),
     SimpleError(String),
     Nil,
+    NullArray,
+    /// RESP3 map, sent as a flat array to RESP2 clients
+    Map(Vec<(RespValue, RespValue)>),
+    /// RESP3 out of band push, sent as an array to RESP2 clients
+    Push(Vec<RespValue>),
     Eof,
 }
 
@@ -21,8 +26,12 @@ impl RespValue {
             RespValue::Boolean(b) => RespValue::serialize_boolean(b),
             RespValue::Array(ref a) => RespValue::serialize_array(a)?,
             RespValue::SimpleError(e) => RespValue::serialize_simple_error(e),
-            RespValue::Nil => {
-                todo!();
+            RespValue::Nil => RespValue::serialize_null(),
+            RespValue::NullArray => String::from("*-1\r\n"),
+            RespValue::Map(ref m) => RespValue::serialize_map(m)?,
+            RespValue::Push(ref p) => {
+                let array = RespValue::serialize_array(p)?;
+                format!(">{}", &array[1..])
             }
             RespValue::Eof => {
                 todo!();
@@ -41,12 +50,12 @@ impl RespValue {
     }
 
     pub fn serialize_bulk_string(s: &str) -> String {
-        // Null bulk string string
-        if s.is_empty() {
-            String::from("$-1\r\n")
-        } else {
-            format!("${}\r\n{}\r\n", s.len(), s)
-        }
+        format!("${}\r\n{}\r\n", s.len(), s)
+    }
+
+    /// Null bulk string
+    pub fn serialize_null() -> String {
+        String::from("$-1\r\n")
     }
 
     pub fn serialize_boolean(b: &bool) -> String {
@@ -72,6 +81,31 @@ impl RespValue {
         Ok(format!("*{}\r\n{}", a.len(), parts))
     }
 
+    pub fn serialize_map(m: &[(RespValue, RespValue)]) -> Result<String, RespError> {
+        let mut parts = String::new();
+        for (k, v) in m {
+            parts.push_str(&k.serialize_value()?);
+            parts.push_str(&v.serialize_value()?);
+        }
+
+        Ok(format!("%{}\r\n{}", m.len(), parts))
+    }
+
+    /// Downgrade RESP3 only types to their RESP2 counterparts
+    pub fn into_resp2(self) -> RespValue {
+        match self {
+            RespValue::Map(m) => RespValue::Array(
+                m.into_iter()
+                    .flat_map(|(k, v)| [k.into_resp2(), v.into_resp2()])
+                    .collect(),
+            ),
+            RespValue::Push(p) | RespValue::Array(p) => {
+                RespValue::Array(p.into_iter().map(RespValue::into_resp2).collect())
+            }
+            v => v,
+        }
+    }
+
     pub fn serialize(&mut self) -> Result<Vec<u8>, RespError> {
         let serialized = self.serialize_value()?;
         Ok(serialized.as_bytes().to_vec())
@@ -80,18 +114,33 @@ impl RespValue {
 
 pub type RespParseResult = Result<RespValue, RespError>;
 
+/// Longest bulk string the parser accepts, the default proto-max-bulk-len
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Most elements an array may have
+const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+
 #[derive(Debug, PartialEq)]
 pub struct RespError {
     msg: String,
     idx: usize,
-    char: char,
+    /// Set when the input ended early, to how many bytes the value takes at least
+    needed: Option<usize>,
+}
+
+impl RespError {
+    /// Bytes of input a value that isn't complete yet needs at least, None if the
+    /// input is invalid
+    pub fn needed(&self) -> Option<usize> {
+        self.needed
+    }
 }
 
 impl Error for RespError {}
 
 impl Display for RespError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} at {}, char {}", self.msg, self.idx, self.char)
+        write!(f, "{} at {}", self.msg, self.idx)
     }
 }
 
@@ -99,61 +148,78 @@ impl Display for RespError {
 ///
 /// It parses one item at a time, ie. from the next item type (`:, +, #, ...`) identifier to the next `\r\n``
 ///
-/// Built on a iterator
+/// Built on a iterator of bytes, strings have to be valid UTF-8
 ///
 /// Parsing happens step-wise, and methods reflect this as they are broken down into common operators
 pub struct RespParser<I>
 where
-    I: Iterator<Item = char>,
+    I: Iterator<Item = u8>,
 {
-    chars: Peekable<I>,
+    bytes: Peekable<I>,
     idx: usize,
 }
 
-impl<I: Iterator<Item = char>> RespParser<I> {
+impl<I: Iterator<Item = u8>> RespParser<I> {
     pub fn new(it: I) -> Self {
         Self {
-            chars: it.peekable(),
+            bytes: it.peekable(),
             idx: 0,
         }
     }
 
+    /// Bytes consumed so far
+    pub fn position(&self) -> usize {
+        self.idx
+    }
+
     /// Construct a error message and return a [`RespParseResult`]
     pub fn err(&mut self, msg: String) -> RespParseResult {
         Err(RespError {
             msg,
             idx: self.idx,
-            char: 'a',
+            needed: None,
         })
     }
 
     /// Unexpected EOF
     pub fn unexpected_eof(&mut self) -> RespParseResult {
-        self.err("unexpected eof".into())
+        self.unexpected_eof_needing(self.idx + 1)
+    }
+
+    /// Unexpected EOF when it's known the input has to be at least `needed` bytes long
+    fn unexpected_eof_needing(&mut self, needed: usize) -> RespParseResult {
+        Err(RespError {
+            msg: "unexpected eof".into(),
+            idx: self.idx,
+            needed: Some(needed),
+        })
     }
 
-    /// Consume and return the next item in `char` iterator
-    pub fn next(&mut self) -> Option<char> {
-        self.idx += 1;
-        self.chars.next()
+    /// Consume and return the next byte
+    pub fn next(&mut self) -> Option<u8> {
+        let b = self.bytes.next();
+        if b.is_some() {
+            self.idx += 1;
+        }
+        b
     }
 
-    /// Pek at next `char` in iterator
-    pub fn peek(&mut self) -> Option<char> {
-        self.chars.peek().copied()
+    /// Peek at the next byte
+    pub fn peek(&mut self) -> Option<u8> {
+        self.bytes.peek().copied()
     }
 
-    /// Check that next two chars are `\r\n', if yes consume them
+    /// Check that next two bytes are `\r\n', if yes consume them
     fn correct_sep(&mut self) -> RespParseResult {
         match self.next() {
-            Some('\r') => {}
-            Some(c) => return self.err(format!("\\r separator expected, found {}", c)),
+            Some(b'\r') => {}
+            Some(c) => return self.err(format!("\\r separator expected, found {}", c as char)),
             None => return self.unexpected_eof(),
         };
 
         match self.next() {
-            Some('\n') => {}
-            Some(c) => return self.err(format!("\\n separator expected, found {}", c)),
+            Some(b'\n') => {}
+            Some(c) => return self.err(format!("\\n separator expected, found {}", c as char)),
             None => return self.unexpected_eof(),
         };
 
@@ -161,11 +227,15 @@ impl<I: Iterator<Item = char>> RespParser<I> {
     }
 
     /// Parse an arbitraty constant
+    #[allow(dead_code)]
     pub fn parse_constant(&mut self, s: &str) -> Option<String> {
-        for c in s.chars() {
+        for c in s.bytes() {
             match self.next() {
                 Some(x) if x != c => {
-                    let msg = format!("unexpected value {} while parsing {} of {:?}", x, c, s);
+                    let msg = format!(
+                        "unexpected value {} while parsing {} of {:?}",
+                        x as char, c as char, s
+                    );
                     return Some(msg);
                 }
                 Some(_) => {}
@@ -180,101 +250,125 @@ impl<I: Iterator<Item = char>> RespParser<I> {
         let mut s = String::new();
 
         match self.peek() {
-            Some('-' | '+') => {
-                s.push(self.next().unwrap());
+            Some(b'-' | b'+') => {
+                s.push(self.next().unwrap() as char);
+            }
+            Some(b'0'..=b'9') => {}
+            Some(c) => {
+                return self.err(format!(
+                    "invalid character while parsing integer '{}'",
+                    c as char
+                ))
             }
-            Some('0'..='9') => {}
-            Some(c) => return self.err(format!("invalid character while parsing integer '{}'", c)),
             None => {}
         }
 
-        while Some('\r') != self.peek() {
+        while Some(b'\r') != self.peek() {
             match self.peek() {
-                Some('0'..='9') => s.push(self.next().unwrap()),
+                Some(b'0'..=b'9') => s.push(self.next().unwrap() as char),
                 Some(c) => {
-                    return self.err(format!("invalid char '{}' found while parsing integer", c));
+                    return self.err(format!(
+                        "invalid char '{}' found while parsing integer",
+                        c as char
+                    ));
                 }
-                None => return self.err(String::from("Unterminated integer")),
+                None => return self.unexpected_eof(),
             }
         }
 
         self.correct_sep()?;
 
-        Ok(RespValue::Integer(s.parse::<i64>().unwrap()))
+        match s.parse::<i64>() {
+            Ok(i) => Ok(RespValue::Integer(i)),
+            Err(_) => self.err(format!("invalid integer '{}'", s)),
+        }
     }
 
     /// Parse a boolean value
     pub fn parse_bool(&mut self) -> RespParseResult {
         match self.next() {
-            Some('f') => {
+            Some(b'f') => {
                 self.correct_sep()?;
                 Ok(RespValue::Boolean(false))
             }
-            Some('t') => {
+            Some(b't') => {
                 self.correct_sep()?;
                 Ok(RespValue::Boolean(true))
             }
-            Some(c) => self.err(format!("invalid value for boolean: '{}'", c)),
+            Some(c) => self.err(format!("invalid value for boolean: '{}'", c as char)),
             None => self.unexpected_eof(),
         }
     }
 
     /// Parse a simple string
     pub fn parse_simple_string(&mut self) -> RespParseResult {
-        let mut s = String::new();
+        let mut s = Vec::new();
 
-        while let Some(c) = self.peek() {
-            if c == '\r' {
-                self.correct_sep()?;
-                break;
-            } else {
-                s.push(self.next().unwrap());
+        loop {
+            match self.peek() {
+                Some(b'\r') => {
+                    self.correct_sep()?;
+                    break;
+                }
+                Some(_) => s.push(self.next().unwrap()),
+                None => return self.unexpected_eof(),
             }
         }
 
-        Ok(RespValue::SimpleString(s))
+        match String::from_utf8(s) {
+            Ok(s) => Ok(RespValue::SimpleString(s)),
+            Err(_) => self.err("invalid UTF-8 in simple string".into()),
+        }
     }
 
     /// Parse a bulk string
     pub fn parse_bulk_string(&mut self) -> RespParseResult {
         let mut s = String::new();
 
-        while let Some('0'..='9') = self.peek() {
-            s.push(self.next().unwrap());
+        while let Some(b'0'..=b'9') = self.peek() {
+            s.push(self.next().unwrap() as char);
         }
 
         let size = match s.parse::<usize>() {
-            Ok(v) => v,
-            Err(_) => return self.err(format!("failed to parse bulk string size {}", s)),
+            Ok(v) if v <= MAX_BULK_LEN => v,
+            _ if self.peek().is_none() => return self.unexpected_eof(),
+            _ => return self.err(format!("invalid bulk string size {}", s)),
         };
 
         self.correct_sep()?;
 
-        let mut blk_string = String::with_capacity(size);
+        // the size isn't trusted until the bytes are there
+        let mut blk_string = Vec::with_capacity(size.min(64 * 1024));
+        let end = self.idx + size + 2;
 
         for _ in 0..size {
             match self.next() {
                 Some(c) => blk_string.push(c),
-                None => return self.unexpected_eof(),
+                None => return self.unexpected_eof_needing(end),
             }
         }
 
         self.correct_sep()?;
 
-        Ok(RespValue::BulkString(blk_string))
+        match String::from_utf8(blk_string) {
+            Ok(s) => Ok(RespValue::BulkString(s)),
+            Err(_) => self.err("invalid UTF-8 in bulk string".into()),
+        }
     }
 
     pub fn parse_array(&mut self) -> RespParseResult {
         let size = match self.parse_int()? {
-            RespValue::Integer(c) => c,
-            _ => return self.err("invalid size".into()),
+            RespValue::Integer(c) if c <= MAX_ARRAY_LEN => c,
+            _ => return self.err("invalid array size".into()),
         };
 
-        let mut arr: Vec<RespValue> = Vec::with_capacity(size as usize);
+        let mut arr: Vec<RespValue> = Vec::with_capacity(size.clamp(0, 1024) as usize);
 
         for _ in 0..size {
-            let v = self.parse_next()?;
-            arr.push(v);
+            match self.parse_next()? {
+                RespValue::Eof => return self.unexpected_eof(),
+                v => arr.push(v),
+            }
         }
 
         // self.correct_sep()?;
@@ -285,6 +379,7 @@ impl<I: Iterator<Item = char>> RespParser<I> {
     pub fn parse_simple_error(&mut self) -> RespParseResult {
         let simple_error = match self.parse_simple_string() {
             Ok(RespValue::SimpleString(s)) => s,
+            Err(e) if e.needed.is_some() => return Err(e),
             Err(_) | Ok(_) => return self.err("Failed to parse simple error".into()),
         };
 
@@ -293,13 +388,13 @@ impl<I: Iterator<Item = char>> RespParser<I> {
 
     pub fn parse_next(&mut self) -> RespParseResult {
         match self.next() {
-            Some('+') => self.parse_simple_string(),
-            Some(':') => self.parse_int(),
-            Some('#') => self.parse_bool(),
-            Some('$') => self.parse_bulk_string(),
-            Some('*') => self.parse_array(),
-            Some('-') => self.parse_simple_error(),
-            Some(c) => self.err(format!("invalid type identifier found: '{}'", c)),
+            Some(b'+') => self.parse_simple_string(),
+            Some(b':') => self.parse_int(),
+            Some(b'#') => self.parse_bool(),
+            Some(b'$') => self.parse_bulk_string(),
+            Some(b'*') => self.parse_array(),
+            Some(b'-') => self.parse_simple_error(),
+            Some(c) => self.err(format!("invalid type identifier found: '{}'", c as char)),
             // Expected EOF
             None => Ok(RespValue::Eof),
         }
@@ -312,12 +407,12 @@ pub mod test {
 
     #[test]
     fn parse_simple_string() {
-        let mut parser = RespParser::new("Testing\r\n".chars());
+        let mut parser = RespParser::new("Testing\r\n".bytes());
         let out = parser.parse_simple_string().unwrap();
 
         assert_eq!(out, RespValue::SimpleString(String::from("Testing")));
 
-        let mut parser = RespParser::new("Test ing\r\n".chars());
+        let mut parser = RespParser::new("Test ing\r\n".bytes());
         let out = parser.parse_simple_string().unwrap();
 
         assert_eq!(out, RespValue::SimpleString(String::from("Test ing")));
@@ -325,17 +420,17 @@ pub mod test {
 
     #[test]
     fn parse_int() {
-        let mut parser = RespParser::new("89\r\n".chars());
+        let mut parser = RespParser::new("89\r\n".bytes());
         let out = parser.parse_int().unwrap();
 
         assert_eq!(out, RespValue::Integer(89));
 
-        let mut parser = RespParser::new("+32\r\n".chars());
+        let mut parser = RespParser::new("+32\r\n".bytes());
         let out = parser.parse_int().unwrap();
 
         assert_eq!(out, RespValue::Integer(32));
 
-        let mut parser = RespParser::new("-1223\r\n".chars());
+        let mut parser = RespParser::new("-1223\r\n".bytes());
         let out = parser.parse_int().unwrap();
 
         assert_eq!(out, RespValue::Integer(-1223));
@@ -343,12 +438,12 @@ pub mod test {
 
     #[test]
     fn parse_bool() {
-        let mut parser = RespParser::new("t\r\n".chars());
+        let mut parser = RespParser::new("t\r\n".bytes());
         let out = parser.parse_bool().unwrap();
 
         assert_eq!(out, RespValue::Boolean(true));
 
-        let mut parser = RespParser::new("f\r\n".chars());
+        let mut parser = RespParser::new("f\r\n".bytes());
         let out = parser.parse_bool().unwrap();
 
         assert_eq!(out, RespValue::Boolean(false));
@@ -356,11 +451,11 @@ pub mod test {
 
     #[test]
     fn parse_bulk_string() {
-        let mut parser = RespParser::new("2\r\nOK\r\n".chars());
+        let mut parser = RespParser::new("2\r\nOK\r\n".bytes());
         let out = parser.parse_bulk_string().unwrap();
         assert_eq!(out, RespValue::BulkString("OK".into()));
 
-        let mut parser = RespParser::new("24\r\nthis is a \rlonge\nr value\r\n".chars());
+        let mut parser = RespParser::new("24\r\nthis is a \rlonge\nr value\r\n".bytes());
         let out = parser.parse_bulk_string().unwrap();
         assert_eq!(
             out,
@@ -369,7 +464,7 @@ pub mod test {
     }
     #[test]
     fn parse_basic_array() {
-        let mut parser = RespParser::new("2\r\n:32\r\n+test\r\n".chars());
+        let mut parser = RespParser::new("2\r\n:32\r\n+test\r\n".bytes());
         let out = parser.parse_array().unwrap();
 
         assert_eq!(
@@ -380,7 +475,7 @@ pub mod test {
             ])
         );
 
-        let mut parser = RespParser::new("4\r\n:32\r\n+test\r\n$2\r\nOK\r\n#t\r\n".chars());
+        let mut parser = RespParser::new("4\r\n:32\r\n+test\r\n$2\r\nOK\r\n#t\r\n".bytes());
         let out = parser.parse_array().unwrap();
 
         assert_eq!(
@@ -397,7 +492,7 @@ pub mod test {
     #[test]
     fn parse_nested_array() {
         let mut parser =
-            RespParser::new("2\r\n*3\r\n:1\r\n:2\r\n:3\r\n*2\r\n+Hello\r\n-World\r\n".chars());
+            RespParser::new("2\r\n*3\r\n:1\r\n:2\r\n:3\r\n*2\r\n+Hello\r\n-World\r\n".bytes());
 
         let out = parser.parse_array().unwrap();
 
@@ -419,7 +514,7 @@ pub mod test {
 
     #[test]
     fn test_parser() {
-        let mut parser = RespParser::new("*3\r\n+ECHO\r\n$2\r\nOK\r\n+test\r\n".chars());
+        let mut parser = RespParser::new("*3\r\n+ECHO\r\n$2\r\nOK\r\n+test\r\n".bytes());
 
         let out = parser.parse_next().unwrap();
 
@@ -433,6 +528,48 @@ pub mod test {
         )
     }
 
+    #[test]
+    fn parse_bytes() {
+        // sizes count bytes rather than chars
+        let mut parser = RespParser::new("*1\r\n$2\r\né\r\n".bytes());
+        assert_eq!(
+            parser.parse_next().unwrap(),
+            RespValue::Array(vec![RespValue::BulkString("é".into())])
+        );
+        assert_eq!(parser.position(), 12);
+
+        let mut parser = RespParser::new(b"*1\r\n$2\r\n\xff\xfe\r\n".iter().copied());
+        let err = parser.parse_next().unwrap_err();
+        assert_eq!(err.to_string(), "invalid UTF-8 in bulk string at 12");
+        assert_eq!(err.needed(), None);
+
+        // requests that aren't all there yet, with the length they need at least
+        for (partial, needed) in [
+            ("*2\r\n$3\r\nGET\r\n", 14),
+            ("*1\r\n$3000\r\nGE", 3013),
+            ("*1\r\n$1", 7),
+            ("*1", 3),
+            ("+OK", 4),
+        ] {
+            let err = RespParser::new(partial.bytes()).parse_next().unwrap_err();
+            assert_eq!(err.needed(), Some(needed), "{:?}", partial);
+        }
+
+        for invalid in [
+            "*1\r\n$-1\r\n",
+            "*1\r\n$99999999999\r\n",
+            "*x\r\n",
+            "*-\r\n",
+            "*1\r\n!",
+        ] {
+            assert!(
+                RespParser::new(invalid.bytes()).parse_next().is_err(),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
     #[test]
     fn serialize_int() {
         assert_eq!(
@@ -473,6 +610,20 @@ pub mod test {
         assert_eq!(
             b"$2\r\nOK\r\n".to_vec(),
             RespValue::BulkString("OK".into()).serialize().unwrap()
+        );
+
+        assert_eq!(
+            b"$0\r\n\r\n".to_vec(),
+            RespValue::BulkString("".into()).serialize().unwrap()
+        )
+    }
+
+    #[test]
+    fn serialize_nil() {
+        assert_eq!(b"$-1\r\n".to_vec(), RespValue::Nil.serialize().unwrap());
+        assert_eq!(
+            b"*-1\r\n".to_vec(),
+            RespValue::NullArray.serialize().unwrap()
         )
     }
     #[test]
@@ -485,6 +636,33 @@ pub mod test {
         )
     }
 
+    #[test]
+    fn serialize_resp3() {
+        let map = RespValue::Map(vec![(
+            RespValue::BulkString("proto".into()),
+            RespValue::Integer(3),
+        )]);
+        assert_eq!(
+            b"%1\r\n$5\r\nproto\r\n:3\r\n".to_vec(),
+            RespValue::Map(vec![(
+                RespValue::BulkString("proto".into()),
+                RespValue::Integer(3),
+            )])
+            .serialize()
+            .unwrap()
+        );
+        assert_eq!(
+            b"*2\r\n$5\r\nproto\r\n:3\r\n".to_vec(),
+            map.into_resp2().serialize().unwrap()
+        );
+        assert_eq!(
+            b">1\r\n:1\r\n".to_vec(),
+            RespValue::Push(vec![RespValue::Integer(1)])
+                .serialize()
+                .unwrap()
+        );
+    }
+
     #[test]
     fn serialize_nested_array() {
         assert_eq!(
@@ -0,0 +1,418 @@
+//! Lua scripting for EVAL and EVALSHA
+//!
+//! Scripts see the `redis` library, `KEYS` and `ARGV` on top of the Lua standard
+//! library. Commands reach the server through a [`Host`], replies are converted to Lua
+//! values and back following the same rules as Redis.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::log::Level;
+use crate::lua::{self, Block, Host, Interp, LuaError, LuaResult, Table, Value};
+use crate::resp::RespValue;
+use crate::server::REDIS_VERSION;
+use crate::sha1::sha1_hex;
+
+/// Scripts by the hex SHA1 of their body, filled by EVAL and SCRIPT LOAD
+#[derive(Default)]
+pub struct ScriptCache {
+    scripts: HashMap<String, String>,
+}
+
+impl ScriptCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a script, returns its SHA1
+    pub fn insert(&mut self, body: String) -> String {
+        let sha = sha1_hex(body.as_bytes());
+        self.scripts.insert(sha.clone(), body);
+        sha
+    }
+
+    pub fn get(&self, sha: &str) -> Option<&str> {
+        self.scripts.get(&sha.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.scripts.contains_key(&sha.to_lowercase())
+    }
+
+    pub fn flush(&mut self) {
+        self.scripts.clear();
+    }
+
+    pub fn count(&self) -> usize {
+        self.scripts.len()
+    }
+}
+
+/// Parse a script body, the error is the reply for scripts that don't compile
+pub fn compile(body: &str) -> Result<Block, RespValue> {
+    lua::parse(body).map_err(|e| {
+        RespValue::SimpleError(format!("ERR Error compiling script (new function): {}", e))
+    })
+}
+
+/// Run a compiled script and turn its result or error into a reply
+pub fn run_script(
+    host: &mut dyn Host,
+    script: Block,
+    sha: &str,
+    keys: Vec<String>,
+    args: Vec<String>,
+) -> RespValue {
+    let globals = Rc::new(RefCell::new(Table::new()));
+    {
+        let mut globals = globals.borrow_mut();
+        lua::open_libs(&mut globals);
+        globals.set_str("redis", redis_lib());
+        globals.set_str("KEYS", string_array(keys));
+        globals.set_str("ARGV", string_array(args));
+    }
+    lua::reset_random();
+
+    let mut interp = Interp::new(host, globals);
+    let result = interp.run_chunk(script, Vec::new());
+    script_reply(result, sha, "user_script")
+}
+
+/// Reply for what a script or function returned
+///
+/// Errors name the script and where it failed, `name` being the SHA1 of a script or
+/// the name of a function.
+pub fn script_reply(result: LuaResult<Vec<Value>>, name: &str, chunk: &str) -> RespValue {
+    match result {
+        Ok(values) => lua_to_reply(values.into_iter().next().unwrap_or_default()),
+        Err(LuaError::Error { value, line }) => {
+            let msg = match error_field(&value) {
+                Some(err) => err,
+                None => format!("ERR {}", value.to_display()),
+            };
+            RespValue::SimpleError(format!("{} script: {}, on @{}:{}.", msg, name, chunk, line))
+        }
+        Err(LuaError::Killed) => {
+            RespValue::SimpleError("ERR Script killed by user with SCRIPT KILL...".into())
+        }
+    }
+}
+
+pub fn string_array(strings: Vec<String>) -> Value {
+    Value::table(Table::from_array(
+        strings.iter().map(|s| Value::str(s)).collect(),
+    ))
+}
+
+/// The `redis` library scripts and functions see when they run
+pub fn redis_lib() -> Value {
+    let mut redis = redis_base_lib();
+    redis.set_str("call", Value::builtin(redis_call));
+    redis.set_str("pcall", Value::builtin(redis_pcall));
+    redis.set_str("error_reply", Value::builtin(redis_error_reply));
+    redis.set_str("status_reply", Value::builtin(redis_status_reply));
+    redis.set_str("sha1hex", Value::builtin(redis_sha1hex));
+    Value::table(redis)
+}
+
+/// The part of the `redis` library that doesn't run commands, also available while a
+/// function library loads
+pub fn redis_base_lib() -> Table {
+    let mut redis = Table::new();
+    redis.set_str("log", Value::builtin(redis_log));
+
+    for (i, level) in ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"]
+        .iter()
+        .enumerate()
+    {
+        redis.set_str(level, Value::Number(i as f64));
+    }
+
+    redis.set_str("REDIS_VERSION", Value::str(REDIS_VERSION));
+    let version_num = REDIS_VERSION
+        .split('.')
+        .fold(0, |n, part| (n << 8) | part.parse::<u32>().unwrap_or(0));
+    redis.set_str("REDIS_VERSION_NUM", Value::Number(version_num as f64));
+
+    redis
+}
+
+/// The `err` field of an error table
+fn error_field(value: &Value) -> Option<String> {
+    match value {
+        Value::Table(t) => match t.borrow().get_str("err") {
+            Value::Str(s) => Some(s.to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn single_field_table(field: &str, value: &str) -> Value {
+    let mut table = Table::new();
+    table.set_str(field, Value::str(value));
+    Value::table(table)
+}
+
+/// Raise an error table like a failed `redis.call` does
+fn raise(interp: &Interp<'_>, msg: &str) -> LuaError {
+    LuaError::Error {
+        value: single_field_table("err", msg),
+        line: interp.line,
+    }
+}
+
+/// Run the command in `args` through the host
+fn host_call(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<RespValue> {
+    if args.is_empty() {
+        return Err(raise(
+            interp,
+            "ERR Please specify at least one argument for this redis lib call",
+        ));
+    }
+
+    let mut strings = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Value::Str(_) | Value::Number(_) => strings.push(arg.to_display()),
+            _ => {
+                return Err(raise(
+                    interp,
+                    "ERR Lua redis lib command arguments must be strings or integers",
+                ))
+            }
+        }
+    }
+
+    Ok(interp.host.call(strings).into_resp2())
+}
+
+/// Errors are raised and end the script unless it catches them with `pcall`
+fn redis_call(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    match host_call(interp, args)? {
+        RespValue::SimpleError(e) => Err(raise(interp, &e)),
+        reply => Ok(vec![reply_to_lua(reply)]),
+    }
+}
+
+/// Errors are returned as error tables
+fn redis_pcall(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let reply = match host_call(interp, args) {
+        Ok(reply) => reply,
+        Err(LuaError::Error { value, .. }) => return Ok(vec![value]),
+        Err(e) => return Err(e),
+    };
+    Ok(vec![reply_to_lua(reply)])
+}
+
+fn string_arg(interp: &Interp<'_>, args: &[Value], name: &str) -> LuaResult<Rc<str>> {
+    match args.first().and_then(|v| v.to_str()) {
+        Some(s) if args.len() == 1 => Ok(s),
+        _ => Err(interp.error(format!("wrong number or type of arguments to {}", name))),
+    }
+}
+
+fn redis_error_reply(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let msg = string_arg(interp, &args, "redis.error_reply")?;
+    Ok(vec![single_field_table("err", &msg)])
+}
+
+fn redis_status_reply(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let msg = string_arg(interp, &args, "redis.status_reply")?;
+    Ok(vec![single_field_table("ok", &msg)])
+}
+
+fn redis_sha1hex(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = string_arg(interp, &args, "redis.sha1hex")?;
+    Ok(vec![Value::str(&sha1_hex(s.as_bytes()))])
+}
+
+fn redis_log(interp: &mut Interp<'_>, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if args.len() < 2 {
+        return Err(interp.error("redis.log() requires two arguments or more."));
+    }
+    let level = match args[0].to_number() {
+        Some(level) => level,
+        None => return Err(interp.error("First argument must be a number (log level).")),
+    };
+    let Some(level) = Level::from_script(level as i64) else {
+        return Err(interp.error("Invalid log level."));
+    };
+
+    let msg: Vec<String> = args[1..].iter().map(|v| v.to_display()).collect();
+    interp.host.log(level, &msg.join(" "));
+    Ok(Vec::new())
+}
+
+/// RESP2 reply to Lua value
+///
+/// Status and error replies become tables with an `ok` or `err` field and nil replies
+/// become false.
+pub fn reply_to_lua(reply: RespValue) -> Value {
+    match reply {
+        RespValue::Integer(i) => Value::Number(i as f64),
+        RespValue::BulkString(s) => Value::str(&s),
+        RespValue::SimpleString(s) => single_field_table("ok", &s),
+        RespValue::SimpleError(e) => single_field_table("err", &e),
+        RespValue::Boolean(b) => Value::Bool(b),
+        RespValue::Array(items) | RespValue::Push(items) => Value::table(Table::from_array(
+            items.into_iter().map(reply_to_lua).collect(),
+        )),
+        RespValue::Map(entries) => Value::table(Table::from_array(
+            entries
+                .into_iter()
+                .flat_map(|(k, v)| [reply_to_lua(k), reply_to_lua(v)])
+                .collect(),
+        )),
+        RespValue::Nil | RespValue::NullArray | RespValue::Eof => Value::Bool(false),
+    }
+}
+
+/// Lua value to reply
+///
+/// Numbers are truncated to integers, true becomes 1 and false nil. Arrays stop at the
+/// first nil.
+pub fn lua_to_reply(value: Value) -> RespValue {
+    match value {
+        Value::Number(n) => RespValue::Integer(n as i64),
+        Value::Str(s) => RespValue::BulkString(s.to_string()),
+        Value::Bool(true) => RespValue::Integer(1),
+        Value::Table(t) => {
+            let t = t.borrow();
+            if let Value::Str(err) = t.get_str("err") {
+                return RespValue::SimpleError(err.to_string());
+            }
+            if let Value::Str(ok) = t.get_str("ok") {
+                return RespValue::SimpleString(ok.to_string());
+            }
+
+            RespValue::Array(
+                (1..=t.len())
+                    .map(|i| lua_to_reply(t.get(&Value::Number(i as f64))))
+                    .collect(),
+            )
+        }
+        Value::Bool(false) | Value::Nil | Value::Function(_) => RespValue::Nil,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every command with the reply of the first argument
+    struct EchoHost;
+
+    impl Host for EchoHost {
+        fn call(&mut self, args: Vec<String>) -> RespValue {
+            match args[0].as_str() {
+                "int" => RespValue::Integer(7),
+                "status" => RespValue::SimpleString("OK".into()),
+                "error" => RespValue::SimpleError("ERR nope".into()),
+                "nil" => RespValue::Nil,
+                _ => RespValue::Array(args.into_iter().map(RespValue::BulkString).collect()),
+            }
+        }
+
+        fn log(&mut self, _level: Level, _msg: &str) {}
+
+        fn keep_running(&mut self) -> bool {
+            true
+        }
+    }
+
+    fn eval(body: &str, keys: &[&str], args: &[&str]) -> RespValue {
+        let script = match compile(body) {
+            Ok(script) => script,
+            Err(e) => return e,
+        };
+        run_script(
+            &mut EchoHost,
+            script,
+            "sha",
+            keys.iter().map(|k| k.to_string()).collect(),
+            args.iter().map(|a| a.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(
+            eval(
+                "return {1, 2.9, 'x', true, false, nil, 'unreached'}",
+                &[],
+                &[]
+            ),
+            RespValue::Array(vec![
+                RespValue::Integer(1),
+                RespValue::Integer(2),
+                RespValue::BulkString("x".into()),
+                RespValue::Integer(1),
+                RespValue::Nil,
+            ])
+        );
+        assert_eq!(
+            eval(
+                "return {redis.call('int'), redis.call('status').ok, redis.call('nil')}",
+                &[],
+                &[]
+            ),
+            RespValue::Array(vec![
+                RespValue::Integer(7),
+                RespValue::BulkString("OK".into()),
+                RespValue::Nil,
+            ])
+        );
+        assert_eq!(
+            eval(
+                "return redis.call('echo', KEYS[1], ARGV[1], 3)",
+                &["k"],
+                &["a"]
+            ),
+            RespValue::Array(vec![
+                RespValue::BulkString("echo".into()),
+                RespValue::BulkString("k".into()),
+                RespValue::BulkString("a".into()),
+                RespValue::BulkString("3".into()),
+            ])
+        );
+        assert_eq!(
+            eval("return redis.status_reply('FINE')", &[], &[]),
+            RespValue::SimpleString("FINE".into())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            eval("return redis.pcall('error')", &[], &[]),
+            RespValue::SimpleError("ERR nope".into())
+        );
+        assert_eq!(
+            eval("\nredis.call('error')", &[], &[]),
+            RespValue::SimpleError("ERR nope script: sha, on @user_script:2.".into())
+        );
+        assert_eq!(
+            eval("return redis.call({})", &[], &[]),
+            RespValue::SimpleError(
+                "ERR Lua redis lib command arguments must be strings or integers script: sha, on @user_script:1."
+                    .into()
+            )
+        );
+        assert_eq!(
+            eval("return nosuch", &[], &[]),
+            RespValue::SimpleError(
+                "ERR user_script:1: Script attempted to access nonexistent global variable 'nosuch' script: sha, on @user_script:1."
+                    .into()
+            )
+        );
+        assert_eq!(
+            eval("return (", &[], &[]),
+            RespValue::SimpleError(
+                "ERR Error compiling script (new function): user_script:1: unexpected symbol near '<eof>'"
+                    .into()
+            )
+        );
+    }
+}
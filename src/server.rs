@@ -1,7 +1,14 @@
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
+use std::fmt::Display;
+use std::fs::{self, Permissions};
+use std::hash::{BuildHasher, Hasher};
 use std::io::{Read, Write};
-use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
 use std::thread::sleep;
 use std::time::{Duration, Instant, SystemTime};
 
@@ -27,51 +34,9 @@ pub fn gen_master_id() -> String {
     rnd
 }
 
-pub struct CliArgs {
-    pub port: Option<u32>,
-    pub replicaof: Option<(String, u32)>,
-}
-
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-impl CliArgs {
-    pub fn from_args() -> Result<CliArgs> {
-        let mut args = env::args().peekable();
-
-        let _ = args.next();
-
-        let mut port = None;
-        let mut replicaof = None;
-
-        while args.peek().is_some() {
-            match args.next().unwrap().as_str() {
-                "--port" => {
-                    let p = args.next().unwrap();
-                    port = Some(p.parse().unwrap())
-                }
-                "--replicaof" => {
-                    let host = args.next().unwrap();
-                    let port = args.next().unwrap();
-
-                    replicaof = Some((host, port.parse().unwrap()))
-                }
-                a => return Err(format!("unexpected arg: {}", a).into()),
-            }
-        }
-        Ok(Self { port, replicaof })
-    }
-}
-
-#[derive(PartialEq, Debug)]
-pub struct StoredValue {
-    value: String,
-    px: Option<Instant>,
-}
-
-impl StoredValue {
-    pub fn new(value: String, px: Option<Instant>) -> StoredValue {
-        Self { value, px }
-    }
+/// A random number from the std hasher seed, good enough for picking keys
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
 }
 
 pub struct Replication {
@@ -81,18 +46,6 @@ pub struct Replication {
     pub master_repl_offset: u64,
 }
 
-impl Replication {
-    fn serialize(&self) -> String {
-        let role = self.role.as_str();
-        let master_replid = format!("master_replid:{}", self.master_replid);
-
-        let master_repl_offset = format!("master_repl_offset:{}", self.master_repl_offset);
-
-        let serialized = [role, master_replid.as_str(), master_repl_offset.as_str()].join("\r\n");
-        String::from_utf8(RespValue::BulkString(serialized).serialize().unwrap()).unwrap()
-    }
-}
-
 impl Default for Replication {
     fn default() -> Self {
         Self {
@@ -112,33 +65,130 @@ pub enum ServerRole {
 impl ServerRole {
     fn as_str(&self) -> &str {
         match self {
-            Self::Master => "role:master",
-            Self::Slave => "role:slave",
+            Self::Master => "master",
+            Self::Slave => "slave",
         }
     }
 }
 
 pub struct Server {
-    listener: TcpListener,
-    streams: Vec<TcpStream>,
+    /// One for every `bind` address, none when `port` is 0
+    listeners: Vec<TcpListener>,
+    /// Listening on the `unixsocket` path when it is set
+    unix_listener: Option<UnixListener>,
+    clients: Vec<Client>,
     to_close: Vec<usize>,
     shutdown: bool,
-    storage: HashMap<String, StoredValue>,
+    dbs: Vec<Db>,
     replication: Replication,
+    // held so the connection to the master stays open
+    #[allow(dead_code)]
     master_stream: Option<TcpStream>,
+    lazyfree: LazyFree,
+    last_expire_cycle: Instant,
+    /// Memory limit in bytes, 0 for no limit
+    maxmemory: u64,
+    maxmemory_policy: MaxmemoryPolicy,
+    eviction_pool: EvictionPool,
+    /// Where random eviction continues from
+    next_eviction_db: usize,
+    watched_keys: WatchedKeys,
+    next_client_id: u64,
+    pubsub: PubSub,
+    /// `NOTIFY_*` classes of keyspace events to publish
+    notify_keyspace_events: u32,
+    tracking_table: TrackingTable,
+    scripts: ScriptCache,
+    functions: Libraries,
+    /// How long a script runs before other clients get BUSY replies and it can be killed
+    busy_reply_threshold: Duration,
+    /// Set by CLIENT PAUSE, until when and which commands are held back
+    pause: Option<(Instant, PauseMode)>,
+    stats: Stats,
+    started: Instant,
+    /// Random id of this run of the server
+    run_id: String,
+    log: Log,
+    config: Config,
+    slowlog: SlowLog,
+    latency: LatencyMonitor,
+    /// Clients that ran MONITOR, commands aren't formatted for them while there are none
+    monitors: usize,
+    acl: Acl,
+    acl_log: AclLog,
 }
 
-use crate::commads::InfoType;
+/// Redis version this server presents itself as
+pub const REDIS_VERSION: &str = "7.2.0";
+
+/// ACL LOAD and ACL SAVE without the `aclfile` parameter
+const NO_ACL_FILE: &str = "ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.";
+
+/// Sent to non-loopback clients before closing them while protected mode is on
+const PROTECTED_MODE_ERR: &str = "-DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. Use CONFIG REWRITE to make this change permanent. 2) Alternatively you can just disable the protected mode by editing the Redis configuration file, and setting the protected mode option to 'no', and then restarting the server. 3) If you started the server manually just for testing, restart it with the '--protected-mode no' option. 4) Set up an authentication password for the default user. NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.\r\n";
+
+/// How often the active expire cycle runs
+const ACTIVE_EXPIRE_CYCLE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Time the active expire cycle may spend per run
+const ACTIVE_EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(25);
+
+use crate::acl::{Acl, AclLog, AclLogEntry, Denied, ACL_CATEGORIES};
+use crate::client::{Client, Connection, MAX_QUERY_BUFFER, READ_BUFFER_SIZE};
+use crate::commads::{
+    AclCommand, ClientCommand, ClientKillCommand, ClientKillFilter, ClientListCommand,
+    CommandListFilter, CommandParseResult, ConfigCommand, CopyCommand, EvalCommand, ExpireCommand,
+    FunctionCommand, InfoSection, IntrospectCommand, LatencyCommand, PauseMode, PubSubCommand,
+    ReplconfType, ReplyMode, RestorePolicy, ScanCommand, ScriptCommand, SetCommand, SlowLogCommand,
+    TrackingCommand,
+};
+use crate::command_table::{self, all_commands, get_keys, COMMAND_TABLE};
+use crate::config::Config;
+use crate::db::{instant_from_unix_ms, unix_ms_from_instant, unix_time_ms, Db, StoredValue, Value};
+use crate::eviction::{EvictionPool, MaxmemoryPolicy, MAXMEMORY_SAMPLES};
+use crate::functions::{call_function, load_library, parse_dump, Libraries};
+use crate::glob::{glob_match, is_literal};
+use crate::latency::LatencyMonitor;
+use crate::lazyfree::LazyFree;
+use crate::log::{Level, Log};
+use crate::lua::Host;
+use crate::multi::WatchedKeys;
+use crate::notify::{
+    NOTIFY_EVICTED, NOTIFY_EXPIRED, NOTIFY_GENERIC, NOTIFY_KEYEVENT, NOTIFY_KEYSPACE,
+    NOTIFY_KEY_MISS, NOTIFY_NEW, NOTIFY_STRING,
+};
+use crate::pubsub::{PubSub, SubscriptionKind};
+use crate::rdb;
+use crate::scripting::{compile, run_script, ScriptCache};
+use crate::slowlog::SlowLog;
+use crate::stats::{bytes_to_human, cpu_times, Stats};
+use crate::tracking::{overlapping_prefix, TrackingState, TrackingTable, INVALIDATE_CHANNEL};
 use crate::Command;
 use crate::CommandParser;
 use crate::RespParser;
 use crate::RespValue;
 
 impl Server {
-    pub fn new<A: ToSocketAddrs>(address: A, replicaof: Option<(String, u32)>) -> Self {
+    /// Server with the default config listening on just `address`
+    #[cfg(test)]
+    pub fn new<A: std::net::ToSocketAddrs>(
+        address: A,
+        replicaof: Option<(String, u32)>,
+        databases: usize,
+    ) -> Self {
         let listener = TcpListener::bind(address).unwrap();
         listener.set_nonblocking(true).unwrap();
+        let mut server = Self::with_listeners(vec![listener], replicaof, databases);
+        // tests shouldn't leave snapshots behind
+        server.config.set("save", "").unwrap();
+        server
+    }
 
+    fn with_listeners(
+        listeners: Vec<TcpListener>,
+        replicaof: Option<(String, u32)>,
+        databases: usize,
+    ) -> Self {
         let mut replication = Replication::default();
         let mut master_stream = None;
 
@@ -161,219 +211,4812 @@ impl Server {
             master_stream = Some(stream);
         };
 
+        let mut config = Config::new();
+        let port = listeners
+            .first()
+            .map_or(0, |l| l.local_addr().unwrap().port());
+        config.set("port", &port.to_string()).unwrap();
+        config.set("databases", &databases.to_string()).unwrap();
+        if let Some((host, port)) = &replication.replicaof {
+            config
+                .set("replicaof", &format!("{} {}", host, port))
+                .unwrap();
+        }
+
         Server {
-            listener,
-            streams: Vec::<TcpStream>::new(),
+            listeners,
+            unix_listener: None,
+            clients: Vec::<Client>::new(),
             to_close: Vec::<usize>::new(),
             shutdown: false,
-            storage: HashMap::<String, StoredValue>::new(),
+            dbs: (0..databases).map(|_| Db::new()).collect(),
             replication,
-            master_stream: master_stream,
+            master_stream,
+            lazyfree: LazyFree::new(),
+            last_expire_cycle: Instant::now(),
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            eviction_pool: EvictionPool::new(),
+            next_eviction_db: 0,
+            watched_keys: WatchedKeys::new(),
+            next_client_id: 1,
+            pubsub: PubSub::new(),
+            notify_keyspace_events: 0,
+            tracking_table: TrackingTable::new(),
+            scripts: ScriptCache::new(),
+            functions: Libraries::new(),
+            busy_reply_threshold: Duration::from_millis(config.int("busy-reply-threshold") as u64),
+            pause: None,
+            stats: Stats::new(),
+            started: Instant::now(),
+            run_id: gen_master_id(),
+            log: Log::from_config(&config),
+            config,
+            slowlog: SlowLog::new(),
+            latency: LatencyMonitor::new(),
+            monitors: 0,
+            acl: Acl::new(),
+            acl_log: AclLog::new(),
+        }
+    }
+
+    /// Server listening where `config` says, with its parameters applied and the users of
+    /// its ACL file loaded, or why the config can't be used
+    pub fn from_config(config: Config) -> std::result::Result<Self, String> {
+        let port = config.int("port") as u16;
+        if port == 0 && config.string("unixsocket").is_empty() {
+            return Err("Configured to not listen anywhere, exiting.".into());
+        }
+        // port 0 turns TCP off
+        let listeners = match port {
+            0 => Vec::new(),
+            _ => listen_tcp(config.string("bind"), port, &Log::from_config(&config))?,
+        };
+        let replicaof = config
+            .string("replicaof")
+            .split_once(' ')
+            .map(|(host, port)| (host.to_string(), port.parse().unwrap()));
+
+        let mut server =
+            Server::with_listeners(listeners, replicaof, config.int("databases") as usize);
+        server.config = config;
+        for name in Config::names() {
+            server.apply_config(name);
+        }
+
+        let unixsocket = server.config.string("unixsocket");
+        if !unixsocket.is_empty() {
+            let perm = server.config.int("unixsocketperm") as u32;
+            server.unix_listener = Some(listen_unix(unixsocket, perm)?);
+        }
+
+        // the file's default user wins over requirepass
+        if let Some(path) = server.acl_file() {
+            server.acl = Acl::load_file(&path)?;
+        }
+
+        let path = server.rdb_path();
+        if path.exists() {
+            server.load_rdb(&path).map_err(|e| {
+                format!(
+                    "Fatal error loading the DB from '{}': {}",
+                    path.display(),
+                    e
+                )
+            })?;
+        }
+        Ok(server)
+    }
+
+    fn rdb_path(&self) -> PathBuf {
+        Path::new(self.config.string("dir")).join(self.config.string("dbfilename"))
+    }
+
+    /// Load the function libraries kept in the snapshot at `path`
+    fn load_rdb(&mut self, path: &Path) -> std::result::Result<(), String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        for code in rdb::load(&data)? {
+            self.functions.add(load_library(&code, &self.log)?, false)?;
         }
+        Ok(())
+    }
+
+    /// Snapshot of the dataset, which only holds the function libraries
+    fn rdb(&self) -> Vec<u8> {
+        rdb::save(self.functions.list(None).map(|l| l.code.as_str()))
+    }
+
+    /// Address of the first TCP listener
+    #[cfg(test)]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listeners[0].local_addr().unwrap()
+    }
+
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap())
+            .collect()
     }
 
     pub fn poll_streams(&mut self) {
         // Read from and respond to connection if readable
-        for (idx, mut stream) in self.streams.iter().enumerate() {
+        for idx in 0..self.clients.len() {
             if self.shutdown {
                 println!("shutting down stream");
-                stream.shutdown(Shutdown::Both).unwrap();
+                self.clients[idx].stream.shutdown(Shutdown::Both).unwrap();
                 continue;
             }
 
-            let mut buf: [u8; 1024] = [0; 1024];
-
-            match stream.read(&mut buf) {
-                Ok(n) if n > 0 => {
-                    let a = buf;
-                    println!("{}", String::from_utf8(a.to_vec()).unwrap());
-
-                    let parsed_resp =
-                        match RespParser::new(String::from_utf8(buf.to_vec()).unwrap().chars())
-                            .parse_next()
-                        {
-                            Ok(r) => r,
-                            Err(e) => {
-                                let _ = stream.write(e.to_string().as_bytes()).unwrap();
-                                stream.flush().unwrap();
-                                continue;
-                            }
-                        };
-
-                    println!("parsed value: {:?}", parsed_resp);
-
-                    let inner_cmd = match parsed_resp {
-                        RespValue::Array(a) => a,
-                        _ => {
-                            let _ = stream
-                                .write(
-                                    format!("invalid type expected Array, got {:?}", parsed_resp)
-                                        .as_bytes(),
-                                )
-                                .unwrap();
-                            stream.flush().unwrap();
-                            continue;
-                        }
-                    };
+            let closed = self.clients[idx].held.is_none() && self.read_query(idx);
 
-                    let cmd = match CommandParser::new(inner_cmd.into_iter()).parse_next() {
-                        Ok(v) => v,
-                        Err(e) => {
-                            let _ = stream.write(e.to_string().as_bytes()).unwrap();
-                            stream.flush().unwrap();
-                            continue;
-                        }
-                    };
-
-                    let resp = match cmd {
-                        Command::Ping => {
-                            RespValue::SimpleString("PONG".into()).serialize().unwrap()
-                        }
-                        Command::Echo(mut s) => s.serialize().unwrap(),
-                        Command::Llen => todo!(),
-                        Command::Shutdown => {
-                            self.shutdown = true;
-                            RespValue::SimpleString("OK".into()).serialize().unwrap()
-                        }
-                        Command::Set(set_command) => {
-                            self.storage.insert(set_command.key, set_command.value);
-                            RespValue::BulkString("OK".into()).serialize().unwrap()
-                        }
-                        Command::Get(key) => {
-                            let v = match self.storage.get(&key) {
-                                Some(v) => &v.value,
-                                None => "",
-                            };
-                            RespValue::BulkString(v.to_string()).serialize().unwrap()
-                        }
-                        Command::Info(t) => match t {
-                            InfoType::Replication => {
-                                self.replication.serialize().as_bytes().to_vec()
-                            }
-                        },
-                        Command::Replconf(_s) => vec![0],
-                    };
+            // run every complete command, pipelined ones arrive together
+            loop {
+                let request = match self.clients[idx].held.take() {
+                    Some(held) => Some(Ok(held)),
+                    None => self.next_request(idx),
+                };
 
-                    let _ = stream.write(&resp[0..]).unwrap();
-                    stream.flush().unwrap();
+                match request {
+                    Some(Ok((name, Ok(cmd)))) if self.is_paused_for(idx, &cmd) => {
+                        self.clients[idx].held = Some((name, Ok(cmd)));
+                        break;
+                    }
+                    Some(request) => self.handle_request(idx, request),
+                    None => break,
                 }
-                // 0 bytes
-                Ok(_) => {}
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
-                Err(e) => {
-                    println!("Io error: {}", e);
-                    stream.shutdown(std::net::Shutdown::Both).unwrap();
-                    self.to_close.push(idx);
+
+                if self.shutdown || self.to_close.contains(&idx) {
+                    break;
                 }
             }
 
+            if closed && !self.to_close.contains(&idx) {
+                let _ = self.clients[idx].stream.shutdown(Shutdown::Both);
+                self.to_close.push(idx);
+            }
+
             sleep(Duration::from_millis(10));
         }
 
         // clear streams set for removal
+        self.to_close.sort_unstable();
+        self.to_close.dedup();
         while let Some(idx) = self.to_close.pop() {
-            self.streams.remove(idx);
+            self.unwatch_all(idx);
+            self.disable_tracking(idx);
+            self.unsubscribe_all(idx);
+            if self.clients[idx].monitor {
+                self.monitors -= 1;
+            }
+            self.clients.remove(idx);
         }
     }
 
-    /// veru good optimization :)
-    pub fn remove_expired(&mut self) {
-        let mut to_remove = Vec::new();
-        for (k, v) in self.storage.iter() {
-            if v.px.is_some_and(|px| px <= Instant::now()) {
-                to_remove.push(k.to_string());
+    /// Run a request read from the client at `idx` and answer it
+    fn handle_request(
+        &mut self,
+        idx: usize,
+        request: std::result::Result<(String, CommandParseResult), String>,
+    ) {
+        // CLIENT REPLY SKIP drops the reply to the command after it only
+        let reply_mode = self.clients[idx].reply_mode;
+        if reply_mode == ReplyMode::Skip {
+            self.clients[idx].reply_mode = ReplyMode::On;
+        }
+
+        let resp = match request {
+            Ok((name, Ok(cmd))) => self.process_command(idx, &name, cmd),
+            Ok((_, Err(e))) => {
+                // a command that can't be queued aborts the transaction
+                let multi = &mut self.clients[idx].multi;
+                if multi.in_multi() {
+                    multi.dirty_exec = true;
+                }
+                RespValue::SimpleError(format!("ERR {}", e))
+            }
+            Err(msg) => RespValue::SimpleError(format!("ERR {}", msg)),
+        };
+
+        if let RespValue::SimpleError(e) = &resp {
+            self.stats.record_error_reply(e);
+        }
+
+        // commands that wrote their own reply return Eof
+        if !matches!(resp, RespValue::Eof) && reply_mode == ReplyMode::On {
+            self.reply(idx, resp);
+        }
+
+        if self.clients[idx].close_after_reply {
+            let _ = self.clients[idx].stream.shutdown(Shutdown::Both);
+            self.to_close.push(idx);
+        }
+    }
+
+    /// The pause set by CLIENT PAUSE if it isn't over yet
+    fn active_pause(&mut self) -> Option<PauseMode> {
+        match self.pause {
+            Some((until, mode)) if Instant::now() < until => Some(mode),
+            Some(_) => {
+                self.pause = None;
+                None
             }
+            None => None,
+        }
+    }
+
+    /// Whether `cmd` from the client at `idx` has to wait for the pause to end
+    ///
+    /// Replicas are never paused.
+    fn is_paused_for(&mut self, idx: usize, cmd: &Command) -> bool {
+        let mode = match self.active_pause() {
+            Some(mode) => mode,
+            None => return false,
+        };
+        if self.clients[idx].replica {
+            return false;
         }
 
-        for k in to_remove {
-            self.storage.remove(&k);
+        match (mode, cmd) {
+            (PauseMode::All, _) => true,
+            (PauseMode::Write, Command::Exec) => self.clients[idx]
+                .multi
+                .queued
+                .iter()
+                .flatten()
+                .any(Command::may_replicate),
+            (PauseMode::Write, cmd) => cmd.may_replicate(),
         }
     }
 
-    pub fn run(&mut self) {
+    /// Read everything the client at `idx` sent so far into its query buffer
+    ///
+    /// Returns true if the client closed its end of the connection, what it sent before
+    /// is still in the buffer to be run.
+    fn read_query(&mut self, idx: usize) -> bool {
+        let mut buf: [u8; READ_BUFFER_SIZE] = [0; READ_BUFFER_SIZE];
+
         loop {
-            // Pick up new connections
-            if let Ok((stream, _)) = self.listener.accept() {
-                println!("got connection");
-                stream.set_nonblocking(true).unwrap();
-                self.streams.push(stream);
+            let client = &mut self.clients[idx];
+            let mut stream = &client.stream;
+            match stream.read(&mut buf) {
+                Ok(n) if n > 0 => {
+                    client.query_buf.extend_from_slice(&buf[..n]);
+                    client.last_interaction = Instant::now();
+                    self.stats.total_net_input_bytes += n as u64;
+                }
+                Ok(_) => {
+                    let msg = format!("Client closed connection {}", client.info_line());
+                    self.log.log(Level::Verbose, &msg);
+                    return true;
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return false,
+                Err(e) => {
+                    let msg = format!("Error reading from client: {}", e);
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                    self.to_close.push(idx);
+                    self.log.log(Level::Verbose, &msg);
+                    return false;
+                }
             }
 
-            self.poll_streams();
-            self.remove_expired();
+            if client.query_buf.len() > MAX_QUERY_BUFFER {
+                let msg = format!(
+                    "Closing client that reached max query buffer length: {}",
+                    client.info_line()
+                );
+                client.query_buf = Vec::new();
+                let _ = client.stream.shutdown(Shutdown::Both);
+                self.to_close.push(idx);
+                self.log.log(Level::Warning, &msg);
+                return false;
+            }
+        }
+    }
 
-            //cleanup was done in poll, safe to break
-            if self.shutdown {
-                println!("shuttding down server");
-                break;
+    /// Parse the request at the start of the query buffer of the client at `idx`, if it
+    /// has a complete one
+    ///
+    /// Gives the lowercase command name with the parsed command or why it couldn't be
+    /// parsed, or an error if the request isn't a RESP array at all.
+    fn next_request(
+        &mut self,
+        idx: usize,
+    ) -> Option<std::result::Result<(String, CommandParseResult), String>> {
+        let client = &mut self.clients[idx];
+        let inner_cmd = loop {
+            if client.query_buf.len() < client.query_needed.max(1) {
+                return None;
             }
+
+            let mut parser = RespParser::new(client.query_buf.iter().copied());
+            let parsed = parser.parse_next();
+            let consumed = parser.position();
+            let parsed_resp = match parsed {
+                Ok(r) => r,
+                Err(e) => match e.needed() {
+                    Some(needed) => {
+                        client.query_needed = needed;
+                        return None;
+                    }
+                    None => {
+                        // there's no telling where the next request starts
+                        client.query_buf = Vec::new();
+                        client.close_after_reply = true;
+                        return Some(Err(format!("Protocol error: {}", e)));
+                    }
+                },
+            };
+            client.query_buf.drain(..consumed);
+            client.query_buf.shrink_to(READ_BUFFER_SIZE);
+            client.query_needed = 0;
+
+            match parsed_resp {
+                // empty requests are skipped like redis does
+                RespValue::Array(a) if a.is_empty() => continue,
+                RespValue::Array(a) => break a,
+                _ => {
+                    return Some(Err(format!(
+                        "invalid type expected Array, got {:?}",
+                        parsed_resp
+                    )))
+                }
+            }
+        };
+
+        let name = match inner_cmd.first() {
+            Some(RespValue::BulkString(s) | RespValue::SimpleString(s)) => s.to_lowercase(),
+            _ => String::new(),
+        };
+        let mut argv: Vec<String> = inner_cmd
+            .iter()
+            .map(|arg| match arg {
+                RespValue::BulkString(s) | RespValue::SimpleString(s) => s.clone(),
+                RespValue::Integer(i) => i.to_string(),
+                other => format!("{:?}", other),
+            })
+            .collect();
+        redact_passwords(&name, &mut argv);
+        self.clients[idx].argv = argv;
+        self.clients[idx].last_command = match inner_cmd.get(1) {
+            Some(RespValue::BulkString(sub) | RespValue::SimpleString(sub))
+                if command_table::lookup(&name).is_some_and(|c| !c.subcommands.is_empty()) =>
+            {
+                format!("{}|{}", name, sub.to_lowercase())
+            }
+            _ => name.clone(),
+        };
+
+        Some(Ok((name, CommandParser::new(inner_cmd).parse_next())))
+    }
+
+    /// Serialize and write `resp` to the client at `idx`
+    ///
+    /// A client that can't be written to is closed
+    fn reply(&mut self, idx: usize, mut resp: RespValue) {
+        if self.clients[idx].resp == 2 {
+            resp = resp.into_resp2();
         }
+
+        self.write_raw(idx, &resp.serialize().unwrap());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::thread::{self, JoinHandle};
+    fn write_raw(&mut self, idx: usize, bytes: &[u8]) {
+        self.stats.total_net_output_bytes += bytes.len() as u64;
+        let mut stream = &self.clients[idx].stream;
 
-    use super::*;
+        let written = stream.write_all(bytes).and_then(|_| stream.flush());
+
+        if written.is_err() {
+            self.to_close.push(idx);
+        }
+    }
+
+    fn client_idx(&self, client_id: u64) -> Option<usize> {
+        self.clients.iter().position(|c| c.id == client_id)
+    }
+
+    /// Checks that apply before a command is run, then runs or queues it
+    pub fn process_command(&mut self, idx: usize, name: &str, cmd: Command) -> RespValue {
+        let in_multi = self.clients[idx].multi.in_multi();
+
+        if self.clients[idx].in_subscribed_mode() && !cmd.is_allowed_in_subscribed_mode() {
+            self.stats.record_rejected(&self.clients[idx].last_command);
+            return RespValue::SimpleError(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                name
+            ));
+        }
+
+        // the command table flags the ones that can't be queued, like PSYNC
+        let no_multi = command_table::lookup_args(&self.clients[idx].argv)
+            .is_some_and(|spec| spec.flags.contains(&"no_multi"));
+        if in_multi && no_multi {
+            self.clients[idx].multi.dirty_exec = true;
+            self.stats.record_rejected(&self.clients[idx].last_command);
+            return RespValue::SimpleError("ERR Command not allowed inside a transaction".into());
+        }
+
+        let argv = std::mem::take(&mut self.clients[idx].argv);
+        let context = if in_multi { "multi" } else { "toplevel" };
+        let allowed = self.check_acl(idx, &argv, &cmd, context);
+        self.clients[idx].argv = argv;
+        if let Err(e) = allowed {
+            if in_multi {
+                self.clients[idx].multi.dirty_exec = true;
+            }
+            self.stats.record_rejected(&self.clients[idx].last_command);
+            return e;
+        }
+
+        if self.maxmemory > 0 && !self.perform_evictions() && is_denyoom(&self.clients[idx].argv) {
+            if in_multi {
+                self.clients[idx].multi.dirty_exec = true;
+            }
+
+            self.stats.record_rejected(&self.clients[idx].last_command);
+            return RespValue::SimpleError(
+                "OOM command not allowed when used memory > 'maxmemory'.".into(),
+            );
+        }
+
+        // only commands that made it past the checks are shown
+        if self.monitors > 0 {
+            let client = &self.clients[idx];
+            let (db, addr, argv) = (client.db, client.addr.clone(), client.argv.clone());
+            self.feed_monitors(db, &addr, &argv);
+        }
+
+        if in_multi && !cmd.is_transaction_control() {
+            if let Some(queued) = self.clients[idx].multi.queued.as_mut() {
+                queued.push(cmd);
+            }
+            return RespValue::SimpleString("QUEUED".into());
+        }
 
-    const ADDR: &'static str = "127.0.0.1:6379";
+        let caching = matches!(cmd, Command::Client(ClientCommand::Caching(_)));
 
-    fn stream_helper(to_send: &str) -> Result<String> {
-        let mut stream = TcpStream::connect(tests::ADDR).unwrap();
-        let mut buf: Vec<u8> = Vec::new();
-        stream.write(to_send.as_bytes()).unwrap();
-        stream.flush().unwrap();
+        let started = Instant::now();
+        let resp = self.execute(idx, cmd);
+        let duration = started.elapsed();
+        self.stats.record_call(
+            &self.clients[idx].last_command,
+            duration,
+            matches!(resp, RespValue::SimpleError(_)),
+        );
+        self.record_command_latency(idx, name, duration);
+        self.handle_modified_keys(Some(self.clients[idx].id));
 
-        match stream.read_to_end(&mut buf) {
-            Ok(_) => Ok(String::from_utf8(buf).unwrap()),
-            Err(_) => Err("an error".into()),
+        // CLIENT CACHING only covers the command that follows it
+        if !caching {
+            self.clients[idx].tracking.caching = None;
         }
+
+        resp
     }
 
-    /// Creates and runs the server
-    /// use a stream to write to the server
-    /// Join on the returned [`JoinHandle`]
-    fn server_helper() -> JoinHandle<()> {
-        let mut server = Server::new(ADDR, None);
+    /// Whether protected mode keeps a client connecting from `ip` out, which it does for
+    /// anything but loopback while the default user has no password
+    fn protected_mode_refuses(&self, ip: IpAddr) -> bool {
+        self.config.bool("protected-mode")
+            && self.acl.user("default").is_some_and(|u| u.nopass)
+            && !ip.to_canonical().is_loopback()
+    }
 
-        let handle = thread::spawn(move || {
-            server.run();
+    /// Whether clients may run commands as the default user without authenticating
+    fn default_user_open(&self) -> bool {
+        self.acl
+            .user("default")
+            .is_some_and(|u| u.enabled && u.nopass)
+    }
+
+    /// Whether the client at `idx` has to authenticate before running commands
+    fn auth_required(&self, idx: usize) -> bool {
+        !self.default_user_open() && !self.clients[idx].authenticated
+    }
+
+    /// NOAUTH or NOPERM error if the client at `idx` may not run `cmd` given as the full
+    /// command line `args`, denials are logged to ACL LOG with `context`
+    fn check_acl(
+        &mut self,
+        idx: usize,
+        args: &[String],
+        cmd: &Command,
+        context: &'static str,
+    ) -> std::result::Result<(), RespValue> {
+        let Some(spec) = command_table::lookup_args(args) else {
+            return Ok(());
+        };
+
+        if self.auth_required(idx) && !spec.flags.contains(&"no_auth") {
+            return Err(RespValue::SimpleError(
+                "NOAUTH Authentication required.".into(),
+            ));
+        }
+
+        let client = &self.clients[idx];
+        let (channels, patterns) = cmd.acl_channels();
+        let denied = match self.acl.user(&client.user) {
+            Some(user) => match user.check(spec, args, channels, patterns) {
+                Ok(()) => return Ok(()),
+                Err(denied) => denied,
+            },
+            None => Denied::Command,
+        };
+
+        let msg = match &denied {
+            Denied::Command => format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                client.user, spec.name
+            ),
+            Denied::Key(_) => "NOPERM No permissions to access a key".into(),
+            Denied::Channel(_) => "NOPERM No permissions to access a channel".into(),
+        };
+        let reason = denied.reason();
+        let object = match denied {
+            Denied::Command => spec.name.to_string(),
+            Denied::Key(name) | Denied::Channel(name) => name,
+        };
+        let entry = AclLogEntry::new(
+            reason,
+            context,
+            object,
+            client.user.clone(),
+            client.info_line(),
+            unix_time_ms(),
+        );
+        self.acl_log
+            .push(entry, self.config.int("acllog-max-len") as usize);
+
+        Err(RespValue::SimpleError(msg))
+    }
+
+    /// Log the client at `idx` in as `username` if `password` is theirs
+    fn authenticate(&mut self, idx: usize, username: &str, password: &str) -> bool {
+        if self.acl.authenticate(username, password) {
+            let client = &mut self.clients[idx];
+            client.user = username.into();
+            client.authenticated = true;
+            return true;
+        }
+
+        let client = &self.clients[idx];
+        let entry = AclLogEntry::new(
+            "auth",
+            "toplevel",
+            "AUTH".into(),
+            username.into(),
+            client.info_line(),
+            unix_time_ms(),
+        );
+        self.acl_log
+            .push(entry, self.config.int("acllog-max-len") as usize);
+        false
+    }
+
+    fn auth(&mut self, idx: usize, username: Option<String>, password: String) -> RespValue {
+        let nopass = self.acl.user("default").is_some_and(|u| u.nopass);
+        if username.is_none() && nopass {
+            return RespValue::SimpleError("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".into());
+        }
+
+        let username = username.unwrap_or_else(|| "default".into());
+        match self.authenticate(idx, &username, &password) {
+            true => RespValue::SimpleString("OK".into()),
+            false => RespValue::SimpleError(
+                "WRONGPASS invalid username-password pair or user is disabled.".into(),
+            ),
+        }
+    }
+
+    /// The `aclfile` parameter, None when users aren't kept in a file
+    fn acl_file(&self) -> Option<PathBuf> {
+        match self.config.string("aclfile") {
+            "" => None,
+            path => Some(PathBuf::from(path)),
+        }
+    }
+
+    /// Close the connections of clients logged in as `username`
+    fn kill_user_clients(&mut self, idx: usize, username: &str) {
+        for target in 0..self.clients.len() {
+            if self.clients[target].user == username {
+                self.kill_client(idx, target);
+            }
+        }
+    }
+
+    fn acl_command(&mut self, idx: usize, acl_command: AclCommand) -> RespValue {
+        let bulk = |s: &str| RespValue::BulkString(s.into());
+
+        match acl_command {
+            AclCommand::Cat(None) => {
+                RespValue::Array(ACL_CATEGORIES.iter().map(|c| bulk(c)).collect())
+            }
+            AclCommand::Cat(Some(category)) => {
+                let category = category.to_lowercase();
+                if !ACL_CATEGORIES.contains(&category.as_str()) {
+                    return RespValue::SimpleError(format!("ERR Unknown category '{}'", category));
+                }
+                RespValue::Array(
+                    all_commands()
+                        .filter(|c| c.acl_categories.contains(&category.as_str()))
+                        .map(|c| bulk(c.name))
+                        .collect(),
+                )
+            }
+            AclCommand::DelUser(names) => {
+                if names.iter().any(|n| n == "default") {
+                    return RespValue::SimpleError(
+                        "ERR The 'default' user cannot be removed".into(),
+                    );
+                }
+                let mut deleted = 0;
+                for name in names {
+                    if self.acl.delete_user(&name) {
+                        self.kill_user_clients(idx, &name);
+                        deleted += 1;
+                    }
+                }
+                RespValue::Integer(deleted)
+            }
+            AclCommand::DryRun(username, args) => {
+                let Some(user) = self.acl.user(&username) else {
+                    return RespValue::SimpleError(format!("ERR User '{}' not found", username));
+                };
+                let Some(spec) = command_table::lookup_args(&args) else {
+                    return RespValue::SimpleError(format!("ERR Command '{}' not found", args[0]));
+                };
+                if !spec.arity_matches(args.len()) {
+                    return RespValue::SimpleError(format!(
+                        "ERR wrong number of arguments for '{}' command",
+                        spec.name
+                    ));
+                }
+
+                let resp_args = args.iter().map(|a| RespValue::BulkString(a.clone()));
+                let cmd = CommandParser::new(resp_args).parse_next().ok();
+                let (channels, patterns) =
+                    cmd.as_ref().map_or((&[][..], false), |c| c.acl_channels());
+                match user.check(spec, &args, channels, patterns) {
+                    Ok(()) => RespValue::SimpleString("OK".into()),
+                    Err(Denied::Command) => RespValue::BulkString(format!(
+                        "User {} has no permissions to run the '{}' command",
+                        username, spec.name
+                    )),
+                    Err(Denied::Key(key)) => RespValue::BulkString(format!(
+                        "User {} has no permissions to access the '{}' key",
+                        username, key
+                    )),
+                    Err(Denied::Channel(channel)) => RespValue::BulkString(format!(
+                        "User {} has no permissions to access the '{}' channel",
+                        username, channel
+                    )),
+                }
+            }
+            AclCommand::GetUser(username) => match self.acl.user(&username) {
+                Some(user) => user.reply(),
+                None => RespValue::Nil,
+            },
+            AclCommand::List => {
+                RespValue::Array(self.acl.users().map(|u| bulk(&u.describe())).collect())
+            }
+            AclCommand::Load => {
+                let Some(path) = self.acl_file() else {
+                    return RespValue::SimpleError(NO_ACL_FILE.into());
+                };
+                match Acl::load_file(&path) {
+                    Ok(acl) => {
+                        self.acl = acl;
+                        let deleted: BTreeSet<String> = self
+                            .clients
+                            .iter()
+                            .filter(|c| self.acl.user(&c.user).is_none())
+                            .map(|c| c.user.clone())
+                            .collect();
+                        for name in deleted {
+                            self.kill_user_clients(idx, &name);
+                        }
+                        RespValue::SimpleString("OK".into())
+                    }
+                    Err(e) => RespValue::SimpleError(format!(
+                        "ERR {} WARNING: ACL errors detected, no change to the previously active ACL rules was performed",
+                        e
+                    )),
+                }
+            }
+            AclCommand::Save => {
+                let Some(path) = self.acl_file() else {
+                    return RespValue::SimpleError(NO_ACL_FILE.into());
+                };
+                match self.acl.save_file(&path) {
+                    Ok(()) => RespValue::SimpleString("OK".into()),
+                    Err(e) => {
+                        self.log.log(Level::Warning, &e);
+                        RespValue::SimpleError("ERR There was an error trying to save the ACLs. Please check the server logs for more information".into())
+                    }
+                }
+            }
+            AclCommand::Log(count) => {
+                let now = unix_time_ms();
+                RespValue::Array(self.acl_log.newest(count).map(|e| e.reply(now)).collect())
+            }
+            AclCommand::LogReset => {
+                self.acl_log.reset();
+                RespValue::SimpleString("OK".into())
+            }
+            AclCommand::SetUser(username, rules) => match self.acl.set_user(&username, &rules) {
+                Ok(()) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::SimpleError(format!("ERR {}", e)),
+            },
+            AclCommand::Users => {
+                RespValue::Array(self.acl.users().map(|u| bulk(&u.name)).collect())
+            }
+            AclCommand::WhoAmI => bulk(&self.clients[idx].user),
+        }
+    }
+
+    /// Send the command line `argv` run by `source` to clients that ran MONITOR, except for
+    /// admin commands and those that show the commands they run instead
+    fn feed_monitors(&mut self, db: usize, source: &str, argv: &[String]) {
+        let hidden = command_table::lookup_args(argv).is_some_and(|spec| {
+            spec.flags
+                .iter()
+                .any(|f| *f == "admin" || *f == "skip_monitor")
         });
+        if hidden {
+            return;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let mut line = format!(
+            "+{}.{:06} [{} {}]",
+            now.as_secs(),
+            now.subsec_micros(),
+            db,
+            source
+        );
+        for arg in argv {
+            line.push(' ');
+            line.push_str(&quoted_repr(arg));
+        }
+        line.push_str("\r\n");
 
-        return handle;
+        for idx in 0..self.clients.len() {
+            if self.clients[idx].monitor {
+                self.write_raw(idx, line.as_bytes());
+            }
+        }
     }
 
-    #[test]
-    fn test_server_creation() {
-        let handle = server_helper();
+    /// Log a command that took `duration` in the slow log, the latency monitor and the
+    /// latency histograms
+    fn record_command_latency(&mut self, idx: usize, name: &str, duration: Duration) {
+        let spec = command_table::lookup(name);
+        let client = &self.clients[idx];
 
-        let _ = stream_helper("*1\r\n+SHUTDOWN\r\n");
+        if self.config.bool("latency-tracking") {
+            self.stats.record_latency(&client.last_command, duration);
+        }
 
-        handle.join().unwrap();
+        let slower_than = self.config.int("slowlog-log-slower-than");
+        let skip = spec.is_some_and(|s| s.flags.contains(&"skip_slowlog"));
+        if slower_than >= 0 && duration.as_micros() as i64 >= slower_than && !skip {
+            self.slowlog.push(
+                unix_time_ms() / 1000,
+                duration,
+                &client.argv,
+                &client.addr,
+                client.name.as_deref().unwrap_or_default(),
+                self.config.int("slowlog-max-len") as usize,
+            );
+        }
+
+        let fast = spec.is_some_and(|s| s.flags.contains(&"fast"));
+        let event = if fast { "fast-command" } else { "command" };
+        self.record_latency_event(event, duration);
     }
 
-    #[test]
-    fn test_ping_command() {
-        let handle = server_helper();
+    /// Add a sample for `event` to the latency monitor if it took at least
+    /// `latency-monitor-threshold`
+    fn record_latency_event(&mut self, event: &str, duration: Duration) {
+        let threshold = self.config.int("latency-monitor-threshold") as u128;
+        if threshold > 0 && duration.as_millis() >= threshold {
+            self.latency
+                .record(event, unix_time_ms() / 1000, duration.as_millis() as u64);
+        }
+    }
 
-        let resp = stream_helper("*1\r\n$4\r\nPING\r\n").unwrap();
+    /// Flag the transactions of clients watching keys modified since the last call and
+    /// invalidate them for tracking clients, `modifier` is the client that ran the command
+    fn handle_modified_keys(&mut self, modifier: Option<u64>) {
+        let mut invalidations: BTreeMap<u64, Vec<String>> = BTreeMap::new();
 
-        let _ = stream_helper("*1\r\n+SHUTDOWN\r\n");
+        for db in 0..self.dbs.len() {
+            for key in self.dbs[db].take_expired() {
+                self.stats.expired_keys += 1;
+                self.notify_keyspace_event(NOTIFY_EXPIRED, "expired", &key, db);
+            }
 
-        handle.join().unwrap();
+            for key in self.dbs[db].take_modified() {
+                self.stats.dirty += 1;
+                if !self.watched_keys.is_empty() {
+                    self.flag_watchers(db, &key);
+                }
 
-        assert_eq!(resp, String::from("+PONG\r\n"));
+                for client_id in self.tracking_table.invalidate(&key) {
+                    let keys = invalidations.entry(client_id).or_default();
+                    if !keys.contains(&key) {
+                        keys.push(key.clone());
+                    }
+                }
+            }
+        }
+
+        for (client_id, keys) in invalidations {
+            let noloop = match self.client_idx(client_id) {
+                Some(i) => self.clients[i].tracking.noloop,
+                None => continue,
+            };
+
+            if !(noloop && modifier == Some(client_id)) {
+                self.send_invalidation(client_id, Some(keys));
+            }
+        }
     }
 
-    #[test]
-    fn test_echo_command() {
-        let handle = server_helper();
+    /// Tell a tracking client to drop `keys` from its cache, or everything for None
+    fn send_invalidation(&mut self, client_id: u64, keys: Option<Vec<String>>) {
+        let idx = match self.client_idx(client_id) {
+            Some(idx) if self.clients[idx].tracking.enabled => idx,
+            _ => return,
+        };
 
-        let resp = stream_helper("*1\r\n$4\r\nECHO\r\n$2\r\nOK\r\n").unwrap();
-        let _ = stream_helper("*1\r\n+SHUTDOWN\r\n");
+        let keys = match keys {
+            Some(keys) => RespValue::Array(keys.into_iter().map(RespValue::BulkString).collect()),
+            None => RespValue::Nil,
+        };
 
-        handle.join().unwrap();
-        assert_eq!(resp, "$2\r\nOK\r\n")
+        let redirect = match self.clients[idx].tracking.redirect {
+            Some(redirect) => redirect,
+            None if self.clients[idx].resp == 3 => {
+                let invalidate = RespValue::BulkString("invalidate".into());
+                self.reply(idx, RespValue::Push(vec![invalidate, keys]));
+                return;
+            }
+            // RESP2 clients can only hear about invalidations through a redirect
+            None => return,
+        };
+
+        // a RESP2 target that isn't subscribed to the invalidation channel can't be told
+        let target = self.client_idx(redirect).filter(|&target| {
+            let target = &self.clients[target];
+            target.resp == 3 || target.subscriptions.channels.contains(INVALIDATE_CHANNEL)
+        });
+        match target {
+            Some(target) if self.clients[target].resp == 3 => {
+                let invalidate = RespValue::BulkString("invalidate".into());
+                self.reply(target, RespValue::Push(vec![invalidate, keys]));
+            }
+            Some(target) => {
+                let message = vec![
+                    RespValue::BulkString("message".into()),
+                    RespValue::BulkString(INVALIDATE_CHANNEL.into()),
+                    keys,
+                ];
+                self.reply(target, RespValue::Push(message));
+            }
+            None => {
+                self.clients[idx].tracking.redirect_broken = true;
+                if self.clients[idx].resp == 3 {
+                    let broken = vec![
+                        RespValue::BulkString("tracking-redir-broken".into()),
+                        RespValue::Integer(redirect as i64),
+                    ];
+                    self.reply(idx, RespValue::Push(broken));
+                }
+            }
+        }
+    }
+
+    /// After a flush every tracking client drops its whole cache
+    fn invalidate_all_tracked_keys(&mut self) {
+        self.tracking_table.clear_keys();
+
+        let tracking: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|c| c.tracking.enabled)
+            .map(|c| c.id)
+            .collect();
+
+        for client_id in tracking {
+            self.send_invalidation(client_id, None);
+        }
+    }
+
+    fn client_tracking(&mut self, idx: usize, tracking_command: TrackingCommand) -> RespValue {
+        let TrackingCommand {
+            on,
+            redirect,
+            prefixes,
+            bcast,
+            optin,
+            optout,
+            noloop,
+        } = tracking_command;
+
+        if !on {
+            self.disable_tracking(idx);
+            return RespValue::SimpleString("OK".into());
+        }
+
+        if let Some(redirect) = redirect {
+            if self.client_idx(redirect).is_none() {
+                return RespValue::SimpleError(
+                    "ERR The client ID you want redirect to does not exist".into(),
+                );
+            }
+        }
+
+        let tracking = &self.clients[idx].tracking;
+        if tracking.enabled && tracking.bcast != bcast {
+            return RespValue::SimpleError(
+                "ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode."
+                    .into(),
+            );
+        }
+
+        let mut all_prefixes = tracking.prefixes.clone();
+        match bcast && prefixes.is_empty() {
+            true => all_prefixes.insert(String::new()),
+            false => {
+                all_prefixes.extend(prefixes);
+                true
+            }
+        };
+
+        if let Some((a, b)) = overlapping_prefix(&all_prefixes) {
+            return RespValue::SimpleError(format!(
+                "ERR Prefix '{}' overlaps with an existing prefix '{}'. Prefixes for a single client must not overlap.",
+                a, b
+            ));
+        }
+
+        let client = &mut self.clients[idx];
+        for prefix in all_prefixes.iter() {
+            self.tracking_table.add_prefix(prefix, client.id);
+        }
+
+        client.tracking = TrackingState {
+            enabled: true,
+            redirect,
+            redirect_broken: false,
+            bcast,
+            prefixes: all_prefixes,
+            optin,
+            optout,
+            noloop,
+            caching: None,
+        };
+
+        RespValue::SimpleString("OK".into())
+    }
+
+    fn disable_tracking(&mut self, idx: usize) {
+        let client = &mut self.clients[idx];
+
+        for prefix in client.tracking.prefixes.iter() {
+            self.tracking_table.remove_prefix(prefix, client.id);
+        }
+
+        // keys it read are dropped from the table lazily
+        client.tracking = TrackingState::default();
+    }
+
+    fn client_command(&mut self, idx: usize, client_command: ClientCommand) -> RespValue {
+        match client_command {
+            ClientCommand::Tracking(tracking_command) => {
+                self.client_tracking(idx, tracking_command)
+            }
+            ClientCommand::Caching(caching) => {
+                let tracking = &mut self.clients[idx].tracking;
+                match (tracking.enabled, caching) {
+                    (true, true) if tracking.optin => {}
+                    (true, false) if tracking.optout => {}
+                    (true, true) => return RespValue::SimpleError(
+                        "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode."
+                            .into(),
+                    ),
+                    (true, false) => return RespValue::SimpleError(
+                        "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode."
+                            .into(),
+                    ),
+                    (false, _) => return RespValue::SimpleError(
+                        "ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled"
+                            .into(),
+                    ),
+                }
+
+                tracking.caching = Some(caching);
+                RespValue::SimpleString("OK".into())
+            }
+            ClientCommand::TrackingInfo => {
+                let tracking = &self.clients[idx].tracking;
+
+                let mut flags = Vec::new();
+                match tracking.enabled {
+                    true => flags.push("on"),
+                    false => flags.push("off"),
+                }
+                for (set, flag) in [
+                    (tracking.bcast, "bcast"),
+                    (tracking.optin, "optin"),
+                    (tracking.optout, "optout"),
+                    (tracking.caching == Some(true), "caching-yes"),
+                    (tracking.caching == Some(false), "caching-no"),
+                    (tracking.noloop, "noloop"),
+                    (tracking.redirect_broken, "broken_redirect"),
+                ] {
+                    if set {
+                        flags.push(flag);
+                    }
+                }
+
+                let redirect = match (tracking.enabled, tracking.redirect) {
+                    (false, _) => -1,
+                    (true, None) => 0,
+                    (true, Some(r)) => r as i64,
+                };
+
+                RespValue::Map(vec![
+                    (
+                        RespValue::BulkString("flags".into()),
+                        RespValue::Array(
+                            flags
+                                .into_iter()
+                                .map(|f| RespValue::BulkString(f.into()))
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        RespValue::BulkString("redirect".into()),
+                        RespValue::Integer(redirect),
+                    ),
+                    (
+                        RespValue::BulkString("prefixes".into()),
+                        RespValue::Array(
+                            tracking
+                                .prefixes
+                                .iter()
+                                .map(|p| RespValue::BulkString(p.clone()))
+                                .collect(),
+                        ),
+                    ),
+                ])
+            }
+            ClientCommand::GetRedir => {
+                let tracking = &self.clients[idx].tracking;
+                RespValue::Integer(match (tracking.enabled, tracking.redirect) {
+                    (false, _) => -1,
+                    (true, None) => 0,
+                    (true, Some(r)) => r as i64,
+                })
+            }
+            ClientCommand::Id => RespValue::Integer(self.clients[idx].id as i64),
+            ClientCommand::SetName(name) => {
+                if name.chars().any(|c| !('!'..='~').contains(&c)) {
+                    return RespValue::SimpleError(
+                        "ERR Client names cannot contain spaces, newlines or special characters."
+                            .into(),
+                    );
+                }
+
+                self.clients[idx].name = match name.is_empty() {
+                    true => None,
+                    false => Some(name),
+                };
+                RespValue::SimpleString("OK".into())
+            }
+            ClientCommand::GetName => match &self.clients[idx].name {
+                Some(name) => RespValue::BulkString(name.clone()),
+                None => RespValue::Nil,
+            },
+            ClientCommand::List(ClientListCommand { client_type, ids }) => {
+                let lines: String = self
+                    .clients
+                    .iter()
+                    .filter(|c| ids.is_empty() || ids.contains(&c.id))
+                    .filter(|c| match client_type {
+                        Some(client_type) => c.client_type() == client_type,
+                        None => true,
+                    })
+                    .map(|c| c.info_line() + "\n")
+                    .collect();
+                RespValue::BulkString(lines)
+            }
+            ClientCommand::Info => RespValue::BulkString(self.clients[idx].info_line() + "\n"),
+            ClientCommand::Kill(ClientKillCommand::Addr(addr)) => {
+                match self.clients.iter().position(|c| c.addr == addr) {
+                    Some(target) => {
+                        self.kill_client(idx, target);
+                        RespValue::SimpleString("OK".into())
+                    }
+                    None => RespValue::SimpleError("ERR No such client".into()),
+                }
+            }
+            ClientCommand::Kill(ClientKillCommand::Filter(filter)) => {
+                let targets: Vec<usize> = (0..self.clients.len())
+                    .filter(|&target| !(filter.skipme && target == idx))
+                    .filter(|&target| kill_filter_matches(&filter, &self.clients[target]))
+                    .collect();
+
+                for &target in &targets {
+                    self.kill_client(idx, target);
+                }
+                RespValue::Integer(targets.len() as i64)
+            }
+            ClientCommand::Pause(timeout, mode) => {
+                let until = Instant::now() + timeout;
+                // a pause can be extended or made stricter but not shortened or relaxed
+                self.pause = match (self.active_pause(), self.pause) {
+                    (Some(current), Some((current_until, _))) => Some((
+                        until.max(current_until),
+                        match (current, mode) {
+                            (PauseMode::Write, PauseMode::Write) => PauseMode::Write,
+                            _ => PauseMode::All,
+                        },
+                    )),
+                    _ => Some((until, mode)),
+                };
+                RespValue::SimpleString("OK".into())
+            }
+            ClientCommand::Unpause => {
+                self.pause = None;
+                RespValue::SimpleString("OK".into())
+            }
+            ClientCommand::Reply(mode) => {
+                self.clients[idx].reply_mode = mode;
+                match mode {
+                    // the mode was off when this command came in, answer it anyway
+                    ReplyMode::On => {
+                        self.reply(idx, RespValue::SimpleString("OK".into()));
+                        RespValue::Eof
+                    }
+                    ReplyMode::Off | ReplyMode::Skip => RespValue::Eof,
+                }
+            }
+            ClientCommand::NoEvict(no_evict) => {
+                self.clients[idx].no_evict = no_evict;
+                RespValue::SimpleString("OK".into())
+            }
+        }
+    }
+
+    /// Close the connection of the client at `target` for the client at `idx`
+    ///
+    /// A client killing itself still gets its reply.
+    fn kill_client(&mut self, idx: usize, target: usize) {
+        match target == idx {
+            true => self.clients[idx].close_after_reply = true,
+            false => {
+                let _ = self.clients[target].stream.shutdown(Shutdown::Both);
+                self.to_close.push(target);
+            }
+        }
+    }
+
+    fn flag_watchers(&mut self, db: usize, key: &str) {
+        let watchers = match self.watched_keys.watchers(db, key) {
+            Some(w) => w,
+            None => return,
+        };
+
+        for client in self.clients.iter_mut() {
+            if watchers.contains(&client.id) {
+                client.multi.dirty_cas = true;
+            }
+        }
+    }
+
+    /// Flag watchers of keys in `db` that exist in `db` or `other`, before a flush or swap
+    fn touch_watched_keys_in_db(&mut self, db: usize, other: usize) {
+        for key in self.watched_keys.keys_in_db(db) {
+            if self.dbs[db].contains_key(&key) || self.dbs[other].contains_key(&key) {
+                self.flag_watchers(db, &key);
+            }
+        }
+    }
+
+    fn unwatch_all(&mut self, idx: usize) {
+        let client = &mut self.clients[idx];
+
+        for (db, key) in client.multi.watched.drain(..) {
+            self.watched_keys.unwatch(db, &key, client.id);
+        }
+        client.multi.dirty_cas = false;
+    }
+
+    fn watch(&mut self, idx: usize, keys: Vec<String>) -> RespValue {
+        if self.clients[idx].multi.in_multi() {
+            return RespValue::SimpleError("ERR WATCH inside MULTI is not allowed".into());
+        }
+
+        let db = self.clients[idx].db;
+
+        // keys that already expired don't count as modified later on
+        for key in keys.iter() {
+            self.dbs[db].contains_key(key);
+        }
+        self.handle_modified_keys(None);
+
+        let client = &mut self.clients[idx];
+        for key in keys {
+            if !client.multi.watched.contains(&(db, key.clone())) {
+                self.watched_keys.watch(db, &key, client.id);
+                client.multi.watched.push((db, key));
+            }
+        }
+
+        RespValue::SimpleString("OK".into())
+    }
+
+    /// Publish `event` on `key` if the `class` of events is enabled
+    pub fn notify_keyspace_event(&mut self, class: u32, event: &str, key: &str, db: usize) {
+        let flags = self.notify_keyspace_events;
+        if flags & class == 0 {
+            return;
+        }
+
+        if flags & NOTIFY_KEYSPACE != 0 {
+            self.publish(&format!("__keyspace@{}__:{}", db, key), event);
+        }
+
+        if flags & NOTIFY_KEYEVENT != 0 {
+            self.publish(&format!("__keyevent@{}__:{}", db, event), key);
+        }
+    }
+
+    /// Send `message` to the subscribers of `channel`, returns how many received it
+    pub fn publish(&mut self, channel: &str, message: &str) -> usize {
+        let messages = self.pubsub.messages(channel, message);
+        let receivers = messages.len();
+
+        for (client_id, frame) in messages {
+            if let Some(idx) = self.client_idx(client_id) {
+                self.reply(idx, RespValue::Push(frame));
+            }
+        }
+
+        receivers
+    }
+
+    /// Send `message` to the subscribers of shard channel `channel` and to the replicas,
+    /// returns how many clients received it
+    pub fn spublish(&mut self, channel: &str, message: &str) -> usize {
+        let messages = self.pubsub.shard_messages(channel, message);
+        let receivers = messages.len();
+
+        for (client_id, frame) in messages {
+            if let Some(idx) = self.client_idx(client_id) {
+                self.reply(idx, RespValue::Push(frame));
+            }
+        }
+
+        self.propagate(&["SPUBLISH", channel, message]);
+
+        receivers
+    }
+
+    /// Start a full resynchronization, the connection becomes a replica fed by `propagate`
+    fn psync(&mut self, idx: usize) -> RespValue {
+        let fullresync = RespValue::SimpleString(format!(
+            "FULLRESYNC {} {}",
+            self.replication.master_replid, self.replication.master_repl_offset
+        ));
+        self.reply(idx, fullresync);
+
+        // the snapshot goes out as a bulk string without the trailing CRLF
+        let rdb = self.rdb();
+        let mut payload = format!("${}\r\n", rdb.len()).into_bytes();
+        payload.extend_from_slice(&rdb);
+        self.write_raw(idx, &payload);
+
+        self.clients[idx].replica = true;
+        RespValue::Eof
+    }
+
+    /// Append a command to the replication stream of every replica
+    fn propagate(&mut self, args: &[&str]) {
+        let mut cmd = RespValue::Array(
+            args.iter()
+                .map(|a| RespValue::BulkString(a.to_string()))
+                .collect(),
+        );
+        let bytes = cmd.serialize().unwrap();
+        self.replication.master_repl_offset += bytes.len() as u64;
+
+        for idx in 0..self.clients.len() {
+            if self.clients[idx].replica {
+                self.write_raw(idx, &bytes);
+            }
+        }
+    }
+
+    /// Write all but the last frame right away, the last one is the reply
+    fn push_frames(&mut self, idx: usize, mut frames: Vec<RespValue>) -> RespValue {
+        let last = frames.pop().unwrap_or(RespValue::NullArray);
+
+        for frame in frames {
+            self.reply(idx, frame);
+        }
+
+        last
+    }
+
+    fn subscription_frame(kind: &str, name: Option<String>, count: usize) -> RespValue {
+        RespValue::Push(vec![
+            RespValue::BulkString(kind.into()),
+            name.map_or(RespValue::Nil, RespValue::BulkString),
+            RespValue::Integer(count as i64),
+        ])
+    }
+
+    /// SUBSCRIBE, PSUBSCRIBE or SSUBSCRIBE depending on `kind`
+    fn subscribe(&mut self, idx: usize, names: Vec<String>, kind: SubscriptionKind) -> RespValue {
+        let client = &mut self.clients[idx];
+        let mut frames = Vec::with_capacity(names.len());
+
+        for name in names {
+            if client.subscriptions.of_kind(kind).insert(name.clone()) {
+                self.pubsub.add(kind, &name, client.id);
+            }
+
+            let count = client.subscriptions.count_for(kind);
+            frames.push(Self::subscription_frame(
+                kind.frame_names().0,
+                Some(name),
+                count,
+            ));
+        }
+
+        self.push_frames(idx, frames)
+    }
+
+    /// UNSUBSCRIBE, PUNSUBSCRIBE or SUNSUBSCRIBE, no names means all of them
+    fn unsubscribe(&mut self, idx: usize, names: Vec<String>, kind: SubscriptionKind) -> RespValue {
+        let frame_name = kind.frame_names().1;
+        let client = &mut self.clients[idx];
+
+        let names: Vec<String> = match names.is_empty() {
+            true => client.subscriptions.of_kind(kind).iter().cloned().collect(),
+            false => names,
+        };
+
+        if names.is_empty() {
+            let count = client.subscriptions.count_for(kind);
+            return Self::subscription_frame(frame_name, None, count);
+        }
+
+        let mut frames = Vec::with_capacity(names.len());
+        for name in names {
+            client.subscriptions.of_kind(kind).remove(&name);
+            self.pubsub.remove(kind, &name, client.id);
+
+            let count = client.subscriptions.count_for(kind);
+            frames.push(Self::subscription_frame(frame_name, Some(name), count));
+        }
+
+        self.push_frames(idx, frames)
+    }
+
+    /// Drop every subscription of the client at `idx` without telling it
+    fn unsubscribe_all(&mut self, idx: usize) {
+        let client = &mut self.clients[idx];
+        for kind in [
+            SubscriptionKind::Channel,
+            SubscriptionKind::Pattern,
+            SubscriptionKind::Shard,
+        ] {
+            for name in std::mem::take(client.subscriptions.of_kind(kind)) {
+                self.pubsub.remove(kind, &name, client.id);
+            }
+        }
+    }
+
+    /// RESET, puts the connection back the way it was when it connected
+    fn reset(&mut self, idx: usize) -> RespValue {
+        self.clients[idx].multi.discard();
+        self.unwatch_all(idx);
+        self.disable_tracking(idx);
+        self.unsubscribe_all(idx);
+        if self.clients[idx].monitor {
+            self.monitors -= 1;
+        }
+
+        let authenticated = self.default_user_open();
+        let client = &mut self.clients[idx];
+        client.monitor = false;
+        client.db = 0;
+        client.resp = 2;
+        client.name = None;
+        client.reply_mode = ReplyMode::On;
+        client.no_evict = false;
+        client.user = "default".into();
+        client.authenticated = authenticated;
+        RespValue::SimpleString("RESET".into())
+    }
+
+    fn hello(
+        &mut self,
+        idx: usize,
+        protover: Option<u8>,
+        auth: Option<(String, String)>,
+    ) -> RespValue {
+        if protover.is_some_and(|v| !(2..=3).contains(&v)) {
+            return RespValue::SimpleError("NOPROTO unsupported protocol version".into());
+        }
+
+        if let Some((username, password)) = auth {
+            if !self.authenticate(idx, &username, &password) {
+                return RespValue::SimpleError(
+                    "WRONGPASS invalid username-password pair or user is disabled.".into(),
+                );
+            }
+        }
+        if self.auth_required(idx) {
+            return RespValue::SimpleError("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".into());
+        }
+
+        if let Some(v) = protover {
+            self.clients[idx].resp = v;
+        }
+
+        let role = match self.replication.role {
+            ServerRole::Master => "master",
+            ServerRole::Slave => "replica",
+        };
+
+        let fields = [
+            ("server", RespValue::BulkString("redis".into())),
+            ("version", RespValue::BulkString(REDIS_VERSION.into())),
+            ("proto", RespValue::Integer(self.clients[idx].resp as i64)),
+            ("id", RespValue::Integer(self.clients[idx].id as i64)),
+            ("mode", RespValue::BulkString("standalone".into())),
+            ("role", RespValue::BulkString(role.into())),
+            ("modules", RespValue::Array(vec![])),
+        ];
+
+        RespValue::Map(
+            fields
+                .into_iter()
+                .map(|(k, v)| (RespValue::BulkString(k.into()), v))
+                .collect(),
+        )
+    }
+
+    fn exec(&mut self, idx: usize) -> RespValue {
+        if !self.clients[idx].multi.in_multi() {
+            return RespValue::SimpleError("ERR EXEC without MULTI".into());
+        }
+
+        if self.clients[idx].multi.dirty_exec {
+            self.clients[idx].multi.discard();
+            self.unwatch_all(idx);
+            return RespValue::SimpleError(
+                "EXECABORT Transaction discarded because of previous errors.".into(),
+            );
+        }
+
+        // expiring a watched key counts as modifying it
+        for (db, key) in self.clients[idx].multi.watched.clone() {
+            self.dbs[db].contains_key(&key);
+        }
+        self.handle_modified_keys(None);
+
+        let dirty_cas = self.clients[idx].multi.dirty_cas;
+        let queued = self.clients[idx].multi.discard();
+        self.unwatch_all(idx);
+
+        if dirty_cas {
+            return RespValue::NullArray;
+        }
+
+        let replies = queued
+            .into_iter()
+            .map(|cmd| self.execute(idx, cmd))
+            .collect();
+
+        RespValue::Array(replies)
+    }
+
+    /// Run a single command for the client at `idx` and produce its reply
+    pub fn execute(&mut self, idx: usize, cmd: Command) -> RespValue {
+        let db = self.clients[idx].db;
+
+        if self.clients[idx].tracking.tracks_reads() {
+            let client_id = self.clients[idx].id;
+            for key in cmd.read_keys() {
+                self.tracking_table.remember(&key, client_id);
+            }
+        }
+
+        match cmd {
+            Command::Ping => match self.clients[idx].in_subscribed_mode() {
+                true => RespValue::Array(vec![
+                    RespValue::BulkString("pong".into()),
+                    RespValue::BulkString("".into()),
+                ]),
+                false => RespValue::SimpleString("PONG".into()),
+            },
+            Command::Echo(s) => s,
+            Command::Shutdown => {
+                // with save points set the snapshot is written for the next start
+                let save = !self.config.string("save").is_empty();
+                match save.then(|| rdb::save_file(&self.rdb_path(), &self.rdb())) {
+                    Some(Err(e)) => {
+                        self.log.log(Level::Warning, &e);
+                        RespValue::SimpleError(format!("ERR Errors trying to SHUTDOWN: {}", e))
+                    }
+                    _ => {
+                        self.shutdown = true;
+                        RespValue::SimpleString("OK".into())
+                    }
+                }
+            }
+            Command::Set(SetCommand { key, value }) => {
+                let volatile = value.px.is_some();
+                if self.dbs[db].insert(key.clone(), value).is_none() {
+                    self.notify_keyspace_event(NOTIFY_NEW, "new", &key, db);
+                }
+
+                self.notify_keyspace_event(NOTIFY_STRING, "set", &key, db);
+                if volatile {
+                    self.notify_keyspace_event(NOTIFY_GENERIC, "expire", &key, db);
+                }
+                RespValue::BulkString("OK".into())
+            }
+            Command::Get(key) => match self.dbs[db].get(&key) {
+                Some(StoredValue {
+                    value: Value::String(s),
+                    ..
+                }) => {
+                    let s = s.to_string();
+                    self.stats.keyspace_hits += 1;
+                    RespValue::BulkString(s)
+                }
+                None => {
+                    self.stats.keyspace_misses += 1;
+                    self.notify_keyspace_event(NOTIFY_KEY_MISS, "keymiss", &key, db);
+                    RespValue::Nil
+                }
+            },
+            Command::Info(sections) => RespValue::BulkString(self.info(&sections)),
+            Command::Replconf(ReplconfType::ListeningPort(port)) => {
+                self.clients[idx].listening_port = Some(port);
+                RespValue::SimpleString("OK".into())
+            }
+            Command::Replconf(_) => RespValue::SimpleString("OK".into()),
+            Command::Del(keys) => {
+                let mut deleted = 0;
+                for k in keys {
+                    if self.dbs[db].remove(&k).is_some() {
+                        self.notify_keyspace_event(NOTIFY_GENERIC, "del", &k, db);
+                        deleted += 1;
+                    }
+                }
+                RespValue::Integer(deleted)
+            }
+            Command::Unlink(keys) => {
+                let mut unlinked = 0;
+                for k in keys {
+                    if let Some(v) = self.dbs[db].remove(&k) {
+                        self.lazyfree.free(v);
+                        self.notify_keyspace_event(NOTIFY_GENERIC, "del", &k, db);
+                        unlinked += 1;
+                    }
+                }
+                RespValue::Integer(unlinked)
+            }
+            Command::Exists(keys) => {
+                let found = keys.iter().filter(|k| self.dbs[db].contains_key(k)).count();
+                RespValue::Integer(found as i64)
+            }
+            Command::Touch(keys) => {
+                let found = keys
+                    .iter()
+                    .filter(|k| self.dbs[db].get(k).is_some())
+                    .count();
+                RespValue::Integer(found as i64)
+            }
+            Command::Type(key) => match self.dbs[db].get(&key) {
+                Some(v) => RespValue::SimpleString(v.value.type_name().into()),
+                None => RespValue::SimpleString("none".into()),
+            },
+            Command::Rename(key, newkey) => match self.rename(db, &key, newkey, false) {
+                Ok(_) => RespValue::SimpleString("OK".into()),
+                Err(e) => e,
+            },
+            Command::RenameNx(key, newkey) => match self.rename(db, &key, newkey, true) {
+                Ok(renamed) => RespValue::Integer(renamed as i64),
+                Err(e) => e,
+            },
+            Command::Copy(copy_command) => match self.copy(db, copy_command) {
+                Ok(copied) => RespValue::Integer(copied as i64),
+                Err(e) => e,
+            },
+            Command::RandomKey => match self.dbs[db].random_key() {
+                Some(k) => RespValue::BulkString(k),
+                None => RespValue::Nil,
+            },
+            Command::DbSize => RespValue::Integer(self.dbs[db].len() as i64),
+            Command::Keys(pattern) => {
+                let keys = if is_literal(&pattern) {
+                    // no need to walk the keyspace for a plain key
+                    self.dbs[db]
+                        .get(&pattern)
+                        .map(|_| vec![RespValue::BulkString(pattern)])
+                        .unwrap_or_default()
+                } else {
+                    self.dbs[db]
+                        .keys()
+                        .filter(|k| glob_match(&pattern, k, false))
+                        .map(|k| RespValue::BulkString(k.to_string()))
+                        .collect()
+                };
+                RespValue::Array(keys)
+            }
+            Command::Select(index) => match index < self.dbs.len() {
+                true => {
+                    self.clients[idx].db = index;
+                    RespValue::SimpleString("OK".into())
+                }
+                false => RespValue::SimpleError("ERR DB index is out of range".into()),
+            },
+            Command::SwapDb(a, b) => match a < self.dbs.len() && b < self.dbs.len() {
+                // clients keep their db index so they see the swapped data right away
+                true => {
+                    self.touch_watched_keys_in_db(a, b);
+                    self.touch_watched_keys_in_db(b, a);
+                    self.dbs.swap(a, b);
+                    RespValue::SimpleString("OK".into())
+                }
+                false => RespValue::SimpleError("ERR DB index is out of range".into()),
+            },
+            Command::Move(key, target) => match self.move_key(db, &key, target) {
+                Ok(moved) => RespValue::Integer(moved as i64),
+                Err(e) => e,
+            },
+            Command::FlushDb(lazy) => {
+                self.touch_watched_keys_in_db(db, db);
+                self.flush_db(db, lazy);
+                self.invalidate_all_tracked_keys();
+                RespValue::SimpleString("OK".into())
+            }
+            Command::FlushAll(lazy) => {
+                for i in 0..self.dbs.len() {
+                    self.touch_watched_keys_in_db(i, i);
+                    self.flush_db(i, lazy);
+                }
+                self.invalidate_all_tracked_keys();
+                RespValue::SimpleString("OK".into())
+            }
+            Command::Multi => match self.clients[idx].multi.in_multi() {
+                true => RespValue::SimpleError("ERR MULTI calls can not be nested".into()),
+                false => {
+                    self.clients[idx].multi.queued = Some(Vec::new());
+                    RespValue::SimpleString("OK".into())
+                }
+            },
+            Command::Exec => self.exec(idx),
+            Command::Discard => match self.clients[idx].multi.in_multi() {
+                true => {
+                    self.clients[idx].multi.discard();
+                    self.unwatch_all(idx);
+                    RespValue::SimpleString("OK".into())
+                }
+                false => RespValue::SimpleError("ERR DISCARD without MULTI".into()),
+            },
+            Command::Watch(keys) => self.watch(idx, keys),
+            Command::Subscribe(channels) => {
+                self.subscribe(idx, channels, SubscriptionKind::Channel)
+            }
+            Command::Unsubscribe(channels) => {
+                self.unsubscribe(idx, channels, SubscriptionKind::Channel)
+            }
+            Command::PSubscribe(patterns) => {
+                self.subscribe(idx, patterns, SubscriptionKind::Pattern)
+            }
+            Command::PUnsubscribe(patterns) => {
+                self.unsubscribe(idx, patterns, SubscriptionKind::Pattern)
+            }
+            Command::SSubscribe(channels) => self.subscribe(idx, channels, SubscriptionKind::Shard),
+            Command::SUnsubscribe(channels) => {
+                self.unsubscribe(idx, channels, SubscriptionKind::Shard)
+            }
+            Command::Publish(channel, message) => {
+                RespValue::Integer(self.publish(&channel, &message) as i64)
+            }
+            Command::SPublish(channel, message) => {
+                RespValue::Integer(self.spublish(&channel, &message) as i64)
+            }
+            Command::Psync(_replid, _offset) => self.psync(idx),
+            Command::PubSub(pubsub_command) => match pubsub_command {
+                PubSubCommand::Channels(pattern) => RespValue::Array(
+                    self.pubsub
+                        .active_channels(pattern.as_deref())
+                        .into_iter()
+                        .map(RespValue::BulkString)
+                        .collect(),
+                ),
+                PubSubCommand::NumSub(channels) => RespValue::Map(
+                    channels
+                        .into_iter()
+                        .map(|c| {
+                            let n = self.pubsub.numsub(&c) as i64;
+                            (RespValue::BulkString(c), RespValue::Integer(n))
+                        })
+                        .collect(),
+                ),
+                PubSubCommand::NumPat => RespValue::Integer(self.pubsub.numpat() as i64),
+                PubSubCommand::ShardChannels(pattern) => RespValue::Array(
+                    self.pubsub
+                        .active_shard_channels(pattern.as_deref())
+                        .into_iter()
+                        .map(RespValue::BulkString)
+                        .collect(),
+                ),
+                PubSubCommand::ShardNumSub(channels) => RespValue::Map(
+                    channels
+                        .into_iter()
+                        .map(|c| {
+                            let n = self.pubsub.shard_numsub(&c) as i64;
+                            (RespValue::BulkString(c), RespValue::Integer(n))
+                        })
+                        .collect(),
+                ),
+            },
+            Command::Hello(protover, auth) => self.hello(idx, protover, auth),
+            Command::Auth(username, password) => self.auth(idx, username, password),
+            Command::Quit => {
+                self.clients[idx].close_after_reply = true;
+                RespValue::SimpleString("OK".into())
+            }
+            Command::Reset => self.reset(idx),
+            Command::Client(client_command) => self.client_command(idx, client_command),
+            Command::Eval(eval_command) => self.eval(idx, eval_command, false),
+            Command::EvalSha(eval_command) => self.eval(idx, eval_command, true),
+            Command::Script(script_command) => self.script_command(script_command),
+            Command::Function(function_command) => self.function_command(function_command),
+            Command::Config(config_command) => self.config_command(config_command),
+            Command::Introspect(introspect_command) => command_reply(introspect_command),
+            Command::SlowLog(slowlog_command) => self.slowlog_command(slowlog_command),
+            Command::Latency(latency_command) => self.latency_command(latency_command),
+            Command::Acl(acl_command) => self.acl_command(idx, acl_command),
+            Command::Monitor => {
+                if !self.clients[idx].monitor {
+                    self.clients[idx].monitor = true;
+                    self.monitors += 1;
+                }
+                RespValue::SimpleString("OK".into())
+            }
+            Command::FCall(eval_command) => self.fcall(idx, eval_command, false),
+            Command::FCallRo(eval_command) => self.fcall(idx, eval_command, true),
+            Command::Unwatch => {
+                self.unwatch_all(idx);
+                RespValue::SimpleString("OK".into())
+            }
+            Command::Scan(scan_command) => self.scan(db, scan_command),
+            Command::Expire(expire_command) => {
+                RespValue::Integer(self.expire(db, expire_command) as i64)
+            }
+            Command::Ttl(key) => match self.dbs[db].get(&key) {
+                // round to the nearest second like redis
+                Some(v) => match v.px {
+                    Some(px) => RespValue::Integer(
+                        (px.saturating_duration_since(Instant::now()).as_millis() as i64 + 500)
+                            / 1000,
+                    ),
+                    None => RespValue::Integer(-1),
+                },
+                None => RespValue::Integer(-2),
+            },
+            Command::Pttl(key) => match self.dbs[db].get(&key) {
+                Some(v) => match v.px {
+                    Some(px) => RespValue::Integer(
+                        px.saturating_duration_since(Instant::now()).as_millis() as i64,
+                    ),
+                    None => RespValue::Integer(-1),
+                },
+                None => RespValue::Integer(-2),
+            },
+            Command::ExpireTime(key) => match self.dbs[db].get(&key) {
+                Some(v) => match v.px {
+                    Some(px) => RespValue::Integer(unix_ms_from_instant(px) / 1000),
+                    None => RespValue::Integer(-1),
+                },
+                None => RespValue::Integer(-2),
+            },
+            Command::PexpireTime(key) => match self.dbs[db].get(&key) {
+                Some(v) => match v.px {
+                    Some(px) => RespValue::Integer(unix_ms_from_instant(px)),
+                    None => RespValue::Integer(-1),
+                },
+                None => RespValue::Integer(-2),
+            },
+            Command::Persist(key) => match self.dbs[db].get(&key) {
+                Some(v) if v.px.is_some() => {
+                    self.dbs[db].set_expire(&key, None);
+                    self.notify_keyspace_event(NOTIFY_GENERIC, "persist", &key, db);
+                    RespValue::Integer(1)
+                }
+                _ => RespValue::Integer(0),
+            },
+        }
+    }
+
+    /// EVAL or EVALSHA, `by_sha` tells whether the script is given by its SHA1
+    fn eval(&mut self, idx: usize, eval_command: EvalCommand, by_sha: bool) -> RespValue {
+        let EvalCommand { script, keys, args } = eval_command;
+
+        let (sha, body) = match by_sha {
+            true => match self.scripts.get(&script) {
+                Some(body) => (script.to_lowercase(), body.to_string()),
+                None => {
+                    return RespValue::SimpleError(
+                        "NOSCRIPT No matching script. Please use EVAL.".into(),
+                    )
+                }
+            },
+            false => (String::new(), script),
+        };
+
+        let compiled = match compile(&body) {
+            Ok(compiled) => compiled,
+            Err(e) => return e,
+        };
+        let sha = match by_sha {
+            true => sha,
+            false => self.scripts.insert(body),
+        };
+
+        self.run_script_for(idx, false, |run| {
+            run_script(run, compiled, &sha, keys, args)
+        })
+    }
+
+    /// FCALL or FCALL_RO, `read_only` tells which
+    fn fcall(&mut self, idx: usize, eval_command: EvalCommand, read_only: bool) -> RespValue {
+        let EvalCommand {
+            script: function,
+            keys,
+            args,
+        } = eval_command;
+
+        let (library, no_writes) = match self.functions.function(&function) {
+            Some((library, info)) => (library.clone(), info.no_writes()),
+            None => return RespValue::SimpleError("ERR Function not found".into()),
+        };
+
+        if read_only && !no_writes {
+            return RespValue::SimpleError(
+                "ERR Can not execute a script with write flag using *_ro command.".into(),
+            );
+        }
+        if !no_writes && matches!(self.replication.role, ServerRole::Slave) {
+            return RespValue::SimpleError(
+                "READONLY You can't write against a read only replica.".into(),
+            );
+        }
+
+        self.run_script_for(idx, no_writes, |run| {
+            call_function(run, &library, &function, keys, args)
+        })
+    }
+
+    /// Run a script or function for the client at `idx` and replicate what it wrote
+    ///
+    /// Scripts that are `no_writes` get an error for every write command they call.
+    fn run_script_for(
+        &mut self,
+        idx: usize,
+        no_writes: bool,
+        f: impl FnOnce(&mut ScriptRun<'_>) -> RespValue,
+    ) -> RespValue {
+        // a SELECT inside the script doesn't stick
+        let db = self.clients[idx].db;
+        let mut run = ScriptRun {
+            client_id: self.clients[idx].id,
+            server: self,
+            idx,
+            started: Instant::now(),
+            effects: Vec::new(),
+            killed: false,
+            no_writes,
+        };
+        let resp = f(&mut run);
+        let effects = run.effects;
+        self.clients[idx].db = db;
+
+        // replicas get the writes the script made instead of the script
+        if !effects.is_empty() {
+            self.propagate(&["MULTI"]);
+            for effect in effects {
+                let args: Vec<&str> = effect.iter().map(String::as_str).collect();
+                self.propagate(&args);
+            }
+            self.propagate(&["EXEC"]);
+        }
+
+        resp
+    }
+
+    fn script_command(&mut self, script_command: ScriptCommand) -> RespValue {
+        match script_command {
+            ScriptCommand::Load(body) => match compile(&body) {
+                Ok(_) => RespValue::BulkString(self.scripts.insert(body)),
+                Err(e) => e,
+            },
+            ScriptCommand::Exists(shas) => RespValue::Array(
+                shas.iter()
+                    .map(|sha| RespValue::Integer(self.scripts.contains(sha) as i64))
+                    .collect(),
+            ),
+            ScriptCommand::Flush => {
+                self.scripts.flush();
+                RespValue::SimpleString("OK".into())
+            }
+            // a running script is killed from serve_while_busy
+            ScriptCommand::Kill => {
+                RespValue::SimpleError("NOTBUSY No scripts in execution right now.".into())
+            }
+        }
+    }
+
+    fn config_command(&mut self, config_command: ConfigCommand) -> RespValue {
+        match config_command {
+            ConfigCommand::Get(patterns) => RespValue::Map(
+                self.config
+                    .matching(&patterns)
+                    .into_iter()
+                    .map(|(name, value)| {
+                        (
+                            RespValue::BulkString(name.into()),
+                            RespValue::BulkString(value),
+                        )
+                    })
+                    .collect(),
+            ),
+            ConfigCommand::Set(pairs) => match self.config_set(&pairs) {
+                Ok(()) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::SimpleError(format!("ERR {}", e)),
+            },
+            ConfigCommand::ResetStat => {
+                // dirty counts changes since the last save, not a statistic
+                let dirty = self.stats.dirty;
+                self.stats = Stats::new();
+                self.stats.dirty = dirty;
+                self.stats.record_memory(self.used_memory());
+                RespValue::SimpleString("OK".into())
+            }
+            ConfigCommand::Rewrite => match self.config.rewrite() {
+                Ok(()) => RespValue::SimpleString("OK".into()),
+                Err(e) => RespValue::SimpleError(format!("ERR {}", e)),
+            },
+        }
+    }
+
+    fn slowlog_command(&mut self, slowlog_command: SlowLogCommand) -> RespValue {
+        match slowlog_command {
+            SlowLogCommand::Get(count) => {
+                RespValue::Array(self.slowlog.newest(count).map(|e| e.reply()).collect())
+            }
+            SlowLogCommand::Len => RespValue::Integer(self.slowlog.len() as i64),
+            SlowLogCommand::Reset => {
+                self.slowlog.reset();
+                RespValue::SimpleString("OK".into())
+            }
+        }
+    }
+
+    fn latency_command(&mut self, latency_command: LatencyCommand) -> RespValue {
+        match latency_command {
+            LatencyCommand::Latest => RespValue::Array(
+                self.latency
+                    .events()
+                    .filter_map(|(name, event)| {
+                        let last = event.history.back()?;
+                        Some(RespValue::Array(vec![
+                            RespValue::BulkString(name.clone()),
+                            RespValue::Integer(last.time),
+                            RespValue::Integer(last.latency as i64),
+                            RespValue::Integer(event.max as i64),
+                        ]))
+                    })
+                    .collect(),
+            ),
+            LatencyCommand::History(event) => RespValue::Array(
+                self.latency
+                    .event(&event)
+                    .map(|e| {
+                        e.history
+                            .iter()
+                            .map(|sample| {
+                                RespValue::Array(vec![
+                                    RespValue::Integer(sample.time),
+                                    RespValue::Integer(sample.latency as i64),
+                                ])
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            ),
+            LatencyCommand::Reset(events) => RespValue::Integer(self.latency.reset(&events) as i64),
+            LatencyCommand::Histogram(commands) => RespValue::Map(
+                self.stats
+                    .latency_histograms
+                    .iter()
+                    .filter(|(name, _)| {
+                        commands.is_empty() || commands.iter().any(|c| c.eq_ignore_ascii_case(name))
+                    })
+                    .map(|(name, histogram)| {
+                        (RespValue::BulkString(name.clone()), histogram.reply())
+                    })
+                    .collect(),
+            ),
+            LatencyCommand::Doctor => {
+                let enabled = self.config.int("latency-monitor-threshold") > 0;
+                RespValue::BulkString(self.latency.doctor(enabled))
+            }
+        }
+    }
+
+    fn function_command(&mut self, function_command: FunctionCommand) -> RespValue {
+        match function_command {
+            FunctionCommand::Load { code, replace } => {
+                let library = match load_library(&code, &self.log) {
+                    Ok(library) => library,
+                    Err(e) => return RespValue::SimpleError(format!("ERR {}", e)),
+                };
+                let name = library.name.clone();
+                if let Err(e) = self.functions.add(library, replace) {
+                    return RespValue::SimpleError(format!("ERR {}", e));
+                }
+
+                match replace {
+                    true => self.propagate(&["FUNCTION", "LOAD", "REPLACE", &code]),
+                    false => self.propagate(&["FUNCTION", "LOAD", &code]),
+                }
+                RespValue::BulkString(name)
+            }
+            FunctionCommand::List {
+                library_name,
+                with_code,
+            } => RespValue::Array(
+                self.functions
+                    .list(library_name.as_deref())
+                    .map(|library| {
+                        let functions = library
+                            .functions
+                            .iter()
+                            .map(|f| {
+                                RespValue::Map(vec![
+                                    (
+                                        RespValue::BulkString("name".into()),
+                                        RespValue::BulkString(f.name.clone()),
+                                    ),
+                                    (
+                                        RespValue::BulkString("description".into()),
+                                        match &f.description {
+                                            Some(d) => RespValue::BulkString(d.clone()),
+                                            None => RespValue::Nil,
+                                        },
+                                    ),
+                                    (
+                                        RespValue::BulkString("flags".into()),
+                                        RespValue::Array(
+                                            f.flags
+                                                .iter()
+                                                .map(|flag| RespValue::SimpleString(flag.clone()))
+                                                .collect(),
+                                        ),
+                                    ),
+                                ])
+                            })
+                            .collect();
+
+                        let mut entries = vec![
+                            (
+                                RespValue::BulkString("library_name".into()),
+                                RespValue::BulkString(library.name.clone()),
+                            ),
+                            (
+                                RespValue::BulkString("engine".into()),
+                                RespValue::BulkString("LUA".into()),
+                            ),
+                            (
+                                RespValue::BulkString("functions".into()),
+                                RespValue::Array(functions),
+                            ),
+                        ];
+                        if with_code {
+                            entries.push((
+                                RespValue::BulkString("library_code".into()),
+                                RespValue::BulkString(library.code.clone()),
+                            ));
+                        }
+                        RespValue::Map(entries)
+                    })
+                    .collect(),
+            ),
+            FunctionCommand::Delete(name) => match self.functions.remove(&name) {
+                true => {
+                    self.propagate(&["FUNCTION", "DELETE", &name]);
+                    RespValue::SimpleString("OK".into())
+                }
+                false => RespValue::SimpleError("ERR Library not found".into()),
+            },
+            FunctionCommand::Dump => RespValue::BulkString(self.functions.dump()),
+            FunctionCommand::Restore { payload, policy } => {
+                self.restore_functions(&payload, policy)
+            }
+            FunctionCommand::Flush => {
+                self.functions.flush();
+                self.propagate(&["FUNCTION", "FLUSH"]);
+                RespValue::SimpleString("OK".into())
+            }
+            // a running function is killed from serve_while_busy
+            FunctionCommand::Kill => {
+                RespValue::SimpleError("NOTBUSY No scripts in execution right now.".into())
+            }
+        }
+    }
+
+    /// FUNCTION RESTORE, all the libraries in the payload are loaded or none are
+    fn restore_functions(&mut self, payload: &str, policy: RestorePolicy) -> RespValue {
+        let codes = match parse_dump(payload) {
+            Some(codes) => codes,
+            None => {
+                return RespValue::SimpleError("ERR payload version or checksum are wrong".into())
+            }
+        };
+
+        let mut restored = match policy {
+            RestorePolicy::Flush => Libraries::new(),
+            RestorePolicy::Append | RestorePolicy::Replace => self.functions.clone(),
+        };
+        for code in &codes {
+            let added = load_library(code, &self.log)
+                .and_then(|library| restored.add(library, policy == RestorePolicy::Replace));
+            if let Err(e) = added {
+                return RespValue::SimpleError(format!("ERR {}", e));
+            }
+        }
+        self.functions = restored;
+
+        let policy = match policy {
+            RestorePolicy::Append => "APPEND",
+            RestorePolicy::Replace => "REPLACE",
+            RestorePolicy::Flush => "FLUSH",
+        };
+        self.propagate(&["FUNCTION", "RESTORE", payload, policy]);
+        RespValue::SimpleString("OK".into())
+    }
+
+    /// Answer other clients while a script runs past the busy threshold
+    ///
+    /// Only SCRIPT KILL and SHUTDOWN are served, everything else gets a BUSY error.
+    /// Returns true if the script should be stopped.
+    fn serve_while_busy(&mut self, running: usize, wrote: bool) -> bool {
+        let mut stop = false;
+
+        for idx in 0..self.clients.len() {
+            if idx == running {
+                continue;
+            }
+
+            if self.read_query(idx) {
+                let _ = self.clients[idx].stream.shutdown(Shutdown::Both);
+                self.to_close.push(idx);
+            }
+            let resp = match self.next_request(idx) {
+                Some(Ok((
+                    _,
+                    Ok(Command::Script(ScriptCommand::Kill) | Command::Function(FunctionCommand::Kill)),
+                ))) => match wrote {
+                    true => RespValue::SimpleError(
+                        "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.".into(),
+                    ),
+                    false => {
+                        stop = true;
+                        RespValue::SimpleString("OK".into())
+                    }
+                },
+                Some(Ok((_, Ok(Command::Shutdown)))) => {
+                    self.shutdown = true;
+                    stop = true;
+                    RespValue::SimpleString("OK".into())
+                }
+                Some(_) => RespValue::SimpleError(
+                    "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.".into(),
+                ),
+                None => continue,
+            };
+            self.reply(idx, resp);
+        }
+
+        stop
+    }
+
+    /// Move the value at `key` to `newkey` keeping its ttl
+    ///
+    /// Returns false without doing anything if `nx` is set and `newkey` exists
+    fn rename(
+        &mut self,
+        db: usize,
+        key: &str,
+        newkey: String,
+        nx: bool,
+    ) -> std::result::Result<bool, RespValue> {
+        if !self.dbs[db].contains_key(key) {
+            return Err(RespValue::SimpleError("ERR no such key".into()));
+        }
+
+        if key == newkey {
+            return Ok(!nx);
+        }
+
+        if nx && self.dbs[db].contains_key(&newkey) {
+            return Ok(false);
+        }
+
+        let value = self.dbs[db].remove(key).unwrap();
+        if let Some(old) = self.dbs[db].insert(newkey.clone(), value) {
+            self.lazyfree.free(old);
+        }
+
+        self.notify_keyspace_event(NOTIFY_GENERIC, "rename_from", key, db);
+        self.notify_keyspace_event(NOTIFY_GENERIC, "rename_to", &newkey, db);
+
+        Ok(true)
+    }
+
+    /// Copy `source` in `db` to `destination` in the target db, defaulting to the same one
+    fn copy(
+        &mut self,
+        db: usize,
+        copy_command: CopyCommand,
+    ) -> std::result::Result<bool, RespValue> {
+        let CopyCommand {
+            source,
+            destination,
+            db: target,
+            replace,
+        } = copy_command;
+
+        let target = target.unwrap_or(db);
+        if target >= self.dbs.len() {
+            return Err(RespValue::SimpleError(
+                "ERR DB index is out of range".into(),
+            ));
+        }
+
+        if source == destination && target == db {
+            return Err(RespValue::SimpleError(
+                "ERR source and destination objects are the same".into(),
+            ));
+        }
+
+        let value = match self.dbs[db].get(&source) {
+            Some(v) => v.clone(),
+            None => return Ok(false),
+        };
+
+        if !replace && self.dbs[target].contains_key(&destination) {
+            return Ok(false);
+        }
+
+        if let Some(old) = self.dbs[target].insert(destination.clone(), value) {
+            self.lazyfree.free(old);
+        }
+
+        self.notify_keyspace_event(NOTIFY_GENERIC, "copy_to", &destination, target);
+
+        Ok(true)
+    }
+
+    /// Move `key` from `db` to `target`, only if it doesn't exist there yet
+    fn move_key(
+        &mut self,
+        db: usize,
+        key: &str,
+        target: usize,
+    ) -> std::result::Result<bool, RespValue> {
+        if target >= self.dbs.len() {
+            return Err(RespValue::SimpleError(
+                "ERR DB index is out of range".into(),
+            ));
+        }
+
+        if target == db {
+            return Err(RespValue::SimpleError(
+                "ERR source and destination objects are the same".into(),
+            ));
+        }
+
+        if !self.dbs[db].contains_key(key) || self.dbs[target].contains_key(key) {
+            return Ok(false);
+        }
+
+        let value = self.dbs[db].remove(key).unwrap();
+        self.dbs[target].insert(key.to_string(), value);
+
+        self.notify_keyspace_event(NOTIFY_GENERIC, "move_from", key, db);
+        self.notify_keyspace_event(NOTIFY_GENERIC, "move_to", key, target);
+
+        Ok(true)
+    }
+
+    /// Empty the db at `db`, handing the old contents to the background thread if `lazy`
+    fn flush_db(&mut self, db: usize, lazy: bool) {
+        let old = std::mem::take(&mut self.dbs[db]);
+
+        if lazy {
+            self.lazyfree.free_db(old);
+        }
+    }
+
+    /// INFO, `field:value` lines under a `# Title` line for each section
+    fn info(&mut self, sections: &BTreeSet<InfoSection>) -> String {
+        self.stats.record_memory(self.used_memory());
+
+        let mut info = String::new();
+        for &section in sections {
+            if !info.is_empty() {
+                info.push_str("\r\n");
+            }
+            info.push_str(&format!("# {}\r\n", section.title()));
+
+            for (name, value) in self.info_fields(section) {
+                info.push_str(&format!("{}:{}\r\n", name, value));
+            }
+        }
+
+        info
+    }
+
+    fn info_fields(&self, section: InfoSection) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        let mut field = |name: &str, value: &dyn Display| {
+            fields.push((name.to_string(), value.to_string()));
+        };
+
+        match section {
+            InfoSection::Server => {
+                let uptime = self.started.elapsed().as_secs();
+                let executable = env::current_exe()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default();
+
+                field("redis_version", &REDIS_VERSION);
+                field("redis_git_sha1", &"00000000");
+                field("redis_git_dirty", &0);
+                field("redis_mode", &"standalone");
+                field("os", &format!("{} {}", env::consts::OS, env::consts::ARCH));
+                field("arch_bits", &usize::BITS);
+                field("process_id", &std::process::id());
+                field("run_id", &self.run_id);
+                field("tcp_port", &self.config.int("port"));
+                field("server_time_usec", &(unix_time_ms() * 1000));
+                field("uptime_in_seconds", &uptime);
+                field("uptime_in_days", &(uptime / 86400));
+                field("executable", &executable);
+                let config_file = self
+                    .config
+                    .file()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default();
+                field("config_file", &config_file);
+            }
+            InfoSection::Clients => {
+                let clients = || self.clients.iter();
+
+                field(
+                    "connected_clients",
+                    &clients().filter(|c| !c.replica).count(),
+                );
+                field("cluster_connections", &0);
+                field(
+                    "client_recent_max_input_buffer",
+                    &clients().map(|c| c.query_buf.len()).max().unwrap_or(0),
+                );
+                field("client_recent_max_output_buffer", &0);
+                field("blocked_clients", &0);
+                field(
+                    "tracking_clients",
+                    &clients().filter(|c| c.tracking.enabled).count(),
+                );
+                field(
+                    "pubsub_clients",
+                    &clients().filter(|c| !c.subscriptions.is_empty()).count(),
+                );
+                field(
+                    "watching_clients",
+                    &clients().filter(|c| !c.multi.watched.is_empty()).count(),
+                );
+                field(
+                    "total_watched_keys",
+                    &clients().map(|c| c.multi.watched.len()).sum::<usize>(),
+                );
+            }
+            InfoSection::Memory => {
+                let used_memory = self.used_memory();
+                let peak = self.stats.peak_memory;
+
+                field("used_memory", &used_memory);
+                field("used_memory_human", &bytes_to_human(used_memory));
+                field("used_memory_peak", &peak);
+                field("used_memory_peak_human", &bytes_to_human(peak));
+                field("number_of_cached_scripts", &self.scripts.count());
+                field("number_of_functions", &self.functions.function_count());
+                field("number_of_libraries", &self.functions.list(None).count());
+                field("maxmemory", &self.maxmemory);
+                field("maxmemory_human", &bytes_to_human(self.maxmemory));
+                field("maxmemory_policy", &self.maxmemory_policy.as_str());
+                field("mem_allocator", &"libc");
+            }
+            InfoSection::Persistence => {
+                let started = unix_time_ms() / 1000 - self.started.elapsed().as_secs() as i64;
+
+                field("loading", &0);
+                field("async_loading", &0);
+                field("rdb_changes_since_last_save", &self.stats.dirty);
+                field("rdb_bgsave_in_progress", &0);
+                field("rdb_last_save_time", &started);
+                field("rdb_last_bgsave_status", &"ok");
+                field("aof_enabled", &0);
+                field("aof_rewrite_in_progress", &0);
+            }
+            InfoSection::Stats => {
+                let stats = &self.stats;
+
+                field(
+                    "total_connections_received",
+                    &stats.total_connections_received,
+                );
+                field("total_commands_processed", &stats.total_commands_processed);
+                field("total_net_input_bytes", &stats.total_net_input_bytes);
+                field("total_net_output_bytes", &stats.total_net_output_bytes);
+                field("rejected_connections", &self.stats.rejected_connections);
+                field("expired_keys", &stats.expired_keys);
+                field("evicted_keys", &stats.evicted_keys);
+                field("keyspace_hits", &stats.keyspace_hits);
+                field("keyspace_misses", &stats.keyspace_misses);
+                field("pubsub_channels", &self.pubsub.active_channels(None).len());
+                field("pubsub_patterns", &self.pubsub.numpat());
+                field(
+                    "pubsub_shardchannels",
+                    &self.pubsub.active_shard_channels(None).len(),
+                );
+                field("total_error_replies", &stats.total_error_replies);
+            }
+            InfoSection::Replication => {
+                let replication = &self.replication;
+                let offset = replication.master_repl_offset;
+
+                field("role", &replication.role.as_str());
+                if let Some((host, port)) = &replication.replicaof {
+                    let link = match self.master_stream {
+                        Some(_) => "up",
+                        None => "down",
+                    };
+                    field("master_host", host);
+                    field("master_port", port);
+                    field("master_link_status", &link);
+                    field("slave_repl_offset", &offset);
+                    field("slave_read_only", &1);
+                }
+
+                let replicas: Vec<&Client> = self.clients.iter().filter(|c| c.replica).collect();
+                field("connected_slaves", &replicas.len());
+                for (i, replica) in replicas.iter().enumerate() {
+                    let ip = replica.addr.rsplit_once(':').map(|(ip, _)| ip);
+                    field(
+                        &format!("slave{}", i),
+                        &format!(
+                            "ip={},port={},state=online,offset={},lag=0",
+                            ip.unwrap_or_default(),
+                            replica.listening_port.unwrap_or(0),
+                            offset
+                        ),
+                    );
+                }
+
+                field("master_replid", &replication.master_replid);
+                field("master_replid2", &"0".repeat(40));
+                field("master_repl_offset", &offset);
+                field("second_repl_offset", &-1);
+                field("repl_backlog_active", &0);
+            }
+            InfoSection::Cpu => {
+                let (sys, user) = cpu_times();
+                field("used_cpu_sys", &format!("{:.6}", sys));
+                field("used_cpu_user", &format!("{:.6}", user));
+            }
+            InfoSection::Modules => {}
+            InfoSection::CommandStats => {
+                for (name, stats) in &self.stats.commands {
+                    let per_call = match stats.calls {
+                        0 => 0.0,
+                        calls => stats.usec as f64 / calls as f64,
+                    };
+                    field(
+                        &format!("cmdstat_{}", name),
+                        &format!(
+                            "calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+                            stats.calls,
+                            stats.usec,
+                            per_call,
+                            stats.rejected_calls,
+                            stats.failed_calls
+                        ),
+                    );
+                }
+            }
+            InfoSection::ErrorStats => {
+                for (code, count) in &self.stats.errors {
+                    field(&format!("errorstat_{}", code), &format!("count={}", count));
+                }
+            }
+            InfoSection::Cluster => field("cluster_enabled", &0),
+            InfoSection::Keyspace => {
+                for (i, db) in self.dbs.iter().enumerate() {
+                    if db.len() == 0 {
+                        continue;
+                    }
+
+                    field(
+                        &format!("db{}", i),
+                        &format!(
+                            "keys={},expires={},avg_ttl={}",
+                            db.len(),
+                            db.volatile_len(),
+                            db.avg_ttl_ms()
+                        ),
+                    );
+                }
+            }
+        }
+
+        fields
+    }
+
+    /// Apply an expire time to a key if the NX/XX/GT/LT conditions allow it
+    ///
+    /// A time in the past deletes the key right away
+    fn expire(&mut self, db: usize, expire_command: ExpireCommand) -> bool {
+        let ExpireCommand {
+            key,
+            at_ms,
+            nx,
+            xx,
+            gt,
+            lt,
+        } = expire_command;
+
+        let current = match self.dbs[db].get(&key) {
+            Some(v) => v.px.map(unix_ms_from_instant),
+            None => return false,
+        };
+
+        // a key without ttl counts as an infinite ttl for GT and LT
+        let allowed = match current {
+            Some(_) if nx => false,
+            Some(current) if gt => at_ms > current,
+            Some(current) if lt => at_ms < current,
+            Some(_) => true,
+            None => !xx && !gt,
+        };
+
+        if !allowed {
+            return false;
+        }
+
+        match instant_from_unix_ms(at_ms) {
+            Some(px) => {
+                self.dbs[db].set_expire(&key, Some(px));
+                self.notify_keyspace_event(NOTIFY_GENERIC, "expire", &key, db);
+            }
+            None => {
+                if let Some(v) = self.dbs[db].remove(&key) {
+                    self.lazyfree.free(v);
+                }
+                self.notify_keyspace_event(NOTIFY_GENERIC, "del", &key, db);
+            }
+        }
+
+        true
+    }
+
+    /// Filters are applied after the keys are visited so a call may return fewer than `count`
+    fn scan(&mut self, db: usize, scan_command: ScanCommand) -> RespValue {
+        let (cursor, keys) = self.dbs[db].scan(scan_command.cursor, scan_command.count);
+
+        let keys = keys
+            .into_iter()
+            .filter(|k| match &scan_command.pattern {
+                Some(p) => glob_match(p, k, false),
+                None => true,
+            })
+            .filter(|k| match &scan_command.key_type {
+                Some(t) => self.dbs[db]
+                    .get(k)
+                    .is_some_and(|v| v.value.type_name() == t),
+                None => true,
+            })
+            .map(RespValue::BulkString)
+            .collect();
+
+        RespValue::Array(vec![
+            RespValue::BulkString(cursor.to_string()),
+            RespValue::Array(keys),
+        ])
+    }
+
+    /// CONFIG SET, also used to configure the server in tests
+    pub fn config_set(&mut self, pairs: &[(String, String)]) -> std::result::Result<(), String> {
+        for name in self.config.set_runtime(pairs)? {
+            self.apply_config(name);
+        }
+        Ok(())
+    }
+
+    /// Bring the state kept outside the config in line with the parameter `name`
+    fn apply_config(&mut self, name: &str) {
+        match name {
+            "maxmemory" | "maxmemory-policy" => {
+                self.maxmemory = self.config.int("maxmemory") as u64;
+                self.maxmemory_policy =
+                    MaxmemoryPolicy::from_str(self.config.string("maxmemory-policy")).unwrap();
+                self.eviction_pool.clear();
+            }
+            "notify-keyspace-events" => {
+                self.notify_keyspace_events = self.config.int("notify-keyspace-events") as u32;
+            }
+            "requirepass" => {
+                let rules = match self.config.string("requirepass") {
+                    "" => vec!["nopass".to_string()],
+                    password => vec!["resetpass".to_string(), format!(">{}", password)],
+                };
+                self.acl.set_user("default", &rules).unwrap();
+            }
+            "loglevel" | "logfile" => self.log = Log::from_config(&self.config),
+            "busy-reply-threshold" => {
+                self.busy_reply_threshold =
+                    Duration::from_millis(self.config.int("busy-reply-threshold") as u64);
+            }
+            _ => {}
+        }
+    }
+
+    /// Approximate bytes held by the keyspace
+    pub fn used_memory(&self) -> u64 {
+        self.dbs.iter().map(|db| db.used_memory() as u64).sum()
+    }
+
+    /// Evict keys according to the maxmemory policy until memory usage is under the limit
+    ///
+    /// Returns false if usage is still over the limit because nothing could be evicted
+    fn perform_evictions(&mut self) -> bool {
+        while self.used_memory() > self.maxmemory {
+            let policy = self.maxmemory_policy;
+
+            let candidate = match policy {
+                MaxmemoryPolicy::NoEviction => None,
+                _ if policy.is_random() => {
+                    // go around the dbs so they are evicted from evenly
+                    (0..self.dbs.len()).find_map(|i| {
+                        let db = (self.next_eviction_db + i) % self.dbs.len();
+                        self.next_eviction_db = db + 1;
+
+                        self.dbs[db]
+                            .sample(policy.volatile_only(), 1)
+                            .first()
+                            .map(|(k, _)| (db, k.to_string()))
+                    })
+                }
+                _ => {
+                    for (i, db) in self.dbs.iter().enumerate() {
+                        self.eviction_pool
+                            .populate(i, db, policy, MAXMEMORY_SAMPLES);
+                    }
+
+                    // the pool may hold keys that are gone by now
+                    let mut candidate = None;
+                    while let Some((db, key)) = self.eviction_pool.pop() {
+                        if self.dbs[db].contains_key(&key) {
+                            candidate = Some((db, key));
+                            break;
+                        }
+                    }
+                    candidate
+                }
+            };
+
+            match candidate {
+                Some((db, key)) => {
+                    self.dbs[db].remove(&key);
+                    self.stats.evicted_keys += 1;
+                    self.notify_keyspace_event(NOTIFY_EVICTED, "evicted", &key, db);
+                }
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Run the active expire cycle if it is due
+    ///
+    /// Expired keys are also removed lazily whenever they are accessed, this only takes care
+    /// of the ones nobody asks for anymore.
+    pub fn remove_expired(&mut self) {
+        // keys don't expire while clients are paused so the dataset stays put
+        if self.active_pause().is_some() {
+            return;
+        }
+
+        let now = Instant::now();
+        if now < self.last_expire_cycle + ACTIVE_EXPIRE_CYCLE_PERIOD {
+            return;
+        }
+        self.last_expire_cycle = now;
+        self.stats.record_memory(self.used_memory());
+
+        // dbs share the time budget
+        let deadline = now + ACTIVE_EXPIRE_CYCLE_BUDGET;
+        for db in self.dbs.iter_mut() {
+            if Instant::now() >= deadline {
+                break;
+            }
+
+            let _ = db.active_expire_cycle(deadline);
+        }
+        self.record_latency_event("expire-cycle", now.elapsed());
+
+        self.handle_modified_keys(None);
+    }
+
+    /// Close clients idle for longer than `timeout`, except replicas, pub/sub clients and
+    /// ones whose command waits for a pause to end
+    fn close_idle_clients(&mut self) {
+        let timeout = self.config.int("timeout");
+        if timeout == 0 {
+            return;
+        }
+
+        let timeout = Duration::from_secs(timeout as u64);
+        for (idx, client) in self.clients.iter().enumerate() {
+            if client.replica
+                || client.held.is_some()
+                || !client.subscriptions.is_empty()
+                || client.last_interaction.elapsed() <= timeout
+            {
+                continue;
+            }
+
+            self.log.log(Level::Verbose, "Closing idle client");
+            let _ = client.stream.shutdown(Shutdown::Both);
+            self.to_close.push(idx);
+        }
+    }
+
+    pub fn run(&mut self) {
+        loop {
+            // Pick up new connections
+            let accepted: Vec<TcpStream> = self
+                .listeners
+                .iter()
+                .filter_map(|l| l.accept().ok())
+                .map(|(stream, _)| stream)
+                .collect();
+            for stream in accepted {
+                self.accept(Connection::Tcp(stream));
+            }
+            let unix = self.unix_listener.as_ref().and_then(|l| l.accept().ok());
+            if let Some((stream, _)) = unix {
+                let path = self.config.string("unixsocket").to_string();
+                self.accept(Connection::Unix(stream, path));
+            }
+
+            self.close_idle_clients();
+            self.poll_streams();
+            self.remove_expired();
+
+            //cleanup was done in poll, safe to break
+            if self.shutdown {
+                println!("shuttding down server");
+                if self.unix_listener.is_some() {
+                    let _ = fs::remove_file(self.config.string("unixsocket"));
+                }
+                break;
+            }
+        }
+    }
+
+    fn accept(&mut self, stream: Connection) {
+        if self.clients.len() as i64 >= self.config.int("maxclients") {
+            let _ = (&stream).write_all(b"-ERR max number of clients reached\r\n");
+            self.stats.rejected_connections += 1;
+            return;
+        }
+        if let Connection::Tcp(tcp) = &stream {
+            if tcp
+                .peer_addr()
+                .is_ok_and(|a| self.protected_mode_refuses(a.ip()))
+            {
+                let _ = (&stream).write_all(PROTECTED_MODE_ERR.as_bytes());
+                return;
+            }
+        }
+        stream.set_nonblocking(true).unwrap();
+        let keepalive = self.config.int("tcp-keepalive");
+        if keepalive > 0 {
+            if let Err(e) = stream.set_keepalive(keepalive as i32) {
+                self.log.log(
+                    Level::Warning,
+                    &format!("Error setting TCP keepalive: {}", e),
+                );
+            }
+        }
+        let mut client = Client::new(self.next_client_id, stream);
+        self.log
+            .log(Level::Verbose, &format!("Accepted {}", client.addr));
+        // connecting while no password is needed counts as authenticating
+        client.authenticated = self.default_user_open();
+        self.clients.push(client);
+        self.next_client_id += 1;
+        self.stats.total_connections_received += 1;
+    }
+}
+
+/// Whether the command table flags the command line's command `denyoom`, so it is refused
+/// once used memory is over maxmemory
+fn is_denyoom(args: &[String]) -> bool {
+    command_table::lookup_args(args).is_some_and(|spec| spec.flags.contains(&"denyoom"))
+}
+
+/// Listen on every address in `bind` with `port`. `*` and `::*` stand for all IPv4 and IPv6
+/// addresses, and addresses starting with `-` are left out with a warning when they can't
+/// be bound
+fn listen_tcp(bind: &str, port: u16, log: &Log) -> std::result::Result<Vec<TcpListener>, String> {
+    let mut listeners = Vec::new();
+    for addr in bind.split(' ').filter(|a| !a.is_empty()) {
+        let (addr, optional) = match addr.strip_prefix('-') {
+            Some(addr) => (addr, true),
+            None => (addr, false),
+        };
+        let host = match addr {
+            "*" => "0.0.0.0",
+            "::*" => "::",
+            _ => addr,
+        };
+
+        match TcpListener::bind((host, port)) {
+            Ok(listener) => {
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                listeners.push(listener);
+            }
+            Err(e) if optional => {
+                let msg = format!("Skipping optional bind address {}:{}: {}", addr, port, e);
+                log.log(Level::Warning, &msg);
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Could not create server TCP listening socket {}:{}: {}",
+                    addr, port, e
+                ))
+            }
+        }
+    }
+
+    if listeners.is_empty() {
+        return Err(format!(
+            "Failed listening on port {} (tcp), aborting.",
+            port
+        ));
+    }
+    Ok(listeners)
+}
+
+/// Listen on a Unix socket at `path`, with the permission bits `perm` unless they are 0
+fn listen_unix(path: &str, perm: u32) -> std::result::Result<UnixListener, String> {
+    // a socket left behind by an earlier run would make bind fail
+    let _ = fs::remove_file(path);
+
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("Failed opening Unix socket: {}", e))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Failed opening Unix socket: {}", e))?;
+    if perm != 0 {
+        fs::set_permissions(path, Permissions::from_mode(perm))
+            .map_err(|e| format!("Failed setting Unix socket permissions: {}", e))?;
+    }
+
+    Ok(listener)
+}
+
+fn kill_filter_matches(filter: &ClientKillFilter, client: &Client) -> bool {
+    let ClientKillFilter {
+        id,
+        addr,
+        laddr,
+        user,
+        client_type,
+        maxage,
+        skipme: _,
+    } = filter;
+
+    id.iter().all(|&id| client.id == id)
+        && addr.iter().all(|addr| &client.addr == addr)
+        && laddr.iter().all(|laddr| &client.laddr == laddr)
+        && user.iter().all(|user| &client.user == user)
+        && client_type.iter().all(|&t| client.client_type() == t)
+        && maxage
+            .iter()
+            .all(|&age| client.created.elapsed().as_secs() >= age)
+}
+
+/// `arg` in double quotes with unprintable bytes escaped, the way MONITOR shows arguments
+fn quoted_repr(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for &b in arg.as_bytes() {
+        match b {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x20..=0x7e => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Replace the passwords in the command line `argv` of the command `name` so they
+/// don't show in SLOWLOG and MONITOR
+fn redact_passwords(name: &str, argv: &mut [String]) {
+    let from = match name {
+        "auth" => 1,
+        "hello" => match argv.iter().position(|a| a.eq_ignore_ascii_case("auth")) {
+            Some(i) => i + 1,
+            None => return,
+        },
+        _ => return,
+    };
+    for arg in argv.iter_mut().skip(from) {
+        *arg = "(redacted)".into();
+    }
+}
+
+/// Reply to COMMAND, it only looks at the command table
+fn command_reply(introspect_command: IntrospectCommand) -> RespValue {
+    match introspect_command {
+        IntrospectCommand::Info(names) if names.is_empty() => {
+            RespValue::Array(COMMAND_TABLE.iter().map(|c| c.info()).collect())
+        }
+        IntrospectCommand::Info(names) => RespValue::Array(
+            names
+                .iter()
+                .map(|name| match command_table::lookup_full_name(name) {
+                    Some(spec) => spec.info(),
+                    None => RespValue::Nil,
+                })
+                .collect(),
+        ),
+        IntrospectCommand::Count => RespValue::Integer(COMMAND_TABLE.len() as i64),
+        IntrospectCommand::Docs(names) => {
+            let specs: Vec<_> = if names.is_empty() {
+                COMMAND_TABLE.iter().collect()
+            } else {
+                names
+                    .iter()
+                    .filter_map(|name| command_table::lookup_full_name(name))
+                    .collect()
+            };
+            RespValue::Map(
+                specs
+                    .into_iter()
+                    .map(|spec| (RespValue::BulkString(spec.name.into()), spec.docs()))
+                    .collect(),
+            )
+        }
+        IntrospectCommand::List(filter) => RespValue::Array(
+            all_commands()
+                .filter(|spec| match &filter {
+                    None => true,
+                    // there are no modules
+                    Some(CommandListFilter::Module(_)) => false,
+                    Some(CommandListFilter::AclCat(category)) => spec
+                        .acl_categories
+                        .iter()
+                        .any(|c| c.eq_ignore_ascii_case(category)),
+                    Some(CommandListFilter::Pattern(pattern)) => {
+                        glob_match(pattern, spec.name, true)
+                    }
+                })
+                .map(|spec| RespValue::BulkString(spec.name.into()))
+                .collect(),
+        ),
+        IntrospectCommand::GetKeys(args) => match get_keys(&args) {
+            Ok(keys) => RespValue::Array(keys.into_iter().map(RespValue::BulkString).collect()),
+            Err(e) => RespValue::SimpleError(format!("ERR {}", e)),
+        },
+    }
+}
+
+/// A script being run for the client at `idx`, how its `redis.call`s reach the server
+struct ScriptRun<'a> {
+    server: &'a mut Server,
+    idx: usize,
+    client_id: u64,
+    started: Instant,
+    /// Write commands the script ran, replicated in its place
+    effects: Vec<Vec<String>>,
+    killed: bool,
+    /// Write commands are refused, for functions flagged `no-writes`
+    no_writes: bool,
+}
+
+impl Host for ScriptRun<'_> {
+    fn call(&mut self, args: Vec<String>) -> RespValue {
+        let resp_args = args.iter().map(|a| RespValue::BulkString(a.clone()));
+        let cmd = match CommandParser::new(resp_args).parse_next() {
+            Ok(cmd) => cmd,
+            Err(e) => return RespValue::SimpleError(format!("ERR {}", e)),
+        };
+
+        if cmd.is_noscript() {
+            return RespValue::SimpleError(
+                "ERR This Redis command is not allowed from script".into(),
+            );
+        }
+
+        if let Err(e) = self.server.check_acl(self.idx, &args, &cmd, "lua") {
+            return e;
+        }
+
+        if self.no_writes && cmd.is_write() {
+            return RespValue::SimpleError(
+                "ERR Write commands are not allowed from read-only scripts.".into(),
+            );
+        }
+
+        if self.server.maxmemory > 0 && is_denyoom(&args) && !self.server.perform_evictions() {
+            return RespValue::SimpleError(
+                "OOM command not allowed when used memory > 'maxmemory'.".into(),
+            );
+        }
+
+        if self.server.monitors > 0 {
+            let db = self.server.clients[self.idx].db;
+            self.server.feed_monitors(db, "lua", &args);
+        }
+
+        let write = cmd.is_write();
+        let resp = self.server.execute(self.idx, cmd);
+        self.server.handle_modified_keys(Some(self.client_id));
+
+        if write && !matches!(resp, RespValue::SimpleError(_)) {
+            self.effects.push(args);
+        }
+
+        resp
+    }
+
+    fn log(&mut self, level: Level, msg: &str) {
+        self.server.log.log(level, msg);
+    }
+
+    fn keep_running(&mut self) -> bool {
+        if !self.killed && self.started.elapsed() >= self.server.busy_reply_threshold {
+            self.killed = self
+                .server
+                .serve_while_busy(self.idx, !self.effects.is_empty());
+        }
+
+        !self.killed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    const ADDR: &str = "127.0.0.1:0";
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    /// Encode `args` as a RESP array of bulk strings
+    fn cmd(args: &[&str]) -> String {
+        RespValue::Array(
+            args.iter()
+                .map(|a| RespValue::BulkString(a.to_string()))
+                .collect(),
+        )
+        .serialize_value()
+        .unwrap()
+    }
+
+    /// Write to an open connection and read back a single reply
+    fn send(stream: &mut TcpStream, to_send: &str) -> Result<String> {
+        let mut buf: [u8; 1024] = [0; 1024];
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.write_all(to_send.as_bytes())?;
+        stream.flush()?;
+
+        let n = stream.read(&mut buf)?;
+        Ok(String::from_utf8(buf[..n].to_vec()).unwrap())
+    }
+
+    fn set_config(server: &mut Server, name: &str, value: &str) {
+        server
+            .config_set(&[(name.to_string(), value.to_string())])
+            .unwrap();
+    }
+
+    fn stream_helper(addr: SocketAddr, to_send: &str) -> Result<String> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        send(&mut stream, to_send)
+    }
+
+    /// Creates and runs the server
+    /// use a stream to write to the server
+    /// Join on the returned [`JoinHandle`]
+    fn server_helper() -> (JoinHandle<()>, SocketAddr) {
+        let mut server = Server::new(ADDR, None, 16);
+        let addr = server.local_addr();
+
+        let handle = thread::spawn(move || {
+            server.run();
+        });
+
+        (handle, addr)
+    }
+
+    /// A port nothing listens on, for configs that need a real one
+    fn free_port() -> String {
+        let listener = TcpListener::bind(ADDR).unwrap();
+        listener.local_addr().unwrap().port().to_string()
+    }
+
+    fn shutdown_helper(handle: JoinHandle<()>, addr: SocketAddr) {
+        let _ = stream_helper(addr, "*1\r\n+SHUTDOWN\r\n");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_server_creation() {
+        let (handle, addr) = server_helper();
+
+        shutdown_helper(handle, addr);
+    }
+
+    #[test]
+    fn test_ping_command() {
+        let (handle, addr) = server_helper();
+
+        let resp = stream_helper(addr, "*1\r\n$4\r\nPING\r\n").unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(resp, String::from("+PONG\r\n"));
+    }
+
+    #[test]
+    fn test_echo_command() {
+        let (handle, addr) = server_helper();
+
+        let resp = stream_helper(addr, "*2\r\n$4\r\nECHO\r\n$2\r\nOK\r\n").unwrap();
+
+        shutdown_helper(handle, addr);
+        assert_eq!(resp, "$2\r\nOK\r\n")
+    }
+
+    #[test]
+    fn test_keyspace_commands() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &cmd(&["SET", "a", "1"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "b", "2"])).unwrap();
+
+        let exists = send(&mut stream, &cmd(&["EXISTS", "a", "a", "c"])).unwrap();
+        let key_type = send(&mut stream, &cmd(&["TYPE", "a"])).unwrap();
+        let none_type = send(&mut stream, &cmd(&["TYPE", "c"])).unwrap();
+        let renamenx = send(&mut stream, &cmd(&["RENAMENX", "a", "b"])).unwrap();
+        let rename = send(&mut stream, &cmd(&["RENAME", "a", "c"])).unwrap();
+        let copy = send(&mut stream, &cmd(&["COPY", "c", "b"])).unwrap();
+        let copy_replace = send(&mut stream, &cmd(&["COPY", "c", "b", "REPLACE"])).unwrap();
+        let get = send(&mut stream, &cmd(&["GET", "b"])).unwrap();
+        let del = send(&mut stream, &cmd(&["DEL", "b", "c", "missing"])).unwrap();
+        let dbsize = send(&mut stream, &cmd(&["DBSIZE"])).unwrap();
+        let rename_missing = send(&mut stream, &cmd(&["RENAME", "a", "c"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(exists, ":2\r\n");
+        assert_eq!(key_type, "+string\r\n");
+        assert_eq!(none_type, "+none\r\n");
+        assert_eq!(renamenx, ":0\r\n");
+        assert_eq!(rename, "+OK\r\n");
+        assert_eq!(copy, ":0\r\n");
+        assert_eq!(copy_replace, ":1\r\n");
+        assert_eq!(get, "$1\r\n1\r\n");
+        assert_eq!(del, ":2\r\n");
+        assert_eq!(dbsize, ":0\r\n");
+        assert_eq!(rename_missing, "-ERR no such key\r\n");
+    }
+
+    #[test]
+    fn test_ttl_commands() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &cmd(&["SET", "a", "1"])).unwrap();
+
+        let ttl_missing = send(&mut stream, &cmd(&["TTL", "missing"])).unwrap();
+        let ttl_persistent = send(&mut stream, &cmd(&["TTL", "a"])).unwrap();
+        let expire_xx = send(&mut stream, &cmd(&["EXPIRE", "a", "100", "XX"])).unwrap();
+        let expire = send(&mut stream, &cmd(&["EXPIRE", "a", "100"])).unwrap();
+        let ttl = send(&mut stream, &cmd(&["TTL", "a"])).unwrap();
+        let expire_gt = send(&mut stream, &cmd(&["EXPIRE", "a", "50", "GT"])).unwrap();
+        let expire_lt = send(&mut stream, &cmd(&["EXPIRE", "a", "50", "LT"])).unwrap();
+        let ttl_lt = send(&mut stream, &cmd(&["TTL", "a"])).unwrap();
+        let persist = send(&mut stream, &cmd(&["PERSIST", "a"])).unwrap();
+        let expiretime = send(&mut stream, &cmd(&["EXPIRETIME", "a"])).unwrap();
+        let expire_past = send(&mut stream, &cmd(&["PEXPIREAT", "a", "1"])).unwrap();
+        let exists = send(&mut stream, &cmd(&["EXISTS", "a"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(ttl_missing, ":-2\r\n");
+        assert_eq!(ttl_persistent, ":-1\r\n");
+        assert_eq!(expire_xx, ":0\r\n");
+        assert_eq!(expire, ":1\r\n");
+        assert_eq!(ttl, ":100\r\n");
+        assert_eq!(expire_gt, ":0\r\n");
+        assert_eq!(expire_lt, ":1\r\n");
+        assert_eq!(ttl_lt, ":50\r\n");
+        assert_eq!(persist, ":1\r\n");
+        assert_eq!(expiretime, ":-1\r\n");
+        assert_eq!(expire_past, ":1\r\n");
+        assert_eq!(exists, ":0\r\n");
+    }
+
+    #[test]
+    fn test_expired_keys_are_not_returned() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &cmd(&["SET", "a", "1", "PX", "20"])).unwrap();
+        sleep(Duration::from_millis(50));
+
+        let get = send(&mut stream, &cmd(&["GET", "a"])).unwrap();
+        let exists = send(&mut stream, &cmd(&["EXISTS", "a"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(get, "$-1\r\n");
+        assert_eq!(exists, ":0\r\n");
+    }
+
+    #[test]
+    fn test_databases() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut other = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &cmd(&["SET", "a", "0"])).unwrap();
+        let select = send(&mut stream, &cmd(&["SELECT", "1"])).unwrap();
+        let get_db1 = send(&mut stream, &cmd(&["GET", "a"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "a", "1"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "b", "1"])).unwrap();
+
+        let move_existing = send(&mut stream, &cmd(&["MOVE", "a", "0"])).unwrap();
+        let move_key = send(&mut stream, &cmd(&["MOVE", "b", "0"])).unwrap();
+        let select_out_of_range = send(&mut stream, &cmd(&["SELECT", "16"])).unwrap();
+
+        // the other client stays on db 0 and sees the swap
+        let swapdb = send(&mut stream, &cmd(&["SWAPDB", "0", "1"])).unwrap();
+        let get_swapped = send(&mut other, &cmd(&["GET", "a"])).unwrap();
+        let keyspace = send(&mut other, &cmd(&["INFO", "keyspace"])).unwrap();
+
+        let flushdb = send(&mut other, &cmd(&["FLUSHDB", "ASYNC"])).unwrap();
+        let dbsize_flushed = send(&mut other, &cmd(&["DBSIZE"])).unwrap();
+        let dbsize_db1 = send(&mut stream, &cmd(&["DBSIZE"])).unwrap();
+        send(&mut stream, &cmd(&["FLUSHALL"])).unwrap();
+        let dbsize_flushall = send(&mut stream, &cmd(&["DBSIZE"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(select, "+OK\r\n");
+        assert_eq!(get_db1, "$-1\r\n");
+        assert_eq!(move_existing, ":0\r\n");
+        assert_eq!(move_key, ":1\r\n");
+        assert_eq!(select_out_of_range, "-ERR DB index is out of range\r\n");
+        assert_eq!(swapdb, "+OK\r\n");
+        assert_eq!(get_swapped, "$1\r\n1\r\n");
+        assert!(keyspace.contains("db0:keys=1,expires=0,avg_ttl=0"));
+        assert!(keyspace.contains("db1:keys=2,expires=0,avg_ttl=0"));
+        assert_eq!(flushdb, "+OK\r\n");
+        assert_eq!(dbsize_flushed, ":0\r\n");
+        assert_eq!(dbsize_db1, ":2\r\n");
+        assert_eq!(dbsize_flushall, ":0\r\n");
+    }
+
+    #[test]
+    fn test_maxmemory() {
+        let mut server = Server::new(ADDR, None, 16);
+        let addr = server.local_addr();
+        set_config(&mut server, "maxmemory", "2000");
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let value = "x".repeat(500);
+
+        for i in 0..5 {
+            send(&mut stream, &cmd(&["SET", &format!("k{}", i), &value])).unwrap();
+        }
+        let oom = send(&mut stream, &cmd(&["SET", "k5", &value])).unwrap();
+        let function_oom = send(
+            &mut stream,
+            &cmd(&[
+                "FUNCTION",
+                "LOAD",
+                "#!lua name=lib\nredis.register_function('f', function() return 1 end)",
+            ]),
+        )
+        .unwrap();
+        // commands that don't grow memory still work
+        let del = send(&mut stream, &cmd(&["DEL", "k0", "k1"])).unwrap();
+        let set = send(&mut stream, &cmd(&["SET", "k5", &value])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert!(oom.starts_with("-OOM"));
+        assert!(function_oom.starts_with("-OOM"), "{}", function_oom);
+        assert_eq!(del, ":2\r\n");
+        assert_eq!(set, "$2\r\nOK\r\n");
+    }
+
+    #[test]
+    fn test_maxmemory_evicts_lru() {
+        let mut server = Server::new(ADDR, None, 16);
+        set_config(&mut server, "maxmemory", "2000");
+        set_config(&mut server, "maxmemory-policy", "allkeys-lru");
+
+        let value = Value::String("x".repeat(500));
+        for i in 0..10 {
+            server.dbs[0].insert(format!("k{}", i), StoredValue::new(value.clone(), None));
+        }
+
+        assert!(server.perform_evictions());
+        assert!(server.used_memory() <= 2000);
+        assert!(server.dbs[0].len() < 10);
+    }
+
+    #[test]
+    fn test_maxmemory_volatile_without_expires() {
+        let mut server = Server::new(ADDR, None, 16);
+        set_config(&mut server, "maxmemory", "100");
+        set_config(&mut server, "maxmemory-policy", "volatile-ttl");
+
+        let value = Value::String("x".repeat(500));
+        server.dbs[0].insert("k".into(), StoredValue::new(value, None));
+
+        assert!(!server.perform_evictions());
+        assert_eq!(server.dbs[0].len(), 1);
+    }
+
+    #[test]
+    fn test_pipelining() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let pipeline = [
+            cmd(&["MULTI"]),
+            cmd(&["SET", "a", "1"]),
+            cmd(&["SET", "b", "2"]),
+            cmd(&["EXEC"]),
+            cmd(&["GET", "b"]),
+        ]
+        .concat();
+        stream.write_all(pipeline.as_bytes()).unwrap();
+        let expected = "+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n$2\r\nOK\r\n$2\r\nOK\r\n$1\r\n2\r\n";
+        let mut replies = vec![0; expected.len()];
+        stream.read_exact(&mut replies).unwrap();
+
+        // a request bigger than a read, arriving in pieces
+        let set = cmd(&["SET", "big", &"x".repeat(100_000)]);
+        let (start, rest) = set.split_at(10);
+        stream.write_all(start.as_bytes()).unwrap();
+        sleep(Duration::from_millis(50));
+        let set = send(&mut stream, rest).unwrap();
+        let script = format!(
+            "{}return #redis.call('GET', 'big')",
+            "-- padding\n".repeat(300)
+        );
+        let len = send(&mut stream, &cmd(&["EVAL", &script, "0"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(String::from_utf8(replies).unwrap(), expected);
+        assert_eq!(set, "$2\r\nOK\r\n");
+        assert_eq!(len, ":100000\r\n");
+    }
+
+    #[test]
+    fn test_multi_exec() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let multi = send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        let queued = send(&mut stream, &cmd(&["SET", "a", "1"])).unwrap();
+        send(&mut stream, &cmd(&["GET", "a"])).unwrap();
+        let nested = send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        let exec = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+        let exec_without_multi = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "a", "2"])).unwrap();
+        let syntax_error = send(&mut stream, &cmd(&["NOTACOMMAND"])).unwrap();
+        let execabort = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "a", "3"])).unwrap();
+        let discard = send(&mut stream, &cmd(&["DISCARD"])).unwrap();
+        let get = send(&mut stream, &cmd(&["GET", "a"])).unwrap();
+
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        let psync = send(&mut stream, &cmd(&["PSYNC", "?", "-1"])).unwrap();
+        let psync_exec = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        let reply = send(&mut stream, &cmd(&["CLIENT", "REPLY", "ON"])).unwrap();
+        let reply_exec = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(multi, "+OK\r\n");
+        assert_eq!(queued, "+QUEUED\r\n");
+        assert_eq!(nested, "-ERR MULTI calls can not be nested\r\n");
+        assert_eq!(exec, "*2\r\n$2\r\nOK\r\n$1\r\n1\r\n");
+        assert_eq!(exec_without_multi, "-ERR EXEC without MULTI\r\n");
+        assert!(syntax_error.starts_with("-ERR"));
+        assert!(execabort.starts_with("-EXECABORT"));
+        assert_eq!(discard, "+OK\r\n");
+        assert_eq!(get, "$1\r\n1\r\n");
+        assert_eq!(psync, "-ERR Command not allowed inside a transaction\r\n");
+        assert!(psync_exec.starts_with("-EXECABORT"));
+        assert_eq!(reply, "-ERR Command not allowed inside a transaction\r\n");
+        assert!(reply_exec.starts_with("-EXECABORT"));
+    }
+
+    #[test]
+    fn test_watch() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut other = TcpStream::connect(addr).unwrap();
+
+        // untouched watched key
+        send(&mut stream, &cmd(&["WATCH", "a"])).unwrap();
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "a", "1"])).unwrap();
+        let exec = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        // modified by another client
+        send(&mut stream, &cmd(&["WATCH", "a"])).unwrap();
+        send(&mut other, &cmd(&["SET", "a", "2"])).unwrap();
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "a", "3"])).unwrap();
+        let exec_modified = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        // flushed
+        send(&mut stream, &cmd(&["WATCH", "a"])).unwrap();
+        send(&mut other, &cmd(&["FLUSHALL"])).unwrap();
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        let exec_flushed = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        // expired
+        send(&mut stream, &cmd(&["SET", "b", "1", "PX", "200"])).unwrap();
+        send(&mut stream, &cmd(&["WATCH", "b"])).unwrap();
+        sleep(Duration::from_millis(300));
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        let exec_expired = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        // unwatched
+        send(&mut stream, &cmd(&["WATCH", "a"])).unwrap();
+        send(&mut stream, &cmd(&["UNWATCH"])).unwrap();
+        send(&mut other, &cmd(&["SET", "a", "4"])).unwrap();
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        let exec_unwatched = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(exec, "*1\r\n$2\r\nOK\r\n");
+        assert_eq!(exec_modified, "*-1\r\n");
+        assert_eq!(exec_flushed, "*-1\r\n");
+        assert_eq!(exec_expired, "*-1\r\n");
+        assert_eq!(exec_unwatched, "*0\r\n");
+    }
+
+    #[test]
+    fn test_pubsub() {
+        let (handle, addr) = server_helper();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut psubscriber = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+
+        let subscribe = send(&mut subscriber, &cmd(&["SUBSCRIBE", "news"])).unwrap();
+        let psubscribe = send(&mut psubscriber, &cmd(&["PSUBSCRIBE", "n*"])).unwrap();
+        let restricted = send(&mut subscriber, &cmd(&["GET", "a"])).unwrap();
+        let ping = send(&mut subscriber, &cmd(&["PING"])).unwrap();
+
+        let channels = send(&mut publisher, &cmd(&["PUBSUB", "CHANNELS"])).unwrap();
+        let numsub = send(&mut publisher, &cmd(&["PUBSUB", "NUMSUB", "news", "x"])).unwrap();
+        let numpat = send(&mut publisher, &cmd(&["PUBSUB", "NUMPAT"])).unwrap();
+        let publish = send(&mut publisher, &cmd(&["PUBLISH", "news", "hi"])).unwrap();
+
+        let mut buf = [0; 1024];
+        let n = subscriber.read(&mut buf).unwrap();
+        let message = String::from_utf8(buf[..n].to_vec()).unwrap();
+        let n = psubscriber.read(&mut buf).unwrap();
+        let pmessage = String::from_utf8(buf[..n].to_vec()).unwrap();
+
+        let unsubscribe = send(&mut subscriber, &cmd(&["UNSUBSCRIBE"])).unwrap();
+        let get = send(&mut subscriber, &cmd(&["GET", "a"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(subscribe, "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n");
+        assert_eq!(psubscribe, "*3\r\n$10\r\npsubscribe\r\n$2\r\nn*\r\n:1\r\n");
+        assert!(restricted.starts_with("-ERR Can't execute 'get'"));
+        assert_eq!(ping, "*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+        assert_eq!(channels, "*1\r\n$4\r\nnews\r\n");
+        assert_eq!(numsub, "*4\r\n$4\r\nnews\r\n:1\r\n$1\r\nx\r\n:0\r\n");
+        assert_eq!(numpat, ":1\r\n");
+        assert_eq!(publish, ":2\r\n");
+        assert_eq!(message, "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n");
+        assert_eq!(
+            pmessage,
+            "*4\r\n$8\r\npmessage\r\n$2\r\nn*\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+        assert_eq!(
+            unsubscribe,
+            "*3\r\n$11\r\nunsubscribe\r\n$4\r\nnews\r\n:0\r\n"
+        );
+        assert_eq!(get, "$-1\r\n");
+    }
+
+    #[test]
+    fn test_sharded_pubsub() {
+        let (handle, addr) = server_helper();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut replica = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+
+        send(&mut subscriber, &cmd(&["SSUBSCRIBE", "{a}x"])).unwrap();
+        let ssubscribe = send(&mut subscriber, &cmd(&["SSUBSCRIBE", "{a}y"])).unwrap();
+        let restricted = send(&mut subscriber, &cmd(&["GET", "a"])).unwrap();
+        replica
+            .write_all(cmd(&["PSYNC", "?", "-1"]).as_bytes())
+            .unwrap();
+        let mut buf = [0; 1024];
+        let n = replica.read(&mut buf).unwrap();
+        let fullresync = String::from_utf8_lossy(&buf[..n]).to_string();
+
+        let shardchannels = send(&mut publisher, &cmd(&["PUBSUB", "SHARDCHANNELS", "*x"])).unwrap();
+        let shardnumsub = send(&mut publisher, &cmd(&["PUBSUB", "SHARDNUMSUB", "{a}y"])).unwrap();
+        // classic publishers don't reach shard subscribers
+        let publish = send(&mut publisher, &cmd(&["PUBLISH", "{a}x", "hi"])).unwrap();
+        let spublish = send(&mut publisher, &cmd(&["SPUBLISH", "{a}x", "hi"])).unwrap();
+
+        let n = subscriber.read(&mut buf).unwrap();
+        let message = String::from_utf8(buf[..n].to_vec()).unwrap();
+        let n = replica.read(&mut buf).unwrap();
+        let propagated = String::from_utf8_lossy(&buf[..n]).to_string();
+
+        let sunsubscribe = send(&mut subscriber, &cmd(&["SUNSUBSCRIBE"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(
+            ssubscribe,
+            "*3\r\n$10\r\nssubscribe\r\n$4\r\n{a}y\r\n:2\r\n"
+        );
+        assert!(restricted.starts_with("-ERR Can't execute 'get'"));
+        assert!(fullresync.starts_with("+FULLRESYNC "));
+        assert_eq!(shardchannels, "*1\r\n$4\r\n{a}x\r\n");
+        assert_eq!(shardnumsub, "*2\r\n$4\r\n{a}y\r\n:1\r\n");
+        assert_eq!(publish, ":0\r\n");
+        assert_eq!(spublish, ":1\r\n");
+        assert_eq!(
+            message,
+            "*3\r\n$8\r\nsmessage\r\n$4\r\n{a}x\r\n$2\r\nhi\r\n"
+        );
+        assert!(propagated.ends_with("*3\r\n$8\r\nSPUBLISH\r\n$4\r\n{a}x\r\n$2\r\nhi\r\n"));
+        // one frame per channel, written separately
+        assert!(sunsubscribe.starts_with("*3\r\n$12\r\nsunsubscribe\r\n$4\r\n{a}x\r\n:1\r\n"));
+    }
+
+    #[test]
+    fn test_eval() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut replica = TcpStream::connect(addr).unwrap();
+
+        replica
+            .write_all(cmd(&["PSYNC", "?", "-1"]).as_bytes())
+            .unwrap();
+        let mut buf = [0; 1024];
+        let n = replica.read(&mut buf).unwrap();
+        let fullresync = String::from_utf8_lossy(&buf[..n]).to_string();
+
+        let set = "return redis.call('set', KEYS[1], ARGV[1])";
+        let eval_set = send(&mut stream, &cmd(&["EVAL", set, "1", "k", "v"])).unwrap();
+        let eval_get = send(
+            &mut stream,
+            &cmd(&["EVAL", "return {redis.call('get', KEYS[1]), 1.5}", "1", "k"]),
+        )
+        .unwrap();
+
+        // the writes are replicated wrapped in a transaction
+        let mut propagated = String::new();
+        while !propagated.contains("EXEC") {
+            let n = replica.read(&mut buf).unwrap();
+            propagated.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+
+        let load = send(&mut stream, &cmd(&["SCRIPT", "LOAD", "return ARGV[1]"])).unwrap();
+        let sha = load.lines().nth(1).unwrap().to_string();
+        let evalsha = send(&mut stream, &cmd(&["EVALSHA", &sha, "0", "x"])).unwrap();
+        let exists = send(&mut stream, &cmd(&["SCRIPT", "EXISTS", &sha, "ffff"])).unwrap();
+        send(&mut stream, &cmd(&["SCRIPT", "FLUSH"])).unwrap();
+        let noscript = send(&mut stream, &cmd(&["EVALSHA", &sha, "0"])).unwrap();
+
+        let not_allowed = send(
+            &mut stream,
+            &cmd(&["EVAL", "return redis.call('multi')", "0"]),
+        )
+        .unwrap();
+        let compile_error = send(&mut stream, &cmd(&["EVAL", "return (", "0"])).unwrap();
+        let notbusy = send(&mut stream, &cmd(&["SCRIPT", "KILL"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert!(fullresync.starts_with("+FULLRESYNC "));
+        assert_eq!(eval_set, "$2\r\nOK\r\n");
+        assert_eq!(eval_get, "*2\r\n$1\r\nv\r\n:1\r\n");
+        assert!(propagated.ends_with(
+            "*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nset\r\n$1\r\nk\r\n$1\r\nv\r\n*1\r\n$4\r\nEXEC\r\n"
+        ));
+        assert_eq!(sha, "098e0f0d1448c0a81dafe820f66d460eb09263da");
+        assert_eq!(evalsha, "$1\r\nx\r\n");
+        assert_eq!(exists, "*2\r\n:1\r\n:0\r\n");
+        assert_eq!(
+            noscript,
+            "-NOSCRIPT No matching script. Please use EVAL.\r\n"
+        );
+        assert!(not_allowed.starts_with("-ERR This Redis command is not allowed from script"));
+        assert!(compile_error.starts_with("-ERR Error compiling script"));
+        assert_eq!(notbusy, "-NOTBUSY No scripts in execution right now.\r\n");
+    }
+
+    #[test]
+    fn test_functions() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let code = "#!lua name=mylib\n\
+            redis.register_function('setit', function(keys, args) return redis.call('set', keys[1], args[1]) end)\n\
+            redis.register_function{function_name='getit', callback=function(keys) return redis.call('get', keys[1]) end, flags={'no-writes'}}\n\
+            redis.register_function{function_name='sneaky', callback=function(keys) return redis.call('set', keys[1], 'x') end, flags={'no-writes'}}";
+        let load = send(&mut stream, &cmd(&["FUNCTION", "LOAD", code])).unwrap();
+        let load_again = send(&mut stream, &cmd(&["FUNCTION", "LOAD", code])).unwrap();
+
+        let fcall = send(&mut stream, &cmd(&["FCALL", "setit", "1", "k", "v"])).unwrap();
+        let fcall_ro = send(&mut stream, &cmd(&["FCALL_RO", "getit", "1", "k"])).unwrap();
+        let ro_write = send(&mut stream, &cmd(&["FCALL_RO", "setit", "1", "k", "v"])).unwrap();
+        let sneaky = send(&mut stream, &cmd(&["FCALL", "sneaky", "1", "k"])).unwrap();
+        let missing = send(&mut stream, &cmd(&["FCALL", "nosuch", "0"])).unwrap();
+
+        let list = send(
+            &mut stream,
+            &cmd(&["FUNCTION", "LIST", "LIBRARYNAME", "my*"]),
+        )
+        .unwrap();
+        let dump = send(&mut stream, &cmd(&["FUNCTION", "DUMP"])).unwrap();
+        let payload = dump.split_once("\r\n").unwrap().1.trim_end_matches("\r\n");
+        let delete = send(&mut stream, &cmd(&["FUNCTION", "DELETE", "mylib"])).unwrap();
+        let deleted = send(&mut stream, &cmd(&["FCALL", "getit", "1", "k"])).unwrap();
+        let restore = send(&mut stream, &cmd(&["FUNCTION", "RESTORE", payload])).unwrap();
+        let restored = send(&mut stream, &cmd(&["FCALL", "getit", "1", "k"])).unwrap();
+        let bad_payload = send(&mut stream, &cmd(&["FUNCTION", "RESTORE", "junk"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(load, "$5\r\nmylib\r\n");
+        assert_eq!(load_again, "-ERR Library 'mylib' already exists\r\n");
+        assert_eq!(fcall, "$2\r\nOK\r\n");
+        assert_eq!(fcall_ro, "$1\r\nv\r\n");
+        assert_eq!(
+            ro_write,
+            "-ERR Can not execute a script with write flag using *_ro command.\r\n"
+        );
+        assert!(sneaky.starts_with("-ERR Write commands are not allowed from read-only scripts."));
+        assert_eq!(missing, "-ERR Function not found\r\n");
+        assert!(list.starts_with("*1\r\n*6\r\n$12\r\nlibrary_name\r\n$5\r\nmylib\r\n"));
+        assert!(list.contains("$5\r\nflags\r\n*1\r\n+no-writes\r\n"));
+        assert_eq!(delete, "+OK\r\n");
+        assert_eq!(deleted, "-ERR Function not found\r\n");
+        assert_eq!(restore, "+OK\r\n");
+        assert_eq!(restored, "$1\r\nv\r\n");
+        assert_eq!(
+            bad_payload,
+            "-ERR payload version or checksum are wrong\r\n"
+        );
+    }
+
+    #[test]
+    fn test_functions_persist() {
+        let dir = env::temp_dir().join(format!("rdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = || {
+            let mut config = Config::new();
+            config.set("port", &free_port()).unwrap();
+            config.set("dir", dir.to_str().unwrap()).unwrap();
+            config
+        };
+        let start = || {
+            let mut server = Server::from_config(config()).unwrap();
+            let addr = server.local_addr();
+            (thread::spawn(move || server.run()), addr)
+        };
+
+        let (handle, addr) = start();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let code = "#!lua name=kept\nredis.register_function('answer', function() return 42 end)";
+        send(&mut stream, &cmd(&["FUNCTION", "LOAD", code])).unwrap();
+        shutdown_helper(handle, addr);
+        let saved = dir.join("dump.rdb").exists();
+
+        let (handle, addr) = start();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let fcall = send(&mut stream, &cmd(&["FCALL", "answer", "0"])).unwrap();
+        // a directory in the way makes saving fail, which SHUTDOWN reports
+        std::fs::remove_file(dir.join("dump.rdb")).unwrap();
+        std::fs::create_dir_all(dir.join("dump.rdb").join("in-the-way")).unwrap();
+        let failed = send(&mut stream, &cmd(&["SHUTDOWN"])).unwrap();
+        std::fs::remove_dir_all(dir.join("dump.rdb")).unwrap();
+        shutdown_helper(handle, addr);
+
+        std::fs::write(dir.join("dump.rdb"), "REDIS0011junk").unwrap();
+        let damaged = Server::from_config(config()).err().unwrap();
+
+        // a Redis dump with keys isn't loaded, so it can't be saved over either
+        let mut with_keys = b"REDIS0011\xfe\x00\x00\x01k\x01v\xff".to_vec();
+        with_keys.extend_from_slice(&[0; 8]);
+        std::fs::write(dir.join("dump.rdb"), &with_keys).unwrap();
+        let has_keys = Server::from_config(config()).err().unwrap();
+        let kept = std::fs::read(dir.join("dump.rdb")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(saved);
+        assert_eq!(fcall, ":42\r\n");
+        assert!(
+            failed.starts_with("-ERR Errors trying to SHUTDOWN: Failed saving the DB to '"),
+            "{}",
+            failed
+        );
+        assert!(
+            damaged.starts_with("Fatal error loading the DB from"),
+            "{}",
+            damaged
+        );
+        assert!(has_keys.contains("The RDB holds keys"), "{}", has_keys);
+        assert_eq!(kept, with_keys);
+    }
+
+    #[test]
+    fn test_script_log() {
+        let path = env::temp_dir().join(format!("script-{}.log", std::process::id()));
+        let mut config = Config::new();
+        config.set("port", &free_port()).unwrap();
+        config.set("save", "").unwrap();
+        config.set("logfile", path.to_str().unwrap()).unwrap();
+        config.set("loglevel", "warning").unwrap();
+        let mut server = Server::from_config(config).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let script = "redis.log(redis.LOG_NOTICE, 'quiet') redis.log(redis.LOG_WARNING, 'loud', 1)";
+        send(&mut stream, &cmd(&["EVAL", script, "0"])).unwrap();
+        send(&mut stream, &cmd(&["CONFIG", "SET", "loglevel", "notice"])).unwrap();
+        send(&mut stream, &cmd(&["EVAL", script, "0"])).unwrap();
+        let invalid = send(&mut stream, &cmd(&["EVAL", "redis.log(4, 'x')", "0"])).unwrap();
+        shutdown_helper(handle, addr);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let logged: Vec<&str> = contents
+            .lines()
+            .filter(|l| l.ends_with("loud 1") || l.ends_with("quiet"))
+            .collect();
+        assert_eq!(logged.len(), 3, "{}", contents);
+        assert!(logged[0].ends_with(" # loud 1"));
+        assert!(logged[1].ends_with(" * quiet"));
+        assert!(logged[2].ends_with(" # loud 1"));
+        assert!(invalid.contains("Invalid log level."), "{}", invalid);
+    }
+
+    #[test]
+    fn test_script_kill() {
+        let mut server = Server::new("127.0.0.1:0", None, 16);
+        set_config(&mut server, "busy-reply-threshold", "50");
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut runner = TcpStream::connect(addr).unwrap();
+        let mut other = TcpStream::connect(addr).unwrap();
+        // let the server pick up both connections
+        send(&mut other, &cmd(&["PING"])).unwrap();
+
+        runner
+            .write_all(cmd(&["EVAL", "while true do end", "0"]).as_bytes())
+            .unwrap();
+        sleep(Duration::from_millis(200));
+        let busy = send(&mut other, &cmd(&["GET", "a"])).unwrap();
+        let kill = send(&mut other, &cmd(&["SCRIPT", "KILL"])).unwrap();
+        let mut buf = [0; 1024];
+        let n = runner.read(&mut buf).unwrap();
+        let killed = String::from_utf8(buf[..n].to_vec()).unwrap();
+
+        // a script that wrote can only be stopped by shutting down
+        runner
+            .write_all(
+                cmd(&["EVAL", "redis.call('set', 'a', '1') while true do end", "0"]).as_bytes(),
+            )
+            .unwrap();
+        sleep(Duration::from_millis(200));
+        let unkillable = send(&mut other, &cmd(&["SCRIPT", "KILL"])).unwrap();
+        let shutdown = send(&mut other, &cmd(&["SHUTDOWN"])).unwrap();
+        handle.join().unwrap();
+
+        assert!(busy.starts_with("-BUSY Redis is busy running a script."));
+        assert_eq!(kill, "+OK\r\n");
+        assert_eq!(killed, "-ERR Script killed by user with SCRIPT KILL...\r\n");
+        assert!(unkillable.starts_with("-UNKILLABLE"));
+        assert_eq!(shutdown, "+OK\r\n");
+    }
+
+    #[test]
+    fn test_keyspace_notifications() {
+        let mut server = Server::new("127.0.0.1:0", None, 16);
+        set_config(&mut server, "notify-keyspace-events", "KEA");
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut subscriber, &cmd(&["PSUBSCRIBE", "__key*@0__:*"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "a", "1", "PX", "100"])).unwrap();
+        send(&mut stream, &cmd(&["GET", "missing"])).unwrap();
+        thread::sleep(Duration::from_millis(300));
+
+        // set and expire twice over, then the active expire cycle's expired twice
+        let mut events = String::new();
+        while events.matches("pmessage").count() < 6 {
+            let mut buf = [0; 1024];
+            let n = subscriber.read(&mut buf).unwrap();
+            events.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+        }
+
+        shutdown_helper(handle, addr);
+
+        let channels: Vec<&str> = events
+            .split("\r\n")
+            .filter(|l| l.starts_with("__key") && !l.contains('*'))
+            .collect();
+        assert_eq!(
+            channels,
+            vec![
+                "__keyspace@0__:a",
+                "__keyevent@0__:set",
+                "__keyspace@0__:a",
+                "__keyevent@0__:expire",
+                "__keyspace@0__:a",
+                "__keyevent@0__:expired",
+            ]
+        );
+        // key misses are not part of A
+        assert!(!events.contains("keymiss"));
+    }
+
+    #[test]
+    fn test_info() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &cmd(&["SET", "k", "v"])).unwrap();
+        send(&mut stream, &cmd(&["GET", "k"])).unwrap();
+        send(&mut stream, &cmd(&["GET", "missing"])).unwrap();
+        send(&mut stream, &cmd(&["SELECT", "99"])).unwrap();
+
+        // the whole reply doesn't fit in one read
+        let mut info = |args: &[&str]| {
+            stream.write_all(cmd(args).as_bytes()).unwrap();
+            let mut reply = String::new();
+            let mut buf = [0; 1024];
+            while !reply.contains("# Keyspace") {
+                let n = stream.read(&mut buf).unwrap();
+                reply.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            reply
+        };
+        let default = info(&["INFO"]);
+        let some = info(&["INFO", "Keyspace", "commandstats", "errorstats", "nosuch"]);
+        let all = info(&["INFO", "all"]);
+        let replication = send(&mut stream, &cmd(&["INFO", "replication"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        for title in ["Server", "Clients", "Memory", "Stats", "Replication", "CPU"] {
+            assert!(default.contains(&format!("# {}\r\n", title)), "{}", title);
+        }
+        assert!(default.contains("\r\nredis_version:7.2.0\r\n"));
+        assert!(default.contains("\r\nconnected_clients:1\r\n"));
+        assert!(default.contains("\r\nkeyspace_hits:1\r\nkeyspace_misses:1\r\n"));
+        assert!(!default.contains("# Commandstats"));
+
+        // sections come in the usual order whatever order they were asked for
+        let commandstats = some.find("# Commandstats").unwrap();
+        let errorstats = some.find("# Errorstats").unwrap();
+        let keyspace = some.find("# Keyspace").unwrap();
+        assert!(commandstats < errorstats && errorstats < keyspace);
+        assert!(some.contains("\r\ncmdstat_get:calls=2,usec="));
+        assert!(some.contains(",rejected_calls=0,failed_calls=1\r\n"));
+        assert!(some.contains("\r\nerrorstat_ERR:count=1\r\n"));
+        assert!(some.contains("\r\ndb0:keys=1,expires=0,avg_ttl=0\r\n"));
+
+        assert!(replication.contains("# Replication\r\nrole:master\r\nconnected_slaves:0\r\n"));
+        assert!(replication.contains("\r\nmaster_repl_offset:0\r\n"));
+        assert!(all.contains("# Commandstats"));
+    }
+
+    #[test]
+    fn test_client_commands() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut other = TcpStream::connect(addr).unwrap();
+
+        let id = send(&mut stream, &cmd(&["CLIENT", "ID"])).unwrap();
+        let bad_name = send(&mut stream, &cmd(&["CLIENT", "SETNAME", "a b"])).unwrap();
+        send(&mut stream, &cmd(&["CLIENT", "SETNAME", "conn"])).unwrap();
+        let name = send(&mut stream, &cmd(&["CLIENT", "GETNAME"])).unwrap();
+        let info = send(&mut stream, &cmd(&["CLIENT", "INFO"])).unwrap();
+        let other_id = send(&mut other, &cmd(&["CLIENT", "ID"])).unwrap();
+        let list = send(&mut stream, &cmd(&["CLIENT", "LIST"])).unwrap();
+
+        // replies can be turned off, ON itself is answered
+        stream
+            .write_all(cmd(&["CLIENT", "REPLY", "OFF"]).as_bytes())
+            .unwrap();
+        sleep(Duration::from_millis(100));
+        stream
+            .write_all(cmd(&["SET", "k", "v"]).as_bytes())
+            .unwrap();
+        sleep(Duration::from_millis(100));
+        let reply_on = send(&mut stream, &cmd(&["CLIENT", "REPLY", "ON"])).unwrap();
+
+        let other_id = other_id.trim_start_matches(':').trim_end().to_string();
+        let kill = send(&mut stream, &cmd(&["CLIENT", "KILL", "ID", &other_id])).unwrap();
+        let mut buf = [0; 16];
+        let closed = other.read(&mut buf).unwrap();
+        let kill_missing = send(&mut stream, &cmd(&["CLIENT", "KILL", "1.2.3.4:5"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(id, ":1\r\n");
+        assert!(bad_name.starts_with("-ERR Client names cannot contain spaces"));
+        assert_eq!(name, "$4\r\nconn\r\n");
+        assert!(info.contains("id=1 addr=127.0.0.1:"));
+        assert!(info.contains(" name=conn age=0 idle=0 flags=N db=0 "));
+        assert!(info.contains(" cmd=client|info user=default "));
+        assert_eq!(list.matches("\n").count(), 4);
+        assert!(list.contains("id=2 "));
+        assert_eq!(reply_on, "+OK\r\n");
+        assert_eq!(kill, ":1\r\n");
+        assert_eq!(closed, 0);
+        assert_eq!(kill_missing, "-ERR No such client\r\n");
+    }
+
+    #[test]
+    fn test_client_pause() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut writer = TcpStream::connect(addr).unwrap();
+
+        let pause = send(&mut stream, &cmd(&["CLIENT", "PAUSE", "10000", "WRITE"])).unwrap();
+        writer
+            .write_all(cmd(&["SET", "k", "v"]).as_bytes())
+            .unwrap();
+        sleep(Duration::from_millis(100));
+
+        // reads go through, the write waits for the pause to end
+        let get = send(&mut stream, &cmd(&["GET", "k"])).unwrap();
+        send(&mut stream, &cmd(&["CLIENT", "UNPAUSE"])).unwrap();
+        let mut buf = [0; 64];
+        let n = writer.read(&mut buf).unwrap();
+        let set = String::from_utf8(buf[..n].to_vec()).unwrap();
+        let get_after = send(&mut stream, &cmd(&["GET", "k"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(pause, "+OK\r\n");
+        assert_eq!(get, "$-1\r\n");
+        assert_eq!(set, "$2\r\nOK\r\n");
+        assert_eq!(get_after, "$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_config() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let set = send(
+            &mut stream,
+            &cmd(&[
+                "CONFIG",
+                "SET",
+                "maxmemory",
+                "1kb",
+                "maxmemory-policy",
+                "allkeys-lru",
+            ]),
+        )
+        .unwrap();
+        let get = send(&mut stream, &cmd(&["CONFIG", "GET", "maxmemory*"])).unwrap();
+        let alias = send(&mut stream, &cmd(&["CONFIG", "GET", "lua-time-limit"])).unwrap();
+        let immutable = send(&mut stream, &cmd(&["CONFIG", "SET", "port", "1"])).unwrap();
+        let invalid = send(&mut stream, &cmd(&["CONFIG", "SET", "timeout", "soon"])).unwrap();
+        let unknown = send(&mut stream, &cmd(&["CONFIG", "SET", "nope", "1"])).unwrap();
+        send(&mut stream, &cmd(&["GET", "k"])).unwrap();
+        let resetstat = send(&mut stream, &cmd(&["CONFIG", "RESETSTAT"])).unwrap();
+        let stats = send(&mut stream, &cmd(&["INFO", "stats"])).unwrap();
+        let rewrite = send(&mut stream, &cmd(&["CONFIG", "REWRITE"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(set, "+OK\r\n");
+        assert_eq!(
+            get,
+            "*4\r\n$9\r\nmaxmemory\r\n$4\r\n1024\r\n$16\r\nmaxmemory-policy\r\n$11\r\nallkeys-lru\r\n"
+        );
+        assert_eq!(alias, "*2\r\n$14\r\nlua-time-limit\r\n$4\r\n5000\r\n");
+        assert_eq!(
+            immutable,
+            "-ERR CONFIG SET failed (possibly related to argument 'port') - can't set immutable config\r\n"
+        );
+        assert!(
+            invalid.starts_with("-ERR CONFIG SET failed (possibly related to argument 'timeout')")
+        );
+        assert_eq!(
+            unknown,
+            "-ERR Unknown option or number of arguments for CONFIG SET - 'nope'\r\n"
+        );
+        assert_eq!(resetstat, "+OK\r\n");
+        assert!(stats.contains("keyspace_misses:0\r\n"));
+        assert_eq!(
+            rewrite,
+            "-ERR The server is running without a config file\r\n"
+        );
+    }
+
+    #[test]
+    fn test_acl() {
+        let (handle, addr) = server_helper();
+        let mut admin = TcpStream::connect(addr).unwrap();
+        let mut alice = TcpStream::connect(addr).unwrap();
+
+        let setuser = send(
+            &mut admin,
+            &cmd(&[
+                "ACL",
+                "SETUSER",
+                "alice",
+                "on",
+                ">secret",
+                "~app:*",
+                "&news",
+                "+get",
+                "+set",
+                "+publish",
+                "+acl|whoami",
+            ]),
+        )
+        .unwrap();
+        let list = send(&mut admin, &cmd(&["ACL", "LIST"])).unwrap();
+
+        let wrongpass = send(&mut alice, &cmd(&["AUTH", "alice", "nope"])).unwrap();
+        let auth = send(&mut alice, &cmd(&["AUTH", "alice", "secret"])).unwrap();
+        let whoami = send(&mut alice, &cmd(&["ACL", "WHOAMI"])).unwrap();
+        let set = send(&mut alice, &cmd(&["SET", "app:1", "v"])).unwrap();
+        let key = send(&mut alice, &cmd(&["GET", "other"])).unwrap();
+        let command = send(&mut alice, &cmd(&["DEL", "app:1"])).unwrap();
+        let channel = send(&mut alice, &cmd(&["PUBLISH", "other", "hi"])).unwrap();
+        let publish = send(&mut alice, &cmd(&["PUBLISH", "news", "hi"])).unwrap();
+
+        let log = send(&mut admin, &cmd(&["ACL", "LOG", "1"])).unwrap();
+        let dryrun = send(&mut admin, &cmd(&["ACL", "DRYRUN", "alice", "get", "x"])).unwrap();
+        let cat = send(&mut admin, &cmd(&["ACL", "CAT", "nope"])).unwrap();
+        let kill_default = send(
+            &mut admin,
+            &cmd(&["CLIENT", "KILL", "USER", "default", "SKIPME", "yes"]),
+        )
+        .unwrap();
+        let kill_alice = send(&mut admin, &cmd(&["CLIENT", "KILL", "USER", "alice"])).unwrap();
+        let deluser = send(&mut admin, &cmd(&["ACL", "DELUSER", "alice", "bob"])).unwrap();
+        let mut buf = [0; 16];
+        let closed = alice.read(&mut buf).unwrap();
+
+        send(&mut admin, &cmd(&["CONFIG", "SET", "requirepass", "pw"])).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let noauth = send(&mut stream, &cmd(&["GET", "k"])).unwrap();
+        let hello = send(&mut stream, &cmd(&["HELLO", "3"])).unwrap();
+        let default_auth = send(&mut stream, &cmd(&["AUTH", "pw"])).unwrap();
+        // already connected clients stay logged in
+        let admin_get = send(&mut admin, &cmd(&["GET", "k"])).unwrap();
+        send(&mut admin, &cmd(&["CONFIG", "SET", "requirepass", ""])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(kill_default, ":0\r\n");
+        assert_eq!(kill_alice, ":1\r\n");
+        assert_eq!(setuser, "+OK\r\n");
+        assert!(list.contains(&format!(
+            "user alice on #{} ~app:* resetchannels &news -@all +get +set +publish +acl|whoami",
+            crate::sha256::sha256_hex(b"secret")
+        )));
+        assert_eq!(
+            wrongpass,
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+        assert_eq!(auth, "+OK\r\n");
+        assert_eq!(whoami, "$5\r\nalice\r\n");
+        assert_eq!(set, "$2\r\nOK\r\n");
+        assert_eq!(key, "-NOPERM No permissions to access a key\r\n");
+        assert_eq!(
+            command,
+            "-NOPERM User alice has no permissions to run the 'del' command\r\n"
+        );
+        assert_eq!(channel, "-NOPERM No permissions to access a channel\r\n");
+        assert_eq!(publish, ":0\r\n");
+        assert!(log.contains(
+            "$7\r\nchannel\r\n$7\r\ncontext\r\n$8\r\ntoplevel\r\n$6\r\nobject\r\n$5\r\nother\r\n"
+        ));
+        assert_eq!(
+            dryrun,
+            "$51\r\nUser alice has no permissions to access the 'x' key\r\n"
+        );
+        assert_eq!(cat, "-ERR Unknown category 'nope'\r\n");
+        assert_eq!(deluser, ":1\r\n");
+        assert_eq!(closed, 0);
+        assert_eq!(noauth, "-NOAUTH Authentication required.\r\n");
+        assert!(hello.starts_with("-NOAUTH HELLO must be called"));
+        assert_eq!(default_auth, "+OK\r\n");
+        assert_eq!(admin_get, "$-1\r\n");
+    }
+
+    #[test]
+    fn test_acl_file() {
+        let path = env::temp_dir().join(format!("users-{}.acl", std::process::id()));
+        let config = || {
+            let mut config = Config::new();
+            config.set("port", &free_port()).unwrap();
+            config.set("aclfile", path.to_str().unwrap()).unwrap();
+            config.set("save", "").unwrap();
+            config
+        };
+
+        // an ACL file that can't be read or has errors keeps the server from starting
+        let missing = Server::from_config(config()).err().unwrap();
+        assert!(
+            missing.starts_with("Error loading ACLs, opening file"),
+            "{}",
+            missing
+        );
+        std::fs::write(&path, "user alice on >pw ~* +@all\nuser alice off\n").unwrap();
+        let err = Server::from_config(config()).err().unwrap();
+        assert!(
+            err.ends_with(":2: Duplicate user 'alice' found."),
+            "{}",
+            err
+        );
+
+        std::fs::write(&path, "user alice on >pw ~* &* +@all\n").unwrap();
+        let mut server = Server::from_config(config()).unwrap();
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut admin = TcpStream::connect(addr).unwrap();
+        let mut alice = TcpStream::connect(addr).unwrap();
+        let auth = send(&mut alice, &cmd(&["AUTH", "alice", "pw"])).unwrap();
+        send(
+            &mut admin,
+            &cmd(&["ACL", "SETUSER", "bob", "on", "nopass", "+get"]),
+        )
+        .unwrap();
+        let save = send(&mut admin, &cmd(&["ACL", "SAVE"])).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+
+        std::fs::write(&path, "user carol on nopass +@all\nuser dave +nope\n").unwrap();
+        let bad_load = send(&mut admin, &cmd(&["ACL", "LOAD"])).unwrap();
+        std::fs::write(&path, "user carol on nopass +@all\n").unwrap();
+        let load = send(&mut admin, &cmd(&["ACL", "LOAD"])).unwrap();
+        let users = send(&mut admin, &cmd(&["ACL", "USERS"])).unwrap();
+        let mut buf = [0; 16];
+        let closed = alice.read(&mut buf).unwrap();
+
+        shutdown_helper(handle, addr);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(auth, "+OK\r\n");
+        assert_eq!(save, "+OK\r\n");
+        assert_eq!(
+            saved,
+            format!(
+                "user alice on #{} ~* &* +@all\nuser bob on nopass resetchannels -@all +get\nuser default on nopass ~* &* +@all\n",
+                crate::sha256::sha256_hex(b"pw")
+            )
+        );
+        assert!(bad_load.starts_with("-ERR "));
+        assert!(bad_load.ends_with(
+            "Unknown command or category name in ACL. WARNING: ACL errors detected, no change to the previously active ACL rules was performed\r\n"
+        ));
+        assert_eq!(load, "+OK\r\n");
+        assert_eq!(users, "*2\r\n$5\r\ncarol\r\n$7\r\ndefault\r\n");
+        assert_eq!(closed, 0);
+    }
+
+    #[test]
+    fn test_reset() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &cmd(&["SELECT", "1"])).unwrap();
+        send(&mut stream, &cmd(&["CLIENT", "SETNAME", "app"])).unwrap();
+        send(&mut stream, &cmd(&["SUBSCRIBE", "news"])).unwrap();
+        let reset = send(&mut stream, &cmd(&["RESET"])).unwrap();
+        let info = send(&mut stream, &cmd(&["CLIENT", "INFO"])).unwrap();
+        let numsub = send(&mut stream, &cmd(&["PUBSUB", "NUMSUB", "news"])).unwrap();
+        send(&mut stream, &cmd(&["MULTI"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "k", "v"])).unwrap();
+        let reset_multi = send(&mut stream, &cmd(&["RESET"])).unwrap();
+        let exec = send(&mut stream, &cmd(&["EXEC"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(reset, "+RESET\r\n");
+        assert!(info.contains(" name= "), "{}", info);
+        assert!(info.contains(" db=0 sub=0 "), "{}", info);
+        assert_eq!(numsub, "*2\r\n$4\r\nnews\r\n:0\r\n");
+        assert_eq!(reset_multi, "+RESET\r\n");
+        assert_eq!(exec, "-ERR EXEC without MULTI\r\n");
+    }
+
+    #[test]
+    fn test_timeout_and_maxclients() {
+        let mut server = Server::new(ADDR, None, 16);
+        set_config(&mut server, "timeout", "1");
+        set_config(&mut server, "maxclients", "2");
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        let mut idle = TcpStream::connect(addr).unwrap();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        send(&mut subscriber, &cmd(&["SUBSCRIBE", "news"])).unwrap();
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        let mut buf = [0; 64];
+        let n = rejected.read(&mut buf).unwrap();
+        let refused = String::from_utf8(buf[..n].to_vec()).unwrap();
+
+        sleep(Duration::from_millis(2500));
+        idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let closed = idle.read(&mut buf).unwrap();
+        let ping = send(&mut subscriber, &cmd(&["PING"])).unwrap();
+
+        // the idle client's slot is free again
+        let mut admin = TcpStream::connect(addr).unwrap();
+        let set = send(
+            &mut admin,
+            &cmd(&["CONFIG", "SET", "timeout", "0", "maxclients", "10"]),
+        )
+        .unwrap();
+        let info = send(&mut admin, &cmd(&["INFO", "stats"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(refused, "-ERR max number of clients reached\r\n");
+        assert_eq!(closed, 0);
+        assert_eq!(ping, "*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+        assert_eq!(set, "+OK\r\n");
+        assert!(info.contains("\r\nrejected_connections:1\r\n"), "{}", info);
+    }
+
+    #[test]
+    fn test_closed_connections() {
+        let mut server = Server::new(ADDR, None, 16);
+        set_config(&mut server, "maxclients", "3");
+        let addr = server.local_addr();
+        let handle = thread::spawn(move || server.run());
+
+        // clients that hang up give their slot back
+        let mut pings = Vec::new();
+        for _ in 0..5 {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            pings.push(send(&mut stream, &cmd(&["PING"])).unwrap());
+        }
+        // what was sent before hanging up still runs
+        let mut hung_up = TcpStream::connect(addr).unwrap();
+        hung_up
+            .write_all(cmd(&["SET", "k", "v"]).as_bytes())
+            .unwrap();
+        drop(hung_up);
+        sleep(Duration::from_millis(200));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let get = send(&mut stream, &cmd(&["GET", "k"])).unwrap();
+        let info = send(&mut stream, &cmd(&["INFO", "clients"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert!(pings.iter().all(|p| p == "+PONG\r\n"), "{:?}", pings);
+        assert_eq!(get, "$1\r\nv\r\n");
+        assert!(info.contains("\r\nconnected_clients:1\r\n"), "{}", info);
+    }
+
+    #[test]
+    fn test_bind_and_protected_mode() {
+        let port = free_port();
+        let log = env::temp_dir().join(format!("bind-{}.log", std::process::id()));
+        let config = |bind: &str, port: &str| {
+            let mut config = Config::new();
+            config.set("bind", bind).unwrap();
+            config.set("port", port).unwrap();
+            config.set("logfile", log.to_str().unwrap()).unwrap();
+            config
+        };
+
+        assert_eq!(
+            Server::from_config(config("127.0.0.1", "0")).err(),
+            Some("Configured to not listen anywhere, exiting.".into())
+        );
+        assert!(Server::from_config(config("127.0.0.1 192.0.2.1", &port))
+            .err()
+            .unwrap()
+            .starts_with("Could not create server TCP listening socket 192.0.2.1:"));
+
+        let mut server = Server::from_config(config("127.0.0.1 -192.0.2.1", &port)).unwrap();
+        assert_eq!(
+            server.local_addrs(),
+            vec![format!("127.0.0.1:{}", port).parse().unwrap()]
+        );
+        let logged = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_file(&log).unwrap();
+        assert_eq!(logged.lines().count(), 1, "{}", logged);
+        assert!(logged.contains(&format!(
+            " # Skipping optional bind address 192.0.2.1:{}: ",
+            port
+        )));
+
+        let ip = |ip: &str| ip.parse().unwrap();
+        assert!(!server.protected_mode_refuses(ip("127.0.0.1")));
+        assert!(!server.protected_mode_refuses(ip("::1")));
+        assert!(!server.protected_mode_refuses(ip("::ffff:127.0.0.1")));
+        assert!(server.protected_mode_refuses(ip("10.0.0.1")));
+        set_config(&mut server, "requirepass", "secret");
+        assert!(!server.protected_mode_refuses(ip("10.0.0.1")));
+        set_config(&mut server, "requirepass", "");
+        set_config(&mut server, "protected-mode", "no");
+        assert!(!server.protected_mode_refuses(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn test_unixsocket() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::net::UnixStream;
+
+        let path = env::temp_dir().join(format!("redis-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut config = Config::new();
+        config.set("port", "0").unwrap();
+        config.set("unixsocket", &path).unwrap();
+        config.set("unixsocketperm", "700").unwrap();
+        config.set("save", "").unwrap();
+        let mut server = Server::from_config(config).unwrap();
+        let tcp = server.local_addrs();
+        let perm = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        let handle = thread::spawn(move || server.run());
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0; 1024];
+        stream.write_all(cmd(&["PING"]).as_bytes()).unwrap();
+        let n = stream.read(&mut buf).unwrap();
+        let ping = String::from_utf8(buf[..n].to_vec()).unwrap();
+        stream
+            .write_all(cmd(&["CLIENT", "INFO"]).as_bytes())
+            .unwrap();
+        let n = stream.read(&mut buf).unwrap();
+        let info = String::from_utf8(buf[..n].to_vec()).unwrap();
+        stream.write_all(cmd(&["SHUTDOWN"]).as_bytes()).unwrap();
+        handle.join().unwrap();
+
+        assert!(tcp.is_empty());
+        assert_eq!(perm, 0o700);
+        assert_eq!(ping, "+PONG\r\n");
+        assert!(
+            info.contains(&format!(" addr={}:0 laddr={}:0 ", path, path)),
+            "{}",
+            info
+        );
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_command() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let count = send(&mut stream, &cmd(&["COMMAND", "COUNT"])).unwrap();
+        let info = send(&mut stream, &cmd(&["COMMAND", "INFO", "get", "nope"])).unwrap();
+        let getkeys = send(
+            &mut stream,
+            &cmd(&["COMMAND", "GETKEYS", "rename", "old", "new"]),
+        )
+        .unwrap();
+        let list = send(
+            &mut stream,
+            &cmd(&["COMMAND", "LIST", "FILTERBY", "PATTERN", "config|*"]),
+        )
+        .unwrap();
+        let docs = send(&mut stream, &cmd(&["COMMAND", "DOCS", "echo"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(count, format!(":{}\r\n", COMMAND_TABLE.len()));
+        assert!(info.starts_with(
+            "*2\r\n*10\r\n$3\r\nget\r\n:2\r\n*2\r\n+readonly\r\n+fast\r\n:1\r\n:1\r\n:1\r\n*3\r\n+@read\r\n+@string\r\n+@fast\r\n"
+        ));
+        assert!(info.ends_with("$-1\r\n"));
+        assert_eq!(getkeys, "*2\r\n$3\r\nold\r\n$3\r\nnew\r\n");
+        assert_eq!(
+            list,
+            "*4\r\n$10\r\nconfig|get\r\n$10\r\nconfig|set\r\n$16\r\nconfig|resetstat\r\n$14\r\nconfig|rewrite\r\n"
+        );
+        assert_eq!(
+            docs,
+            "*2\r\n$4\r\necho\r\n*6\r\n$7\r\nsummary\r\n$25\r\nReturns the given string.\r\n$5\r\nsince\r\n$5\r\n1.0.0\r\n$5\r\ngroup\r\n$10\r\nconnection\r\n"
+        );
+    }
+
+    #[test]
+    fn test_slowlog_latency() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(
+            &mut stream,
+            &cmd(&[
+                "CONFIG",
+                "SET",
+                "slowlog-log-slower-than",
+                "0",
+                "slowlog-max-len",
+                "2",
+                "latency-monitor-threshold",
+                "1",
+            ]),
+        )
+        .unwrap();
+        send(&mut stream, &cmd(&["CLIENT", "SETNAME", "slow"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "k", "v"])).unwrap();
+        send(&mut stream, &cmd(&["GET", "k"])).unwrap();
+        let len = send(&mut stream, &cmd(&["SLOWLOG", "LEN"])).unwrap();
+        let get = send(&mut stream, &cmd(&["SLOWLOG", "GET", "1"])).unwrap();
+        send(&mut stream, &cmd(&["SLOWLOG", "RESET"])).unwrap();
+        let len_after_reset = send(&mut stream, &cmd(&["SLOWLOG", "LEN"])).unwrap();
+
+        // a script busy for a while is a latency spike
+        send(
+            &mut stream,
+            &cmd(&["EVAL", "local i = 0 while i < 300000 do i = i + 1 end", "0"]),
+        )
+        .unwrap();
+        let latest = send(&mut stream, &cmd(&["LATENCY", "LATEST"])).unwrap();
+        let histogram = send(&mut stream, &cmd(&["LATENCY", "HISTOGRAM", "get"])).unwrap();
+        let reset = send(&mut stream, &cmd(&["LATENCY", "RESET"])).unwrap();
+        let doctor = send(&mut stream, &cmd(&["LATENCY", "DOCTOR"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(len, ":2\r\n");
+        // newest first, the entry for SLOWLOG LEN itself
+        assert!(get.starts_with("*1\r\n*6\r\n:4\r\n"));
+        assert!(get.contains("*2\r\n$7\r\nSLOWLOG\r\n$3\r\nLEN\r\n"));
+        assert!(get.ends_with("$4\r\nslow\r\n"));
+        assert_eq!(len_after_reset, ":1\r\n");
+        assert!(latest.starts_with("*1\r\n*4\r\n$7\r\ncommand\r\n"));
+        assert!(histogram.starts_with(
+            "*2\r\n$3\r\nget\r\n*4\r\n$5\r\ncalls\r\n:1\r\n$14\r\nhistogram_usec\r\n"
+        ));
+        assert_eq!(reset, ":1\r\n");
+        assert!(doctor.contains("no latency spike"));
+    }
+
+    #[test]
+    fn test_monitor() {
+        let (handle, addr) = server_helper();
+        let mut monitor = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let ok = send(&mut monitor, &cmd(&["MONITOR"])).unwrap();
+        send(&mut stream, &cmd(&["SELECT", "2"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "k", "a \"b\"\n\u{1}"])).unwrap();
+        send(&mut stream, &cmd(&["CONFIG", "GET", "port"])).unwrap();
+        send(
+            &mut stream,
+            &cmd(&["EVAL", "return redis.call('GET', KEYS[1])", "1", "k"]),
+        )
+        .unwrap();
+        send(&mut stream, &cmd(&["SET", "bin", "\u{0}é\u{7f}"])).unwrap();
+
+        // commands turned away by ACLs aren't shown
+        send(
+            &mut stream,
+            &cmd(&["ACL", "SETUSER", "reader", "on", "nopass", "+get"]),
+        )
+        .unwrap();
+        let mut reader = TcpStream::connect(addr).unwrap();
+        send(&mut reader, &cmd(&["AUTH", "reader", "pw"])).unwrap();
+        let noperm = send(&mut reader, &cmd(&["SET", "k", "v"])).unwrap();
+
+        // arguments have to be UTF-8, the server answers with an error and hangs up
+        let mut binary = TcpStream::connect(addr).unwrap();
+        binary
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nx\r\n$2\r\n\xff\xfe\r\n")
+            .unwrap();
+        let mut buf = [0; 1024];
+        let n = binary.read(&mut buf).unwrap();
+        let protocol_error = String::from_utf8(buf[..n].to_vec()).unwrap();
+        let closed = binary.read(&mut buf).unwrap();
+        sleep(Duration::from_millis(100));
+
+        let n = monitor.read(&mut buf).unwrap();
+        let lines = String::from_utf8(buf[..n].to_vec()).unwrap();
+        let local = stream.local_addr().unwrap();
+        let reader_local = reader.local_addr().unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(ok, "+OK\r\n");
+        assert!(noperm.starts_with("-NOPERM"), "{}", noperm);
+        assert_eq!(
+            protocol_error,
+            "-ERR Protocol error: invalid UTF-8 in bulk string at 28\r\n"
+        );
+        assert_eq!(closed, 0);
+        let lines: Vec<&str> = lines.split_terminator("\r\n").collect();
+        assert_eq!(lines.len(), 5, "{:?}", lines);
+        assert!(lines[0].starts_with('+'));
+        assert!(lines[0].ends_with(&format!("[0 {}] \"SELECT\" \"2\"", local)));
+        assert!(lines[1].ends_with(&format!(
+            "[2 {}] \"SET\" \"k\" \"a \\\"b\\\"\\n\\x01\"",
+            local
+        )));
+        // admin commands and EVAL itself are left out, the commands scripts run aren't
+        assert!(lines[2].ends_with("[2 lua] \"GET\" \"k\""));
+        assert!(lines[3].ends_with(&format!(
+            "[2 {}] \"SET\" \"bin\" \"\\x00\\xc3\\xa9\\x7f\"",
+            local
+        )));
+        assert!(lines[4].ends_with(&format!(
+            "[0 {}] \"AUTH\" \"(redacted)\" \"(redacted)\"",
+            reader_local
+        )));
+    }
+
+    #[test]
+    fn test_client_tracking() {
+        let (handle, addr) = server_helper();
+        let mut cache = TcpStream::connect(addr).unwrap();
+        let mut bcast = TcpStream::connect(addr).unwrap();
+        let mut writer = TcpStream::connect(addr).unwrap();
+
+        send(&mut cache, &cmd(&["HELLO", "3"])).unwrap();
+        send(&mut cache, &cmd(&["CLIENT", "TRACKING", "on"])).unwrap();
+        send(&mut cache, &cmd(&["GET", "a"])).unwrap();
+
+        send(&mut bcast, &cmd(&["HELLO", "3"])).unwrap();
+        let tracking = send(
+            &mut bcast,
+            &cmd(&["CLIENT", "TRACKING", "on", "BCAST", "PREFIX", "b"]),
+        )
+        .unwrap();
+        let info = send(&mut bcast, &cmd(&["CLIENT", "TRACKINGINFO"])).unwrap();
+        let caching = send(&mut bcast, &cmd(&["CLIENT", "CACHING", "yes"])).unwrap();
+
+        send(&mut writer, &cmd(&["SET", "a", "1"])).unwrap();
+        send(&mut writer, &cmd(&["SET", "b1", "1"])).unwrap();
+
+        let mut buf = [0; 1024];
+        let n = cache.read(&mut buf).unwrap();
+        let invalidate = String::from_utf8(buf[..n].to_vec()).unwrap();
+        let n = bcast.read(&mut buf).unwrap();
+        let bcast_invalidate = String::from_utf8(buf[..n].to_vec()).unwrap();
+
+        // a second write only invalidates once the key was read again
+        send(&mut writer, &cmd(&["SET", "a", "2"])).unwrap();
+        let get = send(&mut cache, &cmd(&["GET", "a"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(invalidate, ">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\na\r\n");
+        assert_eq!(tracking, "+OK\r\n");
+        assert!(info.starts_with("%3\r\n$5\r\nflags\r\n*2\r\n$2\r\non\r\n$5\r\nbcast\r\n"));
+        assert!(caching.starts_with("-ERR CLIENT CACHING YES"));
+        assert_eq!(
+            bcast_invalidate,
+            ">2\r\n$10\r\ninvalidate\r\n*1\r\n$2\r\nb1\r\n"
+        );
+        assert_eq!(get, "$1\r\n2\r\n");
+    }
+
+    #[test]
+    fn test_client_tracking_redirect() {
+        let (handle, addr) = server_helper();
+        let mut cache = TcpStream::connect(addr).unwrap();
+        let mut invalidations = TcpStream::connect(addr).unwrap();
+
+        let id = send(&mut invalidations, &cmd(&["CLIENT", "GETREDIR"])).unwrap();
+        let hello = send(&mut invalidations, &cmd(&["HELLO"])).unwrap();
+        send(
+            &mut invalidations,
+            &cmd(&["SUBSCRIBE", "__redis__:invalidate"]),
+        )
+        .unwrap();
+
+        // the id of the redirect client is in its HELLO reply
+        let redirect_id = hello
+            .split("\r\n")
+            .skip_while(|l| *l != "id")
+            .nth(1)
+            .unwrap()
+            .trim_start_matches(':')
+            .to_string();
+        send(
+            &mut cache,
+            &cmd(&[
+                "CLIENT",
+                "TRACKING",
+                "on",
+                "REDIRECT",
+                &redirect_id,
+                "NOLOOP",
+            ]),
+        )
+        .unwrap();
+        send(&mut cache, &cmd(&["GET", "a"])).unwrap();
+        send(&mut cache, &cmd(&["GET", "b"])).unwrap();
+        // NOLOOP skips the client's own writes
+        send(&mut cache, &cmd(&["SET", "b", "1"])).unwrap();
+        send(&mut cache, &cmd(&["FLUSHALL"])).unwrap();
+
+        let mut buf = [0; 1024];
+        let n = invalidations.read(&mut buf).unwrap();
+        let message = String::from_utf8(buf[..n].to_vec()).unwrap();
+
+        // a target subscribed to some other channel doesn't get invalidations
+        let mut other_cache = TcpStream::connect(addr).unwrap();
+        let mut news = TcpStream::connect(addr).unwrap();
+        let news_id = send(&mut news, &cmd(&["CLIENT", "ID"])).unwrap();
+        let news_id = news_id.trim_start_matches(':').trim_end().to_string();
+        send(&mut news, &cmd(&["SUBSCRIBE", "news"])).unwrap();
+        send(
+            &mut other_cache,
+            &cmd(&["CLIENT", "TRACKING", "on", "REDIRECT", &news_id]),
+        )
+        .unwrap();
+        send(&mut other_cache, &cmd(&["GET", "c"])).unwrap();
+        send(&mut cache, &cmd(&["SET", "c", "1"])).unwrap();
+        let news_ping = send(&mut news, &cmd(&["PING"])).unwrap();
+        let info = send(&mut other_cache, &cmd(&["CLIENT", "TRACKINGINFO"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(id, ":-1\r\n");
+        assert_eq!(
+            message,
+            "*3\r\n$7\r\nmessage\r\n$20\r\n__redis__:invalidate\r\n$-1\r\n"
+        );
+        assert_eq!(news_ping, "*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+        assert!(info.contains("broken_redirect"), "{}", info);
+    }
+
+    #[test]
+    fn test_resp3_push() {
+        let (handle, addr) = server_helper();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+
+        let hello = send(&mut subscriber, &cmd(&["HELLO", "3"])).unwrap();
+        let subscribe = send(&mut subscriber, &cmd(&["SUBSCRIBE", "news"])).unwrap();
+        // RESP3 clients can keep running commands while subscribed
+        let get = send(&mut subscriber, &cmd(&["GET", "a"])).unwrap();
+        send(&mut publisher, &cmd(&["PUBLISH", "news", "hi"])).unwrap();
+
+        let mut buf = [0; 1024];
+        let n = subscriber.read(&mut buf).unwrap();
+        let message = String::from_utf8(buf[..n].to_vec()).unwrap();
+
+        let noproto = send(&mut publisher, &cmd(&["HELLO", "4"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert!(hello.starts_with("%7\r\n"));
+        assert!(hello.contains("$5\r\nproto\r\n:3\r\n"));
+        assert_eq!(subscribe, ">3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n");
+        assert_eq!(get, "$-1\r\n");
+        assert_eq!(message, ">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n");
+        assert!(noproto.starts_with("-NOPROTO"));
+    }
+
+    #[test]
+    fn test_keys_and_scan() {
+        let (handle, addr) = server_helper();
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        send(&mut stream, &cmd(&["SET", "user:1", "a"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "user:2", "b"])).unwrap();
+        send(&mut stream, &cmd(&["SET", "order:1", "c"])).unwrap();
+
+        let keys = send(&mut stream, &cmd(&["KEYS", "order:*"])).unwrap();
+        let literal = send(&mut stream, &cmd(&["KEYS", "user:1"])).unwrap();
+        let scan = send(&mut stream, &cmd(&["SCAN", "0", "MATCH", "order:[0-9]"])).unwrap();
+
+        shutdown_helper(handle, addr);
+
+        assert_eq!(keys, "*1\r\n$7\r\norder:1\r\n");
+        assert_eq!(literal, "*1\r\n$6\r\nuser:1\r\n");
+        assert_eq!(scan, "*2\r\n$1\r\n0\r\n*1\r\n$7\r\norder:1\r\n");
     }
 }
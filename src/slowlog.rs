@@ -0,0 +1,157 @@
+//! SLOWLOG, commands that took longer than `slowlog-log-slower-than`
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::resp::RespValue;
+
+/// Arguments kept per entry, the last one is replaced by how many were left out
+const SLOWLOG_ENTRY_MAX_ARGC: usize = 32;
+
+/// Bytes kept per argument
+const SLOWLOG_ENTRY_MAX_STRING: usize = 128;
+
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// Unix time in seconds the command ran at
+    pub time: i64,
+    pub duration: Duration,
+    pub args: Vec<String>,
+    pub client_addr: String,
+    pub client_name: String,
+}
+
+impl SlowLogEntry {
+    pub fn reply(&self) -> RespValue {
+        RespValue::Array(vec![
+            RespValue::Integer(self.id as i64),
+            RespValue::Integer(self.time),
+            RespValue::Integer(self.duration.as_micros() as i64),
+            RespValue::Array(
+                self.args
+                    .iter()
+                    .map(|a| RespValue::BulkString(a.clone()))
+                    .collect(),
+            ),
+            RespValue::BulkString(self.client_addr.clone()),
+            RespValue::BulkString(self.client_name.clone()),
+        ])
+    }
+}
+
+/// Arguments as they are logged, long lists and strings are cut short
+fn trim_args(args: &[String]) -> Vec<String> {
+    let mut trimmed: Vec<String> = args
+        .iter()
+        .take(SLOWLOG_ENTRY_MAX_ARGC)
+        .map(|arg| {
+            if arg.len() <= SLOWLOG_ENTRY_MAX_STRING {
+                return arg.clone();
+            }
+            let mut end = SLOWLOG_ENTRY_MAX_STRING;
+            while !arg.is_char_boundary(end) {
+                end -= 1;
+            }
+            format!("{}... ({} more bytes)", &arg[..end], arg.len() - end)
+        })
+        .collect();
+
+    if args.len() > SLOWLOG_ENTRY_MAX_ARGC {
+        trimmed[SLOWLOG_ENTRY_MAX_ARGC - 1] = format!(
+            "... ({} more arguments)",
+            args.len() - SLOWLOG_ENTRY_MAX_ARGC + 1
+        );
+    }
+
+    trimmed
+}
+
+#[derive(Default)]
+pub struct SlowLog {
+    /// Newest first
+    entries: VecDeque<SlowLogEntry>,
+    next_id: u64,
+}
+
+impl SlowLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry, dropping the oldest ones past `max_len`
+    pub fn push(
+        &mut self,
+        time: i64,
+        duration: Duration,
+        args: &[String],
+        client_addr: &str,
+        client_name: &str,
+        max_len: usize,
+    ) {
+        self.entries.push_front(SlowLogEntry {
+            id: self.next_id,
+            time,
+            duration,
+            args: trim_args(args),
+            client_addr: client_addr.into(),
+            client_name: client_name.into(),
+        });
+        self.next_id += 1;
+
+        self.entries.truncate(max_len);
+    }
+
+    /// The `count` newest entries, all of them for None
+    pub fn newest(&self, count: Option<usize>) -> impl Iterator<Item = &SlowLogEntry> {
+        self.entries.iter().take(count.unwrap_or(usize::MAX))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Drop all entries, ids keep counting up
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let mut log = SlowLog::new();
+        for i in 0..5 {
+            log.push(
+                0,
+                Duration::from_micros(i),
+                &[i.to_string()],
+                "127.0.0.1:1",
+                "",
+                3,
+            );
+        }
+
+        assert_eq!(log.len(), 3);
+        let ids: Vec<u64> = log.newest(Some(2)).map(|e| e.id).collect();
+        assert_eq!(ids, vec![4, 3]);
+
+        log.reset();
+        log.push(0, Duration::ZERO, &[], "", "", 3);
+        assert_eq!(log.newest(None).next().unwrap().id, 5);
+    }
+
+    #[test]
+    fn test_trim_args() {
+        let args: Vec<String> = (0..40).map(|i| i.to_string()).collect();
+        let trimmed = trim_args(&args);
+        assert_eq!(trimmed.len(), 32);
+        assert_eq!(trimmed[30], "30");
+        assert_eq!(trimmed[31], "... (9 more arguments)");
+
+        let trimmed = trim_args(&["x".repeat(130)]);
+        assert_eq!(trimmed[0], format!("{}... (2 more bytes)", "x".repeat(128)));
+    }
+}
@@ -0,0 +1,161 @@
+//! Counters reported by INFO
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::latency::Histogram;
+
+/// What INFO commandstats reports for one command
+#[derive(Default, Debug, PartialEq)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub usec: u64,
+    /// Refused before running, like writes over maxmemory
+    pub rejected_calls: u64,
+    /// Ran and replied with an error
+    pub failed_calls: u64,
+}
+
+#[derive(Default)]
+pub struct Stats {
+    pub total_connections_received: u64,
+    /// Connections refused because of maxclients
+    pub rejected_connections: u64,
+    pub total_commands_processed: u64,
+    pub total_net_input_bytes: u64,
+    pub total_net_output_bytes: u64,
+    pub expired_keys: u64,
+    pub evicted_keys: u64,
+    pub keyspace_hits: u64,
+    pub keyspace_misses: u64,
+    pub total_error_replies: u64,
+    /// Keys changed since the server started, there are no saves to reset it
+    pub dirty: u64,
+    pub peak_memory: u64,
+    /// By full command name, like `client|list`
+    pub commands: BTreeMap<String, CommandStats>,
+    /// Error replies by their code, the first word of the error
+    pub errors: BTreeMap<String, u64>,
+    /// By full command name, kept while `latency-tracking` is on
+    pub latency_histograms: BTreeMap<String, Histogram>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_memory(&mut self, used_memory: u64) {
+        self.peak_memory = self.peak_memory.max(used_memory);
+    }
+
+    pub fn record_call(&mut self, name: &str, duration: Duration, failed: bool) {
+        self.total_commands_processed += 1;
+
+        let stats = self.commands.entry(name.to_string()).or_default();
+        stats.calls += 1;
+        stats.usec += duration.as_micros() as u64;
+        if failed {
+            stats.failed_calls += 1;
+        }
+    }
+
+    pub fn record_latency(&mut self, name: &str, duration: Duration) {
+        self.latency_histograms
+            .entry(name.to_string())
+            .or_default()
+            .record(duration.as_micros() as u64);
+    }
+
+    pub fn record_rejected(&mut self, name: &str) {
+        self.commands
+            .entry(name.to_string())
+            .or_default()
+            .rejected_calls += 1;
+    }
+
+    pub fn record_error_reply(&mut self, error: &str) {
+        self.total_error_replies += 1;
+
+        let code = error.split(' ').next().unwrap_or_default();
+        *self.errors.entry(code.to_string()).or_default() += 1;
+    }
+}
+
+/// System and user CPU time used by the process in seconds, zero where `/proc` isn't
+/// there to ask
+pub fn cpu_times() -> (f64, f64) {
+    // clock ticks are 1/100s on every platform with /proc this runs on
+    const TICKS_PER_SECOND: f64 = 100.0;
+
+    let stat = std::fs::read_to_string("/proc/self/stat").unwrap_or_default();
+    // fields after the command name, which is in parentheses and may contain spaces
+    let fields: Vec<&str> = match stat.rsplit_once(')') {
+        Some((_, rest)) => rest.split_whitespace().collect(),
+        None => Vec::new(),
+    };
+    let ticks = |i: usize| {
+        fields
+            .get(i)
+            .and_then(|f| f.parse::<f64>().ok())
+            .unwrap_or(0.0)
+    };
+
+    // utime and stime are the 14th and 15th fields, counting the pid and name
+    (ticks(12) / TICKS_PER_SECOND, ticks(11) / TICKS_PER_SECOND)
+}
+
+/// Byte count the way INFO prints the `_human` fields, like `1.50K`
+pub fn bytes_to_human(bytes: u64) -> String {
+    let units = ["K", "M", "G", "T", "P"];
+
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", value, units[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes_to_human() {
+        assert_eq!(bytes_to_human(0), "0B");
+        assert_eq!(bytes_to_human(1023), "1023B");
+        assert_eq!(bytes_to_human(1536), "1.50K");
+        assert_eq!(bytes_to_human(3 * 1024 * 1024), "3.00M");
+    }
+
+    #[test]
+    fn test_record() {
+        let mut stats = Stats::new();
+        stats.record_call("get", Duration::from_micros(10), false);
+        stats.record_call("get", Duration::from_micros(5), true);
+        stats.record_rejected("get");
+        stats.record_error_reply("WRONGTYPE Operation against a key");
+        stats.record_error_reply("ERR syntax error");
+        stats.record_error_reply("ERR unknown command");
+
+        assert_eq!(stats.total_commands_processed, 2);
+        assert_eq!(
+            stats.commands["get"],
+            CommandStats {
+                calls: 2,
+                usec: 15,
+                rejected_calls: 1,
+                failed_calls: 1,
+            }
+        );
+        assert_eq!(stats.total_error_replies, 3);
+        assert_eq!(stats.errors["ERR"], 2);
+        assert_eq!(stats.errors["WRONGTYPE"], 1);
+    }
+}
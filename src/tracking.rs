@@ -0,0 +1,152 @@
+//! Server assisted client side caching, the state behind CLIENT TRACKING
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Pub/sub channel RESP2 clients receive redirected invalidations on
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// Per-client tracking options
+#[derive(Default)]
+pub struct TrackingState {
+    pub enabled: bool,
+    /// Client id invalidations are sent to instead of this client
+    pub redirect: Option<u64>,
+    /// Set once the redirect client went away
+    pub redirect_broken: bool,
+    pub bcast: bool,
+    pub prefixes: BTreeSet<String>,
+    pub optin: bool,
+    pub optout: bool,
+    pub noloop: bool,
+    /// Set by CLIENT CACHING, applies to the next command only
+    pub caching: Option<bool>,
+}
+
+impl TrackingState {
+    /// Whether keys read by the current command should be remembered for this client
+    pub fn tracks_reads(&self) -> bool {
+        if !self.enabled || self.bcast {
+            return false;
+        }
+
+        match self.caching {
+            _ if !self.optin && !self.optout => true,
+            Some(caching) => caching,
+            None => self.optout,
+        }
+    }
+}
+
+/// Which clients may hold which keys in their cache
+#[derive(Default)]
+pub struct TrackingTable {
+    /// Keys read by clients in the default mode
+    keys: HashMap<String, HashSet<u64>>,
+    /// BCAST clients by the prefixes they subscribed to, the empty prefix matches everything
+    prefixes: BTreeMap<String, HashSet<u64>>,
+}
+
+impl TrackingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn remember(&mut self, key: &str, client_id: u64) {
+        self.keys
+            .entry(key.to_string())
+            .or_default()
+            .insert(client_id);
+    }
+
+    pub fn add_prefix(&mut self, prefix: &str, client_id: u64) {
+        self.prefixes
+            .entry(prefix.to_string())
+            .or_default()
+            .insert(client_id);
+    }
+
+    pub fn remove_prefix(&mut self, prefix: &str, client_id: u64) {
+        if let Some(clients) = self.prefixes.get_mut(prefix) {
+            clients.remove(&client_id);
+            if clients.is_empty() {
+                self.prefixes.remove(prefix);
+            }
+        }
+    }
+
+    /// Clients to send an invalidation of `key` to
+    ///
+    /// Default mode clients are only told once, until they read the key again.
+    pub fn invalidate(&mut self, key: &str) -> HashSet<u64> {
+        let mut clients = self.keys.remove(key).unwrap_or_default();
+
+        for (prefix, prefix_clients) in self.prefixes.iter() {
+            if key.starts_with(prefix.as_str()) {
+                clients.extend(prefix_clients);
+            }
+        }
+
+        clients
+    }
+
+    /// Forget all keys, every tracking client is told to drop its whole cache instead
+    pub fn clear_keys(&mut self) {
+        self.keys.clear();
+    }
+}
+
+/// Finds a pair of overlapping prefixes, a client's BCAST prefixes must not overlap
+pub fn overlapping_prefix<'a>(
+    prefixes: impl IntoIterator<Item = &'a String> + Clone,
+) -> Option<(&'a String, &'a String)> {
+    for a in prefixes.clone() {
+        for b in prefixes.clone() {
+            if a != b && a.starts_with(b.as_str()) {
+                return Some((a, b));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidate() {
+        let mut table = TrackingTable::new();
+        table.remember("user:1", 1);
+        table.remember("user:1", 2);
+        table.add_prefix("user:", 3);
+        table.add_prefix("", 4);
+
+        let clients = table.invalidate("user:1");
+        assert_eq!(clients, HashSet::from([1, 2, 3, 4]));
+
+        // default mode clients have to read the key again to hear about it
+        let clients = table.invalidate("user:1");
+        assert_eq!(clients, HashSet::from([3, 4]));
+
+        table.remove_prefix("user:", 3);
+        assert_eq!(table.invalidate("user:1"), HashSet::from([4]));
+    }
+
+    #[test]
+    fn test_tracks_reads() {
+        let mut state = TrackingState {
+            enabled: true,
+            optin: true,
+            ..Default::default()
+        };
+        assert!(!state.tracks_reads());
+
+        state.caching = Some(true);
+        assert!(state.tracks_reads());
+
+        let prefixes = ["a".to_string(), "ab".to_string()];
+        assert!(overlapping_prefix(&prefixes).is_some());
+        assert!(overlapping_prefix(&prefixes[..1]).is_none());
+    }
+}